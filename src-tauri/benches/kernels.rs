@@ -0,0 +1,64 @@
+//! Throughput of the SIMD kernels against their scalar references on a 24 MP frame.
+//!
+//! Run with `cargo bench --bench kernels`. On AArch64 (Apple Silicon) the dispatching
+//! kernels take the NEON paths; elsewhere both columns run the same scalar code.
+use app_lib::image_ops::simd::{self, Adjustments};
+use std::time::{Duration, Instant};
+
+const WIDTH: usize = 6000;
+const HEIGHT: usize = 4000;
+const RUNS: usize = 10;
+
+/// Best-of-`RUNS` wall time, after one warm-up run.
+fn best(mut run: impl FnMut()) -> Duration {
+    run();
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, scalar: Duration, simd: Duration) {
+    let mpix = (WIDTH * HEIGHT) as f64 / 1e6;
+    println!(
+        "{:<12} scalar {:>8.1} MP/s   simd {:>8.1} MP/s   x{:.2}",
+        name,
+        mpix / scalar.as_secs_f64(),
+        mpix / simd.as_secs_f64(),
+        scalar.as_secs_f64() / simd.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut state = 1u32;
+    let cfa: Vec<u16> = (0..WIDTH * HEIGHT)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((state >> 16) & 0x3fff) as u16
+        })
+        .collect();
+    let white = 16383.0;
+    report(
+        "demosaic",
+        best(|| drop(std::hint::black_box(simd::demosaic_rggb_scalar(&cfa, WIDTH, HEIGHT, white)))),
+        best(|| drop(std::hint::black_box(simd::demosaic_rggb(&cfa, WIDTH, HEIGHT, white)))),
+    );
+
+    let rgb = simd::demosaic_rggb(&cfa, WIDTH, HEIGHT, white);
+    let adjustments = Adjustments { gains: Some([1.1, 1.0, 0.9]), brightness_offset: 5.0, contrast: 1.2, saturation: 1.3 };
+    let mut buffer = rgb.clone();
+    // Single-threaded so the comparison measures the kernels, not the thread pool
+    let scalar = best(|| {
+        buffer.copy_from_slice(&rgb);
+        simd::adjust_rgb8_scalar(&mut buffer, &adjustments);
+    });
+    let vector = best(|| {
+        buffer.copy_from_slice(&rgb);
+        simd::adjust_rgb8(&mut buffer, &adjustments);
+    });
+    report("adjust", scalar, vector);
+}
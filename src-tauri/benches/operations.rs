@@ -0,0 +1,104 @@
+//! Criterion benchmarks of the pipeline's heavy operations: demosaic, the fused basic
+//! adjustments, denoise and encode.
+//!
+//! Run with `cargo bench --bench operations -- --save-baseline main` on the base branch,
+//! then `cargo bench --bench operations` on the change and
+//! `cargo run --example compare_benchmarks -- main` to flag regressions.
+use app_lib::commands::{DenoiseMethod, DenoiseOptions, JpegOptions, ProcessOptions, TiffOptions};
+use app_lib::image_ops::{self, encode, simd};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use image::{DynamicImage, RgbImage};
+use std::hint::black_box;
+
+const WIDTH: usize = 2000;
+const HEIGHT: usize = 1500;
+
+/// Noisy 14-bit RGGB sensels, as a RAW file would hold.
+fn cfa() -> Vec<u16> {
+    let mut state = 1u32;
+    (0..WIDTH * HEIGHT)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((state >> 16) & 0x3fff) as u16
+        })
+        .collect()
+}
+
+/// A demosaiced frame: smooth gradients with sensor noise, so encoders see real texture.
+fn frame(width: usize, height: usize) -> DynamicImage {
+    let rgb = simd::demosaic_rggb(&cfa(), WIDTH, HEIGHT, 16383.0);
+    let noisy = RgbImage::from_raw(WIDTH as u32, HEIGHT as u32, rgb).unwrap();
+    let img = RgbImage::from_fn(WIDTH as u32, HEIGHT as u32, |x, y| {
+        let n = noisy.get_pixel(x, y).0;
+        let mix = |base: u32, n: u8| ((base * 3 + n as u32) / 4) as u8;
+        image::Rgb([mix(x * 255 / WIDTH as u32, n[0]), mix(y * 255 / HEIGHT as u32, n[1]), mix(128, n[2])])
+    });
+    DynamicImage::ImageRgb8(img).crop_imm(0, 0, width as u32, height as u32)
+}
+
+fn demosaic(c: &mut Criterion) {
+    let cfa = cfa();
+    let mut group = c.benchmark_group("demosaic");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.bench_function("rggb", |b| b.iter(|| simd::demosaic_rggb(black_box(&cfa), WIDTH, HEIGHT, 16383.0)));
+    group.finish();
+}
+
+fn fused_filters(c: &mut Criterion) {
+    let img = frame(WIDTH, HEIGHT);
+    let options = ProcessOptions { brightness: 8.0, contrast: 1.2, saturation: 1.3, ..Default::default() };
+    let mut group = c.benchmark_group("fused_filters");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.bench_function("adjust_rgb8", |b| {
+        let adjustments = simd::Adjustments { gains: Some([1.1, 1.0, 0.9]), brightness_offset: 5.0, contrast: 1.2, saturation: 1.3 };
+        let rgb = img.to_rgb8().into_raw();
+        b.iter_batched_ref(|| rgb.clone(), |buffer| simd::adjust_rgb8(buffer, &adjustments), BatchSize::LargeInput)
+    });
+    group.bench_function("apply_filters", |b| {
+        b.iter_batched(|| img.clone(), |img| image_ops::apply_filters(img, &options), BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+fn denoise(c: &mut Criterion) {
+    // NL-means takes seconds on a full frame; all methods run on the same 1 MP crop
+    let img = frame(1000, 1000);
+    let mut group = c.benchmark_group("denoise");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1_000_000));
+    for (name, method, strength) in [
+        ("median", DenoiseMethod::Median, 1.0),
+        ("bilateral", DenoiseMethod::Bilateral, 1.0),
+        ("box_chroma", DenoiseMethod::BoxChroma, 2.0),
+        ("nl_means", DenoiseMethod::NlMeans, 1.0),
+    ] {
+        let options = ProcessOptions { denoise: Some(DenoiseOptions { method, strength, ..Default::default() }), ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter_batched(|| img.clone(), |img| image_ops::apply_filters(img, &options), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn encoders(c: &mut Criterion) {
+    let img = frame(WIDTH, HEIGHT);
+    let path = std::env::temp_dir().join(format!("cliobulk_bench_{}.tif", std::process::id()));
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.bench_function("jpeg", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            encode::encode_jpeg(&img, &mut out, &JpegOptions::default(), None, None).unwrap();
+            out
+        })
+    });
+    group.bench_function("tiff", |b| {
+        b.iter(|| encode::tiff::save_tiff(&img, &path.to_string_lossy(), &TiffOptions::default(), None, None).unwrap())
+    });
+    group.finish();
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, demosaic, fused_filters, denoise, encoders);
+criterion_main!(benches);
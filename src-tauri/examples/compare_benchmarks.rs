@@ -0,0 +1,79 @@
+//! Compares the latest criterion run against a saved baseline and fails on regressions.
+//!
+//! Usage: `cargo run --example compare_benchmarks -- <baseline> [--threshold <percent>] [--dir <criterion dir>]`
+//!
+//! Every benchmark with both `<baseline>` and the latest (`new`) estimates is compared on
+//! its mean time; one slower than the baseline by more than the threshold (10% by
+//! default) is flagged and the command exits with status 1. The criterion folder
+//! defaults to `$CARGO_TARGET_DIR/criterion`, or `target/criterion`.
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Mean time in nanoseconds of a criterion `estimates.json`.
+fn mean(path: &Path) -> Option<f64> {
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    json["mean"]["point_estimate"].as_f64()
+}
+
+/// Benchmark folders under `dir` (those holding a `new` run), sorted.
+fn benchmarks(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || path.file_name().is_some_and(|n| n == "report") {
+            continue;
+        }
+        if path.join("new").join("estimates.json").is_file() {
+            found.push(path.clone());
+        }
+        benchmarks(&path, found);
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (mut baseline, mut threshold, mut dir) = (None, 10.0, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => threshold = args.next().and_then(|t| t.parse().ok()).unwrap_or(threshold),
+            "--dir" => dir = args.next().map(PathBuf::from),
+            _ => baseline = Some(arg),
+        }
+    }
+    let Some(baseline) = baseline else {
+        eprintln!("usage: compare_benchmarks <baseline> [--threshold <percent>] [--dir <criterion dir>]");
+        return ExitCode::from(2);
+    };
+    let dir = dir.unwrap_or_else(|| {
+        let target = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+        target.join("criterion")
+    });
+
+    let mut found = Vec::new();
+    benchmarks(&dir, &mut found);
+    found.sort();
+    let (mut compared, mut regressions) = (0, 0);
+    for bench in &found {
+        let (Some(base), Some(new)) = (mean(&bench.join(&baseline).join("estimates.json")), mean(&bench.join("new").join("estimates.json")))
+        else {
+            continue;
+        };
+        let change = (new / base - 1.0) * 100.0;
+        let flag = if change > threshold { "REGRESSION" } else { "" };
+        let name = bench.strip_prefix(&dir).unwrap_or(bench).display();
+        println!("{:<32} {:>12.3} ms -> {:>12.3} ms  {:>+7.1}%  {}", name, base / 1e6, new / 1e6, change, flag);
+        compared += 1;
+        regressions += (change > threshold) as usize;
+    }
+
+    if compared == 0 {
+        eprintln!("No benchmarks with a {:?} baseline under {}", baseline, dir.display());
+        return ExitCode::from(2);
+    }
+    if regressions > 0 {
+        eprintln!("{} of {} benchmarks regressed by more than {}%", regressions, compared, threshold);
+        return ExitCode::FAILURE;
+    }
+    println!("{} benchmarks within {}% of {:?}", compared, threshold, baseline);
+    ExitCode::SUCCESS
+}
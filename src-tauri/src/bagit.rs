@@ -0,0 +1,130 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk BagIt Packages
+ *
+ * Packages a finished batch as a BagIt 1.0 bag (RFC 8493) that repositories
+ * accept as a deposit: the outputs under `data/`, keeping their folders
+ * relative to the deepest folder holding all of them, a payload manifest per
+ * checksum algorithm, `bag-info.txt` with the job's metadata fields and tag
+ * manifests over the tag files. Payload checksums are taken from the copies
+ * in the bag, so the manifests vouch for what was actually deposited.
+ */
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use crate::commands::{BagInfoField, BagOptions};
+use crate::fixity;
+
+/// Fields `create` fills in itself.
+const GENERATED_FIELDS: [&str; 3] = ["Bagging-Date", "Payload-Oxum", "Bag-Software-Agent"];
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BagSummary {
+    pub path: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Creates a bag at `bag_dir` (absent or empty) holding `payload`, copied or, with
+/// `options.move_files`, moved.
+pub fn create(bag_dir: &Path, payload: &[PathBuf], options: &BagOptions) -> Result<BagSummary, String> {
+    if options.algorithms.is_empty() {
+        return Err("A bag needs at least one checksum algorithm".to_string());
+    }
+    validate_fields(&options.info)?;
+    if std::fs::read_dir(bag_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(format!("Bag folder is not empty: {}", bag_dir.display()));
+    }
+    let mut payload = payload.to_vec();
+    payload.sort();
+    payload.dedup();
+    let root = fixity::common_dir(&payload).ok_or("A bag needs at least one payload file")?;
+    let data = bag_dir.join("data");
+    std::fs::create_dir_all(&data).map_err(|e| format!("Failed to create {}: {}", data.display(), e))?;
+
+    let names: Vec<String> = payload.iter().map(|p| format!("data/{}", fixity::entry_name(p, &root))).collect();
+    let digests = payload
+        .par_iter()
+        .zip(&names)
+        .map(|(source, name)| {
+            let dest = bag_dir.join(name);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            transfer(source, &dest, options.move_files)?;
+            let bytes = std::fs::metadata(&dest).map_err(|e| e.to_string())?.len();
+            Ok((fixity::digest_file(&dest, &options.algorithms)?, bytes))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let bytes: u64 = digests.iter().map(|(_, b)| b).sum();
+
+    let mut tag_files = vec![write_tag(bag_dir, "bagit.txt", "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n")?];
+    let (date, _) = crate::image_ops::stamp::format_timestamp(
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+    );
+    let mut info = format!(
+        "Bagging-Date: {}\nPayload-Oxum: {}.{}\nBag-Software-Agent: ClioBulk {}\n",
+        date,
+        bytes,
+        payload.len(),
+        env!("CARGO_PKG_VERSION")
+    );
+    for field in &options.info {
+        // Further lines of a value continue with leading whitespace
+        info.push_str(&format!("{}: {}\n", field.label, field.value.trim_end().replace('\n', "\n  ")));
+    }
+    tag_files.push(write_tag(bag_dir, "bag-info.txt", &info)?);
+
+    for (a, &algorithm) in options.algorithms.iter().enumerate() {
+        let mut lines: Vec<String> = names.iter().zip(&digests).map(|(name, (d, _))| manifest_line(&d[a], name)).collect();
+        lines.sort();
+        tag_files.push(write_tag(bag_dir, &format!("manifest-{}.txt", algorithm.name()), &lines.concat())?);
+    }
+    for &algorithm in &options.algorithms {
+        let mut lines = Vec::new();
+        for name in &tag_files {
+            let digest = fixity::digest_file(&bag_dir.join(name), &[algorithm])?.remove(0);
+            lines.push(manifest_line(&digest, name));
+        }
+        lines.sort();
+        write_tag(bag_dir, &format!("tagmanifest-{}.txt", algorithm.name()), &lines.concat())?;
+    }
+    Ok(BagSummary { path: bag_dir.to_string_lossy().to_string(), files: payload.len(), bytes })
+}
+
+fn validate_fields(fields: &[BagInfoField]) -> Result<(), String> {
+    for field in fields {
+        let label = field.label.trim();
+        if label.is_empty() || label != field.label || label.contains([':', '\n', '\r']) {
+            return Err(format!("Invalid bag-info label: {:?}", field.label));
+        }
+        if GENERATED_FIELDS.iter().any(|g| g.eq_ignore_ascii_case(label)) {
+            return Err(format!("{} is filled in when the bag is created", label));
+        }
+    }
+    Ok(())
+}
+
+/// A manifest line, with CR, LF and `%` in the path percent-encoded as BagIt requires.
+fn manifest_line(digest: &str, name: &str) -> String {
+    format!("{}  {}\n", digest, name.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A"))
+}
+
+fn write_tag(bag_dir: &Path, name: &str, text: &str) -> Result<String, String> {
+    let path = bag_dir.join(name);
+    std::fs::write(&path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(name.to_string())
+}
+
+fn transfer(source: &Path, dest: &Path, move_file: bool) -> Result<(), String> {
+    // A rename across drives fails; those moves copy, then remove the source
+    if move_file && std::fs::rename(source, dest).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(source, dest).map_err(|e| format!("Failed to copy {} into the bag: {}", source.display(), e))?;
+    if move_file {
+        std::fs::remove_file(source).map_err(|e| format!("Failed to remove {}: {}", source.display(), e))?;
+    }
+    Ok(())
+}
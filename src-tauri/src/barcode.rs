@@ -0,0 +1,272 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Barcode Detection
+ *
+ * Finds QR codes, Code 39 and Code 128 barcodes on scanned pages, for the
+ * separator sheets scanning operations insert between documents. QR codes
+ * are located and decoded with `rqrr`; Code 39, still the usual symbology of
+ * printed separator sheets, and Code 128, what most label printers produce,
+ * are read along scanlines in both directions, horizontally and vertically,
+ * so a sheet fed sideways or upside down is found too.
+ */
+use image::{DynamicImage, GrayImage};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Long edge pages are reduced to before detection; separator codes are printed large.
+const MAX_EDGE: u32 = 2400;
+/// Scanlines a linear barcode value must be read on, so a stray match in text is not
+/// taken for a barcode.
+const MIN_LINES: usize = 2;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Symbology {
+    Qr,
+    Code39,
+    Code128,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Barcode {
+    pub symbology: Symbology,
+    pub value: String,
+}
+
+/// Every code found on `img`, QR codes first.
+pub fn detect(img: &DynamicImage) -> Vec<Barcode> {
+    let gray = if img.width().max(img.height()) > MAX_EDGE {
+        img.resize(MAX_EDGE, MAX_EDGE, image::imageops::FilterType::Triangle).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let mut found = Vec::new();
+
+    let mut prepared = rqrr::PreparedImage::prepare(gray.clone());
+    for grid in prepared.detect_grids() {
+        if let Ok((_, value)) = grid.decode() {
+            push_unique(&mut found, Barcode { symbology: Symbology::Qr, value });
+        }
+    }
+    for (symbology, value) in read_linear(&gray) {
+        push_unique(&mut found, Barcode { symbology, value });
+    }
+    found
+}
+
+fn push_unique(found: &mut Vec<Barcode>, code: Barcode) {
+    if !found.contains(&code) {
+        found.push(code);
+    }
+}
+
+/// Code 39 and Code 128 values read on at least `MIN_LINES` scanlines, most often
+/// read first.
+fn read_linear(gray: &GrayImage) -> Vec<(Symbology, String)> {
+    let (width, height) = (gray.width() as usize, gray.height() as usize);
+    let pixels = gray.as_raw();
+    let mut reads: BTreeMap<(Symbology, String), usize> = BTreeMap::new();
+    let mut scan = |line: Vec<u8>| {
+        let runs = runs(&line);
+        let reversed: Vec<(bool, u32)> = runs.iter().rev().copied().collect();
+        // Backwards reads find codes printed upside down, or turned the other way
+        for backwards in [false, true] {
+            if let Some(value) = decode_code39(&runs, backwards) {
+                *reads.entry((Symbology::Code39, value)).or_default() += 1;
+            }
+        }
+        for runs in [&runs, &reversed] {
+            if let Some(value) = decode_code128(runs) {
+                *reads.entry((Symbology::Code128, value)).or_default() += 1;
+            }
+        }
+    };
+    // About a hundred lines each way, enough to cross a code of a few millimetres
+    for y in (0..height).step_by((height / 100).max(1)) {
+        scan(pixels[y * width..(y + 1) * width].to_vec());
+    }
+    for x in (0..width).step_by((width / 100).max(1)) {
+        scan((0..height).map(|y| pixels[y * width + x]).collect());
+    }
+    let mut values: Vec<((Symbology, String), usize)> = reads.into_iter().filter(|(_, n)| *n >= MIN_LINES).collect();
+    values.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    values.into_iter().map(|(value, _)| value).collect()
+}
+
+/// Lengths of alternating dark (true) and light runs along a line, thresholded halfway
+/// between its darkest and lightest pixel.
+fn runs(line: &[u8]) -> Vec<(bool, u32)> {
+    let (min, max) = line.iter().fold((255u8, 0u8), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    // A flat line has no bars
+    if max.saturating_sub(min) < 64 {
+        return Vec::new();
+    }
+    let threshold = ((min as u16 + max as u16) / 2) as u8;
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for &v in line {
+        let dark = v < threshold;
+        match runs.last_mut() {
+            Some((d, n)) if *d == dark => *n += 1,
+            _ => runs.push((dark, 1)),
+        }
+    }
+    runs
+}
+
+/// Patterns of the 43 Code 39 characters and the `*` start/stop, bars and spaces
+/// alternating from the first bar, 1 for a wide element.
+const CODE39: [(char, u16); 44] = [
+    ('0', 0b000110100), ('1', 0b100100001), ('2', 0b001100001), ('3', 0b101100000), ('4', 0b000110001),
+    ('5', 0b100110000), ('6', 0b001110000), ('7', 0b000100101), ('8', 0b100100100), ('9', 0b001100100),
+    ('A', 0b100001001), ('B', 0b001001001), ('C', 0b101001000), ('D', 0b000011001), ('E', 0b100011000),
+    ('F', 0b001011000), ('G', 0b000001101), ('H', 0b100001100), ('I', 0b001001100), ('J', 0b000011100),
+    ('K', 0b100000011), ('L', 0b001000011), ('M', 0b101000010), ('N', 0b000010011), ('O', 0b100010010),
+    ('P', 0b001010010), ('Q', 0b000000111), ('R', 0b100000110), ('S', 0b001000110), ('T', 0b000010110),
+    ('U', 0b110000001), ('V', 0b011000001), ('W', 0b111000000), ('X', 0b010010001), ('Y', 0b110010000),
+    ('Z', 0b011010000), ('-', 0b010000101), ('.', 0b110000100), (' ', 0b011000100), ('$', 0b010101000),
+    ('/', 0b010100010), ('+', 0b010001010), ('%', 0b000101010), ('*', 0b010010100),
+];
+
+/// The first Code 39 value on a line: `*`, one or more characters and `*`, each
+/// followed by a narrow gap. The start needs a quiet zone of ten narrow elements.
+/// `backwards` reads a code running right to left along the line.
+fn decode_code39(runs: &[(bool, u32)], backwards: bool) -> Option<String> {
+    for start in 1..runs.len().saturating_sub(8) {
+        let Some(('*', narrow)) = character(&runs[start..start + 9], backwards) else { continue };
+        if runs[start - 1].1 < narrow * 10 {
+            continue;
+        }
+        let mut value = String::new();
+        let mut pos = start + 10;
+        while let Some((c, _)) = runs.get(pos..pos + 9).and_then(|e| character(e, backwards)) {
+            if c == '*' {
+                if value.is_empty() {
+                    break;
+                }
+                return Some(if backwards { value.chars().rev().collect() } else { value });
+            }
+            value.push(c);
+            pos += 10;
+        }
+    }
+    None
+}
+
+/// The character of nine elements starting at a bar, with their narrow width.
+fn character(elements: &[(bool, u32)], backwards: bool) -> Option<(char, u32)> {
+    if !elements[0].0 {
+        return None;
+    }
+    let mut widths: Vec<u32> = elements.iter().map(|e| e.1).collect();
+    widths.sort_unstable();
+    // Exactly three wide elements, clearly wider than the six narrow ones
+    let (widest_narrow, narrowest_wide) = (widths[5], widths[6]);
+    if narrowest_wide * 2 < widest_narrow * 3 || widths[8] > widths[0] * 6 {
+        return None;
+    }
+    let mut pattern = elements.iter().fold(0u16, |bits, e| bits << 1 | (e.1 >= narrowest_wide) as u16);
+    if backwards {
+        pattern = pattern.reverse_bits() >> 7;
+    }
+    CODE39.iter().find(|(_, p)| *p == pattern).map(|(c, _)| (*c, widths[0].max(1)))
+}
+
+/// Element widths in modules of the Code 128 symbols by value, bar first, as decimal
+/// digits: 0-102 are data and function values, 103-105 Start A, B and C.
+const CODE128: [u32; 106] = [
+    212222, 222122, 222221, 121223, 121322, 131222, 122213, 122312, 132212, 221213,
+    221312, 231212, 112232, 122132, 122231, 113222, 123122, 123221, 223211, 221132,
+    221231, 213212, 223112, 312131, 311222, 321122, 321221, 312212, 322112, 322211,
+    212123, 212321, 232121, 111323, 131123, 131321, 112313, 132113, 132311, 211313,
+    231113, 231311, 112133, 112331, 132131, 113123, 113321, 133121, 313121, 211331,
+    231131, 213113, 213311, 213131, 311123, 311321, 331121, 312113, 312311, 332111,
+    314111, 221411, 431111, 111224, 111422, 121124, 121421, 141122, 141221, 112214,
+    112412, 122114, 122411, 142112, 142211, 241211, 221114, 413111, 241112, 134111,
+    111242, 121142, 121241, 114212, 124112, 124211, 411212, 421112, 421211, 212141,
+    214121, 412121, 111143, 111341, 131141, 114113, 114311, 411113, 411311, 113141,
+    114131, 311141, 411131, 211412, 211214, 211232,
+];
+/// The stop pattern, ending in the two-module termination bar.
+const CODE128_STOP: u32 = 2331112;
+const START_A: usize = 103;
+
+/// The first Code 128 value on a line, read left to right: a start code after a quiet
+/// zone of ten modules, data symbols, the check symbol and the stop pattern.
+fn decode_code128(runs: &[(bool, u32)]) -> Option<String> {
+    for start in 1..runs.len().saturating_sub(6) {
+        let Some((code @ START_A.., module)) = symbol(&runs[start..start + 6]) else { continue };
+        if (runs[start - 1].1 as f32) < module * 10.0 {
+            continue;
+        }
+        let mut values = Vec::new();
+        let mut pos = start + 6;
+        loop {
+            if runs.get(pos..pos + 7).and_then(|e| modules(e, 13)).is_some_and(|(pattern, _)| pattern == CODE128_STOP) {
+                match code128_text(code, &values) {
+                    Some(text) => return Some(text),
+                    None => break,
+                }
+            }
+            match runs.get(pos..pos + 6).and_then(symbol) {
+                Some((value, _)) => values.push(value),
+                None => break,
+            }
+            pos += 6;
+        }
+    }
+    None
+}
+
+/// The value of the six elements starting at a bar, with their module width.
+fn symbol(elements: &[(bool, u32)]) -> Option<(usize, f32)> {
+    let (pattern, module) = modules(elements, 11)?;
+    CODE128.iter().position(|&p| p == pattern).map(|value| (value, module))
+}
+
+/// The widths of `elements`, starting at a bar, in modules of a symbol `total` modules
+/// wide, as decimal digits like `CODE128`, when each is one to four modules.
+fn modules(elements: &[(bool, u32)], total: u32) -> Option<(u32, f32)> {
+    if !elements.first()?.0 {
+        return None;
+    }
+    let module = elements.iter().map(|e| e.1).sum::<u32>() as f32 / total as f32;
+    let (mut pattern, mut sum) = (0, 0);
+    for e in elements {
+        let width = (e.1 as f32 / module).round() as u32;
+        if !(1..=4).contains(&width) {
+            return None;
+        }
+        (pattern, sum) = (pattern * 10 + width, sum + width);
+    }
+    (sum == total).then_some((pattern, module))
+}
+
+/// The text of the symbol `values` after the start code `start`, the last value being
+/// the modulo 103 check. Function characters are dropped.
+fn code128_text(start: usize, values: &[usize]) -> Option<String> {
+    let (&check, data) = values.split_last()?;
+    let weighted = data.iter().enumerate().fold(start, |sum, (i, &v)| sum + (i + 1) * v);
+    if data.is_empty() || weighted % 103 != check {
+        return None;
+    }
+    // Code sets A, B and C as 0, 1 and 2; a shift reads the next symbol in the other of A and B
+    let (mut set, mut shift) = (start - START_A, false);
+    let mut text = String::new();
+    for &value in data {
+        let current = if shift { 1 - set } else { set };
+        shift = false;
+        match (current, value) {
+            (2, 0..=99) => text.push_str(&format!("{:02}", value)),
+            (0, 0..=63) | (1, 0..=95) => text.push((b' ' + value as u8) as char),
+            (0, 64..=95) => text.push((value as u8 - 64) as char),
+            (0 | 1, 98) => shift = true,
+            (0 | 1, 99) => set = 2,
+            (0 | 2, 100) => set = 1,
+            (1 | 2, 101) => set = 0,
+            (_, 96..=102) => {}
+            _ => return None,
+        }
+    }
+    Some(text)
+}
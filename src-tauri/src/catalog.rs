@@ -0,0 +1,146 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Catalog
+ *
+ * Per-file notes (retouching instructions, client remarks) and processing
+ * state kept across sessions in `catalog.json` in the app data directory,
+ * keyed by the source path. Exports can carry their note as an XMP
+ * description, so it travels with the batch to whoever opens the files next;
+ * the edited/exported/failed flags let the grid badge what is left to do.
+ */
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const FILE_NAME: &str = "catalog.json";
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Note {
+    pub text: String,
+    /// Unix time of the last edit.
+    pub updated: u64,
+}
+
+/// Processing state of a source file; unknown files have every flag off.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FileState {
+    /// Settings were changed for this file in the editor.
+    pub edited: bool,
+    /// At least one export succeeded.
+    pub exported: bool,
+    /// The last export attempt failed, with `error` saying why.
+    pub failed: bool,
+    pub error: Option<String>,
+    /// Unix time of the last change, 0 when never recorded.
+    pub updated: u64,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct CatalogFile {
+    notes: BTreeMap<String, Note>,
+    /// Absent from catalogs written before state tracking.
+    #[serde(default)]
+    files: BTreeMap<String, FileState>,
+}
+
+/// The catalog, registered as Tauri managed state. Until `open` is called it
+/// holds notes and states in memory only.
+#[derive(Default)]
+pub struct Catalog {
+    file: Mutex<Option<PathBuf>>,
+    data: Mutex<CatalogFile>,
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+impl Catalog {
+    /// Loads `file` and saves every later change to it. A catalog that cannot be
+    /// parsed is set aside as `.bak` rather than overwritten.
+    pub fn open(&self, file: &Path) {
+        let data = match std::fs::read(file) {
+            Ok(bytes) => match serde_json::from_slice::<CatalogFile>(&bytes) {
+                Ok(catalog) => catalog,
+                Err(e) => {
+                    error!("Unreadable catalog {}: {}", file.display(), e);
+                    let _ = std::fs::rename(file, file.with_extension("json.bak"));
+                    CatalogFile::default()
+                }
+            },
+            Err(_) => CatalogFile::default(),
+        };
+        *self.data.lock().unwrap() = data;
+        *self.file.lock().unwrap() = Some(file.to_path_buf());
+    }
+
+    /// Sets the note of `path`; empty text removes it.
+    pub fn set_note(&self, path: &str, text: &str) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        if text.trim().is_empty() {
+            data.notes.remove(path);
+        } else {
+            data.notes.insert(path.to_string(), Note { text: text.to_string(), updated: now() });
+        }
+        self.save(&data)
+    }
+
+    pub fn note(&self, path: &str) -> Option<String> {
+        self.data.lock().unwrap().notes.get(path).map(|n| n.text.clone())
+    }
+
+    pub fn notes(&self) -> BTreeMap<String, Note> {
+        self.data.lock().unwrap().notes.clone()
+    }
+
+    /// Flags `paths` as edited or not, e.g. when their settings are changed or reset.
+    pub fn set_edited(&self, paths: &[String], edited: bool) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        for path in paths {
+            let state = data.files.entry(path.clone()).or_default();
+            state.edited = edited;
+            state.updated = now();
+        }
+        self.save(&data)
+    }
+
+    /// Records the outcome of exporting `path`. Only held in memory, so a batch does not
+    /// rewrite the catalog once per file; `flush` saves it.
+    pub fn record_export(&self, path: &str, error: Option<&str>) {
+        let mut data = self.data.lock().unwrap();
+        let state = data.files.entry(path.to_string()).or_default();
+        state.exported |= error.is_none();
+        state.failed = error.is_some();
+        state.error = error.map(str::to_string);
+        state.updated = now();
+    }
+
+    /// The state of each of `paths`, in order.
+    pub fn file_states(&self, paths: &[String]) -> Vec<FileState> {
+        let data = self.data.lock().unwrap();
+        paths.iter().map(|path| data.files.get(path).cloned().unwrap_or_default()).collect()
+    }
+
+    pub fn flush(&self) -> Result<(), String> {
+        let data = self.data.lock().unwrap();
+        self.save(&data)
+    }
+
+    /// Written next to the catalog and renamed, so a crash never leaves it truncated.
+    fn save(&self, data: &CatalogFile) -> Result<(), String> {
+        let Some(file) = self.file.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_vec_pretty(data).map_err(|e| e.to_string())?;
+        let tmp = file.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &file))
+            .map_err(|e| format!("Failed to save catalog {}: {}", file.display(), e))
+    }
+}
@@ -10,20 +10,2062 @@
 use image::DynamicImage;
 use serde::{Deserialize, Serialize};
 use base64::{Engine as _, engine::general_purpose};
-use tauri::{AppHandle, Emitter, Runtime};
+use tauri::{AppHandle, Emitter, EventTarget, Manager, Runtime, State, WebviewWindow};
 use tauri_plugin_fs::FsExt;
 use log::{info, error};
 use std::sync::Arc;
 use tokio::sync::Semaphore;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 use crate::image_ops;
+use crate::jobs::{self, JobCheckpoint, JobSummary};
+use crate::intake::{self, SharedInbox};
+use crate::looks::{self, LookInfo};
+use crate::image_ops::align::Transform;
+use crate::image_ops::calibration::{self, Calibration};
+use crate::image_ops::lut::Lut3d;
+use crate::image_ops::marks::CropMarks;
+use crate::bagit::{self, BagSummary};
+use crate::barcode;
+use crate::catalog::{Catalog, FileState, Note};
+use crate::conformance::{self, ConformanceReport, OutputSpec};
+use crate::debug::{self, StageDump, StageDumps};
+use crate::device::{self, DeviceState, Throttle};
+use crate::execution::{self, ExecutionStatus};
+use crate::fixity;
+use crate::iiif;
+use crate::memory::{MemorySettings, MemoryStatus};
+use crate::mets::{self, MetsSummary};
+use crate::mix;
+use crate::metadata::{self, KeywordAssignment, KeywordSuggestions, KeywordUpdate, MetadataSync, SyncStatus};
+use crate::naming::{self, CustomTokens, TokenRegistry};
+use crate::preview::{PreviewSessions, PreviewSettings};
+use crate::scan::{self, FolderScan};
+use crate::scheduler::{self, BatchWindow, Halt, RunningJobs, ScheduledBatch, Scheduler};
+use crate::settings::SettingsStore;
+use crate::storage;
+use crate::submission::{self, FileReport, SubmissionProfile, TargetProfile};
+use crate::verification::{self, HeldBatch, PendingBatches};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ProcessOptions {
     pub brightness: f32,
     pub contrast: f32,
     pub saturation: f32,
     pub adaptive_threshold: bool,
-    pub denoise: bool,
+    /// Reduction to one channel ahead of binarization, kept 8- or 16-bit like the source.
+    #[serde(default)]
+    pub grayscale: Option<Grayscale>,
+    /// 1-bit conversion; takes precedence over `adaptive_threshold` when set.
+    #[serde(default)]
+    pub binarization: Option<Binarization>,
+    /// Speck removal on the binarized result; skipped for images that are not 1-bit.
+    #[serde(default)]
+    pub despeckle: Option<DespeckleOptions>,
+    /// Text recognition after binarization, saved as a sidecar of each output.
+    #[serde(default)]
+    pub ocr: Option<OcrOptions>,
+    /// Noise reduction. Presets saved before the method/strength settings send a
+    /// plain boolean, which maps to `true` = 3x3 median and `false` = off.
+    #[serde(default, deserialize_with = "legacy_denoise")]
+    pub denoise: Option<DenoiseOptions>,
+    /// Dust and scratch removal for film and slide scans, ahead of noise reduction.
+    #[serde(default)]
+    pub dust: Option<DustOptions>,
+    #[serde(default)]
+    pub invert_negative: Option<NegativeOptions>,
+    /// Solid boxes burned over sensitive content, right after negative inversion so OCR,
+    /// previews and every variant only ever see the redacted pixels.
+    #[serde(default)]
+    pub redaction: Option<RedactionOptions>,
+    /// Mirror left-right, before any other geometric operation.
+    #[serde(default)]
+    pub flip_h: bool,
+    /// Mirror top-bottom, before any other geometric operation.
+    #[serde(default)]
+    pub flip_v: bool,
+    /// Keystone correction, after flips and before rotation.
+    #[serde(default)]
+    pub perspective: Option<Perspective>,
+    /// Quarter-turn correction of pages captured sideways or upside down, from the
+    /// direction of their text; before deskew.
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+    /// Skew correction for scanned pages, from their lines of text.
+    #[serde(default)]
+    pub deskew: Option<DeskewOptions>,
+    /// Auto-leveling from detected lines, before manual rotation.
+    #[serde(default)]
+    pub straighten: Option<StraightenOptions>,
+    #[serde(default)]
+    pub rotate: Option<RotateOptions>,
+    #[serde(default)]
+    pub crop: Option<Crop>,
+    /// Color correction against a target photographed with the batch, applied before
+    /// white balance.
+    #[serde(default)]
+    pub calibration: Option<CalibrationOptions>,
+    #[serde(default)]
+    pub white_balance: WhiteBalance,
+    /// How the `Auto` and `Lock*` white balance modes estimate the illuminant.
+    #[serde(default)]
+    pub awb_method: AwbMethod,
+    /// Local contrast strength; 0.0 disables, negative values soften.
+    #[serde(default)]
+    pub clarity: f32,
+    /// Flattening of uneven lighting on documents, ahead of contrast and binarization.
+    #[serde(default)]
+    pub illumination: Option<IlluminationOptions>,
+    /// Haze removal strength in 0.0..=1.0; 0.0 disables.
+    #[serde(default)]
+    pub dehaze: f32,
+    #[serde(default)]
+    pub clahe: Option<ClaheOptions>,
+    #[serde(default)]
+    pub channel_mixer: ChannelMixer,
+    #[serde(default)]
+    pub curves: Option<Curves>,
+    #[serde(default)]
+    pub hsl: HslAdjustments,
+    #[serde(default)]
+    pub replace_color: Option<ColorReplacement>,
+    #[serde(default)]
+    pub split_toning: SplitToning,
+    #[serde(default)]
+    pub toning: Option<Toning>,
+    #[serde(default)]
+    pub lut: Option<LutOptions>,
+    #[serde(default)]
+    pub vignette: Option<VignetteOptions>,
+    #[serde(default)]
+    pub orton: Option<OrtonOptions>,
+    /// Gaussian blur standard deviation in pixels; 0.0 disables.
+    #[serde(default)]
+    pub blur: f32,
+    #[serde(default)]
+    pub grain: Option<GrainOptions>,
+    /// Output dimensions, applied after every other stage.
+    #[serde(default)]
+    pub resize: Option<ResizeOptions>,
+    /// Logo composited onto the resized image, inside any canvas border.
+    #[serde(default)]
+    pub watermark: Option<WatermarkOptions>,
+    #[serde(default)]
+    pub text_stamp: Option<TextStampOptions>,
+    /// Padding and border, added around the resized image.
+    #[serde(default)]
+    pub canvas: Option<CanvasOptions>,
+    #[serde(default)]
+    pub output: OutputOptions,
+    /// Extra outputs of every file for other destinations, such as a watermarked web
+    /// proof next to a clean print master.
+    #[serde(default)]
+    pub variants: Vec<VariantSpec>,
+    /// In `process_bulk`, one multi-page TIFF per input folder instead of a file per input.
+    #[serde(default)]
+    pub multipage: Option<MultipageOptions>,
+    /// Checksum manifests of the files a bulk run wrote, as fixity evidence for the transfer.
+    #[serde(default)]
+    pub fixity: Option<FixityOptions>,
+    /// In `process_bulk`, barcode separator sheets splitting the batch into documents.
+    #[serde(default)]
+    pub separation: Option<SeparationOptions>,
+    /// Splitting of two-page book scans, into `_L` and `_R` outputs per source.
+    #[serde(default)]
+    pub page_split: Option<PageSplitOptions>,
+    /// Detection of blank pages (e.g. the versos of a book scan), checked on the source,
+    /// or on each page when splitting.
+    #[serde(default)]
+    pub blank_pages: Option<BlankPageOptions>,
+    /// Custom stage order. Listed stages run in list order (as often as listed) when
+    /// their settings enable them; unlisted stages are skipped. `None` keeps the default order.
+    #[serde(default)]
+    pub pipeline: Option<Vec<Stage>>,
+}
+
+/// A pipeline stage, named for ordering with `ProcessOptions::pipeline`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    InvertNegative,
+    /// Filling redaction boxes.
+    Redact,
+    Flip,
+    Perspective,
+    /// Turning pages upright by quarter turns.
+    Orient,
+    Deskew,
+    Straighten,
+    Rotate,
+    Crop,
+    /// Dust and scratch removal.
+    Dust,
+    Denoise,
+    Illumination,
+    Dehaze,
+    Clahe,
+    /// Color calibration, white balance, brightness, contrast, saturation, channel mixer,
+    /// curves, HSL and toning.
+    Adjust,
+    Lut,
+    Vignette,
+    Clarity,
+    Orton,
+    Blur,
+    Grain,
+    /// Reduction to one channel.
+    Grayscale,
+    /// Thresholding or dithering.
+    Binarize,
+    Despeckle,
+    Ocr,
+    Resize,
+    Watermark,
+    TextStamp,
+    Canvas,
+}
+
+impl Default for ProcessOptions {
+    /// Neutral settings: every stage is a no-op.
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            adaptive_threshold: false,
+            grayscale: None,
+            binarization: None,
+            despeckle: None,
+            ocr: None,
+            variants: Vec::new(),
+            multipage: None,
+            fixity: None,
+            separation: None,
+            denoise: None,
+            dust: None,
+            invert_negative: None,
+            redaction: None,
+            flip_h: false,
+            flip_v: false,
+            perspective: None,
+            orientation: None,
+            deskew: None,
+            straighten: None,
+            rotate: None,
+            crop: None,
+            calibration: None,
+            white_balance: WhiteBalance::default(),
+            awb_method: AwbMethod::default(),
+            clarity: 0.0,
+            illumination: None,
+            dehaze: 0.0,
+            clahe: None,
+            channel_mixer: ChannelMixer::default(),
+            curves: None,
+            hsl: HslAdjustments::default(),
+            replace_color: None,
+            split_toning: SplitToning::default(),
+            toning: None,
+            lut: None,
+            vignette: None,
+            orton: None,
+            blur: 0.0,
+            grain: None,
+            resize: None,
+            watermark: None,
+            text_stamp: None,
+            canvas: None,
+            output: OutputOptions::default(),
+            page_split: None,
+            blank_pages: None,
+            pipeline: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DenoiseOptions {
+    pub method: DenoiseMethod,
+    /// Method-specific strength; 1.0 with the median method is the original 3x3 filter.
+    pub strength: f32,
+    /// Edge of the square patches compared by NL-means (odd, 3..=7).
+    pub patch_size: u32,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self { method: DenoiseMethod::Median, strength: 1.0, patch_size: 3 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DenoiseMethod {
+    /// Median filter with radius `strength` (rounded, 1..=5). Removes salt-and-pepper
+    /// noise but rounds off fine detail.
+    #[default]
+    Median,
+    /// Edge-preserving bilateral filter; `strength` scales the tonal range that is smoothed.
+    Bilateral,
+    /// Averages only the chroma channels over a `2 * strength` pixel box, leaving
+    /// luminance (and therefore text edges) untouched. Targets high-ISO color noise.
+    BoxChroma,
+    /// Non-local means: every pixel becomes a weighted average of pixels in an 11x11
+    /// window whose surrounding patches look alike. Far better on heavy noise than
+    /// the local filters, but several times slower; `strength` sets the filtering level.
+    NlMeans,
+}
+
+fn legacy_denoise<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<DenoiseOptions>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Legacy(bool),
+        Options(DenoiseOptions),
+    }
+    Ok(match Option::<Setting>::deserialize(deserializer)? {
+        None | Some(Setting::Legacy(false)) => None,
+        Some(Setting::Legacy(true)) => Some(DenoiseOptions::default()),
+        Some(Setting::Options(options)) => Some(options),
+    })
+}
+
+/// Correction of uneven lighting (a desk lamp falling off across a page shot with a
+/// camera): each pixel is divided by an estimate of the bare paper around it.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct IlluminationOptions {
+    pub method: BackgroundMethod,
+    /// Size of the estimate in pixels: above the thickest stroke, below the distance
+    /// over which the lighting changes.
+    pub radius: u32,
+    /// Outputs the flattened luminance as grayscale rather than keeping color.
+    pub grayscale: bool,
+}
+
+impl Default for IlluminationOptions {
+    fn default() -> Self {
+        Self { method: BackgroundMethod::Blur, radius: 30, grayscale: false }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundMethod {
+    /// Max filter then Gaussian blur: fast, though the paper is slightly overestimated
+    /// next to large dark areas.
+    #[default]
+    Blur,
+    /// Rolling ball of `radius` pixels, as in ImageJ's background subtraction. Follows
+    /// the lighting more closely around photos and headings, at a few times the cost.
+    RollingBall,
+}
+
+/// Contrast limited adaptive histogram equalization, applied to luminance only.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct ClaheOptions {
+    /// Histogram bins are capped at this multiple of the average bin height; lower
+    /// values limit the contrast boost (and noise amplification). 0.0 disables.
+    pub clip_limit: f32,
+    /// Edge of the square regions equalized independently, in pixels (min 8).
+    pub tile_size: u32,
+}
+
+impl Default for ClaheOptions {
+    fn default() -> Self {
+        Self { clip_limit: 2.0, tile_size: 128 }
+    }
+}
+
+/// Output channels as weighted sums of the input channels.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct ChannelMixer {
+    /// Rows are the output red, green and blue; columns weight the input red, green and blue.
+    pub matrix: [[f32; 3]; 3],
+    /// Use the red row for all three outputs, e.g. a custom black-and-white conversion.
+    pub monochrome: bool,
+}
+
+impl Default for ChannelMixer {
+    fn default() -> Self {
+        Self { matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], monochrome: false }
+    }
+}
+
+impl ChannelMixer {
+    /// The matrix actually applied, with monochrome expanded.
+    pub fn rows(&self) -> [[f32; 3]; 3] {
+        if self.monochrome {
+            [self.matrix[0]; 3]
+        } else {
+            self.matrix
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.rows() == Self::default().matrix
+    }
+}
+
+/// Moves one hue range onto another hue, e.g. to undo a color cast that affects
+/// only some colors across a whole batch.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct ColorReplacement {
+    /// Center of the replaced range, in degrees.
+    pub source_hue: f32,
+    /// Hue, in degrees, the range is moved to.
+    pub target_hue: f32,
+    /// Half-width, in degrees, of the range that is fully replaced.
+    pub tolerance: f32,
+    /// Degrees beyond the tolerance over which the replacement fades out.
+    pub feather: f32,
+}
+
+impl Default for ColorReplacement {
+    fn default() -> Self {
+        Self { source_hue: 0.0, target_hue: 0.0, tolerance: 15.0, feather: 15.0 }
+    }
+}
+
+/// Tints shadows and highlights with separate hues.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SplitToning {
+    /// Hue in degrees.
+    pub shadow_hue: f32,
+    /// 0.0..=1.0; 0.0 disables the shadow tint.
+    pub shadow_strength: f32,
+    pub highlight_hue: f32,
+    pub highlight_strength: f32,
+    /// -1.0..=1.0; positive values extend the highlight tint into the midtones.
+    pub balance: f32,
+}
+
+impl SplitToning {
+    pub fn is_active(&self) -> bool {
+        self.shadow_strength > 0.0 || self.highlight_strength > 0.0
+    }
+}
+
+/// Orton-style glow: a brightened, blurred copy screened over the image.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct OrtonOptions {
+    /// Blend of the glow, 0.0..=1.0; 0.0 disables.
+    pub opacity: f32,
+    /// Overexposure of the glow copy in stops.
+    pub exposure: f32,
+    /// Blur standard deviation as a fraction of the long edge, so previews match exports.
+    pub size: f32,
+}
+
+impl Default for OrtonOptions {
+    fn default() -> Self {
+        Self { opacity: 0.5, exposure: 1.0, size: 0.01 }
+    }
+}
+
+/// Radial vignette centered on the frame.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct VignetteOptions {
+    /// -1.0..=1.0. Negative values darken the corners (creative vignette), positive
+    /// values brighten them by up to one stop to correct lens falloff on scans.
+    pub amount: f32,
+    /// 0.0..=1.0 distance from the center, relative to the corners, where the falloff begins.
+    #[serde(default = "half")]
+    pub midpoint: f32,
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+/// How color is reduced to gray: a weighted mix of the channels, or one channel alone
+/// for microfilm and UV or IR captures where the others only carry noise.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Grayscale {
+    /// Weights are scaled to sum to 1, so they only set the mix and not the exposure;
+    /// negative ones subtract a channel.
+    Weights { red: f32, green: f32, blue: f32 },
+    Red,
+    Green,
+    Blue,
+}
+
+impl Grayscale {
+    /// Red, green and blue weights, summing to 1 unless they cancel out.
+    pub fn weights(&self) -> [f32; 3] {
+        match *self {
+            Grayscale::Weights { red, green, blue } => {
+                let sum = red + green + blue;
+                if sum.abs() > 1e-6 {
+                    [red / sum, green / sum, blue / sum]
+                } else {
+                    [red, green, blue]
+                }
+            }
+            Grayscale::Red => [1.0, 0.0, 0.0],
+            Grayscale::Green => [0.0, 1.0, 0.0],
+            Grayscale::Blue => [0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// How the final image is reduced to pure black and white.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Binarization {
+    /// Local mean threshold over a `window` x `window` square, as with `adaptive_threshold`.
+    Adaptive {
+        #[serde(default = "adaptive_window")]
+        window: u32,
+    },
+    /// One global threshold from Otsu's method; for clean, evenly lit scans.
+    Otsu,
+    /// Local threshold `mean * (1 + k * (std / 128 - 1))`. Follows uneven lighting and
+    /// stained paper while leaving flat background white.
+    Sauvola {
+        #[serde(default = "local_window")]
+        window: u32,
+        #[serde(default = "sauvola_k")]
+        k: f32,
+    },
+    /// Local threshold `mean + k * std`. Catches faint strokes, but turns noise in
+    /// empty areas into specks.
+    Niblack {
+        #[serde(default = "local_window")]
+        window: u32,
+        #[serde(default = "niblack_k")]
+        k: f32,
+    },
+    /// Ordered dithering with a `size` x `size` Bayer matrix (2, 4 or 8).
+    Bayer {
+        #[serde(default = "bayer_size")]
+        size: u32,
+    },
+    /// Error diffusion; keeps the most tonal detail, at the cost of a noisier pattern.
+    FloydSteinberg,
+}
+
+fn bayer_size() -> u32 {
+    4
+}
+
+fn adaptive_window() -> u32 {
+    21
+}
+
+fn local_window() -> u32 {
+    25
+}
+
+fn sauvola_k() -> f32 {
+    0.34
+}
+
+fn niblack_k() -> f32 {
+    -0.2
+}
+
+/// Where two-page book scans are cut in two, see `image_ops::split_spread`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PageSplitOptions {
+    /// Share of the width on either side of the middle searched for the gutter.
+    pub search: f32,
+    /// Pixels each page keeps past the gutter, so text curving into it is not cut.
+    pub overlap: u32,
+}
+
+impl Default for PageSplitOptions {
+    fn default() -> Self {
+        Self { search: 0.15, overlap: 0 }
+    }
+}
+
+/// What a batch does with pages that have next to no ink on them.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BlankPageOptions {
+    /// Largest share of the page (margins excluded) covered by ink for it to count as
+    /// blank; the default lets a page number or a few specks through.
+    pub max_ink: f32,
+    pub policy: BlankPolicy,
+}
+
+impl Default for BlankPageOptions {
+    fn default() -> Self {
+        Self { max_ink: 0.002, policy: BlankPolicy::Tag }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BlankPolicy {
+    /// Writes nothing for blank pages.
+    Skip,
+    /// Writes blank pages into `folder`, relative to their output's directory unless
+    /// absolute. URI outputs cannot be redirected and are written in place.
+    Move {
+        #[serde(default = "blank_folder")]
+        folder: String,
+    },
+    /// Processes blank pages as usual; only the result marks them.
+    Tag,
+}
+
+fn blank_folder() -> String {
+    "blank".to_string()
+}
+
+/// Dust and scratch removal for scans made without an infrared cleaning channel.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DustOptions {
+    /// 0.0-1.0: how faint a spot may be and still count as dust; higher also catches
+    /// fine detail of the picture.
+    pub sensitivity: f32,
+    /// Largest spot, in pixels across, that is filled.
+    pub max_size: u32,
+    /// Also fills scratches: defects longer than `max_size` but at most half as thick.
+    pub scratches: bool,
+}
+
+impl Default for DustOptions {
+    fn default() -> Self {
+        Self { sensitivity: 0.5, max_size: 8, scratches: true }
+    }
+}
+
+/// Removal of isolated specks left by thresholding a scan.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DespeckleOptions {
+    /// Black specks of at most this many pixels are whitened.
+    pub max_area: u32,
+    /// Also closes white pinholes of at most `max_area` pixels inside strokes.
+    pub fill_holes: bool,
+}
+
+impl Default for DespeckleOptions {
+    fn default() -> Self {
+        Self { max_area: 4, fill_holes: true }
+    }
+}
+
+/// Text recognition with Tesseract, in builds with the `ocr` feature. Previews skip it.
+/// Besides the sidecar, PDF outputs get the words as a searchable text layer.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct OcrOptions {
+    /// Tesseract language codes, `+`-joined for pages in several languages ("eng+deu").
+    pub language: String,
+    pub format: OcrFormat,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        Self { language: "eng".to_string(), format: OcrFormat::Text }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OcrFormat {
+    /// Plain text, written as `<name>.txt`.
+    #[default]
+    Text,
+    /// hOCR, HTML with the position of every word, written as `<name>.hocr`.
+    Hocr,
+    /// ALTO 4 XML with word, line and block coordinates in the output's pixels, written
+    /// as `<name>.alto.xml`; the full text of METS packages (see `export_mets`).
+    Alto,
+}
+
+impl OcrFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OcrFormat::Text => "txt",
+            OcrFormat::Hocr => "hocr",
+            OcrFormat::Alto => "alto.xml",
+        }
+    }
+}
+
+/// Rectifies a keystoned quad (a page shot slightly off-axis) to a rectangle.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Perspective {
+    /// Top-left, top-right, bottom-right and bottom-left corners as fractions of the
+    /// image size, so corners picked on a preview apply to the full-size file.
+    Corners { corners: [[f32; 2]; 4] },
+    /// Finds the page against a contrasting background; skipped when no clear quad is found.
+    Auto,
+}
+
+/// How the orientation stage tells which way a page is up. Pages it cannot read with
+/// `min_confidence` are left as they are.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Orientation {
+    /// Line profiles and the ascenders of Latin script (`image_ops::orientation`);
+    /// `min_confidence` is 0.0-1.0.
+    Projection {
+        #[serde(default = "projection_confidence")]
+        min_confidence: f32,
+    },
+    /// Tesseract's orientation detection, for any script it has data for. Needs the
+    /// `ocr` feature and `osd.traineddata`; `min_confidence` is on Tesseract's scale,
+    /// where 2 is already a fairly sure reading.
+    Tesseract {
+        #[serde(default = "tesseract_confidence")]
+        min_confidence: f32,
+    },
+}
+
+fn projection_confidence() -> f32 {
+    0.2
+}
+
+fn tesseract_confidence() -> f32 {
+    2.0
+}
+
+/// Rotation applied before filtering.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RotateOptions {
+    /// Clockwise angle. Multiples of 90 are exact buffer rotations; other angles are
+    /// resampled onto a canvas enlarged to hold the whole rotated image.
+    pub degrees: f32,
+    /// Color of the canvas corners uncovered by an arbitrary rotation.
+    pub fill: HexColor,
+}
+
+impl Default for RotateOptions {
+    fn default() -> Self {
+        Self { degrees: 0.0, fill: HexColor([255, 255, 255]) }
+    }
+}
+
+/// Automatic leveling from the dominant near-horizontal and near-vertical lines.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct StraightenOptions {
+    /// Largest correction in degrees. Images that look tilted by more are left alone,
+    /// as a steep "horizon" is more often a diagonal in the scene.
+    pub max_angle: f32,
+    /// Keeps the largest centered rectangle of the original aspect ratio, so no fill shows.
+    pub crop: bool,
+    /// Color of the uncovered corners when `crop` is off.
+    pub fill: HexColor,
+}
+
+impl Default for StraightenOptions {
+    fn default() -> Self {
+        Self { max_angle: 5.0, crop: true, fill: HexColor([255, 255, 255]) }
+    }
+}
+
+/// Skew correction for scanned documents, so thresholding sees level lines of text.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DeskewOptions {
+    /// Largest correction in degrees; pages that look skewed by more are left alone.
+    pub max_angle: f32,
+    /// Color of the corners rotated in from outside the scan; the page keeps its size.
+    pub fill: HexColor,
+}
+
+impl Default for DeskewOptions {
+    fn default() -> Self {
+        Self { max_angle: 5.0, fill: HexColor([255, 255, 255]) }
+    }
+}
+
+/// Crop applied after flips and rotation.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Crop {
+    /// Pixel rectangle, clamped to the image.
+    Rect { x: u32, y: u32, width: u32, height: u32 },
+    /// Largest region of the given aspect ratio, positioned by `gravity`.
+    Aspect {
+        width: f32,
+        height: f32,
+        #[serde(default)]
+        gravity: Gravity,
+    },
+    /// Trims the black or noisy borders of a scan to the document, keeping `margin`
+    /// pixels of border around it.
+    Auto {
+        #[serde(default)]
+        margin: u32,
+    },
+    /// Removes margins of near-uniform color (within `tolerance` of it on every channel,
+    /// 0-255), keeping `margin` pixels of them. With `uniform`, bulk jobs first trim
+    /// every file and crop all of them to the one box holding all their content, so
+    /// pages keep identical dimensions; this reads each file twice.
+    Trim {
+        #[serde(default = "trim_tolerance")]
+        tolerance: u8,
+        #[serde(default)]
+        margin: u32,
+        #[serde(default)]
+        uniform: bool,
+    },
+    /// Crops exactly to the trim box drawn by printed crop marks, keeping `bleed` pixels
+    /// beyond it. Sheets whose marks are not found on every edge are left uncropped.
+    Marks {
+        #[serde(default)]
+        bleed: u32,
+    },
+}
+
+fn trim_tolerance() -> u8 {
+    16
+}
+
+/// Which part of the image an aspect crop keeps.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Gravity {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Gravity {
+    /// Horizontal and vertical placement of the kept region, 0.0 = left/top, 1.0 = right/bottom.
+    pub fn anchor(&self) -> (f32, f32) {
+        match self {
+            Gravity::Center => (0.5, 0.5),
+            Gravity::Top => (0.5, 0.0),
+            Gravity::Bottom => (0.5, 1.0),
+            Gravity::Left => (0.0, 0.5),
+            Gravity::Right => (1.0, 0.5),
+            Gravity::TopLeft => (0.0, 0.0),
+            Gravity::TopRight => (1.0, 0.0),
+            Gravity::BottomLeft => (0.0, 1.0),
+            Gravity::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+impl Crop {
+    /// The `(x, y, width, height)` region this crop keeps of a `width` x `height` image,
+    /// or `None` when it would keep nothing. Auto crops and trims keep everything here,
+    /// as finding the document takes the pixels (`image_ops::content_bounds`,
+    /// `image_ops::trim_bounds`).
+    pub fn region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let region = match *self {
+            Crop::Auto { .. } | Crop::Trim { .. } | Crop::Marks { .. } => (0, 0, width, height),
+            Crop::Rect { x, y, width: w, height: h } => {
+                let (x, y) = (x.min(width), y.min(height));
+                (x, y, w.min(width - x), h.min(height - y))
+            }
+            Crop::Aspect { width: aw, height: ah, gravity } => {
+                if !(aw > 0.0 && ah > 0.0) {
+                    return None;
+                }
+                let ratio = aw / ah;
+                let (w, h) = if width as f32 / height as f32 > ratio {
+                    (((height as f32 * ratio).round() as u32).min(width), height)
+                } else {
+                    (width, ((width as f32 / ratio).round() as u32).min(height))
+                };
+                let (gx, gy) = gravity.anchor();
+                (((width - w) as f32 * gx).round() as u32, ((height - h) as f32 * gy).round() as u32, w, h)
+            }
+        };
+        (region.2 > 0 && region.3 > 0).then_some(region)
+    }
+}
+
+/// Output size and resampling filter.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ResizeOptions {
+    #[serde(flatten)]
+    pub size: ResizeSize,
+    #[serde(default)]
+    pub filter: ResizeFilter,
+    /// Lets long-edge and exact sizes enlarge smaller images; percentages always apply.
+    #[serde(default)]
+    pub allow_upscale: bool,
+    /// Set in low-memory mode: downscales right after cropping so the pixel stages
+    /// run on the smaller image.
+    #[serde(skip)]
+    pub early: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ResizeSize {
+    LongEdge { pixels: u32 },
+    Percent { percent: f32 },
+    /// Largest size with at most this many million pixels, e.g. an agency's 50 MP cap.
+    Megapixels { megapixels: f32 },
+    /// Rescales to `dpi` pixels per inch of the original, e.g. for a 400 dpi archival
+    /// master. The physical size comes from `width_mm`, the real width of what the image
+    /// shows, else from `source_dpi`, else from the resolution the source is tagged with.
+    /// Applies whether it enlarges or not, like a percentage.
+    Dpi {
+        dpi: f32,
+        #[serde(default)]
+        source_dpi: Option<f32>,
+        #[serde(default)]
+        width_mm: Option<f32>,
+    },
+    /// Box of exactly `width` x `height`; see `ResizeFit` for how the image meets it.
+    Exact {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        fit: ResizeFit,
+        /// Which part `Fill` keeps, and where `Pad` places the image on the canvas.
+        #[serde(default)]
+        gravity: Gravity,
+        /// Color of the `Pad` canvas.
+        #[serde(default = "white")]
+        background: HexColor,
+    },
+}
+
+fn white() -> HexColor {
+    HexColor([255, 255, 255])
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFit {
+    /// Within the box keeping the aspect ratio; the output may be smaller on one side.
+    #[default]
+    Fit,
+    /// Covers the box and crops the overflow.
+    Fill,
+    /// Fits within the box, then pads to exactly its size.
+    Pad,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    #[default]
+    Lanczos3,
+    CatmullRom,
+    Nearest,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(&self) -> image::imageops::FilterType {
+        match self {
+            ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+            ResizeFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+        }
+    }
+}
+
+impl ResizeOptions {
+    /// Scaled size of a `width` x `height` image, before `Fill` crops or `Pad` extends it to the box.
+    pub fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+        let scale = match self.size {
+            ResizeSize::LongEdge { pixels } => pixels as f64 / w.max(h),
+            ResizeSize::Percent { percent } => return scale_dims(w, h, percent as f64 / 100.0),
+            ResizeSize::Dpi { dpi, source_dpi, width_mm } => {
+                let source = width_mm.map(|mm| w * 25.4 / mm as f64).or(source_dpi.map(f64::from));
+                return match source.filter(|s| *s > 0.0) {
+                    Some(source) => scale_dims(w, h, dpi as f64 / source),
+                    None => (width, height),
+                };
+            }
+            ResizeSize::Megapixels { megapixels } => {
+                let scale = (megapixels.max(0.0) as f64 * 1e6 / (w * h)).sqrt();
+                let scale = if self.allow_upscale { scale } else { scale.min(1.0) };
+                if scale >= 1.0 && !self.allow_upscale {
+                    return (width, height);
+                }
+                // Rounded down so the result never exceeds the cap
+                return (((w * scale).floor().max(1.0)) as u32, ((h * scale).floor().max(1.0)) as u32);
+            }
+            ResizeSize::Exact { width: bw, height: bh, fit, .. } => match fit {
+                ResizeFit::Fit | ResizeFit::Pad => (bw as f64 / w).min(bh as f64 / h),
+                ResizeFit::Fill => (bw as f64 / w).max(bh as f64 / h),
+            },
+        };
+        scale_dims(w, h, if self.allow_upscale { scale } else { scale.min(1.0) })
+    }
+}
+
+fn scale_dims(w: f64, h: f64, scale: f64) -> (u32, u32) {
+    ((w * scale).round().max(1.0) as u32, (h * scale).round().max(1.0) as u32)
+}
+
+/// Extends the canvas: first to `aspect` (if set), then by a `border` on every side.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct CanvasOptions {
+    /// Target aspect ratio; the image is padded, never cropped, to reach it.
+    pub aspect: Option<AspectRatio>,
+    /// Where the image sits on a padded canvas.
+    pub gravity: Gravity,
+    /// Border width in pixels.
+    pub border: u32,
+    pub color: HexColor,
+}
+
+impl Default for CanvasOptions {
+    fn default() -> Self {
+        Self { aspect: None, gravity: Gravity::Center, border: 0, color: HexColor([255, 255, 255]) }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct AspectRatio {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl CanvasOptions {
+    /// Canvas size for a `width` x `height` image and the image's offset on it.
+    pub fn layout(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (mut cw, mut ch) = (width, height);
+        if let Some(ratio) = self.aspect.filter(|a| a.width > 0.0 && a.height > 0.0).map(|a| a.width / a.height) {
+            if width as f32 / height as f32 > ratio {
+                ch = ch.max((width as f32 / ratio).round() as u32);
+            } else {
+                cw = cw.max((height as f32 * ratio).round() as u32);
+            }
+        }
+        let (gx, gy) = self.gravity.anchor();
+        let x = ((cw - width) as f32 * gx).round() as u32 + self.border;
+        let y = ((ch - height) as f32 * gy).round() as u32 + self.border;
+        (cw + 2 * self.border, ch + 2 * self.border, x, y)
+    }
+}
+
+/// Tone curves. `rgb` shapes every channel; unless `linked`, the red, green and
+/// blue curves are applied on top of it for color-cast corrections.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Curves {
+    pub linked: bool,
+    pub rgb: Curve,
+    pub red: Curve,
+    pub green: Curve,
+    pub blue: Curve,
+}
+
+impl Default for Curves {
+    fn default() -> Self {
+        Self { linked: true, rgb: Curve::default(), red: Curve::default(), green: Curve::default(), blue: Curve::default() }
+    }
+}
+
+impl Curves {
+    /// The curves in effect for red, green and blue, master first.
+    pub fn channels(&self) -> [Vec<&Curve>; 3] {
+        let own = [&self.red, &self.green, &self.blue];
+        own.map(|c| if self.linked { vec![&self.rgb] } else { vec![&self.rgb, c] })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.channels().iter().flatten().all(|c| c.is_identity())
+    }
+}
+
+/// One curve: control points plus parametric region handles, all on a 0.0..=1.0 scale.
+///
+/// Points are joined by a monotone cubic so the curve never overshoots between
+/// them. The handles then lift (positive) or lower (negative) the shadows, darks,
+/// lights and highlights quarters of the range, keeping black and white fixed.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Curve {
+    /// `[input, output]` pairs; none means the identity.
+    pub points: Vec<[f32; 2]>,
+    pub shadows: f32,
+    pub darks: f32,
+    pub lights: f32,
+    pub highlights: f32,
+}
+
+impl Curve {
+    pub fn is_identity(&self) -> bool {
+        self.points.iter().all(|[x, y]| x == y) && [self.shadows, self.darks, self.lights, self.highlights] == [0.0; 4]
+    }
+}
+
+/// Rectangles filled with `color`, in the pixels of the decoded source.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RedactionOptions {
+    /// Boxes for every file of the job.
+    pub boxes: Vec<RedactionBox>,
+    /// Boxes for single files, keyed by the file name or full path of their source.
+    pub regions: std::collections::BTreeMap<String, Vec<RedactionBox>>,
+    /// Also reads `<source stem>.redact.json` next to each source, a list of boxes, when
+    /// it exists. A sidecar that cannot be read fails the file rather than exporting it
+    /// unredacted.
+    pub sidecar: bool,
+    pub color: HexColor,
+}
+
+impl Default for RedactionOptions {
+    fn default() -> Self {
+        Self { boxes: Vec::new(), regions: std::collections::BTreeMap::new(), sidecar: false, color: HexColor([0, 0, 0]) }
+    }
+}
+
+impl RedactionOptions {
+    /// The boxes in the frame of a crop starting `left` pixels in, such as the right page
+    /// of a split spread. Boxes wholly left of it are dropped.
+    pub fn shifted(&self, left: u32) -> Self {
+        let boxes = self
+            .boxes
+            .iter()
+            .filter(|b| b.x.saturating_add(b.width) > left)
+            .map(|b| RedactionBox { x: b.x.saturating_sub(left), width: b.x.saturating_add(b.width) - b.x.max(left), ..*b })
+            .collect();
+        Self { boxes, ..self.clone() }
+    }
+}
+
+/// A redaction rectangle, clamped to the image.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RedactionBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Color negative film inversion.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct NegativeOptions {
+    /// Unexposed film base (orange mask) as it appears in the input, e.g. sampled
+    /// from the rebate. `None` estimates it from the frame border.
+    pub base: Option<HexColor>,
+    /// Input samples are linear. Set by the backend for RAW files, which are inverted
+    /// before any gamma is applied.
+    #[serde(skip)]
+    pub linear_input: bool,
+}
+
+/// Monochrome toning: luminance is mapped onto a gradient between two colors.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "preset", rename_all = "snake_case")]
+pub enum Toning {
+    /// Warm brown shadows fading to cream highlights.
+    Sepia,
+    /// Prussian blue shadows on white paper.
+    Cyanotype,
+    /// Custom gradient from `shadow` (black point) to `highlight` (white point).
+    Duotone { shadow: HexColor, highlight: HexColor },
+}
+
+impl Toning {
+    /// Shadow and highlight endpoints of the gradient.
+    pub fn endpoints(&self) -> ([u8; 3], [u8; 3]) {
+        match self {
+            Toning::Sepia => ([42, 26, 12], [251, 240, 217]),
+            Toning::Cyanotype => ([11, 42, 91], [240, 244, 248]),
+            Toning::Duotone { shadow, highlight } => (shadow.0, highlight.0),
+        }
+    }
+}
+
+/// RGB color parsed from `#rrggbb` or `#rgb`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct HexColor(pub [u8; 3]);
+
+impl From<HexColor> for String {
+    fn from(color: HexColor) -> Self {
+        format!("#{:02x}{:02x}{:02x}", color.0[0], color.0[1], color.0[2])
+    }
+}
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hex = value.trim().trim_start_matches('#');
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Invalid hex color: {}", value))?;
+        match digits.len() {
+            3 => Ok(HexColor([0, 1, 2].map(|i| digits[i] * 17))),
+            6 => Ok(HexColor([0, 1, 2].map(|i| digits[2 * i] * 16 + digits[2 * i + 1]))),
+            _ => Err(format!("Invalid hex color: {}", value)),
+        }
+    }
+}
+
+/// Synthetic film grain.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct GrainOptions {
+    /// 0.0..=1.0 grain intensity.
+    pub amount: f32,
+    /// Grain clump size in pixels (1.0 = per-pixel noise).
+    #[serde(default = "full_strength")]
+    pub size: f32,
+    /// Noise seed. When omitted, processing derives it from the input file name so
+    /// each frame has its own stable pattern across re-exports.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// A `.cube` 3D LUT applied after the tonal adjustments.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LutOptions {
+    pub path: String,
+    /// Blend between the original (0.0) and the fully graded (1.0) color.
+    #[serde(default = "full_strength")]
+    pub strength: f32,
+    /// Parsed table, filled in once per job by `load_assets` and shared by every file.
+    #[serde(skip)]
+    pub table: Option<Arc<Lut3d>>,
+}
+
+fn full_strength() -> f32 {
+    1.0
+}
+
+/// Image watermark, typically a PNG with alpha.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct WatermarkOptions {
+    pub path: String,
+    pub position: Gravity,
+    /// Watermark width as a fraction of the image width.
+    pub scale: f32,
+    /// Distance from the edges as a fraction of the image's short edge.
+    pub margin: f32,
+    pub opacity: f32,
+    /// Decoded watermark, filled in once per job by `load_assets` and shared by every file.
+    #[serde(skip)]
+    pub image: Option<Arc<image::RgbaImage>>,
+}
+
+impl Default for WatermarkOptions {
+    fn default() -> Self {
+        Self { path: String::new(), position: Gravity::BottomRight, scale: 0.2, margin: 0.02, opacity: 1.0, image: None }
+    }
+}
+
+/// Text burned into each output, built from a template; see `stamp::render_template`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct TextStampOptions {
+    /// e.g. "{filename} {date} {time} #{index:04}"
+    pub template: String,
+    /// TrueType/OpenType font file.
+    pub font: String,
+    /// Text height as a fraction of the image's short edge.
+    pub size: f32,
+    pub color: HexColor,
+    /// Box behind the text for legibility on busy images.
+    pub background: Option<HexColor>,
+    pub position: Gravity,
+    /// Distance from the edges as a fraction of the image's short edge.
+    pub margin: f32,
+    /// Parsed font, filled in once per job by `load_assets` and shared by every file.
+    #[serde(skip)]
+    pub font_data: Option<ab_glyph::FontArc>,
+    /// 1-based position of the file in its batch, set by the batch runner.
+    #[serde(skip)]
+    pub index: usize,
+    /// The template resolved for the current file.
+    #[serde(skip)]
+    pub text: Option<String>,
+}
+
+impl Default for TextStampOptions {
+    fn default() -> Self {
+        Self {
+            template: String::new(),
+            font: String::new(),
+            size: 0.03,
+            color: HexColor([255, 255, 255]),
+            background: None,
+            position: Gravity::BottomLeft,
+            margin: 0.02,
+            font_data: None,
+            index: 0,
+            text: None,
+        }
+    }
+}
+
+/// An extra output of each file for one destination, saved as `<name><suffix>.<ext>`
+/// next to the main output or in its own folder. Unset fields keep the batch settings.
+/// Every variant is rendered from the same decode, so a master, an access copy and a
+/// thumbnail cost one read of the source.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct VariantSpec {
+    /// Appended to the output file name, e.g. "_web". May be empty when `dir` is set.
+    pub suffix: String,
+    /// Extension, and so format, of the variant ("tif"); the main output's when unset.
+    pub extension: Option<String>,
+    /// Folder of the variant, relative to the main output's folder ("access") or absolute.
+    pub dir: Option<String>,
+    /// Filter settings used instead of the batch's, e.g. a sharpened access look. Resize,
+    /// output and overlays still follow the fields here; white balance lock modes in a
+    /// profile behave like Auto, as outside bulk jobs.
+    pub profile: Option<Box<ProcessOptions>>,
+    pub resize: Option<ResizeOptions>,
+    pub output: Option<OutputOptions>,
+    /// Whether the batch watermark is applied; off for clean masters.
+    pub watermark: bool,
+    /// Whether the batch text stamp is applied.
+    pub text_stamp: bool,
+}
+
+impl Default for VariantSpec {
+    fn default() -> Self {
+        Self {
+            suffix: String::new(),
+            extension: None,
+            dir: None,
+            profile: None,
+            resize: None,
+            output: None,
+            watermark: true,
+            text_stamp: true,
+        }
+    }
+}
+
+impl VariantSpec {
+    /// The batch `options` this variant renders with, or its profile's. OCR, splitting
+    /// and blank checks belong to the main output and are left out. Overlays and
+    /// redaction are the batch's, already resolved for the file.
+    pub fn options(&self, options: &ProcessOptions) -> ProcessOptions {
+        let base = self.profile.as_deref().unwrap_or(options);
+        ProcessOptions {
+            resize: self.resize.or(base.resize),
+            watermark: options.watermark.clone().filter(|_| self.watermark),
+            text_stamp: options.text_stamp.clone().filter(|_| self.text_stamp),
+            output: self.output.clone().unwrap_or_else(|| base.output.clone()),
+            redaction: options.redaction.clone(),
+            ocr: None,
+            page_split: None,
+            blank_pages: None,
+            variants: Vec::new(),
+            ..base.clone()
+        }
+    }
+
+    /// Where the variant of the output at `out_path` is saved. A URI output only gets
+    /// variants beside it, since a folder cannot be spliced into a URI.
+    pub fn path(&self, out_path: &str) -> Result<String, String> {
+        if storage::is_uri(out_path) && self.dir.is_some() {
+            return Err(format!("Variant folders need a file path output, not a URI: {}", out_path));
+        }
+        if self.suffix.contains(['/', '\\']) {
+            return Err(format!("Invalid variant suffix: {:?}", self.suffix));
+        }
+        let out = std::path::Path::new(out_path);
+        let stem = out.file_stem().ok_or_else(|| format!("Invalid output path: {}", out_path))?.to_string_lossy();
+        let ext = match &self.extension {
+            Some(ext) => format!(".{}", ext.trim_start_matches('.')),
+            None => out.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default(),
+        };
+        let name = format!("{}{}{}", stem, self.suffix, ext);
+        let path = match &self.dir {
+            Some(dir) => out.parent().unwrap_or(std::path::Path::new("")).join(dir).join(name),
+            None => out.with_file_name(name),
+        };
+        if path == out {
+            return Err(format!("Variant {:?} would overwrite the main output", self.suffix));
+        }
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+/// Checksum manifests written after bulk runs, in the `md5sum`/`sha256sum` format.
+/// Outputs go into `manifest-<algorithm>.txt`, sources into `manifest-inputs-<algorithm>.txt`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct FixityOptions {
+    /// One manifest per algorithm.
+    pub algorithms: Vec<ChecksumAlgorithm>,
+    /// Also checksums the source files, listed by absolute path.
+    pub include_inputs: bool,
+    /// Folder of the manifests; defaults to the deepest folder holding every output.
+    pub dir: Option<String>,
+}
+
+impl Default for FixityOptions {
+    fn default() -> Self {
+        Self { algorithms: vec![ChecksumAlgorithm::Sha256], include_inputs: false, dir: None }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// Name in manifest file names, as in BagIt.
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+/// A METS document over a digitized batch; see `mets::export`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MetsOptions {
+    /// Title of the digitized object, as its label and in a MODS record.
+    pub title: Option<String>,
+    /// Identifier of the object (a shelfmark or catalogue number), as its OBJID.
+    pub identifier: Option<String>,
+    /// Digitizing institution, named as the document's creator.
+    pub organization: Option<String>,
+    /// Checksum recorded for every file.
+    pub algorithm: ChecksumAlgorithm,
+}
+
+impl Default for MetsOptions {
+    fn default() -> Self {
+        Self { title: None, identifier: None, organization: None, algorithm: ChecksumAlgorithm::Sha256 }
+    }
+}
+
+/// Technical metadata records (NISO MIX 2.0), written as `<name>.mix.xml` or
+/// `<name>.mix.json`; see `mix::record`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TechnicalMetadataOptions {
+    pub format: RecordFormat,
+    /// Checksums recorded as the file's fixity.
+    pub algorithms: Vec<ChecksumAlgorithm>,
+}
+
+impl Default for TechnicalMetadataOptions {
+    fn default() -> Self {
+        Self { format: RecordFormat::Xml, algorithms: vec![ChecksumAlgorithm::Sha256] }
+    }
+}
+
+/// Static IIIF Level 0 tiles of each output, in `<dir>/<name>/`; see `iiif::write_tiles`.
+/// Tiles are JPEGs encoded with `OutputOptions::jpeg`, in sRGB whatever the output space.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct IiifOptions {
+    /// URL the tile folders are served under; an image's id is `<base_url>/<name>`.
+    pub base_url: String,
+    /// Folder of the tile folders, relative to the output's folder or absolute.
+    pub dir: String,
+    pub tile_size: u32,
+}
+
+impl Default for IiifOptions {
+    fn default() -> Self {
+        Self { base_url: String::new(), dir: "iiif".to_string(), tile_size: 512 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+    /// MIX XML, for METS packages and repository ingest.
+    #[default]
+    Xml,
+    /// The same fields as JSON.
+    Json,
+}
+
+impl RecordFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Xml => "mix.xml",
+            RecordFormat::Json => "mix.json",
+        }
+    }
+}
+
+/// BagIt packaging of a finished batch for deposit; see `bagit::create`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BagOptions {
+    /// One payload manifest and one tag manifest per algorithm.
+    pub algorithms: Vec<ChecksumAlgorithm>,
+    /// Moves the outputs into the bag instead of copying them.
+    pub move_files: bool,
+    /// `bag-info.txt` fields, in order; labels may repeat.
+    pub info: Vec<BagInfoField>,
+}
+
+impl Default for BagOptions {
+    fn default() -> Self {
+        Self { algorithms: vec![ChecksumAlgorithm::Sha256], move_files: false, info: Vec::new() }
+    }
+}
+
+/// A `bag-info.txt` line, e.g. "Source-Organization: City Archives".
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct BagInfoField {
+    pub label: String,
+    pub value: String,
+}
+
+/// Barcode separator sheets between documents: a page with a QR or Code 39 code is a
+/// separator, and the files after it, up to the next one, are named or filed by its value.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SeparationOptions {
+    /// Only codes starting with it are separators, and it is left out of the value;
+    /// empty makes any code a separator.
+    pub prefix: String,
+    pub route: SeparatorRoute,
+    /// Writes the separator sheets too, as the first file of their document.
+    pub keep_separators: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SeparatorRoute {
+    /// `<output folder>/<value>/<file name>`
+    #[default]
+    Folder,
+    /// `<output folder>/<value>_<file name>`
+    Prefix,
+}
+
+impl SeparationOptions {
+    /// The value of a separator's code, made safe as a file or folder name; `None` for
+    /// codes that are not separators.
+    pub fn separator_value(&self, code: &str) -> Option<String> {
+        let value: String = code
+            .strip_prefix(self.prefix.as_str())?
+            .chars()
+            .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+            .collect();
+        // Windows drops trailing dots and spaces from names, and ".." would step out of the folder
+        let value = value.trim_matches(|c| c == '.' || c == ' ');
+        (!value.is_empty()).then(|| value.to_string())
+    }
+
+    /// Output path of a file in the document started by the separator `value`.
+    pub fn route(&self, out_path: &str, value: &str) -> Result<String, String> {
+        if storage::is_uri(out_path) {
+            return Err(format!("Separator routing needs a file path output, not a URI: {}", out_path));
+        }
+        let out = std::path::Path::new(out_path);
+        let name = out.file_name().ok_or_else(|| format!("Invalid output path: {}", out_path))?.to_string_lossy();
+        let dir = out.parent().unwrap_or(std::path::Path::new(""));
+        let routed = match self.route {
+            SeparatorRoute::Folder => dir.join(value).join(&*name),
+            SeparatorRoute::Prefix => dir.join(format!("{}_{}", value, name)),
+        };
+        Ok(routed.to_string_lossy().to_string())
+    }
+}
+
+/// Assembles a batch into multi-page TIFFs: the pages of each input folder, in batch
+/// order, go into `<folder name>.tif` next to the outputs of that folder.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MultipageOptions {
+    /// Settings for pages that came out black and white, such as binarized text, which
+    /// usually want Group 4. Other pages use `OutputOptions::tiff`.
+    pub bilevel: Option<TiffOptions>,
+}
+
+impl MultipageOptions {
+    /// Compression settings for `page`.
+    pub fn page_options(&self, page: &DynamicImage, output: &OutputOptions) -> TiffOptions {
+        self.bilevel.filter(|_| image_ops::encode::is_bilevel(page)).unwrap_or(output.tiff)
+    }
+}
+
+/// Encoder settings for the saved file; the format itself follows the output extension.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct OutputOptions {
+    pub jpeg: JpegOptions,
+    pub tiff: TiffOptions,
+    pub jp2: Jp2Options,
+    /// Writes `<name>.stats.json` next to each output (not supported for URI outputs).
+    pub stats_sidecar: bool,
+    /// Writes a technical metadata record next to each output (not for URI or PDF outputs).
+    pub technical_metadata: Option<TechnicalMetadataOptions>,
+    /// Writes a static IIIF tile folder for each output (not for URI outputs).
+    pub iiif: Option<IiifOptions>,
+    /// Output color space. Without it, pixels are written as sRGB with no embedded profile.
+    pub color: Option<ColorConversion>,
+    /// Writes the source's catalog note into JPEG and PNG outputs as their XMP description.
+    pub embed_notes: bool,
+    /// Pixel density tagged in JPEG, TIFF and PNG outputs. A DPI resize sets it to its
+    /// target; otherwise TIFF is tagged at 72 dpi and the other formats not at all.
+    /// PDF pages are sized from it, at 300 dpi when unset.
+    pub dpi: Option<f32>,
+    /// Words read by the OCR stage, filled in per file and written as the text layer
+    /// of PDF outputs.
+    #[serde(skip)]
+    pub text_layer: Option<Arc<Vec<image_ops::OcrWord>>>,
+    pub pdf: PdfOptions,
+}
+
+/// Settings for PDF outputs, single-page and combined.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct PdfOptions {
+    pub conformance: PdfConformance,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PdfConformance {
+    #[default]
+    Standard,
+    /// PDF/A-2b for records-management systems: XMP metadata and an embedded output
+    /// intent profile, accepted as archival deliverables.
+    PdfA2b,
+}
+
+/// Conversion from the pipeline's sRGB to the output color space, with its ICC profile embedded.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ColorConversion {
+    pub space: OutputColorSpace,
+    /// The built-in profiles are matrix/TRC profiles, which carry no intent-specific
+    /// tables, so perceptual and saturation map like relative colorimetric.
+    pub intent: RenderingIntent,
+    /// Pipeline previews get a final stage showing the converted colors mapped back to sRGB.
+    pub soft_proof: bool,
+}
+
+/// Color vision deficiency a preview can simulate, to check that charts and UI assets
+/// still read for color-blind viewers before delivery.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorDeficiency {
+    /// No working green cones, the most common form.
+    Deuteranopia,
+    /// No working red cones; reds also look darker.
+    Protanopia,
+    /// No working blue cones, which confuses blue with green and yellow with violet.
+    Tritanopia,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputColorSpace {
+    #[default]
+    Srgb,
+    AdobeRgb,
+    /// Single-channel output with a gamma 2.2 gray profile.
+    GrayGamma22,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderingIntent {
+    Perceptual,
+    #[default]
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct TiffOptions {
+    pub compression: TiffCompression,
+    /// Horizontal differencing predictor; only used with LZW and Deflate.
+    pub predictor: bool,
+    /// Square tile edge in pixels (multiple of 16). `None` writes strips.
+    pub tile_size: Option<u32>,
+    /// Adds half-size levels after the page, down to one tile, for deep-zoom viewers.
+    /// Tiled at 256 px unless `tile_size` is set; single-page files only.
+    pub pyramid: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TiffCompression {
+    #[default]
+    None,
+    Lzw,
+    Deflate,
+    Packbits,
+    /// CCITT Group 4; writes a 1-bit page, thresholding at mid-gray if needed.
+    Group4,
+}
+
+/// Settings for `.jp2` outputs.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Jp2Options {
+    /// Reversible 5-3 wavelet, decoding to the exact pixels; otherwise the 9-7 wavelet
+    /// at `ratio`.
+    pub lossless: bool,
+    /// Compression ratio of lossy files, e.g. 20 for 20:1.
+    pub ratio: f32,
+    /// Square tile edge in pixels. `None` codes the image as a single tile.
+    pub tile_size: Option<u32>,
+    /// Resolution levels, one more than the wavelet decompositions. Lowered for images
+    /// too small to halve that often.
+    pub resolutions: u8,
+}
+
+impl Default for Jp2Options {
+    fn default() -> Self {
+        Self { lossless: true, ratio: 20.0, tile_size: None, resolutions: 6 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct JpegOptions {
+    /// 1-100. Defaults to 75, matching `image`'s encoder used before these settings existed.
+    pub quality: u8,
+    pub subsampling: ChromaSubsampling,
+    /// Restart marker interval in MCUs; 0 disables restart markers.
+    pub restart_interval: u16,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            subsampling: ChromaSubsampling::S420,
+            restart_interval: 0,
+        }
+    }
+}
+
+/// JPEG chroma subsampling. Text-heavy documents need 4:4:4 to keep colored
+/// strokes crisp; photos lose little at 4:2:0 and are noticeably smaller.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChromaSubsampling {
+    #[serde(rename = "4:4:4", alias = "444")]
+    S444,
+    #[serde(rename = "4:2:2", alias = "422")]
+    S422,
+    #[default]
+    #[serde(rename = "4:2:0", alias = "420")]
+    S420,
+}
+
+/// How preview images are encoded before they are sent to the webview.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct PreviewEncoding {
+    pub format: PreviewFormat,
+    /// JPEG quality, 1-100. Ignored for WebP, which is always lossless.
+    pub quality: u8,
+}
+
+impl Default for PreviewEncoding {
+    fn default() -> Self {
+        Self { format: PreviewFormat::Jpeg, quality: 75 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFormat {
+    #[default]
+    Jpeg,
+    /// Lossless WebP: larger payloads, but pixel-exact previews for loupe views.
+    Webp,
+}
+
+/// Battery and thermal state reported by the platform shell.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DeviceConditions {
+    /// Charge in 0.0..=1.0, if the device has a battery.
+    pub battery_level: Option<f32>,
+    pub charging: bool,
+    pub thermal: ThermalState,
+}
+
+/// Thermal pressure, following the levels of iOS `ProcessInfo.ThermalState`
+/// (Android's `PowerManager` thermal statuses map onto the same four).
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermalState {
+    #[default]
+    Nominal,
+    Fair,
+    Serious,
+    Critical,
+}
+
+/// Whether work runs on worker threads or, where threads cannot be created, on the
+/// calling one.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Sequential only when worker threads cannot be started.
+    #[default]
+    Auto,
+    Parallel,
+    Sequential,
+}
+
+/// Whether the backend trades speed for a smaller memory footprint.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LowMemoryMode {
+    /// Enabled on machines with 8 GB of RAM or less.
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+/// Settings for merging a bracketed exposure sequence.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct HdrOptions {
+    /// Register hand-held frames to the reference exposure before merging.
+    pub align: bool,
+    /// Detect moving subjects and take them from a single exposure.
+    pub deghost: bool,
+    /// Index of the exposure frames are aligned to and moving content is taken from;
+    /// defaults to the middle frame.
+    pub reference: Option<usize>,
+    /// Luminance difference (0.0..=1.0) after exposure matching above which a pixel counts as motion.
+    pub ghost_threshold: f32,
+    pub output: OutputOptions,
+}
+
+impl Default for HdrOptions {
+    fn default() -> Self {
+        Self {
+            align: true,
+            deghost: true,
+            reference: None,
+            ghost_threshold: 0.1,
+            output: OutputOptions::default(),
+        }
+    }
+}
+
+/// Settings for registering and integrating a stack of frames.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct StackOptions {
+    pub registration: StackRegistration,
+    /// Index of the frame the others are registered to.
+    pub reference: usize,
+    /// Samples further than `kappa` standard deviations from the mean are rejected.
+    pub kappa: f32,
+    /// Clipping passes per pixel.
+    pub iterations: u32,
+    pub output: OutputOptions,
+}
+
+impl Default for StackOptions {
+    fn default() -> Self {
+        Self {
+            registration: StackRegistration::Stars,
+            reference: 0,
+            kappa: 2.5,
+            iterations: 3,
+            output: OutputOptions::default(),
+        }
+    }
+}
+
+/// Settings for merging the overlapping tiles of an oversize capture.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct StitchOptions {
+    /// Width in pixels over which a tile fades out towards its edge inside overlaps.
+    pub feather: u32,
+    /// Fill for the parts of the canvas no tile covers.
+    pub background: HexColor,
+    pub output: OutputOptions,
+}
+
+impl Default for StitchOptions {
+    fn default() -> Self {
+        Self { feather: 64, background: HexColor([255, 255, 255]), output: OutputOptions::default() }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StackRegistration {
+    /// Frames are already registered (tripod, tracking mount).
+    None,
+    /// Phase correlation; suited to daylight frame averaging.
+    Phase,
+    /// Star triangle matching for night-sky frames.
+    #[default]
+    Stars,
+}
+
+/// Calibration against a color target: the correction bringing the target's patches to
+/// their reference values is applied to every file.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CalibrationOptions {
+    pub target: ColorTarget,
+    /// Batch index of the capture holding the target.
+    pub target_index: usize,
+    /// Outer corners of the patch grid in that capture (the target's top-left, top-right,
+    /// bottom-right and bottom-left, in pixels), when it is not found by itself.
+    pub corners: Option<[(f32, f32); 4]>,
+    /// A correction from `detect_color_target`, used as it is. Bulk jobs resolve it from
+    /// the target capture; without it, single-file commands leave colors uncalibrated.
+    pub matrix: Option<[[f32; 3]; 3]>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ColorTarget {
+    /// X-Rite ColorChecker Classic: 4 rows of 6 patches.
+    #[default]
+    ColorChecker24,
+    /// Any grid of patches, such as the 12 by 22 field of an IT8.7 target, with each
+    /// patch's sRGB reference value, row by row, from the target's data sheet.
+    Grid { rows: usize, cols: usize, reference: Vec<[u8; 3]> },
+    /// An IT8.7/1 or IT8.7/2 target, with the CGATS reference file of its batch as
+    /// shipped by the maker. The commands read it into a `Grid`.
+    It8 { reference_file: String },
+}
+
+impl ColorTarget {
+    /// Rows, columns and reference colors of the patch grid.
+    pub fn layout(&self) -> Result<(usize, usize, &[[u8; 3]]), String> {
+        match self {
+            ColorTarget::ColorChecker24 => Ok((4, 6, &image_ops::calibration::COLORCHECKER_24)),
+            ColorTarget::Grid { rows, cols, reference } => {
+                if *rows < 2 || *cols < 2 {
+                    return Err(format!("A color target needs at least 2 rows and columns, not {}x{}", rows, cols));
+                }
+                if reference.len() != rows * cols {
+                    return Err(format!("A {}x{} target needs {} reference colors, not {}", rows, cols, rows * cols, reference.len()));
+                }
+                Ok((*rows, *cols, reference))
+            }
+            ColorTarget::It8 { reference_file } => Err(format!("The IT8 reference file has not been read: {}", reference_file)),
+        }
+    }
+}
+
+/// White balance strategy.
+///
+/// The `Lock*` modes are resolved once per bulk job into `Fixed` gains so every
+/// frame receives identical correction (no frame-to-frame drift in timelapses).
+/// Outside of a bulk job they behave like `Auto`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum WhiteBalance {
+    #[default]
+    Off,
+    /// Gray-world estimate computed independently for every image.
+    Auto,
+    /// Explicit per-channel multipliers (R, G, B).
+    Fixed { gains: [f32; 3] },
+    /// Estimate from the file at `index` in the batch and apply it to all files.
+    LockReference { index: usize },
+    /// Estimate every file and apply the per-channel median to all files.
+    LockMedian,
+}
+
+/// Illuminant estimator for automatic white balance. Each assumption fails on some
+/// scenes, so the right one depends on the shoot.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AwbMethod {
+    /// The scene averages to gray. Robust on varied scenes, but pulls dominant colors
+    /// (a lawn, a lit stage, a red wall) towards gray.
+    #[default]
+    GrayWorld,
+    /// The brightest 1% of unclipped pixels are white. Suits snow, paper and scenes with
+    /// bright neutral highlights; fooled when the brightest thing is a colored light.
+    WhitePatch,
+    /// Retinex max-RGB: each channel's own highlight level (its 99th percentile) is
+    /// white, so no single neutral surface is needed. Suits strongly tinted lighting
+    /// over mixed surfaces.
+    Retinex,
+}
+
+/// Hue/saturation/luminance shift applied to one color range.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct HslShift {
+    /// Hue rotation in degrees.
+    pub hue: f32,
+    /// Relative saturation change in -1.0..=1.0.
+    pub saturation: f32,
+    /// Luminance change in -1.0..=1.0.
+    pub luminance: f32,
+}
+
+/// Per color range HSL controls. Ranges blend smoothly into their neighbours.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct HslAdjustments {
+    pub reds: HslShift,
+    pub oranges: HslShift,
+    pub yellows: HslShift,
+    pub greens: HslShift,
+    pub aquas: HslShift,
+    pub blues: HslShift,
+    pub purples: HslShift,
+    pub magentas: HslShift,
+}
+
+impl HslAdjustments {
+    /// Ranges paired with their center hue in degrees, ordered around the wheel.
+    pub fn ranges(&self) -> [(f32, HslShift); 8] {
+        [
+            (0.0, self.reds),
+            (30.0, self.oranges),
+            (60.0, self.yellows),
+            (120.0, self.greens),
+            (180.0, self.aquas),
+            (225.0, self.blues),
+            (270.0, self.purples),
+            (315.0, self.magentas),
+        ]
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CompositionScore {
+    pub path: String,
+    #[serde(flatten)]
+    pub composition: Option<image_ops::composition::Composition>,
+    pub error: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -31,174 +2073,2721 @@ pub struct ProcessResult {
     pub success: bool,
     pub path: String,
     pub error: Option<String>,
+    /// Clockwise skew in degrees detected by the deskew stage, when it ran.
+    pub deskew_angle: Option<f32>,
+    /// Trim box and marks found when cropping to crop marks (of the first page of a
+    /// split spread that had them).
+    pub crop_marks: Option<CropMarks>,
+    /// The source, or a page of a split spread, was detected as a blank page.
+    pub blank: bool,
+    /// Pages written for a split spread, left first; `path` is then the first of them.
+    /// Empty when nothing was split.
+    pub pages: Vec<String>,
+    /// One entry per variant, in `ProcessOptions::variants` order (per page when split).
+    /// A failed variant does not fail the main output.
+    pub variants: Vec<VariantResult>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VariantResult {
+    pub success: bool,
+    /// Empty when no path could be built for the variant.
+    pub path: String,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ProgressPayload {
+    pub path: String,
+    pub success: bool,
+    pub error: Option<String>,
+    pub progress: f32,
+    pub stage: String,
+}
+
+/// Decodes a RAW file for a preview display in the UI.
+/// Returns a base64-encoded thumbnail string.
+#[tauri::command]
+pub fn decode_raw(
+    app: AppHandle,
+    settings: State<'_, PreviewSettings>,
+    memory: State<'_, MemorySettings>,
+    path: String,
+) -> Result<String, String> {
+    info!("Decoding RAW file for preview: {}", path);
+
+    if !storage::is_allowed(&app, &path) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    if storage::is_uri(&path) {
+        let img = storage::open_input(&app, &path)?;
+        return encode_data_url(&img.thumbnail(1200, 1200), &settings.encoding());
+    }
+    
+    if !std::path::Path::new(&path).exists() {
+        error!("RAW file not found: {}", path);
+        return Err(format!("File not found: {}", path));
+    }
+
+    let img = execution::run(|| if memory.is_low() {
+        image_ops::decode_raw_superpixel(&path)
+    } else {
+        image_ops::decode_raw_to_image(&path)
+    })?;
+    let thumb = img.thumbnail(1200, 1200);
+    encode_data_url(&thumb, &settings.encoding())
+}
+
+/// Encodes an image as a data URL for display in the webview.
+fn encode_data_url(img: &DynamicImage, encoding: &PreviewEncoding) -> Result<String, String> {
+    let mut buffer = Vec::new();
+    let mime = match encoding.format {
+        PreviewFormat::Jpeg => {
+            let jpeg = JpegOptions { quality: encoding.quality, ..Default::default() };
+            image_ops::encode::encode_jpeg(img, &mut buffer, &jpeg, None, None)?;
+            "image/jpeg"
+        }
+        PreviewFormat::Webp => {
+            // The WebP encoder only takes 8-bit RGB(A)
+            let img = match img {
+                DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => img.clone(),
+                _ if img.color().has_alpha() => DynamicImage::ImageRgba8(img.to_rgba8()),
+                _ => DynamicImage::ImageRgb8(img.to_rgb8()),
+            };
+            img.write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut buffer))
+                .map_err(|e| e.to_string())?;
+            "image/webp"
+        }
+    };
+
+    let base64_str = general_purpose::STANDARD.encode(buffer);
+    Ok(format!("data:{};base64,{}", mime, base64_str))
+}
+
+/// Updates how previews are encoded for the webview and keeps the choice for later launches.
+#[tauri::command]
+pub fn set_preview_encoding(
+    settings: State<'_, PreviewSettings>,
+    store: State<'_, SettingsStore>,
+    encoding: PreviewEncoding,
+) -> Result<(), String> {
+    info!("Preview encoding set to {:?}", encoding);
+    settings.set_encoding(encoding);
+    store.update(|s| s.preview_encoding = encoding)
+}
+
+/// Reports the low-memory mode and the RAM it was resolved against.
+#[tauri::command]
+pub fn memory_status(memory: State<'_, MemorySettings>) -> MemoryStatus {
+    memory.status()
+}
+
+/// Switches low-memory mode; takes effect for jobs and previews started afterwards and
+/// is kept for later launches.
+#[tauri::command]
+pub fn set_low_memory_mode(memory: State<'_, MemorySettings>, store: State<'_, SettingsStore>, mode: LowMemoryMode) -> Result<MemoryStatus, String> {
+    memory.set_mode(mode);
+    let status = memory.status();
+    info!("Low-memory mode set to {:?} (active: {})", mode, status.active);
+    store.update(|s| s.low_memory_mode = mode)?;
+    Ok(status)
+}
+
+/// Reports the execution mode and whether worker threads are available.
+#[tauri::command]
+pub fn execution_status() -> ExecutionStatus {
+    execution::status()
+}
+
+/// Switches between parallel and sequential processing; takes effect for work started
+/// afterwards and is kept for later launches.
+#[tauri::command]
+pub fn set_execution_mode(store: State<'_, SettingsStore>, mode: ExecutionMode) -> Result<ExecutionStatus, String> {
+    execution::set_mode(mode);
+    let status = execution::status();
+    info!("Execution mode set to {:?} (sequential: {})", mode, status.sequential);
+    store.update(|s| s.execution_mode = mode)?;
+    Ok(status)
+}
+
+/// Arms intermediate image dumps for the source at `path` (`None` disarms them) and
+/// returns the folder they are written to, one subfolder per run.
+#[tauri::command]
+pub fn set_stage_dump(dumps: State<'_, StageDumps>, path: Option<String>) -> String {
+    info!("Stage dumps armed for {:?}", path);
+    dumps.arm(path);
+    std::env::temp_dir().join(debug::DIR_NAME).to_string_lossy().to_string()
+}
+
+/// Sets the values of custom template tokens, such as `{client_code}` looked up by a
+/// script; an empty set removes them. Built-in tokens keep their meaning.
+#[tauri::command]
+pub fn set_custom_tokens(tokens: State<'_, TokenRegistry>, custom: CustomTokens) -> Result<(), String> {
+    custom.validate()?;
+    if custom.is_empty() {
+        tokens.unregister(naming::CUSTOM_ID);
+    } else {
+        info!("Custom tokens set: {:?}", custom.values.keys().collect::<Vec<_>>());
+        tokens.register(naming::CUSTOM_ID, Arc::new(custom));
+    }
+    Ok(())
+}
+
+/// Records battery and thermal state from the platform shell; running batches
+/// resize their concurrency to it within a few seconds.
+#[tauri::command]
+pub fn report_device_conditions(device: State<'_, DeviceState>, conditions: DeviceConditions) {
+    info!("Device conditions: {:?}", conditions);
+    device.update(conditions);
+}
+
+/// Decodes a file into a new preview session and returns its id.
+#[tauri::command]
+pub fn open_preview_session(
+    app: AppHandle,
+    window: WebviewWindow,
+    sessions: State<'_, PreviewSessions>,
+    memory: State<'_, MemorySettings>,
+    path: String,
+) -> Result<u64, String> {
+    if !storage::is_allowed(&app, &path) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+    open_session(&app, window.label(), &sessions, &memory, &path)
+}
+
+fn open_session(
+    app: &AppHandle,
+    window: &str,
+    sessions: &PreviewSessions,
+    memory: &MemorySettings,
+    path: &str,
+) -> Result<u64, String> {
+    let img = if memory.is_low() {
+        // Only the session being edited stays resident, and RAWs skip the full-size demosaic
+        sessions.close_window(window);
+        if image_ops::is_raw_path(path) && !storage::is_uri(path) {
+            execution::run(|| image_ops::decode_raw_superpixel(path))?
+        } else {
+            execution::run(|| storage::open_input(app, path))?
+        }
+    } else {
+        execution::run(|| storage::open_input(app, path))?
+    };
+    let id = sessions.open(window, &img);
+    info!("Opened preview session {} for {}", id, path);
+    Ok(id)
+}
+
+#[derive(Serialize, Clone)]
+pub struct SharedImport {
+    /// Accepted inputs, with `file://` URLs turned into paths.
+    pub files: Vec<String>,
+    pub rejected: Vec<String>,
+    /// Preview session opened on the first accepted file.
+    pub session_id: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Returns files the OS delivered (launch arguments, "open with") that the UI has not taken yet.
+#[tauri::command]
+pub fn take_shared_files(inbox: State<'_, SharedInbox>) -> Vec<String> {
+    inbox.take()
+}
+
+/// Accepts files opened with or shared to the app and starts a preview session on the first.
+///
+/// Shared files usually live outside the fs scope; those the OS delivered are added to
+/// it, while anything else must already be allowed, so the webview cannot grant itself
+/// access to arbitrary paths.
+#[tauri::command]
+pub fn import_shared(
+    app: AppHandle,
+    window: WebviewWindow,
+    sessions: State<'_, PreviewSessions>,
+    memory: State<'_, MemorySettings>,
+    inbox: State<'_, SharedInbox>,
+    paths_or_uris: Vec<String>,
+) -> SharedImport {
+    let mut import = SharedImport { files: Vec::new(), rejected: Vec::new(), session_id: None, error: None };
+    for original in paths_or_uris {
+        let location = intake::normalize(&original);
+        let delivered = inbox.was_received(&original) || inbox.was_received(&location);
+        let granted = storage::is_allowed(&app, &location)
+            || (delivered && app.fs_scope().allow_file(&location).is_ok());
+        if granted && intake::is_supported(&location) {
+            import.files.push(location);
+        } else {
+            info!("Rejected shared file: {}", original);
+            import.rejected.push(original);
+        }
+    }
+
+    if let Some(first) = import.files.first() {
+        match open_session(&app, window.label(), &sessions, &memory, first) {
+            Ok(id) => import.session_id = Some(id),
+            Err(e) => {
+                error!("Failed to open shared file {}: {}", first, e);
+                import.error = Some(e);
+            }
+        }
+    }
+    import
+}
+
+/// Installs a `.cliolook` bundle, replacing an installed look with the same name.
+#[tauri::command]
+pub fn install_look(app: AppHandle, path: String) -> Result<LookInfo, String> {
+    if !storage::is_allowed(&app, &path) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    let bytes = storage::read_bytes(&app, &path)?;
+    let look = looks::install(&bytes, &looks_root(&app)?)?;
+    info!("Installed look: {} ({})", look.name, look.id);
+    Ok(look)
+}
+
+#[tauri::command]
+pub fn list_looks(app: AppHandle) -> Result<Vec<LookInfo>, String> {
+    Ok(looks::list(&looks_root(&app)?))
+}
+
+/// Creates the looks directory and adds it to the fs scope so installed LUTs and
+/// assets load like user-picked files.
+fn looks_root<R: Runtime>(app: &AppHandle<R>) -> Result<std::path::PathBuf, String> {
+    let dir = looks::looks_dir(&app.path().app_data_dir().map_err(|e| e.to_string())?);
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    app.fs_scope().allow_directory(&dir, true).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+/// Releases the image held by a preview session.
+#[tauri::command]
+pub fn close_preview_session(window: WebviewWindow, sessions: State<'_, PreviewSessions>, session_id: u64) -> bool {
+    sessions.close(window.label(), session_id)
+}
+
+/// Renders the session's image with `options` as a data URL, for the editor view,
+/// optionally as seen with a color vision deficiency.
+/// Repeated renders that only change later stages reuse the cached leading ones.
+#[tauri::command]
+pub fn render_preview(
+    app: AppHandle,
+    window: WebviewWindow,
+    sessions: State<'_, PreviewSessions>,
+    settings: State<'_, PreviewSettings>,
+    session_id: u64,
+    mut options: ProcessOptions,
+    simulate: Option<ColorDeficiency>,
+) -> Result<String, String> {
+    load_assets(&app, &mut options)?;
+    let output = render_session(window.label(), &sessions, session_id, &options, app.state::<MemorySettings>().is_low())?;
+    let output = match options.output.color.filter(|c| c.soft_proof) {
+        Some(color) => image_ops::color::soft_proof(&output, &color)?,
+        None => output,
+    };
+    let output = match simulate {
+        Some(deficiency) => image_ops::color::simulate_deficiency(&output, deficiency),
+        None => output,
+    };
+    encode_data_url(&output, &settings.encoding())
+}
+
+/// Runs the pipeline over a session's source through its stage cache. Low-memory mode
+/// keeps no cache, so every render starts from the source.
+fn render_session(owner: &str, sessions: &PreviewSessions, session_id: u64, options: &ProcessOptions, low_memory: bool) -> Result<DynamicImage, String> {
+    let source = sessions.source(owner, session_id)?;
+    if low_memory {
+        sessions.clear_checkpoint(owner, session_id);
+        return Ok(execution::run(|| image_ops::apply_filters_cached(&source, options, &mut None)).0);
+    }
+    let mut checkpoint = sessions.checkpoint(owner, session_id);
+    let (output, _) = execution::run(|| image_ops::apply_filters_cached(&source, options, &mut checkpoint));
+    if let Some(checkpoint) = checkpoint {
+        sessions.store_checkpoint(owner, session_id, checkpoint);
+    }
+    Ok(output)
+}
+
+#[derive(Serialize, Clone)]
+pub struct StagePreview {
+    pub stage: String,
+    pub image: String,
+}
+
+/// The stage order used when a job sets no `pipeline`, for the UI to start arranging from.
+#[tauri::command]
+pub fn default_pipeline() -> Vec<Stage> {
+    image_ops::DEFAULT_PIPELINE.to_vec()
+}
+
+/// Debug view of the pipeline: renders a small preview after every stage that runs,
+/// starting with the unprocessed source, so unexpected results can be traced to a stage.
+#[tauri::command]
+pub fn render_pipeline_stages(
+    app: AppHandle,
+    window: WebviewWindow,
+    sessions: State<'_, PreviewSessions>,
+    settings: State<'_, PreviewSettings>,
+    session_id: u64,
+    mut options: ProcessOptions,
+) -> Result<Vec<StagePreview>, String> {
+    load_assets(&app, &mut options)?;
+    let source = sessions.source(window.label(), session_id)?;
+    let encoding = settings.encoding();
+    let mut stages = Vec::new();
+    let mut record = |stage: &str, img: &DynamicImage| {
+        let image = encode_data_url(&img.thumbnail(480, 480), &encoding);
+        stages.push((stage.to_string(), image));
+    };
+
+    record("source", &source);
+    let output = execution::run(|| image_ops::apply_filters_observed((*source).clone(), &options, &mut record));
+    if let Some(color) = options.output.color.filter(|c| c.soft_proof) {
+        record("soft_proof", &image_ops::color::soft_proof(&output, &color)?);
+    }
+
+    stages
+        .into_iter()
+        .map(|(stage, image)| Ok(StagePreview { stage, image: image? }))
+        .collect()
+}
+
+#[derive(Serialize, Clone)]
+pub struct DiffPreview {
+    pub image: String,
+    #[serde(flatten)]
+    pub stats: image_ops::DiffStats,
+}
+
+/// Renders an amplified heatmap of what the pipeline changed, so "lossless" settings
+/// can be verified and accidental destructive operations spotted.
+#[tauri::command]
+pub fn render_diff(
+    app: AppHandle,
+    window: WebviewWindow,
+    sessions: State<'_, PreviewSessions>,
+    settings: State<'_, PreviewSettings>,
+    session_id: u64,
+    mut options: ProcessOptions,
+    amplification: Option<f32>,
+) -> Result<DiffPreview, String> {
+    load_assets(&app, &mut options)?;
+    let source = sessions.source(window.label(), session_id)?;
+    let output = render_session(window.label(), &sessions, session_id, &options, app.state::<MemorySettings>().is_low())?;
+    let (heatmap, stats) = image_ops::diff_heatmap(&source, &output, amplification.unwrap_or(8.0));
+    Ok(DiffPreview {
+        image: encode_data_url(&DynamicImage::ImageRgb8(heatmap), &settings.encoding())?,
+        stats,
+    })
+}
+
+/// Returns the pre-demosaic sensel histogram of a RAW file per CFA channel.
+#[tauri::command]
+pub fn raw_histogram(app: AppHandle, path: String) -> Result<image_ops::RawHistogram, String> {
+    if !storage::is_allowed(&app, &path) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    let raw = if storage::is_uri(&path) {
+        rawloader::decode(&mut std::io::Cursor::new(storage::read_bytes(&app, &path)?))
+    } else {
+        rawloader::decode_file(&path)
+    };
+    let raw = raw.map_err(|e| e.to_string())?;
+    Ok(image_ops::raw_histogram(&raw))
+}
+
+/// Estimates the translation and rotation of every file relative to the first one.
+#[tauri::command]
+pub fn align_images(app: AppHandle, paths: Vec<String>) -> Result<Vec<Transform>, String> {
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    let (frames, transforms) = execution::run(|| {
+        let frames = paths.par_iter().map(|p| storage::open_input(&app, p)).collect::<Result<Vec<_>, _>>()?;
+        let transforms = image_ops::align::align_to(&frames, 0)?;
+        Ok::<_, String>((frames, transforms))
+    })?;
+    info!("Aligned {} images", frames.len());
+    Ok(transforms)
+}
+
+/// Per-file composition score for culling, in input order.
+#[tauri::command]
+pub async fn score_composition(app: AppHandle, paths: Vec<String>) -> Result<Vec<CompositionScore>, String> {
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    execution::run_blocking(move || {
+        paths
+            .par_iter()
+            .map(|path| {
+                // Half-resolution RAW decoding is plenty for a score computed on thumbnails
+                let img = if image_ops::is_raw_path(path) && !storage::is_uri(path) {
+                    image_ops::decode_raw_superpixel(path)
+                } else {
+                    storage::open_input(&app, path)
+                };
+                match img {
+                    Ok(img) => CompositionScore { path: path.clone(), composition: Some(image_ops::composition::score(&img)), error: None },
+                    Err(e) => {
+                        error!("Composition scoring failed for {}: {}", path, e);
+                        CompositionScore { path: path.clone(), composition: None, error: Some(e) }
+                    }
+                }
+            })
+            .collect()
+    })
+    .await
+}
+
+/// Fuses a bracketed exposure sequence into a single image.
+#[tauri::command]
+pub fn merge_exposures(app: AppHandle, paths: Vec<String>, out_path: String, options: HdrOptions) -> ProcessResult {
+    let fail = |e: String| {
+        error!("Exposure merge failed: {}", e);
+        ProcessResult { success: false, path: out_path.clone(), error: Some(e), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+    };
+
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        return fail(format!("Permission denied (read): {}", path));
+    }
+    if !storage::is_allowed(&app, &out_path) {
+        return fail(format!("Permission denied (write): {}", out_path));
+    }
+
+    let merged = execution::run(|| {
+        let frames = paths.par_iter().map(|p| storage::open_input(&app, p)).collect::<Result<Vec<_>, _>>()?;
+        image_ops::hdr::merge_exposures(&frames, &options)
+    });
+    let merged = match merged {
+        Ok(img) => img,
+        Err(e) => return fail(e),
+    };
+    match storage::save_output(&app, &merged, &out_path, &options.output) {
+        Ok(_) => {
+            info!("Merged {} exposures into {}", paths.len(), out_path);
+            ProcessResult { success: true, path: out_path, error: None, deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Registers and sigma-clip integrates a sequence of frames.
+#[tauri::command]
+pub fn stack_images(app: AppHandle, paths: Vec<String>, out_path: String, options: StackOptions) -> ProcessResult {
+    let fail = |e: String| {
+        error!("Stacking failed: {}", e);
+        ProcessResult { success: false, path: out_path.clone(), error: Some(e), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+    };
+
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        return fail(format!("Permission denied (read): {}", path));
+    }
+    if !storage::is_allowed(&app, &out_path) {
+        return fail(format!("Permission denied (write): {}", out_path));
+    }
+
+    let stacked = execution::run(|| {
+        let frames = paths.par_iter().map(|p| storage::open_input(&app, p)).collect::<Result<Vec<_>, _>>()?;
+        image_ops::stack::stack_frames(&frames, &options)
+    });
+    let stacked = match stacked {
+        Ok(img) => img,
+        Err(e) => return fail(e),
+    };
+    match storage::save_output(&app, &stacked, &out_path, &options.output) {
+        Ok(_) => {
+            info!("Stacked {} frames into {}", paths.len(), out_path);
+            ProcessResult { success: true, path: out_path, error: None, deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Stitches the overlapping tiles of a foldout or map, in capture order, into one image.
+#[tauri::command]
+pub fn stitch_images(app: AppHandle, paths: Vec<String>, out_path: String, options: StitchOptions) -> ProcessResult {
+    let fail = |e: String| {
+        error!("Stitching failed: {}", e);
+        ProcessResult { success: false, path: out_path.clone(), error: Some(e), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+    };
+
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        return fail(format!("Permission denied (read): {}", path));
+    }
+    if !storage::is_allowed(&app, &out_path) {
+        return fail(format!("Permission denied (write): {}", out_path));
+    }
+
+    let stitched = execution::run(|| {
+        let tiles = paths.par_iter().map(|p| storage::open_input(&app, p)).collect::<Result<Vec<_>, _>>()?;
+        image_ops::stitch::stitch_tiles(&tiles, &options)
+    });
+    let (stitched, placements) = match stitched {
+        Ok(stitched) => stitched,
+        Err(e) => return fail(e),
+    };
+    for (path, p) in paths.iter().zip(&placements) {
+        info!("Placed {} at ({:.1}, {:.1}), {:.2} deg, x{:.3}", path, p.tx, p.ty, p.rotation(), p.scale());
+    }
+    match storage::save_output(&app, &stitched, &out_path, &options.output) {
+        Ok(_) => {
+            info!("Stitched {} tiles into {}", paths.len(), out_path);
+            ProcessResult { success: true, path: out_path, error: None, deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() }
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Copies EXIF/IPTC from the matching RAW into exports that lack it, in place.
+#[tauri::command]
+pub async fn sync_metadata(
+    app: AppHandle,
+    source_raws: Vec<String>,
+    exported_files: Vec<String>,
+) -> Result<Vec<MetadataSync>, String> {
+    // Metadata is copied in place, which needs file paths
+    if let Some(path) = source_raws.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    if let Some(path) = exported_files.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (write): {}", path));
+    }
+
+    execution::run_blocking(move || {
+        let matches = metadata::match_sources(&source_raws, &exported_files);
+        let results: Vec<MetadataSync> = exported_files
+            .par_iter()
+            .zip(matches.par_iter())
+            .map(|(export, source)| match source {
+                Some(i) => metadata::copy_missing(&source_raws[*i], export),
+                None => MetadataSync {
+                    export: export.clone(),
+                    source: None,
+                    status: SyncStatus::NoSource,
+                    exif: false,
+                    iptc: false,
+                    error: None,
+                },
+            })
+            .collect();
+        let updated = results.iter().filter(|r| r.status == SyncStatus::Updated).count();
+        info!("Metadata sync updated {} of {} exports", updated, results.len());
+        results
+    })
+    .await
+}
+
+/// On-device keyword suggestion with an ONNX image-tagging model, in builds with the
+/// `keywords` feature.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TaggerOptions {
+    /// `.onnx` model taking a 1x3xSxS float image and returning one score per label.
+    pub model: String,
+    /// Text file with one keyword per line, in the order of the model's outputs.
+    pub labels: String,
+    /// Side of the square input the model expects, in pixels.
+    pub input_size: u32,
+    pub activation: TaggerActivation,
+    /// Lowest confidence suggested, 0.0..=1.0 after the activation.
+    pub threshold: f32,
+    pub max_keywords: usize,
+}
+
+impl Default for TaggerOptions {
+    fn default() -> Self {
+        Self {
+            model: String::new(),
+            labels: String::new(),
+            input_size: 224,
+            activation: TaggerActivation::Sigmoid,
+            threshold: 0.5,
+            max_keywords: 10,
+        }
+    }
+}
+
+/// How the model's raw outputs become confidences.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaggerActivation {
+    /// Each label scored on its own, as multi-label taggers are trained.
+    #[default]
+    Sigmoid,
+    /// Scores share one distribution, as single-label classifiers are trained.
+    Softmax,
+    /// The model already outputs probabilities.
+    None,
+}
+
+/// Suggests keywords for each file with an on-device tagging model. Nothing is written:
+/// the UI shows the suggestions and sends the ones the user confirms to `write_keywords`.
+#[tauri::command]
+pub async fn suggest_keywords(app: AppHandle, paths: Vec<String>, options: TaggerOptions) -> Result<Vec<KeywordSuggestions>, String> {
+    if let Some(path) = paths.iter().chain([&options.model, &options.labels]).find(|p| !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    execution::run_blocking(move || suggest_for(&app, &paths, &options)).await?
+}
+
+#[cfg(feature = "keywords")]
+fn suggest_for<R: Runtime>(app: &AppHandle<R>, paths: &[String], options: &TaggerOptions) -> Result<Vec<KeywordSuggestions>, String> {
+    let labels = String::from_utf8(storage::read_bytes(app, &options.labels)?).map_err(|_| format!("Labels file is not UTF-8: {}", options.labels))?;
+    let tagger = crate::tagger::Tagger::new(&storage::read_bytes(app, &options.model)?, &labels, options.input_size)?;
+    let results: Vec<KeywordSuggestions> = paths
+        .par_iter()
+        .map(|path| match storage::open_input(app, path).and_then(|img| tagger.suggest(&img, options)) {
+            Ok(keywords) => KeywordSuggestions { path: path.clone(), keywords, error: None },
+            Err(e) => KeywordSuggestions { path: path.clone(), keywords: Vec::new(), error: Some(e) },
+        })
+        .collect();
+    info!("Suggested keywords for {} files", results.iter().filter(|r| r.error.is_none()).count());
+    Ok(results)
+}
+
+#[cfg(not(feature = "keywords"))]
+fn suggest_for<R: Runtime>(_app: &AppHandle<R>, _paths: &[String], _options: &TaggerOptions) -> Result<Vec<KeywordSuggestions>, String> {
+    Err("Keyword suggestions are not available in this build (enable the `keywords` feature)".to_string())
+}
+
+/// Writes keywords the user confirmed into each file's IPTC record, in place.
+#[tauri::command]
+pub async fn write_keywords(app: AppHandle, assignments: Vec<KeywordAssignment>) -> Result<Vec<KeywordUpdate>, String> {
+    if let Some(a) = assignments.iter().find(|a| !storage::is_allowed(&app, &a.path)) {
+        return Err(format!("Permission denied (write): {}", a.path));
+    }
+
+    execution::run_blocking(move || {
+        let results: Vec<KeywordUpdate> = assignments.par_iter().map(metadata::write_keywords).collect();
+        let updated = results.iter().filter(|r| r.status == SyncStatus::Updated).count();
+        info!("Keywords written to {} of {} files", updated, results.len());
+        results
+    })
+    .await
+}
+
+/// Sets the catalog note of a source file; empty text removes it.
+#[tauri::command]
+pub fn set_note(catalog: State<'_, Catalog>, path: String, text: String) -> Result<(), String> {
+    catalog.set_note(&path, &text)
+}
+
+/// Every catalog note, keyed by source path.
+#[tauri::command]
+pub fn get_notes(catalog: State<'_, Catalog>) -> std::collections::BTreeMap<String, Note> {
+    catalog.notes()
+}
+
+/// Checks finished files against a stock agency or print lab profile before they are sent.
+#[tauri::command]
+pub async fn validate_for_target(
+    app: AppHandle,
+    files: Vec<String>,
+    target_profile: TargetProfile,
+) -> Result<Vec<FileReport>, String> {
+    if let Some(path) = files.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    let profile = target_profile.profile();
+    execution::run_blocking(move || {
+        let reports: Vec<FileReport> = files.par_iter().map(|path| submission::validate(path, &profile)).collect();
+        let failed = reports.iter().filter(|r| !r.passed).count();
+        info!("{}: {} of {} files fail validation", profile.name, failed, reports.len());
+        reports
+    })
+    .await
+}
+
+/// Writes a METS document at `mets_path` over the page images `files`, in page order,
+/// with the ALTO full text and MIX records written next to them. Every file has to be
+/// inside the document's folder, so the package can move as a whole.
+#[tauri::command]
+pub async fn export_mets(app: AppHandle, files: Vec<String>, mets_path: String, options: MetsOptions) -> Result<MetsSummary, String> {
+    if let Some(path) = files.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        error!("Permission denied (read): {}", path);
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    if storage::is_uri(&mets_path) || !storage::is_allowed(&app, &mets_path) {
+        error!("Permission denied (write): {}", mets_path);
+        return Err(format!("Permission denied (write): {}", mets_path));
+    }
+
+    execution::run_blocking(move || {
+        let pages: Vec<std::path::PathBuf> = files.iter().map(std::path::PathBuf::from).collect();
+        let summary = mets::export(std::path::Path::new(&mets_path), &pages, &options)?;
+        info!("Wrote METS for {} pages ({} with ALTO) to {}", summary.pages, summary.alto, summary.path);
+        Ok(summary)
+    })
+    .await?
+}
+
+/// Reads the color target in `path`, found by itself or at `corners`, and returns the
+/// correction it gives with the patches as captured, for review before a batch.
+#[tauri::command]
+pub async fn detect_color_target(
+    app: AppHandle,
+    path: String,
+    target: Option<ColorTarget>,
+    corners: Option<[(f32, f32); 4]>,
+) -> Result<Calibration, String> {
+    if !storage::is_allowed(&app, &path) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    execution::run_blocking(move || {
+        let target = resolve_target(&app, target.unwrap_or_default())?;
+        let img = storage::open_input(&app, &path)?;
+        let calibration = calibration::calibrate(&img, &target, corners)?;
+        info!("Color target in {}: mean ΔE {:.1}, {:.1} corrected", path, calibration.delta_e_before, calibration.delta_e_after);
+        Ok(calibration)
+    })
+    .await?
+}
+
+/// Re-opens written files and decodes them in full, checking each against the job's
+/// output spec, so corrupt or truncated writes are caught before delivery.
+#[tauri::command]
+pub async fn validate_outputs(app: AppHandle, files: Vec<String>, spec: OutputSpec) -> Result<ConformanceReport, String> {
+    if let Some(path) = files.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        error!("Permission denied: {}", path);
+        return Err(format!("Permission denied: {}", path));
+    }
+
+    execution::run_blocking(move || {
+        let report = ConformanceReport::new(files.par_iter().map(|path| conformance::validate(path, &spec)).collect());
+        info!("Output validation: {} of {} files fail", report.failed, report.files.len());
+        report
+    })
+    .await
+}
+
+/// The built-in submission profiles, for the UI to list and to start custom profiles from.
+#[tauri::command]
+pub fn submission_profiles() -> Vec<(submission::ProfileName, SubmissionProfile)> {
+    submission::ProfileName::ALL.iter().map(|name| (*name, name.profile())).collect()
+}
+
+/// Internal processing logic used by both single and bulk operations.
+pub fn process_image_inner<R: Runtime>(
+    app: &AppHandle<R>,
+    window: &str,
+    path: String,
+    out_path: String,
+    mut options: ProcessOptions,
+    progress: f32,
+    halt: &Halt,
+) -> ProcessResult {
+    // Hashed before per-file seeding so every file of a batch shares it
+    let hash = options.output.stats_sidecar.then(|| options_hash(&options));
+    seed_grain(&mut options, &path);
+    if let Some(resize) = options.resize.as_mut() {
+        resize.early = app.state::<MemorySettings>().is_low();
+    }
+    let note = options.output.embed_notes.then(|| app.try_state::<Catalog>().and_then(|c| c.note(&path))).flatten();
+
+    let emit = |stage: &str, success: bool, error: Option<String>| {
+        emit_progress(app, window, ProgressPayload {
+            path: path.clone(),
+            success,
+            error,
+            progress,
+            stage: stage.to_string(),
+        });
+    };
+
+    if !storage::is_allowed(app, &path) {
+        let err_msg = format!("Permission denied (read): {}", path);
+        error!("{}", err_msg);
+        emit("failed", false, Some(err_msg.clone()));
+        return ProcessResult {
+            success: false,
+            path: out_path,
+            error: Some(err_msg),
+            deskew_angle: None,
+            crop_marks: None,
+            blank: false,
+            pages: Vec::new(),
+            variants: Vec::new(),
+        };
+    }
+
+    if !storage::is_allowed(app, &out_path) {
+        let err_msg = format!("Permission denied (write): {}", out_path);
+        error!("{}", err_msg);
+        emit("failed", false, Some(err_msg.clone()));
+        return ProcessResult {
+            success: false,
+            path: out_path,
+            error: Some(err_msg),
+            deskew_angle: None,
+            crop_marks: None,
+            blank: false,
+            pages: Vec::new(),
+            variants: Vec::new(),
+        };
+    }
+
+    // Stamps and DPI resizes read their tags from the file, so only once it may be read
+    resolve_stamp(app, &mut options, &path);
+    resolve_density(&mut options, &path);
+    if let Err(err_msg) = resolve_redaction(app, &mut options, &path).and_then(|_| check_assets(&options)) {
+        error!("{}", err_msg);
+        emit("failed", false, Some(err_msg.clone()));
+        return ProcessResult {
+            success: false,
+            path: out_path,
+            error: Some(err_msg),
+            deskew_angle: None,
+            crop_marks: None,
+            blank: false,
+            pages: Vec::new(),
+            variants: Vec::new(),
+        };
+    }
+
+    if halt.is_cancelled() {
+        emit("cancelled", false, Some(CANCELLED.to_string()));
+        return ProcessResult { success: false, path: out_path, error: Some(CANCELLED.to_string()), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+    }
+    emit("decoding", true, None);
+    let img_res = storage::open_input(app, &path);
+    let dump = if app.state::<StageDumps>().is_armed(&path) {
+        StageDump::create(&std::env::temp_dir().join(debug::DIR_NAME), &path).map_err(|e| error!("{}", e)).ok()
+    } else {
+        None
+    };
+
+    match img_res {
+        Ok(img) => {
+            // Nothing is dumped before the redaction boxes are filled
+            let redacting = options.redaction.as_ref().is_some_and(|r| !r.boxes.is_empty());
+            if let Some(dump) = &dump {
+                info!("Dumping stages of {} to {}", path, dump.dir().display());
+                if !redacting {
+                    dump.write("decoded", &img);
+                }
+            }
+            // Blank checks and the pipeline run per page, so a spread gets one result per side
+            let export = |img: DynamicImage, out_path: String, options: &ProcessOptions| -> (ProcessResult, &'static str) {
+                let blank = options.blank_pages.as_ref().filter(|b| image_ops::is_blank_page(&img, b.max_ink));
+                let out_path = match blank.map(|b| &b.policy) {
+                    Some(BlankPolicy::Skip) => {
+                        info!("Skipping blank page: {}", out_path);
+                        let res = ProcessResult { success: true, path: out_path, error: None, deskew_angle: None, crop_marks: None, blank: true, pages: Vec::new(), variants: Vec::new() };
+                        return (res, "skipped");
+                    }
+                    Some(BlankPolicy::Move { folder }) => match blank_destination(app, &out_path, folder) {
+                        Ok(moved) => moved,
+                        Err(e) => {
+                            error!("{}", e);
+                            let res = ProcessResult { success: false, path: out_path, error: Some(e), deskew_angle: None, crop_marks: None, blank: true, pages: Vec::new(), variants: Vec::new() };
+                            return (res, "failed");
+                        }
+                    },
+                    _ => out_path,
+                };
+                emit("filtering", true, None);
+                let source = (!options.variants.is_empty()).then(|| img.clone());
+                // Per-stage events let the UI show where slow stages (NL-means) are
+                let mut actions = Vec::new();
+                let mut covered = !redacting;
+                let (img, report) = image_ops::apply_filters_until(img, options, &mut |stage, img| {
+                    emit(stage, true, None);
+                    actions.push(stage.to_string());
+                    covered |= stage == "redact";
+                    if let Some(dump) = dump.as_ref().filter(|_| covered) {
+                        dump.write(stage, img);
+                    }
+                }, &|| halt.is_cancelled());
+                if report.cancelled {
+                    info!("Cancelled while filtering: {}", out_path);
+                    let res = ProcessResult { success: false, path: out_path, error: Some(CANCELLED.to_string()), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+                    return (res, "cancelled");
+                }
+                if let Some(dump) = &dump {
+                    dump.write("pre_encode", &img);
+                }
+
+                emit("saving", true, None);
+                let output = match &report.text {
+                    Some(Ok(page)) => OutputOptions { text_layer: Some(Arc::new(page.words.clone())), ..options.output.clone() },
+                    _ => options.output.clone(),
+                };
+                let saved = storage::save_output(app, &img, &out_path, &output)
+                    .and_then(|_| hash.clone().map_or(Ok(()), |hash| write_stats_sidecar(app, &img, &out_path, hash)))
+                    .and_then(|_| match &options.output.technical_metadata {
+                        Some(record) => write_mix_sidecar(app, &path, &out_path, record, &actions),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| match &options.output.iiif {
+                        Some(iiif) => write_iiif_tiles(app, &img, &out_path, iiif, &options.output.jpeg),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| note.as_ref().map_or(Ok(()), |note| embed_note(&out_path, note)))
+                    .and_then(|_| match (&report.text, &options.ocr) {
+                        (Some(page), Some(ocr)) => page.clone().and_then(|page| {
+                            let text = match ocr.format {
+                                OcrFormat::Alto => mets::alto_page(&page, (img.width(), img.height()), &out_path),
+                                _ => page.text,
+                            };
+                            write_text_sidecar(app, &out_path, ocr.format, &text)
+                        }),
+                        _ => Ok(()),
+                    });
+                match saved {
+                    Ok(()) => {
+                        info!("Successfully saved: {}", out_path);
+                        let variants = match &source {
+                            Some(source) => {
+                                emit("variants", true, None);
+                                export_variants(app, source, options, &out_path, note.as_deref())
+                            }
+                            None => Vec::new(),
+                        };
+                        let res = ProcessResult {
+                            success: true,
+                            path: out_path,
+                            error: None,
+                            deskew_angle: report.deskew_angle,
+                            crop_marks: report.crop_marks.clone(),
+                            blank: blank.is_some(),
+                            pages: Vec::new(),
+                            variants,
+                        };
+                        (res, "completed")
+                    },
+                    Err(e) => {
+                        error!("Failed to save {}: {}", out_path, e);
+                        let res = ProcessResult {
+                            success: false,
+                            path: out_path,
+                            error: Some(e.to_string()),
+                            deskew_angle: report.deskew_angle,
+                            crop_marks: report.crop_marks.clone(),
+                            blank: blank.is_some(),
+                            pages: Vec::new(),
+                            variants: Vec::new(),
+                        };
+                        (res, "failed")
+                    },
+                }
+            };
+
+            let Some(split) = options.page_split.as_ref() else {
+                let (res, stage) = export(img, out_path, &options);
+                emit(stage, res.success, res.error.clone());
+                return res;
+            };
+            let page_paths = match page_paths(&out_path) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    error!("{}", e);
+                    emit("failed", false, Some(e.clone()));
+                    return ProcessResult { success: false, path: out_path, error: Some(e), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+                }
+            };
+            emit("splitting", true, None);
+            let (left, right) = image_ops::split_spread(&img, split.search, split.overlap);
+            // Redaction boxes are in the spread's pixels; the right page starts part way in
+            let right_options = page_options(&options, img.width() - right.width());
+            drop(img);
+            let results: Vec<(ProcessResult, &str)> = [(left, &options), (right, &right_options)]
+                .into_iter()
+                .zip(page_paths)
+                .map(|((page, options), page_path)| export(page, page_path, options))
+                .collect();
+
+            let failed = results.iter().map(|(res, _)| res).find(|res| !res.success);
+            let pages: Vec<String> =
+                results.iter().filter(|(_, stage)| *stage == "completed").map(|(res, _)| res.path.clone()).collect();
+            let stage = match (failed, pages.is_empty()) {
+                _ if results.iter().any(|(_, stage)| *stage == "cancelled") => "cancelled",
+                (Some(_), _) => "failed",
+                (None, true) => "skipped",
+                (None, false) => "completed",
+            };
+            let res = ProcessResult {
+                success: failed.is_none(),
+                path: pages.first().cloned().unwrap_or(out_path),
+                error: failed.and_then(|res| res.error.clone()),
+                deskew_angle: results.iter().find_map(|(res, _)| res.deskew_angle),
+                crop_marks: results.iter().find_map(|(res, _)| res.crop_marks.clone()),
+                blank: results.iter().any(|(res, _)| res.blank),
+                pages,
+                variants: results.iter().flat_map(|(res, _)| res.variants.clone()).collect(),
+            };
+            emit(stage, res.success, res.error.clone());
+            res
+        }
+        Err(e) => {
+            error!("Failed to open {}: {}", path, e);
+            let res = ProcessResult {
+                success: false,
+                path: out_path,
+                error: Some(e.clone()),
+                deskew_angle: None,
+                crop_marks: None,
+                blank: false,
+                pages: Vec::new(),
+                variants: Vec::new(),
+            };
+            emit("failed", false, Some(e));
+            res
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FileStatus {
+    pub path: String,
+    #[serde(flatten)]
+    pub state: FileState,
+}
+
+/// Edited/exported/failed flags of source files, for the grid's badges.
+#[tauri::command]
+pub fn get_file_states(catalog: State<'_, Catalog>, paths: Vec<String>) -> Vec<FileStatus> {
+    let states = catalog.file_states(&paths);
+    paths.into_iter().zip(states).map(|(path, state)| FileStatus { path, state }).collect()
+}
+
+/// Flags source files as edited, or clears the flag when their settings are reset.
+#[tauri::command]
+pub fn set_edited(catalog: State<'_, Catalog>, paths: Vec<String>, edited: bool) -> Result<(), String> {
+    catalog.set_edited(&paths, edited)
+}
+
+/// Records the outcome of processing `path` in the catalog, in memory until flushed.
+fn record_export<R: Runtime>(app: &AppHandle<R>, path: &str, result: &ProcessResult) {
+    if let Some(catalog) = app.try_state::<Catalog>() {
+        catalog.record_export(path, if result.success { None } else { Some(result.error.as_deref().unwrap_or("Processing failed")) });
+    }
+}
+
+fn flush_catalog<R: Runtime>(app: &AppHandle<R>) {
+    if let Err(e) = app.try_state::<Catalog>().map_or(Ok(()), |catalog| catalog.flush()) {
+        error!("{}", e);
+    }
+}
+
+/// `name_L.ext` and `name_R.ext` beside `out_path`, for the pages of a spread.
+fn page_paths(out_path: &str) -> Result<[String; 2], String> {
+    if storage::is_uri(out_path) {
+        return Err(format!("Split pages need a file path output, not a URI: {}", out_path));
+    }
+    let out = std::path::Path::new(out_path);
+    let stem = out.file_stem().ok_or_else(|| format!("Invalid output path: {}", out_path))?.to_string_lossy();
+    let ext = out.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    Ok(["_L", "_R"].map(|suffix| out.with_file_name(format!("{}{}{}", stem, suffix, ext)).to_string_lossy().to_string()))
+}
+
+/// Renders and saves every variant of `options` from the decoded `source`. Each
+/// variant is tried even when an earlier one failed.
+fn export_variants<R: Runtime>(
+    app: &AppHandle<R>,
+    source: &DynamicImage,
+    options: &ProcessOptions,
+    out_path: &str,
+    note: Option<&str>,
+) -> Vec<VariantResult> {
+    let low_memory = app.state::<MemorySettings>().is_low();
+    // Variants that only differ in size and overlays share the slow leading stages
+    let mut checkpoint = None;
+    let mut export = |spec: &VariantSpec, path: &str| -> Result<(), String> {
+        if !storage::is_allowed(app, path) {
+            return Err(format!("Permission denied (write): {}", path));
+        }
+        if let Some(dir) = std::path::Path::new(path).parent().filter(|_| !storage::is_uri(path)) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let mut variant = spec.options(options);
+        if let Some(resize) = variant.resize.as_mut() {
+            resize.early = low_memory;
+        }
+        let (img, _) = image_ops::apply_filters_cached(source, &variant, &mut checkpoint);
+        storage::save_output(app, &img, path, &variant.output)?;
+        match note {
+            Some(note) => embed_note(path, note),
+            None => Ok(()),
+        }
+    };
+    options
+        .variants
+        .iter()
+        .map(|spec| {
+            let path = match spec.path(out_path) {
+                Ok(path) => path,
+                Err(e) => {
+                    error!("{}", e);
+                    return VariantResult { success: false, path: String::new(), error: Some(e) };
+                }
+            };
+            match export(spec, &path) {
+                Ok(()) => {
+                    info!("Successfully saved variant: {}", path);
+                    VariantResult { success: true, path, error: None }
+                }
+                Err(e) => {
+                    error!("Failed to save variant {}: {}", path, e);
+                    VariantResult { success: false, path, error: Some(e) }
+                }
+            }
+        })
+        .collect()
+}
+
+/// `<folder name>.tif` next to `out_path`, for the pages of the input `folder`.
+fn multipage_path(out_path: &str, folder: &std::path::Path) -> Result<String, String> {
+    if storage::is_uri(out_path) {
+        return Err(format!("Multi-page TIFFs need a file path output, not a URI: {}", out_path));
+    }
+    let name = folder.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "pages".to_string());
+    Ok(std::path::Path::new(out_path).with_file_name(format!("{}.tif", name)).to_string_lossy().to_string())
+}
+
+/// Where a blank page's output goes under `BlankPolicy::Move`; creates the folder.
+fn blank_destination<R: Runtime>(app: &AppHandle<R>, out_path: &str, folder: &str) -> Result<String, String> {
+    if storage::is_uri(out_path) {
+        info!("Blank page written in place, URI outputs cannot be redirected: {}", out_path);
+        return Ok(out_path.to_string());
+    }
+    let out = std::path::Path::new(out_path);
+    let dir = out.parent().unwrap_or(std::path::Path::new("")).join(folder);
+    let moved = dir.join(out.file_name().ok_or_else(|| format!("Invalid output path: {}", out_path))?);
+    let moved = moved.to_string_lossy().to_string();
+    if !storage::is_allowed(app, &moved) {
+        return Err(format!("Permission denied (write): {}", moved));
+    }
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(moved)
+}
+
+/// Embeds a catalog note into a finished export; URI outputs are skipped, like sidecars.
+fn embed_note(out_path: &str, note: &str) -> Result<(), String> {
+    if storage::is_uri(out_path) {
+        return Ok(());
+    }
+    if metadata::embed_description(out_path, note)? == SyncStatus::Unsupported {
+        info!("Note not embedded, format has no XMP support here: {}", out_path);
+    }
+    Ok(())
+}
+
+/// Error of files a cancelled batch stopped partway through.
+const CANCELLED: &str = "Cancelled";
+/// How often a paused batch checks whether it was resumed.
+const PAUSE_POLL_MS: u64 = 200;
+
+/// Sends a progress event to the window that started the job only, so a second
+/// window (e.g. a loupe next to the grid) never sees another window's progress.
+fn emit_progress<R: Runtime>(app: &AppHandle<R>, window: &str, payload: ProgressPayload) {
+    let _ = app.emit_to(EventTarget::webview_window(window), "process-progress", payload);
+}
+
+/// Processes a single image file with the same progress events and catalog updates as
+/// a batch of one. Runs off the main thread, so the UI stays responsive meanwhile.
+#[tauri::command]
+pub async fn process_image(
+    app: AppHandle,
+    window: WebviewWindow,
+    path: String,
+    out_path: String,
+    mut options: ProcessOptions,
+) -> ProcessResult {
+    let failed = |out_path: String, e: String| ProcessResult { success: false, path: out_path, error: Some(e), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+    if let Err(e) = check_supported(&options).and_then(|_| load_assets(&app, &mut options)) {
+        error!("{}", e);
+        emit_progress(&app, window.label(), ProgressPayload { path, success: false, error: Some(e.clone()), progress: 100.0, stage: "failed".to_string() });
+        return failed(out_path, e);
+    }
+    let (label, out_h) = (window.label().to_string(), out_path.clone());
+    execution::run_blocking(move || {
+        let result = process_image_inner(&app, &label, path.clone(), out_path, options, 100.0, &Halt::default());
+        record_export(&app, &path, &result);
+        flush_catalog(&app);
+        result
+    })
+    .await
+    .unwrap_or_else(|e| failed(out_h, e))
+}
+
+/// Samples a verification run before committing to the whole batch.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct VerificationOptions {
+    /// Share of the batch processed first, in percent; at least one file is sampled.
+    pub sample_percent: f32,
+    /// Fixes the sample, e.g. to re-check the same files after changing a setting.
+    pub seed: Option<u64>,
+}
+
+impl Default for VerificationOptions {
+    fn default() -> Self {
+        Self { sample_percent: 5.0, seed: None }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BulkOutcome {
+    /// Input paths processed by this call.
+    pub processed: Vec<String>,
+    /// Set when a verification run is holding the rest of the batch; pass it to
+    /// `continue_batch` or `cancel_batch`.
+    pub batch_id: Option<u64>,
+    pub remaining: usize,
+    /// Separator sheets found with `ProcessOptions::separation`, in batch order.
+    pub separators: Vec<SeparatorPage>,
+    /// The id `cancel_bulk` took while the batch ran.
+    pub job_id: Option<u64>,
+    /// Stopped by `cancel_bulk`; `processed` then lists only the files that finished.
+    pub cancelled: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SeparatorPage {
+    pub path: String,
+    /// The value files after it were routed by.
+    pub value: String,
+}
+
+/// Core bulk processing logic with CPU-optimized concurrency.
+///
+/// With `verification`, only a random sample is processed; the rest waits for
+/// `continue_batch` so the results can be reviewed first.
+#[tauri::command]
+pub async fn process_bulk(
+    app: AppHandle,
+    window: WebviewWindow,
+    files: Vec<(String, String)>,
+    mut options: ProcessOptions,
+    verification: Option<VerificationOptions>,
+) -> Result<BulkOutcome, String> {
+    check_supported(&options)?;
+    load_assets(&app, &mut options)?;
+    let options = {
+        let app_h = app.clone();
+        let files_h = files.clone();
+        execution::run_blocking(move || lock_batch(&app_h, &files_h, options))
+            .await??
+    };
+
+    let (files, separators) = separate_files(&app, files, &options).await?;
+
+    // Numbered in batch order, so stamps keep their sequence across a verification run
+    let files: Vec<(usize, (String, String))> = files.into_iter().enumerate().collect();
+    if options.multipage.is_some() && verification.is_some() {
+        return Err("Verification runs cannot assemble multi-page TIFFs".to_string());
+    }
+    let Some(verification) = verification else {
+        let (job_id, processed, unstarted) = run_cancellable(&app, window.label(), files, &options, None).await;
+        let outcome = BulkOutcome { processed, batch_id: None, remaining: unstarted.len(), separators, job_id: Some(job_id), cancelled: !unstarted.is_empty() };
+        return Ok(finish_cancellable(&app, window.label(), outcome));
+    };
+
+    let seed = verification.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+    });
+    let sample = verification::sample_indices(files.len(), verification.sample_percent, seed);
+    let (mut sampled, mut held) = (Vec::new(), Vec::new());
+    for file in files {
+        if sample.binary_search(&file.0).is_ok() {
+            sampled.push(file);
+        } else {
+            held.push(file);
+        }
+    }
+    info!("Verification run: {} sampled, {} held", sampled.len(), held.len());
+
+    let (job_id, processed, unstarted) = run_cancellable(&app, window.label(), sampled, &options, None).await;
+    // A cancelled sample holds nothing back
+    let cancelled = !unstarted.is_empty();
+    let remaining = if cancelled { unstarted.len() + held.len() } else { held.len() };
+    let batch_id = (remaining > 0 && !cancelled)
+        .then(|| app.state::<PendingBatches>().hold(window.label(), HeldBatch { files: held, options }));
+    let outcome = BulkOutcome { processed, batch_id, remaining, separators, job_id: Some(job_id), cancelled };
+    Ok(finish_cancellable(&app, window.label(), outcome))
+}
+
+/// Processes the files a verification run held back, once its sample was approved.
+#[tauri::command]
+pub async fn continue_batch(app: AppHandle, window: WebviewWindow, batch_id: u64) -> Result<BulkOutcome, String> {
+    let batch = app.state::<PendingBatches>().take(window.label(), batch_id)?;
+    let (job_id, processed, unstarted) = run_cancellable(&app, window.label(), batch.files, &batch.options, None).await;
+    let outcome = BulkOutcome { processed, batch_id: None, remaining: unstarted.len(), separators: Vec::new(), job_id: Some(job_id), cancelled: !unstarted.is_empty() };
+    Ok(finish_cancellable(&app, window.label(), outcome))
+}
+
+/// Runs `files` as a job `cancel_bulk` can stop, announcing its id with a `bulk-started`
+/// event first. Returns the id, the input paths processed and the files left unfinished.
+async fn run_cancellable(
+    app: &AppHandle,
+    window: &str,
+    files: Vec<(usize, (String, String))>,
+    options: &ProcessOptions,
+    resumed: Option<JobCheckpoint>,
+) -> (u64, Vec<String>, Vec<(usize, (String, String))>) {
+    let (id, halt) = app.state::<RunningJobs>().start(window);
+    let _ = app.emit_to(EventTarget::webview_window(window), "bulk-started", id);
+    let unstarted = run_job(app, window, files.clone(), options, &halt, resumed).await;
+    app.state::<RunningJobs>().finish(id);
+    let processed = files
+        .iter()
+        .filter(|file| unstarted.binary_search_by_key(&file.0, |u| u.0).is_err())
+        .map(|(_, (in_p, _))| in_p.clone())
+        .collect();
+    (id, processed, unstarted)
+}
+
+/// Runs `files` as one multi-page document per folder or one output per file, as
+/// `options` asks, and returns the files left unfinished.
+async fn run_job(
+    app: &AppHandle,
+    window: &str,
+    files: Vec<(usize, (String, String))>,
+    options: &ProcessOptions,
+    halt: &Halt,
+    resumed: Option<JobCheckpoint>,
+) -> Vec<(usize, (String, String))> {
+    match options.multipage {
+        Some(multipage) => run_multipage(app, window, files, options, multipage, halt, resumed).await,
+        None => run_batch(app, window, files, options, halt, resumed).await,
+    }
+}
+
+/// Drops the separator sheets from `files` and routes the rest when `options` has a
+/// separation, returning the files to process and the separators found.
+async fn separate_files(
+    app: &AppHandle,
+    files: Vec<(String, String)>,
+    options: &ProcessOptions,
+) -> Result<(Vec<(String, String)>, Vec<SeparatorPage>), String> {
+    let Some(separation) = options.separation.clone() else {
+        return Ok((files, Vec::new()));
+    };
+    let app_h = app.clone();
+    execution::run_blocking(move || {
+        let mut files = files;
+        separate_batch(&app_h, &mut files, &separation).map(|separators| (files, separators))
+    })
+    .await?
+}
+
+/// Sends a cancelled job's partial summary to its window as a `bulk-cancelled` event.
+fn finish_cancellable(app: &AppHandle, window: &str, outcome: BulkOutcome) -> BulkOutcome {
+    if outcome.cancelled {
+        info!("Bulk job {:?} cancelled: {} files processed, {} not", outcome.job_id, outcome.processed.len(), outcome.remaining);
+        let _ = app.emit_to(EventTarget::webview_window(window), "bulk-cancelled", outcome.clone());
+    }
+    outcome
+}
+
+/// Cancels a running bulk job by the id its `bulk-started` event carried: no further
+/// file starts, and files in flight stop before their next pipeline stage. Returns
+/// whether the job was running.
+#[tauri::command]
+pub fn cancel_bulk(window: WebviewWindow, jobs: State<'_, RunningJobs>, job_id: u64) -> bool {
+    let cancelled = jobs.cancel(window.label(), job_id);
+    if cancelled {
+        info!("Bulk job {} cancelled", job_id);
+    }
+    cancelled
+}
+
+/// Pauses a running bulk job: files in flight finish, and the rest wait in memory
+/// for `resume_bulk`. Returns whether the job was running.
+#[tauri::command]
+pub fn pause_bulk(window: WebviewWindow, jobs: State<'_, RunningJobs>, job_id: u64) -> bool {
+    let paused = jobs.set_paused(window.label(), job_id, true);
+    if paused {
+        info!("Bulk job {} paused", job_id);
+    }
+    paused
+}
+
+/// Resumes a paused bulk job from its next unprocessed file. Returns whether the job
+/// was running.
+#[tauri::command]
+pub fn resume_bulk(window: WebviewWindow, jobs: State<'_, RunningJobs>, job_id: u64) -> bool {
+    let resumed = jobs.set_paused(window.label(), job_id, false);
+    if resumed {
+        info!("Bulk job {} resumed", job_id);
+    }
+    resumed
+}
+
+fn jobs_dir<R: Runtime>(app: &AppHandle<R>) -> Result<std::path::PathBuf, String> {
+    Ok(app.path().app_data_dir().map_err(|e| e.to_string())?.join(jobs::DIR_NAME))
+}
+
+/// Large jobs that stopped before finishing (e.g. on power loss), with their progress.
+#[tauri::command]
+pub fn interrupted_jobs(app: AppHandle) -> Result<Vec<JobSummary>, String> {
+    Ok(jobs::list(&jobs_dir(&app)?))
+}
+
+/// Lists the supported images under `path`, emitting `scan-progress` with the running
+/// totals (at most every `scan::PROGRESS_INTERVAL_MS`, and once when done) so large
+/// archives show progress while they are walked.
+#[tauri::command]
+pub async fn scan_folder(app: AppHandle, window: WebviewWindow, path: String) -> Result<FolderScan, String> {
+    if storage::is_uri(&path) || !storage::is_allowed(&app, &path) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    execution::run_blocking(move || {
+        let interval = std::time::Duration::from_millis(scan::PROGRESS_INTERVAL_MS);
+        let mut last = std::time::Instant::now();
+        let found = scan::scan(std::path::Path::new(&path), &mut |stats| {
+            if stats.done || last.elapsed() >= interval {
+                last = std::time::Instant::now();
+                let _ = app.emit_to(EventTarget::webview_window(window.label()), "scan-progress", stats);
+            }
+        })?;
+        info!("Scanned {}: {} files, {} bytes", path, found.stats.files, found.stats.bytes);
+        Ok(found)
+    })
+    .await?
+}
+
+/// Resumes an interrupted job with the files it had not finished. `options` must be the
+/// ones the job was started with; what the batch locked at its start is reused.
+#[tauri::command]
+pub async fn resume_job(app: AppHandle, window: WebviewWindow, job_id: String, mut options: ProcessOptions) -> Result<BulkOutcome, String> {
+    let job = JobCheckpoint::load(&jobs_dir(&app)?, &job_id)?;
+    check_supported(&options)?;
+    load_assets(&app, &mut options)?;
+    let options = match &job.locks {
+        Some(locks) => locks.apply(options),
+        // Checkpoints from before locks were kept are locked again over their files
+        None => {
+            let app_h = app.clone();
+            let files: Vec<(String, String)> = job.files.iter().map(|(_, file)| file.clone()).collect();
+            execution::run_blocking(move || lock_batch(&app_h, &files, options))
+                .await??
+        }
+    };
+    if options_hash(&options) != job.options_hash {
+        return Err("These options differ from the ones the job was started with".to_string());
+    }
+    let remaining = job.remaining();
+    info!("Resuming job {}: {} of {} files left", job.id, remaining.len(), job.files.len());
+    let (job_id, processed, unstarted) = run_cancellable(&app, window.label(), remaining, &options, Some(job)).await;
+    let outcome = BulkOutcome { processed, batch_id: None, remaining: unstarted.len(), separators: Vec::new(), job_id: Some(job_id), cancelled: !unstarted.is_empty() };
+    Ok(finish_cancellable(&app, window.label(), outcome))
+}
+
+/// Deletes an interrupted job's checkpoint; returns whether it existed.
+#[tauri::command]
+pub fn discard_job(app: AppHandle, job_id: String) -> Result<bool, String> {
+    let dir = jobs_dir(&app)?;
+    Ok(JobCheckpoint::load(&dir, &job_id).map(|job| job.remove()).is_ok())
+}
+
+/// Discards the files a verification run held back; returns whether the batch existed.
+#[tauri::command]
+pub fn cancel_batch(window: WebviewWindow, batches: State<'_, PendingBatches>, batch_id: u64) -> bool {
+    let cancelled = batches.take(window.label(), batch_id).is_ok();
+    if cancelled {
+        info!("Verification batch {} cancelled", batch_id);
+    }
+    cancelled
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ScheduleOutcome {
+    pub schedule_id: u64,
+    #[serde(flatten)]
+    pub outcome: Option<BulkOutcome>,
+    pub error: Option<String>,
+}
+
+/// Queues a batch to run inside `schedule` and returns its id at once. Files still
+/// unstarted at the stop time are held for `continue_batch`; the window receives a
+/// `batch-finished` event with the outcome.
+#[tauri::command]
+pub async fn schedule_batch(
+    app: AppHandle,
+    window: WebviewWindow,
+    files: Vec<(String, String)>,
+    mut options: ProcessOptions,
+    schedule: BatchWindow,
+) -> Result<u64, String> {
+    schedule.validate(scheduler::now())?;
+    check_supported(&options)?;
+    load_assets(&app, &mut options)?;
+    let owner = window.label().to_string();
+    let (id, halt) = app.state::<Scheduler>().add(&owner, files.len(), schedule);
+    info!("Batch {} scheduled: {} files, window {:?}", id, files.len(), schedule);
+
+    tauri::async_runtime::spawn(async move {
+        while !schedule.started(scheduler::now()) && !halt.is_cancelled() {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        if halt.is_cancelled() {
+            info!("Scheduled batch {} cancelled before it started", id);
+            return;
+        }
+        app.state::<Scheduler>().set_running(id);
+
+        // White balance is locked at start time, against the files as they are then
+        let locked = {
+            let (app_h, files_h) = (app.clone(), files.clone());
+            execution::run_blocking(move || lock_batch(&app_h, &files_h, options))
+                .await
+                .and_then(|locked| locked)
+        };
+        let prepared = match locked {
+            Ok(options) => separate_files(&app, files, &options).await.map(|(files, separators)| (options, files, separators)),
+            Err(e) => Err(e),
+        };
+        let outcome = match prepared {
+            Ok((options, files, separators)) => {
+                let files: Vec<(usize, (String, String))> = files.into_iter().enumerate().collect();
+                let unstarted = run_job(&app, &owner, files.clone(), &options, &halt, None).await;
+                let processed = files
+                    .iter()
+                    .filter(|file| unstarted.binary_search_by_key(&file.0, |u| u.0).is_err())
+                    .map(|(_, (in_p, _))| in_p.clone())
+                    .collect();
+                let remaining = unstarted.len();
+                let batch_id = (remaining > 0 && !halt.is_cancelled()).then(|| {
+                    info!("Batch {} stopped at its deadline, {} files held", id, remaining);
+                    app.state::<PendingBatches>().hold(&owner, HeldBatch { files: unstarted, options })
+                });
+                ScheduleOutcome { schedule_id: id, outcome: Some(BulkOutcome { processed, batch_id, remaining, separators, job_id: None, cancelled: halt.is_cancelled() }), error: None }
+            }
+            Err(e) => {
+                error!("Scheduled batch {} failed to start: {}", id, e);
+                ScheduleOutcome { schedule_id: id, outcome: None, error: Some(e) }
+            }
+        };
+        app.state::<Scheduler>().finish(id);
+        let _ = app.emit_to(EventTarget::webview_window(&owner), "batch-finished", outcome);
+    });
+    Ok(id)
+}
+
+/// Batches this window has scheduled that have not finished yet.
+#[tauri::command]
+pub fn list_scheduled_batches(window: WebviewWindow, scheduler: State<'_, Scheduler>) -> Vec<ScheduledBatch> {
+    scheduler.list(window.label())
+}
+
+/// Cancels a scheduled batch; a running one stops the files it has started before
+/// their next pipeline stage.
+#[tauri::command]
+pub fn cancel_scheduled_batch(window: WebviewWindow, scheduler: State<'_, Scheduler>, schedule_id: u64) -> bool {
+    let cancelled = scheduler.cancel(window.label(), schedule_id);
+    if cancelled {
+        info!("Scheduled batch {} cancelled", schedule_id);
+    }
+    cancelled
+}
+
+/// Processes `files`, starting none once `halt` is reached; returns those never
+/// started, or stopped partway by a cancel, in batch order.
+async fn run_batch(
+    app: &AppHandle,
+    window: &str,
+    files: Vec<(usize, (String, String))>,
+    options: &ProcessOptions,
+    halt: &Halt,
+    resumed: Option<JobCheckpoint>,
+) -> Vec<(usize, (String, String))> {
+    let total = files.len() as f32;
+    let (mut job, journal) = open_job(app, &files, options, resumed);
+    let journal = journal.map(Arc::new);
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    // Sequential mode runs each file inline, so more than one in flight would only queue
+    let requested = if execution::is_sequential() { 1 } else { app.state::<MemorySettings>().concurrency(cores) };
+    let device = app.state::<DeviceState>();
+    device.sample();
+    let concurrency = device.concurrency(requested);
+    
+    info!("Starting bulk process with concurrency: {}", concurrency);
+    
+    let semaphore = Arc::new(Semaphore::new(requested.max(concurrency)));
+    // Follows the CPU temperature (and later shell reports) while the batch runs
+    let throttle = Arc::new(Throttle::new(concurrency));
+    let monitor = {
+        let (app_h, throttle_h) = (app.clone(), throttle.clone());
+        tauri::async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(device::THERMAL_INTERVAL)).await;
+                let device = app_h.state::<DeviceState>();
+                device.sample();
+                let limit = device.concurrency(requested);
+                if limit != throttle_h.limit() {
+                    info!("Thermal state {:?}, concurrency now {}", device.conditions().thermal, limit);
+                    throttle_h.set_limit(limit);
+                }
+            }
+        })
+    };
+    let (mut unstarted, mut written) = (Vec::new(), Vec::new());
+    // A window of CHUNK_SIZE spawned files is refilled as each one finishes, so permits
+    // never wait on a slow file from an earlier chunk and a huge batch is never spawned
+    // all at once
+    let mut queued = files.iter().cloned().enumerate();
+    let mut running = tokio::task::JoinSet::new();
+    let (mut finished, mut since_checkpoint) = (Vec::new(), 0);
+    loop {
+        while running.len() < jobs::CHUNK_SIZE {
+            let Some((i, file)) = queued.next() else { break };
+            let (index, (in_p, out_p)) = file.clone();
+            let app_h = app.clone();
+            let window_h = window.to_string();
+            let mut options_h = options.clone();
+            if let Some(stamp) = options_h.text_stamp.as_mut() {
+                stamp.index = index + 1;
+            }
+            let sem_h = semaphore.clone();
+            let progress = ((i + 1) as f32 / total) * 100.0;
+
+            let halt_h = halt.clone();
+            let throttle_h = throttle.clone();
+            let journal_h = journal.clone();
+
+            running.spawn(async move {
+                let _permit = sem_h.acquire().await.unwrap();
+                let _slot = throttle_h.enter().await;
+                while halt_h.is_paused() {
+                    tokio::time::sleep(std::time::Duration::from_millis(PAUSE_POLL_MS)).await;
+                }
+                if halt_h.reached() {
+                    if halt_h.is_cancelled() {
+                        emit_progress(&app_h, &window_h, ProgressPayload { path: in_p, success: false, error: Some(CANCELLED.to_string()), progress, stage: "cancelled".to_string() });
+                    }
+                    return Err((i, file));
+                }
+                let file_h = file.clone();
+                execution::run_blocking(move || {
+                    let result = process_image_inner(&app_h, &window_h, in_p.clone(), out_p, options_h, progress, &halt_h);
+                    // Stopped files are left unrecorded, as if never started
+                    if halt_h.is_cancelled() && result.error.as_deref() == Some(CANCELLED) {
+                        return Err((i, file_h));
+                    }
+                    record_export(&app_h, &in_p, &result);
+                    // Failed files stay out of the journal, so a resume tries them again
+                    if let Some(journal) = journal_h.as_ref().filter(|_| result.success) {
+                        if let Err(e) = journal.record(index) {
+                            error!("{}", e);
+                        }
+                    }
+                    Ok((i, index, in_p, result))
+                }).await.unwrap_or(Err((i, file)))
+            });
+        }
+        let Some(joined) = running.join_next().await else { break };
+        match joined {
+            Ok(Err(file)) => unstarted.push(file),
+            Ok(Ok((i, index, in_p, result))) if result.success => {
+                finished.push(index);
+                if options.fixity.is_some() {
+                    written.push((i, in_p, result));
+                }
+            }
+            _ => {}
+        }
+        // The checkpoint catches up with the journal every CHUNK_SIZE files
+        since_checkpoint += 1;
+        if let Some(job) = job.as_mut().filter(|_| since_checkpoint == jobs::CHUNK_SIZE) {
+            job.advance(&std::mem::take(&mut finished));
+            flush_catalog(app);
+            if let Err(e) = job.save() {
+                error!("{}", e);
+            }
+            since_checkpoint = 0;
+        }
+    }
+    // Files finish out of order; reports and manifests list them in batch order
+    unstarted.sort_by_key(|(i, _)| *i);
+    let unstarted: Vec<(usize, (String, String))> = unstarted.into_iter().map(|(_, file)| file).collect();
+    written.sort_by_key(|(i, _, _)| *i);
+    let written: Vec<(String, ProcessResult)> = written.into_iter().map(|(_, in_p, result)| (in_p, result)).collect();
+    monitor.abort();
+    flush_catalog(app);
+    update_fixity(app, written, options).await;
+    // Finished, or halted with the rest held in memory or cancelled: the checkpoint is done
+    if let Some(job) = &job {
+        job.remove();
+    }
+    
+    if unstarted.is_empty() {
+        info!("Bulk process completed successfully.");
+    } else {
+        info!("Bulk process stopped with {} files not started.", unstarted.len());
+    }
+    unstarted
 }
 
-#[derive(Serialize, Clone)]
-pub struct ProgressPayload {
-    pub path: String,
-    pub success: bool,
-    pub error: Option<String>,
-    pub progress: f32,
-    pub stage: String,
+/// The checkpoint of a job over `files`, or the `resumed` one, saved and with its
+/// journal open. Every job journals its finished files, for `resume_job` after a crash.
+fn open_job(
+    app: &AppHandle,
+    files: &[(usize, (String, String))],
+    options: &ProcessOptions,
+    resumed: Option<JobCheckpoint>,
+) -> (Option<JobCheckpoint>, Option<jobs::Journal>) {
+    let job = resumed.or_else(|| {
+        let dir = app.path().app_data_dir().ok()?.join(jobs::DIR_NAME);
+        let mut job = JobCheckpoint::new(&dir, files.to_vec(), options_hash(options));
+        job.locks = Some(BatchLocks::of(options));
+        Some(job)
+    });
+    let journal = job.as_ref().and_then(|job| job.save().and_then(|_| job.journal()).map_err(|e| error!("{}", e)).ok());
+    (job, journal)
 }
 
-/// Decodes a RAW file for a preview display in the UI.
-/// Returns a base64-encoded thumbnail string.
-#[tauri::command]
-pub fn decode_raw(app: AppHandle, path: String) -> Result<String, String> {
-    info!("Decoding RAW file for preview: {}", path);
+/// Adds the `written` files of a run to the fixity manifests, when the job keeps them.
+async fn update_fixity(app: &AppHandle, written: Vec<(String, ProcessResult)>, options: &ProcessOptions) {
+    if let Some(fixity) = options.fixity.clone().filter(|_| !written.is_empty()) {
+        let (app_h, options_h) = (app.clone(), options.clone());
+        match execution::run_blocking(move || write_fixity(&app_h, &written, &options_h, &fixity)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) | Err(e) => error!("Failed to write fixity manifests: {}", e),
+        }
+    }
+}
 
-    if !app.fs_scope().is_allowed(&path) {
-        error!("Permission denied: {}", path);
-        return Err(format!("Permission denied: {}", path));
+/// `run_batch` in multi-page mode, for `write_multipage`. A document is journaled once
+/// it is in place, so a resume assembles the unfinished ones again from the start.
+async fn run_multipage(
+    app: &AppHandle,
+    window: &str,
+    files: Vec<(usize, (String, String))>,
+    options: &ProcessOptions,
+    multipage: MultipageOptions,
+    halt: &Halt,
+    resumed: Option<JobCheckpoint>,
+) -> Vec<(usize, (String, String))> {
+    let (job, journal) = open_job(app, &files, options, resumed);
+    let (app_h, window_h, options_h, halt_h, files_h) = (app.clone(), window.to_string(), options.clone(), halt.clone(), files.clone());
+    let (mut unstarted, written) = execution::run_blocking(move || {
+        write_multipage(&app_h, &window_h, &files_h, &options_h, multipage, &halt_h, journal.as_ref())
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("Multi-page job failed: {}", e);
+        (files, Vec::new())
+    });
+    update_fixity(app, written, options).await;
+    if let Some(job) = &job {
+        job.remove();
     }
-    
-    if !std::path::Path::new(&path).exists() {
-        error!("RAW file not found: {}", path);
-        return Err(format!("File not found: {}", path));
+    unstarted.sort_by_key(|(index, _)| *index);
+    if unstarted.is_empty() {
+        info!("Multi-page job completed successfully.");
+    } else {
+        info!("Multi-page job stopped with {} files not assembled.", unstarted.len());
     }
+    unstarted
+}
 
-    let img = image_ops::decode_raw_to_image(&path)?;
-    let thumb = img.thumbnail(1200, 1200);
-    
-    let mut buffer = std::io::Cursor::new(Vec::new());
-    thumb.write_to(&mut buffer, image::ImageFormat::Jpeg).map_err(|e| e.to_string())?;
-    
-    let base64_str = general_purpose::STANDARD.encode(buffer.into_inner());
-    Ok(format!("data:image/jpeg;base64,{}", base64_str))
+/// Finds the separator sheets of a batch and routes the files after each one by its
+/// value. Sheets leave the batch unless they are kept; files before the first sheet
+/// keep their outputs.
+///
+/// Every file is decoded here and again when it is processed: the routes have to be
+/// known before the first output is written, and holding the decoded batch in memory
+/// until then is not an option for large jobs.
+fn separate_batch<R: Runtime>(
+    app: &AppHandle<R>,
+    files: &mut Vec<(String, String)>,
+    separation: &SeparationOptions,
+) -> Result<Vec<SeparatorPage>, String> {
+    let values: Vec<Option<String>> = files
+        .par_iter()
+        .map(|(in_p, _)| {
+            // Unreadable files are reported when the batch reaches them
+            if !storage::is_allowed(app, in_p) {
+                return None;
+            }
+            let img = storage::open_input(app, in_p).ok()?;
+            barcode::detect(&img).into_iter().find_map(|code| separation.separator_value(&code.value))
+        })
+        .collect();
+
+    let (mut routed, mut separators) = (Vec::with_capacity(files.len()), Vec::new());
+    let mut current: Option<String> = None;
+    for ((in_p, out_p), value) in std::mem::take(files).into_iter().zip(values) {
+        if let Some(value) = value {
+            info!("Separator {:?} at {}", value, in_p);
+            separators.push(SeparatorPage { path: in_p.clone(), value: value.clone() });
+            current = Some(value);
+            if !separation.keep_separators {
+                continue;
+            }
+        }
+        let out_p = match &current {
+            Some(value) => {
+                let out_p = separation.route(&out_p, value)?;
+                if !storage::is_allowed(app, &out_p) {
+                    return Err(format!("Permission denied (write): {}", out_p));
+                }
+                if let Some(dir) = std::path::Path::new(&out_p).parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+                }
+                out_p
+            }
+            None => out_p,
+        };
+        routed.push((in_p, out_p));
+    }
+    *files = routed;
+    Ok(separators)
 }
 
-/// Internal processing logic used by both single and bulk operations.
-pub fn process_image_inner<R: Runtime>(
+/// Adds the files of a run (outputs, their sidecars and, when asked, the sources) to
+/// the batch's fixity manifests. URI outputs cannot be read back and are left out.
+fn write_fixity<R: Runtime>(
     app: &AppHandle<R>,
-    path: String,
-    out_path: String,
-    options: ProcessOptions,
-    progress: f32,
-) -> ProcessResult {
-    let emit = |stage: &str, success: bool, error: Option<String>| {
-        let _ = app.emit("process-progress", ProgressPayload {
-            path: path.clone(),
-            success,
-            error,
-            progress,
-            stage: stage.to_string(),
-        });
+    written: &[(String, ProcessResult)],
+    options: &ProcessOptions,
+    fixity: &FixityOptions,
+) -> Result<(), String> {
+    let mut outputs = std::collections::BTreeSet::new();
+    for (_, result) in written {
+        for path in std::iter::once(&result.path).chain(&result.pages).chain(result.variants.iter().filter(|v| v.success).map(|v| &v.path)) {
+            if storage::is_uri(path) {
+                continue;
+            }
+            let path = std::path::PathBuf::from(path);
+            if options.output.stats_sidecar {
+                outputs.insert(path.with_extension("stats.json"));
+            }
+            if let Some(record) = &options.output.technical_metadata {
+                outputs.insert(path.with_extension(record.format.extension()));
+            }
+            if let Some(ocr) = &options.ocr {
+                outputs.insert(path.with_extension(ocr.format.extension()));
+            }
+            outputs.insert(path);
+        }
+    }
+    // Blank pages may have been skipped and sidecars refused; list what is on disk
+    let outputs: Vec<_> = outputs.into_iter().filter(|p| p.is_file()).collect();
+    let dir = match &fixity.dir {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => fixity::common_dir(&outputs).ok_or("No folder holds the outputs")?,
     };
+    let mut sets = vec![(false, outputs)];
+    if fixity.include_inputs {
+        let inputs = written.iter().map(|(in_p, _)| in_p).filter(|p| !storage::is_uri(p)).map(std::path::PathBuf::from);
+        sets.push((true, inputs.collect()));
+    }
 
-    if !app.fs_scope().is_allowed(&path) {
-        let err_msg = format!("Permission denied (read): {}", path);
-        error!("{}", err_msg);
-        emit("failed", false, Some(err_msg.clone()));
-        return ProcessResult {
-            success: false,
-            path: out_path,
-            error: Some(err_msg),
-        };
+    for (inputs, paths) in sets {
+        let digests = paths
+            .par_iter()
+            .map(|p| fixity::digest_file(p, &fixity.algorithms))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (a, &algorithm) in fixity.algorithms.iter().enumerate() {
+            let manifest = fixity::manifest_path(&dir, algorithm, inputs);
+            if !storage::is_allowed(app, &manifest.to_string_lossy()) {
+                return Err(format!("Permission denied (write): {}", manifest.display()));
+            }
+            let entries: Vec<_> = paths.iter().zip(&digests).map(|(p, d)| (fixity::entry_name(p, &dir), d[a].clone())).collect();
+            fixity::update_manifest(&manifest, &entries)?;
+        }
+        info!("Fixity manifests in {} updated with {} files", dir.display(), paths.len());
     }
+    Ok(())
+}
 
-    if !app.fs_scope().is_allowed(&out_path) {
-        let err_msg = format!("Permission denied (write): {}", out_path);
-        error!("{}", err_msg);
-        emit("failed", false, Some(err_msg.clone()));
-        return ProcessResult {
-            success: false,
-            path: out_path,
-            error: Some(err_msg),
-        };
+/// SHA-256 of the options a file was processed with. Loaded assets and per-file state
+/// are skipped when serializing. Hashed as JSON rather than `Debug` output, so the value
+/// holds across builds and a job checkpointed by one version resumes under the next.
+pub fn options_hash(options: &ProcessOptions) -> String {
+    let json = serde_json::to_vec(options).unwrap_or_default();
+    format!("{:x}", Sha256::digest(json))
+}
+
+fn write_stats_sidecar<R: Runtime>(app: &AppHandle<R>, img: &DynamicImage, out_path: &str, hash: String) -> Result<(), String> {
+    if storage::is_uri(out_path) {
+        return Err("Stats sidecars need a file path output".to_string());
+    }
+    let sidecar = std::path::Path::new(out_path).with_extension("stats.json");
+    if !storage::is_allowed(app, &sidecar.to_string_lossy()) {
+        return Err(format!("Permission denied (write): {}", sidecar.display()));
     }
+    let stats = image_ops::stats::compute(img, hash);
+    let json = serde_json::to_vec_pretty(&stats).map_err(|e| e.to_string())?;
+    storage::write_atomic(&sidecar, |tmp| std::fs::write(tmp, &json).map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e)))
+}
 
-    emit("decoding", true, None);
-    let path_lc = path.to_lowercase();
-    let img_res = if path_lc.ends_with(".arw") || 
-                   path_lc.ends_with(".cr2") || 
-                   path_lc.ends_with(".nef") || 
-                   path_lc.ends_with(".dng") {
-        image_ops::decode_raw_to_image(&path)
+/// Writes the output's technical metadata record as `<name>.mix.xml` or `<name>.mix.json`.
+/// PDFs are left without one: MIX describes a single raster image.
+fn write_mix_sidecar<R: Runtime>(
+    app: &AppHandle<R>,
+    source: &str,
+    out_path: &str,
+    options: &TechnicalMetadataOptions,
+    actions: &[String],
+) -> Result<(), String> {
+    if storage::is_uri(out_path) {
+        return Err("Technical metadata sidecars need a file path output".to_string());
+    }
+    let out = std::path::Path::new(out_path);
+    if out.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf")) {
+        return Ok(());
+    }
+    let sidecar = out.with_extension(options.format.extension());
+    if !storage::is_allowed(app, &sidecar.to_string_lossy()) {
+        return Err(format!("Permission denied (write): {}", sidecar.display()));
+    }
+    let record = mix::record(source, out_path, &options.algorithms, actions)?;
+    let data = match options.format {
+        RecordFormat::Xml => mix::to_xml(&record),
+        RecordFormat::Json => serde_json::to_string_pretty(&record).map_err(|e| e.to_string())?,
+    };
+    storage::write_atomic(&sidecar, |tmp| std::fs::write(tmp, &data).map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e)))
+}
+
+fn write_iiif_tiles<R: Runtime>(
+    app: &AppHandle<R>,
+    img: &DynamicImage,
+    out_path: &str,
+    options: &IiifOptions,
+    jpeg: &JpegOptions,
+) -> Result<(), String> {
+    if storage::is_uri(out_path) {
+        return Err("IIIF tiles need a file path output".to_string());
+    }
+    if options.base_url.is_empty() {
+        return Err("IIIF tiles need the base URL they are served under".to_string());
+    }
+    let out = std::path::Path::new(out_path);
+    let name = out.file_stem().ok_or_else(|| format!("Invalid output path: {}", out_path))?.to_string_lossy();
+    let dir = out.parent().unwrap_or(std::path::Path::new("")).join(&options.dir).join(name.as_ref());
+    if !storage::is_allowed(app, &dir.to_string_lossy()) {
+        return Err(format!("Permission denied (write): {}", dir.display()));
+    }
+    let id = format!("{}/{}", options.base_url.trim_end_matches('/'), name);
+    let tiles = iiif::write_tiles(img, &dir, &id, options.tile_size, jpeg)?;
+    info!("Wrote {} IIIF tiles: {}", tiles, dir.display());
+    Ok(())
+}
+
+/// Writes recognized text as `<name>.txt`, `<name>.hocr` or `<name>.alto.xml` next to the output.
+fn write_text_sidecar<R: Runtime>(app: &AppHandle<R>, out_path: &str, format: OcrFormat, text: &str) -> Result<(), String> {
+    if storage::is_uri(out_path) {
+        return Err("OCR sidecars need a file path output".to_string());
+    }
+    let sidecar = std::path::Path::new(out_path).with_extension(format.extension());
+    if !storage::is_allowed(app, &sidecar.to_string_lossy()) {
+        return Err(format!("Permission denied (write): {}", sidecar.display()));
+    }
+    storage::write_atomic(&sidecar, |tmp| std::fs::write(tmp, text).map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e)))
+}
+
+/// Resolves the text stamp template for `path`, dated by its capture time when the
+/// file records one and by its modification time otherwise, with the registered
+/// custom tokens.
+fn resolve_stamp<R: Runtime>(app: &AppHandle<R>, options: &mut ProcessOptions, path: &str) {
+    let Some(stamp) = options.text_stamp.as_mut() else {
+        return;
+    };
+    let captured = metadata::capture_time(path)
+        .and_then(|t| t.split_once(' ').map(|(d, t)| (d.replace(':', "-"), t.to_string())));
+    let (date, time) = captured.unwrap_or_else(|| {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let secs = modified.and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        image_ops::stamp::format_timestamp(secs)
+    });
+    let filename = if storage::is_uri(path) {
+        storage::file_name_hint(path)
     } else {
-        image::open(&path).map_err(|e| e.to_string())
+        std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string()
     };
+    let context = image_ops::stamp::StampContext { filename, index: stamp.index.max(1), date, time };
+    stamp.text = Some(match app.try_state::<TokenRegistry>() {
+        Some(tokens) => tokens.render(&stamp.template, &context),
+        None => image_ops::stamp::render_template(&stamp.template, &context),
+    });
+}
 
-    match img_res {
-        Ok(img) => {
-            emit("filtering", true, None);
-            let img = image_ops::apply_filters(img, &options);
-            
-            emit("saving", true, None);
-            match img.save(&out_path) {
-                Ok(_) => {
-                    info!("Successfully saved: {}", out_path);
-                    let res = ProcessResult {
-                        success: true,
-                        path: out_path,
-                        error: None,
-                    };
-                    emit("completed", true, None);
-                    res
-                },
-                Err(e) => {
-                    error!("Failed to save {}: {}", out_path, e);
-                    let res = ProcessResult {
-                        success: false,
-                        path: out_path,
-                        error: Some(e.to_string()),
-                    };
-                    emit("failed", false, Some(e.to_string()));
-                    res
-                },
+/// Completes a DPI resize from the source's tagged resolution when the job gave no
+/// physical size, and tags the output at the target density once it is known.
+fn resolve_density(options: &mut ProcessOptions, path: &str) {
+    let Some(ResizeSize::Dpi { dpi, source_dpi, width_mm }) = options.resize.as_mut().map(|r| &mut r.size) else {
+        return;
+    };
+    if source_dpi.is_none() && width_mm.is_none() {
+        *source_dpi = (!storage::is_uri(path)).then(|| submission::tagged_dpi(path)).flatten();
+    }
+    if source_dpi.is_none() && width_mm.is_none() {
+        info!("No resolution known for {}, DPI resize skipped", path);
+        return;
+    }
+    options.output.dpi.get_or_insert(*dpi);
+}
+
+/// `options` for the page of a spread starting `left` pixels into it, with the
+/// redaction boxes moved into the page's frame.
+fn page_options(options: &ProcessOptions, left: u32) -> ProcessOptions {
+    ProcessOptions { redaction: options.redaction.as_ref().map(|r| r.shifted(left)), ..options.clone() }
+}
+
+/// Gathers the redaction boxes of `path` into `boxes`: the job's, the ones it lists for
+/// the file and those of its sidecar.
+fn resolve_redaction<R: Runtime>(app: &AppHandle<R>, options: &mut ProcessOptions, path: &str) -> Result<(), String> {
+    let Some(redaction) = options.redaction.as_mut() else {
+        return Ok(());
+    };
+    let name = if storage::is_uri(path) {
+        storage::file_name_hint(path)
+    } else {
+        std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string()
+    };
+    let listed = redaction.regions.get(path).or_else(|| redaction.regions.get(&name)).cloned().unwrap_or_default();
+    redaction.boxes.extend(listed);
+    if redaction.sidecar && !storage::is_uri(path) {
+        let sidecar = std::path::Path::new(path).with_extension("redact.json");
+        if sidecar.exists() {
+            if !app.fs_scope().is_allowed(&sidecar) {
+                return Err(format!("Permission denied (read): {}", sidecar.display()));
             }
+            let data = std::fs::read(&sidecar).map_err(|e| format!("Failed to read {}: {}", sidecar.display(), e))?;
+            let boxes: Vec<RedactionBox> = serde_json::from_slice(&data).map_err(|e| format!("Invalid redaction sidecar {}: {}", sidecar.display(), e))?;
+            redaction.boxes.extend(boxes);
         }
-        Err(e) => {
-            error!("Failed to open {}: {}", path, e);
-            let res = ProcessResult {
-                success: false,
-                path: out_path,
-                error: Some(e.clone()),
+    }
+    Ok(())
+}
+
+/// Fills in the per-file parts of the options: a stable grain pattern unless the job
+/// fixed a seed, and whether negative inversion receives linear RAW data.
+fn seed_grain(options: &mut ProcessOptions, path: &str) {
+    if let Some(grain) = options.grain.as_mut().filter(|g| g.seed.is_none()) {
+        let name = std::path::Path::new(path).file_name().unwrap_or_default();
+        grain.seed = Some(image_ops::seed_from_name(&name.to_string_lossy()));
+    }
+    if let Some(negative) = options.invert_negative.as_mut() {
+        negative.linear_input = image_ops::is_raw_path(path);
+    }
+    for profile in options.variants.iter_mut().filter_map(|v| v.profile.as_deref_mut()) {
+        seed_grain(profile, path);
+    }
+}
+
+/// Processes `paths` in order and encodes the results as an H.264 MP4 at `fps`.
+/// Frames whose size differs from the first one are resized to match.
+#[tauri::command]
+pub async fn export_timelapse(
+    app: AppHandle,
+    window: WebviewWindow,
+    paths: Vec<String>,
+    mut options: ProcessOptions,
+    fps: f32,
+    out_mp4: String,
+) -> Result<(), String> {
+    if !(fps > 0.0 && fps <= 240.0) {
+        return Err(format!("Invalid frame rate: {}", fps));
+    }
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    // The encoder writes the video to a file path
+    if storage::is_uri(&out_mp4) || !storage::is_allowed(&app, &out_mp4) {
+        return Err(format!("Permission denied (write): {}", out_mp4));
+    }
+    check_supported(&options)?;
+    load_assets(&app, &mut options)?;
+
+    execution::run_blocking(move || {
+        // Lock modes keep the white balance from flickering between frames
+        let files: Vec<(String, String)> = paths.iter().map(|p| (p.clone(), out_mp4.clone())).collect();
+        let options = lock_batch(&app, &files, options)?;
+        encode_timelapse(&app, window.label(), &paths, &options, fps, &out_mp4)
+    })
+    .await?
+}
+
+#[cfg(feature = "timelapse")]
+fn encode_timelapse<R: Runtime>(
+    app: &AppHandle<R>,
+    window: &str,
+    paths: &[String],
+    options: &ProcessOptions,
+    fps: f32,
+    out_mp4: &str,
+) -> Result<(), String> {
+    let total = paths.len() as f32;
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let low_memory = app.state::<MemorySettings>().is_low();
+    let chunk = if low_memory { app.state::<MemorySettings>().concurrency(cores) } else { cores };
+    let mut encoder: Option<crate::timelapse::FfmpegEncoder> = None;
+    // Once the first frame fixes the output size, low-memory mode shrinks later frames before filtering
+    let size = std::sync::OnceLock::new();
+
+    // Frames are filtered a chunk at a time in parallel, then written in sequence order
+    for (c, batch) in paths.chunks(chunk).enumerate() {
+        let frames = batch
+            .par_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let mut options = options.clone();
+                seed_grain(&mut options, path);
+                if let Some(stamp) = options.text_stamp.as_mut() {
+                    stamp.index = c * chunk + i + 1;
+                }
+                resolve_stamp(app, &mut options, path);
+                resolve_redaction(app, &mut options, path)?;
+                check_assets(&options)?;
+                let img = storage::open_input(app, path)?;
+                let img = match size.get() {
+                    Some(&(w, h)) if low_memory && (img.width(), img.height()) != (w, h) => {
+                        img.resize_exact(w, h, image::imageops::FilterType::Triangle)
+                    }
+                    _ => img,
+                };
+                Ok(image_ops::apply_filters(img, &options).to_rgb8())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        for (i, frame) in frames.into_iter().enumerate() {
+            let encoder = match encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => {
+                    let _ = size.set(frame.dimensions());
+                    encoder.insert(crate::timelapse::FfmpegEncoder::start(out_mp4, fps, frame.width(), frame.height())?)
+                }
             };
-            emit("failed", false, Some(e));
-            res
+            let (width, height) = encoder.dimensions();
+            let frame = if frame.dimensions() == (width, height) {
+                frame
+            } else {
+                image::imageops::resize(&frame, width, height, image::imageops::FilterType::Triangle)
+            };
+            encoder.push(&frame)?;
+
+            let index = c * chunk + i;
+            emit_progress(app, window, ProgressPayload {
+                path: paths[index].clone(),
+                success: true,
+                error: None,
+                progress: (index + 1) as f32 / total * 100.0,
+                stage: "encoding".to_string(),
+            });
         }
     }
+
+    encoder.ok_or("No frames to encode")?.finish()?;
+    info!("Exported timelapse of {} frames to {}", paths.len(), out_mp4);
+    Ok(())
+}
+
+#[cfg(not(feature = "timelapse"))]
+fn encode_timelapse<R: Runtime>(
+    _app: &AppHandle<R>,
+    _window: &str,
+    _paths: &[String],
+    _options: &ProcessOptions,
+    _fps: f32,
+    _out_mp4: &str,
+) -> Result<(), String> {
+    Err("Timelapse export is not available in this build (enable the `timelapse` feature)".to_string())
 }
 
-/// Processes a single image file.
+/// Processes `paths` in order into one PDF with a page per image, or per side when
+/// splitting spreads. With OCR enabled the pages are searchable. Blank pages are left
+/// out when the blank page policy skips them.
 #[tauri::command]
-pub fn process_image(app: AppHandle, path: String, out_path: String, options: ProcessOptions) -> ProcessResult {
-    process_image_inner(&app, path, out_path, options, 100.0)
+pub async fn export_pdf(
+    app: AppHandle,
+    window: WebviewWindow,
+    paths: Vec<String>,
+    mut options: ProcessOptions,
+    out_pdf: String,
+) -> Result<(), String> {
+    if let Some(path) = paths.iter().find(|p| !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    if storage::is_uri(&out_pdf) || !storage::is_allowed(&app, &out_pdf) {
+        return Err(format!("Permission denied (write): {}", out_pdf));
+    }
+    check_supported(&options)?;
+    load_assets(&app, &mut options)?;
+
+    execution::run_blocking(move || {
+        let files: Vec<(String, String)> = paths.iter().map(|p| (p.clone(), out_pdf.clone())).collect();
+        let options = lock_batch(&app, &files, options)?;
+        write_combined_pdf(&app, window.label(), &paths, &options, &out_pdf)
+    })
+    .await?
 }
 
-/// Core bulk processing logic with CPU-optimized concurrency.
+/// Packages the outputs of a finished batch as a BagIt bag at `bag_dir`, ready for
+/// deposit in a repository.
 #[tauri::command]
-pub async fn process_bulk(app: AppHandle, files: Vec<(String, String)>, options: ProcessOptions) -> Result<(), String> {
+pub async fn create_bag(app: AppHandle, files: Vec<String>, bag_dir: String, options: BagOptions) -> Result<BagSummary, String> {
+    if let Some(path) = files.iter().find(|p| storage::is_uri(p) || !storage::is_allowed(&app, p)) {
+        return Err(format!("Permission denied (read): {}", path));
+    }
+    if storage::is_uri(&bag_dir) || !storage::is_allowed(&app, &bag_dir) {
+        return Err(format!("Permission denied (write): {}", bag_dir));
+    }
+    execution::run_blocking(move || {
+        let payload: Vec<std::path::PathBuf> = files.iter().map(std::path::PathBuf::from).collect();
+        let bag = bagit::create(std::path::Path::new(&bag_dir), &payload, &options)?;
+        info!("Bagged {} files ({} bytes) in {}", bag.files, bag.bytes, bag.path);
+        Ok(bag)
+    })
+    .await?
+}
+
+fn write_combined_pdf<R: Runtime>(
+    app: &AppHandle<R>,
+    window: &str,
+    paths: &[String],
+    options: &ProcessOptions,
+    out_pdf: &str,
+) -> Result<(), String> {
+    storage::write_atomic(std::path::Path::new(out_pdf), |tmp| {
+        let file = std::fs::File::create(tmp).map_err(|e| format!("Failed to create {}: {}", out_pdf, e))?;
+        let pdf = image_ops::encode::pdf::PdfWriter::new(std::io::BufWriter::new(file), &options.output)?;
+        write_pdf_pages(app, window, paths, options, pdf)
+    })?;
+    info!("Exported PDF of {} files to {}", paths.len(), out_pdf);
+    Ok(())
+}
+
+fn write_pdf_pages<R: Runtime, W: std::io::Write>(
+    app: &AppHandle<R>,
+    window: &str,
+    paths: &[String],
+    options: &ProcessOptions,
+    mut pdf: image_ops::encode::pdf::PdfWriter<W>,
+) -> Result<(), String> {
+    let total = paths.len() as f32;
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let low_memory = app.state::<MemorySettings>().is_low();
+    let chunk = if low_memory { app.state::<MemorySettings>().concurrency(cores) } else { cores };
+
+    // Files are filtered a chunk at a time in parallel, then written in batch order
+    for (c, batch) in paths.chunks(chunk).enumerate() {
+        let files = batch
+            .par_iter()
+            .enumerate()
+            .map(|(i, path)| render_pages(app, path, options, c * chunk + i, low_memory))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        for (i, pages) in files.into_iter().enumerate() {
+            for (page, output) in pages {
+                pdf.add_page(&page, &output, output.text_layer.as_deref().map_or(&[], |w| w.as_slice()))?;
+            }
+            let index = c * chunk + i;
+            emit_progress(app, window, ProgressPayload {
+                path: paths[index].clone(),
+                success: true,
+                error: None,
+                progress: (index + 1) as f32 / total * 100.0,
+                stage: "encoding".to_string(),
+            });
+        }
+    }
+
+    std::io::Write::flush(&mut pdf.finish()?).map_err(|e| e.to_string())
+}
+
+/// Runs the pipeline over the file at batch position `index` for a document export,
+/// returning its pages (two for a split spread, none for a skipped blank page) with the
+/// per-file output settings, OCR words included.
+fn render_pages<R: Runtime>(
+    app: &AppHandle<R>,
+    path: &str,
+    options: &ProcessOptions,
+    index: usize,
+    low_memory: bool,
+) -> Result<Vec<(DynamicImage, OutputOptions)>, String> {
+    let mut options = options.clone();
+    seed_grain(&mut options, path);
+    if let Some(stamp) = options.text_stamp.as_mut() {
+        stamp.index = index + 1;
+    }
+    resolve_stamp(app, &mut options, path);
+    resolve_density(&mut options, path);
+    resolve_redaction(app, &mut options, path)?;
+    check_assets(&options)?;
+    if let Some(resize) = options.resize.as_mut() {
+        resize.early = low_memory;
+    }
+    let img = storage::open_input(app, path)?;
+    let pages = match options.page_split.as_ref() {
+        Some(split) => {
+            let (left, right) = image_ops::split_spread(&img, split.search, split.overlap);
+            let right_options = page_options(&options, img.width() - right.width());
+            vec![(left, options.clone()), (right, right_options)]
+        }
+        None => vec![(img, options.clone())],
+    };
+    let skip_blank = options.blank_pages.as_ref().filter(|b| matches!(b.policy, BlankPolicy::Skip));
+    pages
+        .into_iter()
+        .filter(|(page, _)| !skip_blank.is_some_and(|b| image_ops::is_blank_page(page, b.max_ink)))
+        .map(|(page, options)| {
+            let (page, report) = image_ops::apply_filters_reported(page, &options, &mut |_, _| {});
+            let text_layer = report.text.transpose()?.map(|text| Arc::new(text.words));
+            Ok((page, OutputOptions { text_layer, ..options.output.clone() }))
+        })
+        .collect()
+}
+
+/// A file of a batch: its batch index, input and output.
+type BatchFile = (usize, (String, String));
+
+/// `process_bulk` in multi-page mode: renders each input folder's files in parallel
+/// chunks and appends their pages in batch order. A file that fails is left out of its
+/// document and reported like a failed file of a normal batch. Each document is written
+/// next to its target and renamed into place once complete; one that cannot be is
+/// dropped whole, its files failed, or returned as unstarted when the job was halted.
+/// Returns the unstarted files and, for fixity, the files written.
+fn write_multipage<R: Runtime>(
+    app: &AppHandle<R>,
+    window: &str,
+    files: &[(usize, (String, String))],
+    options: &ProcessOptions,
+    multipage: MultipageOptions,
+    halt: &Halt,
+    journal: Option<&jobs::Journal>,
+) -> (Vec<BatchFile>, Vec<(String, ProcessResult)>) {
+    // Folders in order of their first file; files keep batch order within a folder
+    let mut folders: Vec<(std::path::PathBuf, Vec<_>)> = Vec::new();
+    for file in files {
+        let (_, (in_p, _)) = file;
+        let folder = std::path::Path::new(in_p).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        match folders.iter_mut().find(|(f, _)| *f == folder) {
+            Some((_, group)) => group.push(file),
+            None => folders.push((folder, vec![file])),
+        }
+    }
     let total = files.len() as f32;
-    // Optimize concurrency: use 75% of logical cores for maximum throughput
-    let concurrency = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
-    let concurrency = (concurrency * 3 / 4).max(1); 
-    
-    info!("Starting bulk process with concurrency: {}", concurrency);
-    
-    let semaphore = Arc::new(Semaphore::new(concurrency));
-    let mut handles = Vec::new();
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let low_memory = app.state::<MemorySettings>().is_low();
+    let chunk = app.state::<MemorySettings>().concurrency(cores);
+    let mut done = 0;
+    let (mut unstarted, mut written) = (Vec::new(), Vec::new());
 
-    for (i, (in_p, out_p)) in files.into_iter().enumerate() {
-        let app_h = app.clone();
-        let options_h = options.clone();
-        let sem_h = semaphore.clone();
-        let progress = ((i + 1) as f32 / total) * 100.0;
-        
-        let handle = tokio::spawn(async move {
-            let _permit = sem_h.acquire().await.unwrap();
-            tokio::task::spawn_blocking(move || {
-                process_image_inner(&app_h, in_p, out_p, options_h, progress)
-            }).await.unwrap()
+    for (folder, group) in folders {
+        let (_, (_, first_out)) = group[0];
+        let mut results = Vec::new();
+        let assembled = multipage_path(first_out, &folder).and_then(|target| {
+            if !storage::is_allowed(app, &target) {
+                return Err(format!("Permission denied (write): {}", target));
+            }
+            storage::write_atomic(std::path::Path::new(&target), |tmp| {
+                let mut tiff = image_ops::encode::tiff::MultipageTiff::create(&tmp.to_string_lossy())?;
+                for batch in group.chunks(chunk) {
+                    while halt.is_paused() {
+                        std::thread::sleep(std::time::Duration::from_millis(PAUSE_POLL_MS));
+                    }
+                    if halt.reached() {
+                        return Err(CANCELLED.to_string());
+                    }
+                    let rendered: Vec<Result<Vec<(DynamicImage, OutputOptions)>, String>> = batch
+                        .par_iter()
+                        .map(|(index, (in_p, _))| {
+                            if !storage::is_allowed(app, in_p) {
+                                return Err(format!("Permission denied (read): {}", in_p));
+                            }
+                            render_pages(app, in_p, options, *index, low_memory)
+                        })
+                        .collect();
+                    for (file, pages) in batch.iter().zip(rendered) {
+                        let (_, (in_p, _)) = file;
+                        let (success, error) = match pages {
+                            Ok(pages) => {
+                                // A failed append leaves the file unusable, so it drops the document
+                                for (page, output) in &pages {
+                                    tiff.append(page, output, &multipage.page_options(page, output))?;
+                                }
+                                (true, None)
+                            }
+                            Err(e) => {
+                                error!("Failed to process {}: {}", in_p, e);
+                                (false, Some(e))
+                            }
+                        };
+                        done += 1;
+                        emit_progress(app, window, ProgressPayload {
+                            path: in_p.clone(),
+                            success,
+                            error: error.clone(),
+                            progress: done as f32 / total * 100.0,
+                            stage: if success { "completed" } else { "failed" }.to_string(),
+                        });
+                        let result = ProcessResult { success, path: target.clone(), error, deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+                        results.push((*file, result));
+                    }
+                }
+                info!("Assembled {} pages into {}", tiff.pages(), target);
+                tiff.finish()
+            })
         });
-        handles.push(handle);
+
+        match assembled {
+            Ok(()) => {
+                for ((index, (in_p, _)), result) in results {
+                    record_export(app, in_p, &result);
+                    // Failed files are recorded too: a resume would rewrite the whole document
+                    if let Some(journal) = journal {
+                        if let Err(e) = journal.record(*index) {
+                            error!("{}", e);
+                        }
+                    }
+                    if result.success && options.fixity.is_some() {
+                        written.push((in_p.clone(), result));
+                    }
+                }
+            }
+            Err(e) if e == CANCELLED => {
+                info!("Halted while assembling {}", folder.display());
+                for file in group {
+                    let (_, (in_p, _)) = file;
+                    if halt.is_cancelled() {
+                        emit_progress(app, window, ProgressPayload { path: in_p.clone(), success: false, error: Some(CANCELLED.to_string()), progress: done as f32 / total * 100.0, stage: "cancelled".to_string() });
+                    }
+                    unstarted.push(file.clone());
+                }
+            }
+            Err(e) => {
+                error!("Failed to assemble {}: {}", folder.display(), e);
+                for (_, (in_p, out_p)) in group {
+                    let result = ProcessResult { success: false, path: out_p.clone(), error: Some(e.clone()), deskew_angle: None, crop_marks: None, blank: false, pages: Vec::new(), variants: Vec::new() };
+                    record_export(app, in_p, &result);
+                    emit_progress(app, window, ProgressPayload { path: in_p.clone(), success: false, error: Some(e.clone()), progress: done as f32 / total * 100.0, stage: "failed".to_string() });
+                }
+            }
+        }
     }
-    
-    for handle in handles {
-        let _ = handle.await;
+    flush_catalog(app);
+    (unstarted, written)
+}
+
+/// Fails a job whose options need a stage this build leaves out, before any file is
+/// written, rather than exporting every page without it. Previews skip such stages.
+pub fn check_supported(options: &ProcessOptions) -> Result<(), String> {
+    if cfg!(not(feature = "ocr")) && options.ocr.is_some() {
+        return Err("OCR is not available in this build (enable the `ocr` feature)".to_string());
+    }
+    for profile in options.variants.iter().filter_map(|v| v.profile.as_deref()) {
+        check_supported(profile)?;
+    }
+    Ok(())
+}
+
+/// Loads file-backed pipeline assets (LUTs) once so per-file clones of the
+/// options share them instead of re-reading from disk for every image.
+fn load_assets<R: Runtime>(app: &AppHandle<R>, options: &mut ProcessOptions) -> Result<(), String> {
+    if let Some(lut) = options.lut.as_mut().filter(|l| l.table.is_none()) {
+        if !app.fs_scope().is_allowed(&lut.path) {
+            return Err(format!("Permission denied (read): {}", lut.path));
+        }
+        lut.table = Some(Arc::new(Lut3d::load(&lut.path)?));
+        info!("Loaded LUT: {}", lut.path);
+    }
+    if let Some(watermark) = options.watermark.as_mut().filter(|w| w.image.is_none()) {
+        if !app.fs_scope().is_allowed(&watermark.path) {
+            return Err(format!("Permission denied (read): {}", watermark.path));
+        }
+        watermark.image = Some(Arc::new(image_ops::open_image(&watermark.path)?.to_rgba8()));
+        info!("Loaded watermark: {}", watermark.path);
+    }
+    if let Some(stamp) = options.text_stamp.as_mut().filter(|s| s.font_data.is_none() && !s.template.is_empty()) {
+        if !app.fs_scope().is_allowed(&stamp.font) {
+            return Err(format!("Permission denied (read): {}", stamp.font));
+        }
+        stamp.font_data = Some(image_ops::stamp::load_font(&stamp.font)?);
+        info!("Loaded stamp font: {}", stamp.font);
+    }
+    for profile in options.variants.iter_mut().filter_map(|v| v.profile.as_deref_mut()) {
+        load_assets(app, profile)?;
+    }
+    Ok(())
+}
+
+/// Fails unless `load_assets` resolved the files the enabled stages read, so a file is
+/// never exported without its look.
+pub fn check_assets(options: &ProcessOptions) -> Result<(), String> {
+    if let Some(lut) = options.lut.as_ref().filter(|l| l.table.is_none()) {
+        return Err(format!("LUT not loaded: {}", lut.path));
+    }
+    if let Some(watermark) = options.watermark.as_ref().filter(|w| w.image.is_none()) {
+        return Err(format!("Watermark not loaded: {}", watermark.path));
+    }
+    if let Some(stamp) = options.text_stamp.as_ref().filter(|s| s.font_data.is_none() && !s.template.is_empty()) {
+        return Err(format!("Stamp font not loaded: {}", stamp.font));
+    }
+    for profile in options.variants.iter().filter_map(|v| v.profile.as_deref()) {
+        check_assets(profile)?;
     }
-    
-    info!("Bulk process completed successfully.");
     Ok(())
 }
+
+/// What `lock_batch` resolved for a job, kept in its checkpoint. A resumed job reuses
+/// it, since re-locking over the files it has left (or over a verification sample)
+/// would measure a different set, or point a reference index at another file.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct BatchLocks {
+    pub calibration: Option<CalibrationOptions>,
+    pub white_balance: WhiteBalance,
+    pub crop: Option<Crop>,
+}
+
+impl BatchLocks {
+    /// The locks of options `lock_batch` returned.
+    pub fn of(options: &ProcessOptions) -> Self {
+        Self { calibration: options.calibration.clone(), white_balance: options.white_balance.clone(), crop: options.crop }
+    }
+
+    /// `options` with the settings `lock_batch` would resolve replaced by these.
+    pub fn apply(&self, mut options: ProcessOptions) -> ProcessOptions {
+        if options.calibration.as_ref().is_some_and(|c| c.matrix.is_none()) {
+            options.calibration = self.calibration.clone();
+        }
+        if matches!(options.white_balance, WhiteBalance::LockReference { .. } | WhiteBalance::LockMedian) {
+            options.white_balance = self.white_balance.clone();
+        }
+        if matches!(options.crop, Some(Crop::Trim { uniform: true, .. })) {
+            options.crop = self.crop;
+        }
+        options
+    }
+}
+
+/// Resolves the settings a bulk job fixes once for all of its files: calibration, white
+/// balance and a uniform trim.
+fn lock_batch<R: Runtime>(app: &AppHandle<R>, files: &[(String, String)], options: ProcessOptions) -> Result<ProcessOptions, String> {
+    lock_trim(app, files, lock_white_balance(app, files, lock_calibration(app, files, options)?)?)
+}
+
+/// Replaces a uniform trim with the crop holding the trimmed content of every file,
+/// each measured as the crop stage sees it. Files that cannot be read are left out,
+/// and a batch where none could be measured fails.
+fn lock_trim<R: Runtime>(app: &AppHandle<R>, files: &[(String, String)], mut options: ProcessOptions) -> Result<ProcessOptions, String> {
+    let Some(Crop::Trim { tolerance, margin, uniform: true }) = options.crop else { return Ok(options) };
+    let boxes: Vec<(u32, u32, u32, u32)> = files
+        .par_iter()
+        .filter_map(|(in_p, _)| {
+            let measured = if storage::is_allowed(app, in_p) {
+                storage::open_input(app, in_p)
+                    .map(|img| image_ops::trim_bounds(&image_ops::apply_filters_before(img, &options, Stage::Crop), tolerance, margin))
+            } else {
+                Err(format!("Permission denied (read): {}", in_p))
+            };
+            match measured {
+                Ok(region) => region,
+                Err(e) => {
+                    error!("Skipping {} for the uniform trim: {}", in_p, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    let (x, y, width, height) = boxes
+        .into_iter()
+        .reduce(|(ax, ay, aw, ah), (bx, by, bw, bh)| {
+            let (x0, y0) = (ax.min(bx), ay.min(by));
+            (x0, y0, (ax + aw).max(bx + bw) - x0, (ay + ah).max(by + bh) - y0)
+        })
+        .ok_or("No file of the batch had content to measure the uniform trim on")?;
+    options.crop = Some(Crop::Rect { x, y, width, height });
+    info!("Locked batch trim: {:?}", options.crop);
+    Ok(options)
+}
+
+/// Resolves the calibration matrix from the batch's target capture, so every file gets
+/// the same correction.
+fn lock_calibration<R: Runtime>(
+    app: &AppHandle<R>,
+    files: &[(String, String)],
+    mut options: ProcessOptions,
+) -> Result<ProcessOptions, String> {
+    let Some(mut calibration) = options.calibration.take() else { return Ok(options) };
+    if calibration.matrix.is_none() {
+        let (target, _) = files
+            .get(calibration.target_index)
+            .ok_or_else(|| format!("Color target index {} out of range", calibration.target_index))?;
+        if !storage::is_allowed(app, target) {
+            return Err(format!("Permission denied (read): {}", target));
+        }
+        calibration.target = resolve_target(app, calibration.target)?;
+        let img = storage::open_input(app, target)?;
+        let found = calibration::calibrate(&img, &calibration.target, calibration.corners)
+            .map_err(|e| format!("Color target in {}: {}", target, e))?;
+        info!("Calibrated batch against {}: mean ΔE {:.1}, {:.1} corrected", target, found.delta_e_before, found.delta_e_after);
+        calibration.matrix = Some(found.matrix);
+    }
+    options.calibration = Some(calibration);
+    Ok(options)
+}
+
+/// `target` with an IT8 reference file read into the patch colors of its grid.
+fn resolve_target<R: Runtime>(app: &AppHandle<R>, target: ColorTarget) -> Result<ColorTarget, String> {
+    let ColorTarget::It8 { reference_file } = &target else { return Ok(target) };
+    if !storage::is_allowed(app, reference_file) {
+        return Err(format!("Permission denied (read): {}", reference_file));
+    }
+    let bytes = storage::read_bytes(app, reference_file)?;
+    let reference = calibration::it8_reference(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("{}: {}", reference_file, e))?;
+    Ok(ColorTarget::Grid { rows: calibration::IT8_ROWS, cols: calibration::IT8_COLS, reference })
+}
+
+/// Resolves the batch-level `Lock*` white balance modes into `Fixed` gains.
+///
+/// Estimation runs on downscaled copies since the estimators' statistics are stable
+/// under resizing, which keeps the extra decode pass for `LockMedian` affordable.
+fn lock_white_balance<R: Runtime>(
+    app: &AppHandle<R>,
+    files: &[(String, String)],
+    mut options: ProcessOptions,
+) -> Result<ProcessOptions, String> {
+    let estimate = |path: &str| -> Result<[f32; 3], String> {
+        if !storage::is_allowed(app, path) {
+            return Err(format!("Permission denied (read): {}", path));
+        }
+        let img = storage::open_input(app, path)?;
+        Ok(image_ops::estimate_gains(&img.thumbnail(512, 512), options.awb_method))
+    };
+
+    let gains = match options.white_balance {
+        WhiteBalance::LockReference { index } => {
+            let (reference, _) = files
+                .get(index)
+                .ok_or_else(|| format!("White balance reference index {} out of range", index))?;
+            estimate(reference)?
+        }
+        WhiteBalance::LockMedian => {
+            let samples: Vec<[f32; 3]> = files
+                .par_iter()
+                .filter_map(|(in_p, _)| match estimate(in_p) {
+                    Ok(gains) => Some(gains),
+                    Err(e) => {
+                        error!("Skipping {} for white balance median: {}", in_p, e);
+                        None
+                    }
+                })
+                .collect();
+            image_ops::median_gains(&samples)
+        }
+        _ => return Ok(options),
+    };
+
+    info!("Locked batch white balance gains: {:?}", gains);
+    options.white_balance = WhiteBalance::Fixed { gains };
+    Ok(options)
+}
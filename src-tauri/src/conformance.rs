@@ -0,0 +1,180 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Output Conformance
+ *
+ * Re-reads written files before delivery. Unlike the submission checks,
+ * which only look at headers, every image is decoded in full, so truncated
+ * or corrupt writes (a full disk, a dropped network share) are caught along
+ * with outputs whose size, bit depth or embedded profile differ from what
+ * the job asked for. JPEG 2000 files are decoded with OpenJPEG; PDFs are
+ * checked for their header and trailer only.
+ */
+use image::{ImageDecoder, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use crate::commands::OutputColorSpace;
+use crate::image_ops::color;
+
+/// What the job's outputs should be. Unset fields are not checked.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct OutputSpec {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub max_long_edge: Option<u32>,
+    /// Bits per channel as stored: 1 for bilevel, 8 or 16.
+    pub bit_depth: Option<u8>,
+    /// The built-in profile that must be embedded.
+    pub color_space: Option<OutputColorSpace>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// Missing, empty, truncated or not decodable.
+    Decode,
+    /// The content does not match the file extension.
+    Format,
+    Dimensions,
+    BitDepth,
+    ColorProfile,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Issue {
+    pub check: Check,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OutputReport {
+    pub path: String,
+    pub format: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per channel as stored.
+    pub bit_depth: u8,
+    pub channels: u8,
+    /// Whether an ICC profile is embedded, and which built-in one it is.
+    pub profile_embedded: bool,
+    pub color_space: Option<OutputColorSpace>,
+    pub issues: Vec<Issue>,
+    pub passed: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConformanceReport {
+    pub files: Vec<OutputReport>,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl ConformanceReport {
+    pub fn new(files: Vec<OutputReport>) -> Self {
+        let passed = files.iter().filter(|f| f.passed).count();
+        Self { failed: files.len() - passed, passed, files }
+    }
+}
+
+/// Decodes the file at `path` and checks it against `spec`.
+pub fn validate(path: &str, spec: &OutputSpec) -> OutputReport {
+    let mut report = OutputReport {
+        path: path.to_string(),
+        format: None,
+        width: 0,
+        height: 0,
+        bit_depth: 0,
+        channels: 0,
+        profile_embedded: false,
+        color_space: None,
+        issues: Vec::new(),
+        passed: false,
+    };
+    if let Err(e) = inspect(path, &mut report) {
+        report.issues.push(Issue { check: Check::Decode, message: e });
+    } else if report.format.as_deref() != Some("pdf") {
+        check(spec, &mut report);
+    }
+    report.passed = report.issues.is_empty();
+    report
+}
+
+fn inspect(path: &str, report: &mut OutputReport) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    if data.is_empty() {
+        return Err("File is empty".to_string());
+    }
+    let ext = std::path::Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if ext == "pdf" {
+        report.format = Some("pdf".to_string());
+        if !data.starts_with(b"%PDF-") {
+            return Err("No PDF header".to_string());
+        }
+        // Writers may pad after the marker, but it has to be in the last kilobyte
+        if !data[data.len().saturating_sub(1024)..].windows(5).any(|w| w == b"%%EOF") {
+            return Err("Truncated: no %%EOF trailer".to_string());
+        }
+        return Ok(());
+    }
+    if ext == "jp2" {
+        report.format = Some("jpeg2000".to_string());
+        let (img, icc) = crate::image_ops::encode::jp2::decode(&data).map_err(|e| format!("Does not decode: {}", e))?;
+        let color = img.color();
+        (report.width, report.height) = (img.width(), img.height());
+        report.channels = color.channel_count();
+        report.bit_depth = (color.bits_per_pixel() / color.channel_count() as u16) as u8;
+        report.profile_embedded = icc.is_some();
+        report.color_space = icc.as_deref().and_then(color::identify_profile);
+        return Ok(());
+    }
+
+    let reader = image::ImageReader::new(Cursor::new(&data)).with_guessed_format().map_err(|e| e.to_string())?;
+    let format = reader.format().ok_or("Not a recognized image format")?;
+    report.format = Some(format!("{:?}", format).to_lowercase());
+    if !format.extensions_str().contains(&ext.as_str()) {
+        report.issues.push(Issue { check: Check::Format, message: format!("{:?} data in a .{} file", format, ext) });
+    }
+    let mut decoder = reader.into_decoder().map_err(|e| format!("Unreadable header: {}", e))?;
+    let icc = decoder.icc_profile().ok().flatten();
+    let stored = decoder.original_color_type();
+    (report.width, report.height) = decoder.dimensions();
+    report.channels = stored.channel_count();
+    report.bit_depth = (stored.bits_per_pixel() / stored.channel_count().max(1) as u16) as u8;
+    report.profile_embedded = icc.is_some();
+    report.color_space = icc.as_deref().and_then(color::identify_profile);
+    image::DynamicImage::from_decoder(decoder).map_err(|e| format!("Does not decode: {}", e))?;
+    // Some decoders fill in a cut-off tail instead of failing, so the end marker is checked too
+    let complete = match format {
+        ImageFormat::Jpeg => data.ends_with(&[0xFF, 0xD9]),
+        ImageFormat::Png => data.ends_with(&[0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82]),
+        _ => true,
+    };
+    if !complete {
+        return Err(format!("Truncated: no {:?} end marker", format));
+    }
+    Ok(())
+}
+
+fn check(spec: &OutputSpec, report: &mut OutputReport) {
+    let mut fail = |check, message: String| report.issues.push(Issue { check, message });
+    let (width, height) = (report.width, report.height);
+    if spec.width.is_some_and(|w| w != width) || spec.height.is_some_and(|h| h != height) {
+        let expected = |v: Option<u32>| v.map_or("any".to_string(), |v| v.to_string());
+        fail(Check::Dimensions, format!("{}x{} px, {}x{} expected", width, height, expected(spec.width), expected(spec.height)));
+    }
+    if let Some(max) = spec.max_long_edge.filter(|&max| width.max(height) > max) {
+        fail(Check::Dimensions, format!("Long edge of {} px is above {} px", width.max(height), max));
+    }
+    if let Some(bits) = spec.bit_depth.filter(|&bits| bits != report.bit_depth) {
+        fail(Check::BitDepth, format!("{} bits per channel, {} expected", report.bit_depth, bits));
+    }
+    if let Some(space) = spec.color_space.filter(|&space| report.color_space != Some(space)) {
+        let found = match report.color_space {
+            Some(found) => format!("{:?} profile", found),
+            None if report.profile_embedded => "An unknown profile".to_string(),
+            None => "No profile".to_string(),
+        };
+        fail(Check::ColorProfile, format!("{} embedded, {:?} expected", found, space));
+    }
+}
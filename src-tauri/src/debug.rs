@@ -0,0 +1,71 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Stage Dumps
+ *
+ * Makes filter-quality bug reports actionable. While a dump is armed for a
+ * source file, every run over it also writes the decoded image (after
+ * demosaicing, for RAW), the result of each stage that ran and the image
+ * handed to the encoder as numbered PNGs into a fresh folder under the temp
+ * directory. Other files are processed as usual.
+ */
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Folder in the temp directory holding one subfolder per dumped run.
+pub const DIR_NAME: &str = "cliobulk-stages";
+
+/// The file dumps are armed for, registered as Tauri managed state.
+#[derive(Default)]
+pub struct StageDumps {
+    armed: Mutex<Option<String>>,
+}
+
+impl StageDumps {
+    /// Arms dumps for `path`, or disarms them with `None`.
+    pub fn arm(&self, path: Option<String>) {
+        *self.armed.lock().unwrap() = path;
+    }
+
+    pub fn is_armed(&self, path: &str) -> bool {
+        self.armed.lock().unwrap().as_deref() == Some(path)
+    }
+}
+
+/// The folder one run dumps its stages into.
+pub struct StageDump {
+    dir: PathBuf,
+    written: AtomicUsize,
+}
+
+impl StageDump {
+    /// Creates `<root>/<source name>-<unix ms>`.
+    pub fn create(root: &Path, source: &str) -> Result<Self, String> {
+        let stem = Path::new(source).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "image".to_string());
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        let dir = root.join(format!("{}-{}", stem, now.as_millis()));
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        Ok(Self { dir, written: AtomicUsize::new(0) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes `img` as `NN_<stage>.png`, keeping 16-bit data; float images are stored
+    /// as 16-bit since PNG has no float samples. Failures are logged, never fatal.
+    pub fn write(&self, stage: &str, img: &DynamicImage) {
+        let n = self.written.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{:02}_{}.png", n, stage));
+        let saved = match img {
+            DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb16(img.to_rgb16()).save(&path),
+            DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgba16(img.to_rgba16()).save(&path),
+            _ => img.save(&path),
+        };
+        if let Err(e) = saved {
+            log::error!("Failed to dump stage {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Device Conditions
+ *
+ * Battery and thermal state as reported by the platform shell (the mobile
+ * webview has the OS APIs the backend lacks), used to keep batches from
+ * draining a phone or pushing it into thermal shutdown. Where the kernel
+ * exposes CPU temperatures (Linux thermal zones), they are sampled during a
+ * batch as well, and a throttling laptop gets fewer images in flight: a
+ * hot CPU clocks down anyway, so extra workers only add heat.
+ */
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use crate::commands::{DeviceConditions, ThermalState};
+
+/// Images in flight on phones and tablets, whatever the core count.
+pub const MOBILE_MAX_CONCURRENCY: usize = 2;
+/// Below this charge, an unplugged device processes one image at a time.
+pub const LOW_BATTERY: f32 = 0.2;
+
+/// Where Linux publishes thermal zones.
+pub const THERMAL_ROOT: &str = "/sys/class/thermal";
+/// How often a running batch re-reads the sensors, in seconds.
+pub const THERMAL_INTERVAL: u64 = 5;
+
+/// Latest reported and measured conditions, registered as Tauri managed state.
+#[derive(Default)]
+pub struct DeviceState {
+    conditions: Mutex<DeviceConditions>,
+    measured: Mutex<Option<ThermalState>>,
+}
+
+impl DeviceState {
+    pub fn update(&self, conditions: DeviceConditions) {
+        *self.conditions.lock().unwrap() = conditions;
+    }
+
+    /// Reported conditions, with the thermal state raised to the measured one if worse.
+    pub fn conditions(&self) -> DeviceConditions {
+        let mut conditions = *self.conditions.lock().unwrap();
+        if let Some(measured) = *self.measured.lock().unwrap() {
+            conditions.thermal = conditions.thermal.max(measured);
+        }
+        conditions
+    }
+
+    /// Records a sensor reading; `None` when there are no sensors to read.
+    pub fn set_measured(&self, thermal: Option<ThermalState>) {
+        *self.measured.lock().unwrap() = thermal;
+    }
+
+    /// Re-reads the CPU temperature, where the platform exposes it.
+    pub fn sample(&self) {
+        self.set_measured(read_thermal(Path::new(THERMAL_ROOT)));
+    }
+
+    /// Reduces a batch's `requested` concurrency to what the device can sustain.
+    pub fn concurrency(&self, requested: usize) -> usize {
+        let conditions = self.conditions();
+        let mut cap = if cfg!(mobile) { MOBILE_MAX_CONCURRENCY } else { usize::MAX };
+        cap = match conditions.thermal {
+            ThermalState::Nominal => cap,
+            ThermalState::Fair => cap.min(requested.div_ceil(2)),
+            ThermalState::Serious | ThermalState::Critical => 1,
+        };
+        if !conditions.charging && conditions.battery_level.is_some_and(|l| l < LOW_BATTERY) {
+            cap = 1;
+        }
+        requested.min(cap).max(1)
+    }
+}
+
+/// Thermal pressure from the hottest CPU zone under `root` (a sysfs `thermal` class
+/// directory), or `None` without readable CPU zones.
+///
+/// Levels are relative to the zone's own trip points when it has them: Serious from
+/// the passive trip, where the kernel starts throttling, Fair 10 °C below it and
+/// Critical 5 °C short of the critical trip. Zones without trips use 75/85/95 °C.
+pub fn read_thermal(root: &Path) -> Option<ThermalState> {
+    let read = |path: &Path| std::fs::read_to_string(path).ok().map(|s| s.trim().to_string());
+    let celsius = |path: &Path| read(path)?.parse::<f32>().ok().map(|milli| milli / 1000.0);
+    let mut worst = None;
+    for entry in std::fs::read_dir(root).ok()?.flatten() {
+        let zone = entry.path();
+        if !entry.file_name().to_string_lossy().starts_with("thermal_zone") {
+            continue;
+        }
+        // Battery, wifi and chassis sensors run on their own schedule
+        let kind = read(&zone.join("type")).unwrap_or_default().to_ascii_lowercase();
+        if !["cpu", "pkg", "core", "soc", "acpitz", "k10temp", "tctl"].iter().any(|k| kind.contains(k)) {
+            continue;
+        }
+        let Some(temp) = celsius(&zone.join("temp")) else {
+            continue;
+        };
+        let (mut passive, mut critical) = (None, None);
+        for trip in 0.. {
+            let Some(kind) = read(&zone.join(format!("trip_point_{}_type", trip))) else {
+                break;
+            };
+            let at = celsius(&zone.join(format!("trip_point_{}_temp", trip))).filter(|t| *t > 0.0);
+            match kind.as_str() {
+                "passive" => passive = passive.or(at),
+                "critical" | "hot" => critical = critical.or(at),
+                _ => {}
+            }
+        }
+        let state = thermal_state(temp, passive, critical);
+        worst = Some(worst.map_or(state, |w: ThermalState| w.max(state)));
+    }
+    worst
+}
+
+/// Maps a temperature to a pressure level, as described at `read_thermal`.
+pub fn thermal_state(temp: f32, passive: Option<f32>, critical: Option<f32>) -> ThermalState {
+    let critical = critical.unwrap_or(100.0) - 5.0;
+    let serious = passive.unwrap_or(85.0).min(critical);
+    if temp >= critical {
+        ThermalState::Critical
+    } else if temp >= serious {
+        ThermalState::Serious
+    } else if temp >= serious - 10.0 {
+        ThermalState::Fair
+    } else {
+        ThermalState::Nominal
+    }
+}
+
+/// A concurrency limit that can change while a batch runs, on top of the batch's
+/// semaphore: lowering it holds back new images until enough running ones finish.
+pub struct Throttle {
+    limit: AtomicUsize,
+    active: AtomicUsize,
+}
+
+/// One image in flight; leaving the throttle when dropped.
+pub struct ThrottleGuard<'a>(&'a Throttle);
+
+impl Drop for ThrottleGuard<'_> {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Throttle {
+    pub fn new(limit: usize) -> Self {
+        Self { limit: AtomicUsize::new(limit.max(1)), active: AtomicUsize::new(0) }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Acquire)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit.max(1), Ordering::Release);
+    }
+
+    /// Admits one more image if that stays within the limit.
+    pub fn try_enter(&self) -> Option<ThrottleGuard<'_>> {
+        let limit = self.limit();
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < limit).then_some(n + 1))
+            .ok()
+            .map(|_| ThrottleGuard(self))
+    }
+
+    pub async fn enter(&self) -> ThrottleGuard<'_> {
+        loop {
+            if let Some(guard) = self.try_enter() {
+                return guard;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+    }
+}
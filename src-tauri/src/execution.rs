@@ -0,0 +1,91 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Sequential Fallback
+ *
+ * Heavily sandboxed environments (locked-down containers, restrictive
+ * mobile profiles) can refuse to create threads, and rayon panics when its
+ * pool cannot start. Thread creation is probed once; when it fails, or when
+ * sequential mode is chosen in settings, blocking work runs on the calling
+ * task instead of the blocking pool, batches process one file at a time, and
+ * the parallel kernels run in a rayon pool made only of the calling thread,
+ * so nothing spawns a thread. The mode is process-wide like the thread pools
+ * it replaces.
+ */
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use crate::commands::ExecutionMode;
+
+static MODE: Mutex<ExecutionMode> = Mutex::new(ExecutionMode::Auto);
+static THREADS: OnceLock<bool> = OnceLock::new();
+
+/// Current mode and what it resolved to, as reported to the UI.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ExecutionStatus {
+    pub mode: ExecutionMode,
+    /// Whether this process could start worker threads when probed.
+    pub threads_available: bool,
+    pub sequential: bool,
+}
+
+pub fn set_mode(mode: ExecutionMode) {
+    *MODE.lock().unwrap() = mode;
+}
+
+pub fn status() -> ExecutionStatus {
+    let mode = *MODE.lock().unwrap();
+    let threads_available = threads_available();
+    let sequential = match mode {
+        ExecutionMode::Auto => !threads_available,
+        ExecutionMode::Parallel => false,
+        ExecutionMode::Sequential => true,
+    };
+    ExecutionStatus { mode, threads_available, sequential }
+}
+
+pub fn is_sequential() -> bool {
+    status().sequential
+}
+
+/// Whether a plain thread and a small rayon pool can be started; probed on first use.
+pub fn threads_available() -> bool {
+    *THREADS.get_or_init(|| {
+        let spawned = std::thread::Builder::new().spawn(|| {}).map(|t| t.join().is_ok()).unwrap_or(false);
+        let pooled = spawned && rayon::ThreadPoolBuilder::new().num_threads(2).build().is_ok();
+        if !pooled {
+            log::error!("Worker threads cannot be created here; falling back to sequential processing");
+        }
+        pooled
+    })
+}
+
+/// Runs `f` with every rayon call inside it executed on the current thread.
+pub fn inline<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread_local! {
+        // Leaked with its thread, as rayon does for pools adopting the current thread
+        static POOL: Option<rayon::ThreadPool> =
+            rayon::ThreadPoolBuilder::new().num_threads(1).use_current_thread().build().ok();
+    }
+    POOL.with(|pool| match pool {
+        Some(pool) => pool.install(f),
+        // Already a worker of another pool
+        None => f(),
+    })
+}
+
+/// Runs blocking work on the blocking thread pool, or inline in sequential mode.
+pub async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+    if is_sequential() {
+        return Ok(inline(f));
+    }
+    tokio::task::spawn_blocking(f).await.map_err(|e| e.to_string())
+}
+
+/// `inline` in sequential mode; otherwise runs `f` as is, on the global rayon pool.
+pub fn run<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    if is_sequential() {
+        inline(f)
+    } else {
+        f()
+    }
+}
@@ -0,0 +1,140 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Fixity Manifests
+ *
+ * Checksums of a batch's files in the `md5sum`/`sha256sum` format, so a
+ * digitization lab can hand fixity evidence over with every transfer and the
+ * receiving side can check it with `sha256sum -c`. The runs of one batch (a
+ * verification sample and its remainder, a resumed job) update the same
+ * manifest instead of replacing it.
+ */
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use crate::commands::ChecksumAlgorithm;
+use crate::storage;
+
+/// `manifest-<algorithm>.txt` for outputs, `manifest-inputs-<algorithm>.txt` for sources.
+pub fn manifest_path(dir: &Path, algorithm: ChecksumAlgorithm, inputs: bool) -> PathBuf {
+    let kind = if inputs { "inputs-" } else { "" };
+    dir.join(format!("manifest-{}{}.txt", kind, algorithm.name()))
+}
+
+/// Hex digests of the file at `path`, one per algorithm, from a single read.
+pub fn digest_file(path: &Path, algorithms: &[ChecksumAlgorithm]) -> Result<Vec<String>, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let (mut md5, mut sha256) = (Md5::new(), Sha256::new());
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        for algorithm in algorithms {
+            match algorithm {
+                ChecksumAlgorithm::Md5 => md5.update(&buf[..n]),
+                ChecksumAlgorithm::Sha256 => sha256.update(&buf[..n]),
+            }
+        }
+    }
+    let (md5, sha256) = (hex(&md5.finalize()), hex(&sha256.finalize()));
+    Ok(algorithms
+        .iter()
+        .map(|algorithm| match algorithm {
+            ChecksumAlgorithm::Md5 => md5.clone(),
+            ChecksumAlgorithm::Sha256 => sha256.clone(),
+        })
+        .collect())
+}
+
+/// Deepest folder holding every path, where a manifest can list them relatively.
+pub fn common_dir(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut dirs = paths.iter().filter_map(|p| p.parent());
+    let mut common: Vec<Component> = dirs.next()?.components().collect();
+    for dir in dirs {
+        let shared = common.iter().zip(dir.components()).take_while(|(a, b)| *a == b).count();
+        common.truncate(shared);
+    }
+    (!common.is_empty()).then(|| common.iter().collect())
+}
+
+/// Name of `path` in a manifest in `dir`: relative with `/` separators when inside it,
+/// so the manifest checks from that folder on any platform, absolute otherwise.
+pub fn entry_name(path: &Path, dir: &Path) -> String {
+    match path.strip_prefix(dir) {
+        Ok(relative) => relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Adds `entries` (name, hex digest) to the manifest at `path`, replacing earlier
+/// digests of the same names, and rewrites it sorted by name. The new manifest replaces
+/// the old one only once fully written.
+pub fn update_manifest(path: &Path, entries: &[(String, String)]) -> Result<(), String> {
+    let mut manifest = match std::fs::read_to_string(path) {
+        Ok(text) => parse_manifest(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    for (name, digest) in entries {
+        manifest.insert(name.clone(), digest.clone());
+    }
+    let text: String = manifest.iter().map(|(name, digest)| format_line(digest, name)).collect();
+    storage::write_atomic(path, |tmp| std::fs::write(tmp, &text).map_err(|e| format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Digests by name of a manifest; lines that are not checksums are dropped.
+pub fn parse_manifest(text: &str) -> BTreeMap<String, String> {
+    let mut manifest = BTreeMap::new();
+    for line in text.lines() {
+        // Names with a backslash or newline are escaped, flagged by a leading backslash
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let Some((digest, name)) = line.split_once(' ') else { continue };
+        // Text mode separates with two spaces, binary mode with " *"
+        let Some(name) = name.strip_prefix([' ', '*']) else { continue };
+        if digest.is_empty() || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let name = if escaped { unescape(name) } else { name.to_string() };
+        manifest.insert(name, digest.to_ascii_lowercase());
+    }
+    manifest
+}
+
+/// A manifest line as `sha256sum` writes it.
+pub fn format_line(digest: &str, name: &str) -> String {
+    if name.contains(['\\', '\n', '\r']) {
+        let name = name.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+        format!("\\{}  {}\n", digest, name)
+    } else {
+        format!("{}  {}\n", digest, name)
+    }
+}
+
+fn unescape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
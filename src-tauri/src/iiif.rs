@@ -0,0 +1,111 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk IIIF Tiles
+ *
+ * Static IIIF Image API 3.0 Level 0 tile folders: an `info.json` and the
+ * JPEG tiles of every scale factor, under the canonical
+ * `{region}/{w},{h}/0/default.jpg` paths viewers such as OpenSeadragon and
+ * Mirador request. Served as plain files from any web server, they give a
+ * deep-zoom view of each output without an image server. Each level is
+ * halved from the one before, so a scale factor's width is `ceil(width / s)`
+ * as the API computes it.
+ */
+use image::DynamicImage;
+use serde_json::json;
+use std::path::Path;
+use crate::commands::JpegOptions;
+use crate::image_ops::encode;
+use crate::storage;
+
+/// Scale factors 1, 2, 4, ... down to the first one at which the image fits in one tile.
+pub fn scale_factors(width: u32, height: u32, tile: u32) -> Vec<u32> {
+    let (tile, mut scale) = (tile.max(1), 1);
+    let mut factors = vec![scale];
+    while width.div_ceil(scale) > tile || height.div_ceil(scale) > tile {
+        scale *= 2;
+        factors.push(scale);
+    }
+    factors
+}
+
+/// The `info.json` of an image served at `id`.
+pub fn info(id: &str, width: u32, height: u32, tile: u32) -> serde_json::Value {
+    let factors = scale_factors(width, height, tile);
+    // Whole-image sizes are the levels that fit in one tile, smallest first
+    let sizes: Vec<_> = factors
+        .iter()
+        .rev()
+        .map(|s| (width.div_ceil(*s), height.div_ceil(*s)))
+        .filter(|(w, h)| *w <= tile && *h <= tile)
+        .map(|(w, h)| json!({ "width": w, "height": h }))
+        .collect();
+    json!({
+        "@context": "http://iiif.io/api/image/3/context.json",
+        "id": id,
+        "type": "ImageService3",
+        "protocol": "http://iiif.io/api/image",
+        "profile": "level0",
+        "width": width,
+        "height": height,
+        "sizes": sizes,
+        "tiles": [{ "width": tile, "scaleFactors": factors }],
+    })
+}
+
+/// Writes the tiles of `img` and its `info.json` into `dir`, and returns how many
+/// tiles were written. Tiles are sRGB JPEGs encoded with `jpeg`.
+///
+/// The folder is built next to `dir` and swapped in once complete, replacing any
+/// earlier tiles, so a failed export never leaves a partial tree behind.
+pub fn write_tiles(img: &DynamicImage, dir: &Path, id: &str, tile: u32, jpeg: &JpegOptions) -> Result<usize, String> {
+    if tile == 0 {
+        return Err("IIIF tile size must be above 0".to_string());
+    }
+    let staging = storage::temp_sibling(dir);
+    let _ = std::fs::remove_dir_all(&staging);
+    let result = write_tree(img, &staging, id, tile, jpeg).and_then(|count| {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir).map_err(|e| format!("Failed to replace {}: {}", dir.display(), e))?;
+        }
+        std::fs::rename(&staging, dir).map_err(|e| format!("Failed to write {}: {}", dir.display(), e))?;
+        Ok(count)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&staging);
+    }
+    result
+}
+
+fn write_tree(img: &DynamicImage, dir: &Path, id: &str, tile: u32, jpeg: &JpegOptions) -> Result<usize, String> {
+    let (width, height) = (img.width(), img.height());
+    let mut count = 0;
+    let mut level = img.clone();
+    for (i, scale) in scale_factors(width, height, tile).into_iter().enumerate() {
+        if i > 0 {
+            level = level.resize_exact(level.width().div_ceil(2), level.height().div_ceil(2), image::imageops::FilterType::Triangle);
+        }
+        for y in (0..level.height()).step_by(tile as usize) {
+            for x in (0..level.width()).step_by(tile as usize) {
+                let (w, h) = (tile.min(level.width() - x), tile.min(level.height() - y));
+                // The region in full-size pixels, "full" when it is the whole image
+                let (rw, rh) = ((tile * scale).min(width - x * scale), (tile * scale).min(height - y * scale));
+                let region = if (rw, rh) == (width, height) {
+                    "full".to_string()
+                } else {
+                    format!("{},{},{},{}", x * scale, y * scale, rw, rh)
+                };
+                let folder = dir.join(region).join(format!("{},{}", w, h)).join("0");
+                std::fs::create_dir_all(&folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+                let path = folder.join("default.jpg");
+                let file = std::fs::File::create(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                encode::encode_jpeg(&level.crop_imm(x, y, w, h), std::io::BufWriter::new(file), jpeg, None, None)?;
+                count += 1;
+            }
+        }
+    }
+    let info = serde_json::to_vec_pretty(&info(id, width, height, tile)).map_err(|e| e.to_string())?;
+    let path = dir.join("info.json");
+    std::fs::write(&path, info).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(count)
+}
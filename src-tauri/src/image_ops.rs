@@ -7,9 +7,44 @@
  * and image filtering. It utilizes 'rayon' for multi-threaded 
  * pixel manipulations and 'rawloader' for camera-agnostic RAW support.
  */
-use image::{DynamicImage, ImageBuffer, Rgb};
-use crate::commands::ProcessOptions;
+use image::{DynamicImage, ImageBuffer, Luma, Rgb};
+use crate::commands::{AspectRatio, AwbMethod, BackgroundMethod, Binarization, CanvasOptions, ColorReplacement, Crop, Curve, Curves, DenoiseMethod, DenoiseOptions, Grayscale, HslShift, IlluminationOptions, OcrOptions, Orientation, OrtonOptions, Perspective, ProcessOptions, RedactionBox, ResizeFit, ResizeOptions, ResizeSize, SplitToning, Stage, WatermarkOptions, WhiteBalance};
 use rayon::prelude::*;
+use std::sync::Arc;
+
+pub mod align;
+pub mod calibration;
+pub mod color;
+pub mod composition;
+pub mod dust;
+pub mod encode;
+pub mod hdr;
+pub mod lut;
+pub mod marks;
+pub mod orientation;
+pub mod simd;
+pub mod stack;
+pub mod stamp;
+pub mod stats;
+pub mod stitch;
+
+/// Whether `path` has one of the camera RAW extensions routed through the demosaicer.
+pub fn is_raw_path(path: &str) -> bool {
+    let path_lc = path.to_lowercase();
+    path_lc.ends_with(".arw") ||
+    path_lc.ends_with(".cr2") ||
+    path_lc.ends_with(".nef") ||
+    path_lc.ends_with(".dng")
+}
+
+/// Opens any supported input, routing camera RAW extensions through the demosaicer.
+pub fn open_image(path: &str) -> Result<DynamicImage, String> {
+    if is_raw_path(path) {
+        decode_raw_to_image(path)
+    } else {
+        image::open(path).map_err(|e| e.to_string())
+    }
+}
 
 /// Decodes a RAW file into a DynamicImage.
 /// Uses Bilinear Demosaicing to provide high-quality full-resolution images.
@@ -18,6 +53,23 @@ use rayon::prelude::*;
 /// It normalizes pixel values based on the camera's white level to ensure correct exposure.
 pub fn decode_raw_to_image(path: &str) -> Result<DynamicImage, String> {
     let raw = rawloader::decode_file(path).map_err(|e| e.to_string())?;
+    develop_raw(&raw)
+}
+
+/// Decodes an in-memory file; `name` (its file name) routes camera RAW extensions
+/// through the demosaicer, and anything `image` cannot identify is tried as RAW.
+pub fn decode_image_bytes(bytes: &[u8], name: &str) -> Result<DynamicImage, String> {
+    if !is_raw_path(name) {
+        if let Ok(format) = image::guess_format(bytes) {
+            return image::load_from_memory_with_format(bytes, format).map_err(|e| e.to_string());
+        }
+    }
+    let raw = rawloader::decode(&mut std::io::Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    develop_raw(&raw)
+}
+
+/// Demosaics decoded RAW data into an 8-bit image.
+pub fn develop_raw(raw: &rawloader::RawImage) -> Result<DynamicImage, String> {
     let width = raw.width;
     let height = raw.height;
     
@@ -26,56 +78,8 @@ pub fn decode_raw_to_image(path: &str) -> Result<DynamicImage, String> {
 
     match raw.data {
         rawloader::RawImageData::Integer(ref data) => {
-            // Bilinear Demosaicing (RGGB assumption)
-            // Parallelized over rows for performance
-            let img_buffer: Vec<u8> = (0..height).into_par_iter().flat_map(|y| {
-                let mut row_pixels = Vec::with_capacity(width * 3);
-                for x in 0..width {
-                    // Safe access with clamping
-                    let get = |dx: i32, dy: i32| -> u32 {
-                         let nx = (x as i32 + dx).clamp(0, width as i32 - 1) as usize;
-                         let ny = (y as i32 + dy).clamp(0, height as i32 - 1) as usize;
-                         data[ny * width + nx] as u32
-                    };
-
-                    let is_red = (y % 2 == 0) && (x % 2 == 0);
-                    let is_green_r = (y % 2 == 0) && (x % 2 == 1);
-                    let is_green_b = (y % 2 == 1) && (x % 2 == 0);
-                    
-                    let (r, g, b) = if is_red {
-                        let r = get(0, 0);
-                        let g = (get(0, -1) + get(0, 1) + get(-1, 0) + get(1, 0)) / 4;
-                        let b = (get(-1, -1) + get(1, -1) + get(-1, 1) + get(1, 1)) / 4;
-                        (r, g, b)
-                    } else if is_green_r {
-                        let r = (get(-1, 0) + get(1, 0)) / 2;
-                        let g = get(0, 0);
-                        let b = (get(0, -1) + get(0, 1)) / 2;
-                        (r, g, b)
-                    } else if is_green_b {
-                        let r = (get(0, -1) + get(0, 1)) / 2;
-                        let g = get(0, 0);
-                        let b = (get(-1, 0) + get(1, 0)) / 2;
-                        (r, g, b)
-                    } else { // Blue pixel
-                        let r = (get(-1, -1) + get(1, -1) + get(-1, 1) + get(1, 1)) / 4;
-                        let g = (get(0, -1) + get(0, 1) + get(-1, 0) + get(1, 0)) / 4;
-                        let b = get(0, 0);
-                        (r, g, b)
-                    };
-
-                    // Scale to 8-bit using white level
-                    let r8 = ((r as f32 / white_level) * 255.0).clamp(0.0, 255.0) as u8;
-                    let g8 = ((g as f32 / white_level) * 255.0).clamp(0.0, 255.0) as u8;
-                    let b8 = ((b as f32 / white_level) * 255.0).clamp(0.0, 255.0) as u8;
-
-                    row_pixels.push(r8);
-                    row_pixels.push(g8);
-                    row_pixels.push(b8);
-                }
-                row_pixels
-            }).collect();
-            
+            // Bilinear Demosaicing (RGGB assumption), NEON-accelerated on AArch64
+            let img_buffer = simd::demosaic_rggb(data, width, height, white_level);
             let img = ImageBuffer::<Rgb<u8>, _>::from_raw(width as u32, height as u32, img_buffer)
                 .ok_or("Failed to create image buffer")?;
             Ok(DynamicImage::ImageRgb8(img))
@@ -130,78 +134,2710 @@ pub fn decode_raw_to_image(path: &str) -> Result<DynamicImage, String> {
     }
 }
 
-/// Applies the selected filters to the image based on user options.
-/// Saturation adjustment is parallelized using Rayon for high performance.
-pub fn apply_filters(mut img: DynamicImage, options: &ProcessOptions) -> DynamicImage {
-    // 1. Denoise (First to avoid amplifying noise)
-    if options.denoise {
-        img = match img {
-            DynamicImage::ImageRgb8(rgb) => {
-                DynamicImage::ImageRgb8(imageproc::filter::median_filter(&rgb, 1, 1))
+/// Decodes a RAW file at half resolution by collapsing each 2x2 CFA block into one pixel.
+///
+/// No interpolation is needed, so this is several times faster than the bilinear
+/// demosaic and never allocates a full-size RGB buffer; used for low-memory previews.
+pub fn decode_raw_superpixel(path: &str) -> Result<DynamicImage, String> {
+    let raw = rawloader::decode_file(path).map_err(|e| e.to_string())?;
+    let (width, height) = (raw.width / 2, raw.height / 2);
+    if width == 0 || height == 0 {
+        return Err("RAW image is too small".to_string());
+    }
+    let white_level = raw.whitelevels[0] as f32;
+    let samples: Vec<f32> = match raw.data {
+        rawloader::RawImageData::Integer(ref data) => data.iter().map(|&v| v as f32 / white_level).collect(),
+        rawloader::RawImageData::Float(ref data) => data.clone(),
+    };
+
+    // 2x2 block (RGGB assumption): R, G, G, B
+    let img_buffer: Vec<u8> = (0..height).into_par_iter().flat_map(|y| {
+        let top = &samples[2 * y * raw.width..];
+        let bottom = &samples[(2 * y + 1) * raw.width..];
+        let mut row_pixels = Vec::with_capacity(width * 3);
+        for x in 0..width {
+            let r = top[2 * x];
+            let g = (top[2 * x + 1] + bottom[2 * x]) / 2.0;
+            let b = bottom[2 * x + 1];
+            row_pixels.push((r.clamp(0.0, 1.0) * 255.0) as u8);
+            row_pixels.push((g.clamp(0.0, 1.0) * 255.0) as u8);
+            row_pixels.push((b.clamp(0.0, 1.0) * 255.0) as u8);
+        }
+        row_pixels
+    }).collect();
+
+    let img = ImageBuffer::<Rgb<u8>, _>::from_raw(width as u32, height as u32, img_buffer)
+        .ok_or("Failed to create image buffer")?;
+    Ok(DynamicImage::ImageRgb8(img))
+}
+
+/// Number of histogram bins spanning `RAW_HISTOGRAM_STOPS` below the white level.
+pub const RAW_HISTOGRAM_BINS: usize = 128;
+/// Dynamic range, in stops, covered by the raw histogram.
+pub const RAW_HISTOGRAM_STOPS: f32 = 16.0;
+
+/// Pre-demosaic histogram of one CFA color.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChannelHistogram {
+    /// "R", "G", "B" or "E" following rawloader's color indices.
+    pub color: String,
+    pub black_level: u16,
+    pub white_level: u16,
+    /// Log-scaled bins: bin `i` starts `RAW_HISTOGRAM_STOPS * (1 - i / RAW_HISTOGRAM_BINS)`
+    /// stops below the white level. Sensels at or below black land in bin 0.
+    pub bins: Vec<u64>,
+    /// Sensels at or above the white level.
+    pub clipped: u64,
+    pub max_value: u16,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RawHistogram {
+    pub make: String,
+    pub model: String,
+    pub cfa: String,
+    pub stops_per_bin: f32,
+    pub channels: Vec<ChannelHistogram>,
+}
+
+/// Builds per-CFA-channel sensel histograms from undemosaiced RAW data, restricted to
+/// the active sensor area, so exposure (ETTR) and clipping can be checked at the sensor.
+pub fn raw_histogram(raw: &rawloader::RawImage) -> RawHistogram {
+    let data: std::borrow::Cow<[u16]> = match &raw.data {
+        rawloader::RawImageData::Integer(data) => std::borrow::Cow::Borrowed(data),
+        rawloader::RawImageData::Float(data) => std::borrow::Cow::Owned(
+            data.iter().map(|&v| (v.clamp(0.0, 1.0) * 65535.0) as u16).collect(),
+        ),
+    };
+    let (black, white) = match raw.data {
+        rawloader::RawImageData::Integer(_) => (raw.blacklevels, raw.whitelevels),
+        rawloader::RawImageData::Float(_) => ([0; 4], [u16::MAX; 4]),
+    };
+    let channels = sensel_histogram(&data, raw.width, &raw.cfa, raw.crops, black, white);
+    RawHistogram {
+        make: raw.clean_make.clone(),
+        model: raw.clean_model.clone(),
+        cfa: raw.cfa.name.clone(),
+        stops_per_bin: RAW_HISTOGRAM_STOPS / RAW_HISTOGRAM_BINS as f32,
+        channels,
+    }
+}
+
+/// Histogram core over raw sensel values. `crops` is top, right, bottom, left as in rawloader.
+pub fn sensel_histogram(
+    data: &[u16],
+    width: usize,
+    cfa: &rawloader::CFA,
+    crops: [usize; 4],
+    black: [u16; 4],
+    white: [u16; 4],
+) -> Vec<ChannelHistogram> {
+    #[derive(Clone)]
+    struct Acc {
+        bins: Vec<u64>,
+        clipped: u64,
+        max_value: u16,
+        total: u64,
+    }
+    let empty = || vec![Acc { bins: vec![0; RAW_HISTOGRAM_BINS], clipped: 0, max_value: 0, total: 0 }; 4];
+
+    let height = data.len().checked_div(width).unwrap_or(0);
+    let [top, right, bottom, left] = crops;
+    let (x0, x1) = (left.min(width), width.saturating_sub(right).max(left.min(width)));
+    let (y0, y1) = (top.min(height), height.saturating_sub(bottom).max(top.min(height)));
+
+    let accs = (y0..y1)
+        .into_par_iter()
+        .fold(empty, |mut accs, y| {
+            for x in x0..x1 {
+                let c = cfa.color_at(y, x).min(3);
+                let v = data[y * width + x];
+                let acc = &mut accs[c];
+                acc.total += 1;
+                acc.max_value = acc.max_value.max(v);
+                if v >= white[c] {
+                    acc.clipped += 1;
+                }
+                let range = white[c].saturating_sub(black[c]).max(1) as f32;
+                let signal = v.saturating_sub(black[c]) as f32;
+                let bin = if signal <= 0.0 {
+                    0
+                } else {
+                    let ev = (signal / range).log2();
+                    (((ev + RAW_HISTOGRAM_STOPS) / RAW_HISTOGRAM_STOPS) * RAW_HISTOGRAM_BINS as f32)
+                        .clamp(0.0, (RAW_HISTOGRAM_BINS - 1) as f32) as usize
+                };
+                acc.bins[bin] += 1;
+            }
+            accs
+        })
+        .reduce(empty, |mut a, b| {
+            for (x, y) in a.iter_mut().zip(b) {
+                x.bins.iter_mut().zip(y.bins).for_each(|(p, q)| *p += q);
+                x.clipped += y.clipped;
+                x.max_value = x.max_value.max(y.max_value);
+                x.total += y.total;
+            }
+            a
+        });
+
+    accs.into_iter()
+        .enumerate()
+        .filter(|(_, acc)| acc.total > 0)
+        .map(|(c, acc)| ChannelHistogram {
+            color: ["R", "G", "B", "E"][c].to_string(),
+            black_level: black[c],
+            white_level: white[c],
+            bins: acc.bins,
+            clipped: acc.clipped,
+            max_value: acc.max_value,
+            total: acc.total,
+        })
+        .collect()
+}
+
+/// Estimates gray-world white balance gains (R, G, B), normalized to green.
+///
+/// Near-black and clipped pixels are ignored since they carry no color information.
+pub fn estimate_white_balance(img: &DynamicImage) -> [f32; 3] {
+    let rgb = img.to_rgb8();
+    let (sum, count) = rgb.as_raw()
+        .par_chunks(3)
+        .filter(|p| {
+            let max = p[0].max(p[1]).max(p[2]);
+            let min = p[0].min(p[1]).min(p[2]);
+            min > 8 && max < 250
+        })
+        .fold(|| ([0f64; 3], 0u64), |(mut sum, count), p| {
+            sum[0] += p[0] as f64;
+            sum[1] += p[1] as f64;
+            sum[2] += p[2] as f64;
+            (sum, count + 1)
+        })
+        .reduce(|| ([0f64; 3], 0u64), |(a, ca), (b, cb)| {
+            ([a[0] + b[0], a[1] + b[1], a[2] + b[2]], ca + cb)
+        });
+
+    if count == 0 || sum.contains(&0.0) {
+        return [1.0, 1.0, 1.0];
+    }
+    [
+        (sum[1] / sum[0]).clamp(0.25, 4.0) as f32,
+        1.0,
+        (sum[1] / sum[2]).clamp(0.25, 4.0) as f32,
+    ]
+}
+
+/// White balance gains (R, G, B) estimated with `method`, normalized to green.
+pub fn estimate_gains(img: &DynamicImage, method: AwbMethod) -> [f32; 3] {
+    match method {
+        AwbMethod::GrayWorld => estimate_white_balance(img),
+        AwbMethod::WhitePatch => estimate_white_patch(img),
+        AwbMethod::Retinex => estimate_retinex(img),
+    }
+}
+
+/// Gains that turn the illuminant color `white` neutral.
+fn gains_for(white: [f64; 3]) -> [f32; 3] {
+    if white.iter().any(|&c| c <= 0.0) {
+        return [1.0, 1.0, 1.0];
+    }
+    [(white[1] / white[0]).clamp(0.25, 4.0) as f32, 1.0, (white[1] / white[2]).clamp(0.25, 4.0) as f32]
+}
+
+/// Per-channel histograms of the pixels with no clipped channel, plus one of their luma.
+fn unclipped_histograms(img: &DynamicImage) -> ([[u64; 256]; 3], [u64; 256]) {
+    img.to_rgb8()
+        .as_raw()
+        .par_chunks(3)
+        .filter(|p| p[0].max(p[1]).max(p[2]) < 250)
+        .fold(
+            || ([[0u64; 256]; 3], [0u64; 256]),
+            |(mut channels, mut luma), p| {
+                for c in 0..3 {
+                    channels[c][p[c] as usize] += 1;
+                }
+                luma[((77 * p[0] as u32 + 150 * p[1] as u32 + 29 * p[2] as u32) >> 8) as usize] += 1;
+                (channels, luma)
             },
-            DynamicImage::ImageLuma8(luma) => {
-                DynamicImage::ImageLuma8(imageproc::filter::median_filter(&luma, 1, 1))
+        )
+        .reduce(
+            || ([[0u64; 256]; 3], [0u64; 256]),
+            |(mut a, mut la), (b, lb)| {
+                for c in 0..3 {
+                    for (x, y) in a[c].iter_mut().zip(b[c].iter()) {
+                        *x += y;
+                    }
+                }
+                for (x, y) in la.iter_mut().zip(lb.iter()) {
+                    *x += y;
+                }
+                (a, la)
             },
-            _ => {
-                let rgb = img.to_rgb8();
-                DynamicImage::ImageRgb8(imageproc::filter::median_filter(&rgb, 1, 1))
+        )
+}
+
+/// Lowest level with at most `share` of the histogram's count above it.
+fn top_share_level(histogram: &[u64; 256], share: f64) -> usize {
+    let total: u64 = histogram.iter().sum();
+    let keep = (total as f64 * share).ceil() as u64;
+    let mut above = 0;
+    for level in (0..256).rev() {
+        above += histogram[level];
+        if above >= keep {
+            return level;
+        }
+    }
+    0
+}
+
+/// White-patch estimate: the mean color of the brightest 1% of unclipped pixels.
+pub fn estimate_white_patch(img: &DynamicImage) -> [f32; 3] {
+    let (_, luma) = unclipped_histograms(img);
+    if luma.iter().sum::<u64>() == 0 {
+        return [1.0, 1.0, 1.0];
+    }
+    let level = top_share_level(&luma, 0.01) as u32;
+    let sum = img
+        .to_rgb8()
+        .as_raw()
+        .par_chunks(3)
+        .filter(|p| p[0].max(p[1]).max(p[2]) < 250 && (77 * p[0] as u32 + 150 * p[1] as u32 + 29 * p[2] as u32) >> 8 >= level)
+        .fold(|| [0f64; 3], |mut sum, p| {
+            for c in 0..3 {
+                sum[c] += p[c] as f64;
             }
-        };
+            sum
+        })
+        .reduce(|| [0f64; 3], |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2]]);
+    gains_for(sum)
+}
+
+/// Retinex (max-RGB) estimate: each channel's 99th percentile over unclipped pixels,
+/// the percentile keeping single hot pixels from setting the white.
+pub fn estimate_retinex(img: &DynamicImage) -> [f32; 3] {
+    let (channels, _) = unclipped_histograms(img);
+    gains_for(channels.map(|h| top_share_level(&h, 0.01) as f64))
+}
+
+/// Per-channel median of a set of white balance estimates.
+pub fn median_gains(samples: &[[f32; 3]]) -> [f32; 3] {
+    if samples.is_empty() {
+        return [1.0, 1.0, 1.0];
+    }
+    let mut out = [1.0; 3];
+    for (c, gain) in out.iter_mut().enumerate() {
+        let mut channel: Vec<f32> = samples.iter().map(|s| s[c]).collect();
+        channel.sort_by(|a, b| a.total_cmp(b));
+        *gain = channel[channel.len() / 2];
+    }
+    out
+}
+
+/// Stage order used when `ProcessOptions::pipeline` is not set: geometry first, then
+/// noise and tone work, creative effects, 1-bit conversion, and the output layout last.
+pub const DEFAULT_PIPELINE: [Stage; 29] = [
+    Stage::InvertNegative,
+    Stage::Redact,
+    Stage::Flip,
+    Stage::Perspective,
+    Stage::Orient,
+    Stage::Deskew,
+    Stage::Straighten,
+    Stage::Rotate,
+    Stage::Crop,
+    Stage::Dust,
+    Stage::Denoise,
+    Stage::Illumination,
+    Stage::Dehaze,
+    Stage::Clahe,
+    Stage::Adjust,
+    Stage::Lut,
+    Stage::Vignette,
+    Stage::Clarity,
+    Stage::Orton,
+    Stage::Blur,
+    Stage::Grain,
+    Stage::Grayscale,
+    Stage::Binarize,
+    Stage::Despeckle,
+    Stage::Ocr,
+    Stage::Resize,
+    Stage::Watermark,
+    Stage::TextStamp,
+    Stage::Canvas,
+];
+
+/// Applies the selected filters to the image based on user options.
+/// Saturation adjustment is parallelized using Rayon for high performance.
+pub fn apply_filters(img: DynamicImage, options: &ProcessOptions) -> DynamicImage {
+    apply_filters_observed(img, options, &mut |_, _| {})
+}
+
+/// Runs the same pipeline as `apply_filters`, handing the stage name and intermediate
+/// result to `observer` after every stage that actually ran.
+pub fn apply_filters_observed(
+    img: DynamicImage,
+    options: &ProcessOptions,
+    observer: &mut dyn FnMut(&str, &DynamicImage),
+) -> DynamicImage {
+    apply_filters_reported(img, options, observer).0
+}
+
+/// What the pipeline measured along the way, for the result payload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineReport {
+    /// Trim box and marks found by a crop to the crop marks.
+    pub crop_marks: Option<marks::CropMarks>,
+    /// Dust spots and scratches the dust stage filled.
+    pub dust_defects: Option<usize>,
+    /// Clockwise quarter turn in degrees the orientation stage read off the text.
+    pub orientation: Option<u32>,
+    /// Clockwise skew in degrees detected by the deskew stage.
+    pub deskew_angle: Option<f32>,
+    /// Text read by the OCR stage, or why it could not be.
+    pub text: Option<Result<OcrPage, String>>,
+    /// The run stopped between stages because it was cancelled; the image is unfinished.
+    pub cancelled: bool,
+}
+
+/// What the OCR stage read from a page.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OcrPage {
+    /// The text in the requested sidecar format (plain text or hOCR; plain text for ALTO).
+    pub text: String,
+    pub words: Vec<OcrWord>,
+    /// Size of the page the words are placed on. Resizing and canvas stages after the
+    /// OCR stage move the words with the page, so this is the output size.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OcrPage {
+    /// Moves the words onto the page scaled to `width` x `height`.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let (sx, sy) = (width as f32 / self.width.max(1) as f32, height as f32 / self.height.max(1) as f32);
+        let scale = |v: u32, s: f32| (v as f32 * s).round() as u32;
+        for word in &mut self.words {
+            let (right, bottom) = (scale(word.left + word.width, sx), scale(word.top + word.height, sy));
+            (word.left, word.top) = (scale(word.left, sx), scale(word.top, sy));
+            (word.width, word.height) = (right - word.left, bottom - word.top);
+        }
+        (self.width, self.height) = (width, height);
+    }
+
+    /// Moves the words onto a `width` x `height` canvas the page sits on at (`left`, `top`).
+    pub fn place(&mut self, width: u32, height: u32, left: u32, top: u32) {
+        for word in &mut self.words {
+            word.left += left;
+            word.top += top;
+        }
+        (self.width, self.height) = (width, height);
+    }
+}
+
+/// A recognized word and its bounding box in the pixels of the page it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OcrWord {
+    pub text: String,
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `apply_filters_observed`, also returning what the stages detected.
+pub fn apply_filters_reported(
+    img: DynamicImage,
+    options: &ProcessOptions,
+    observer: &mut dyn FnMut(&str, &DynamicImage),
+) -> (DynamicImage, PipelineReport) {
+    apply_filters_until(img, options, observer, &|| false)
+}
+
+/// `apply_filters_reported` that checks `stop` before every stage and, once it returns
+/// true, skips the rest and sets `PipelineReport::cancelled`.
+pub fn apply_filters_until(
+    img: DynamicImage,
+    options: &ProcessOptions,
+    observer: &mut dyn FnMut(&str, &DynamicImage),
+    stop: &dyn Fn() -> bool,
+) -> (DynamicImage, PipelineReport) {
+    let mut report = PipelineReport::default();
+    let order = options.pipeline.as_deref().unwrap_or(&DEFAULT_PIPELINE);
+    let img = run_stages(img, order, options, observer, &mut report, &mut false, stop);
+    (img, report)
+}
+
+/// Stages worth caching for interactive editing: slow, and run before the tone and
+/// color stages a slider usually changes.
+const CACHEABLE: [Stage; 14] = [
+    Stage::InvertNegative,
+    Stage::Redact,
+    Stage::Flip,
+    Stage::Perspective,
+    Stage::Orient,
+    Stage::Deskew,
+    Stage::Straighten,
+    Stage::Rotate,
+    Stage::Crop,
+    Stage::Dust,
+    Stage::Denoise,
+    Stage::Illumination,
+    Stage::Dehaze,
+    Stage::Clahe,
+];
+
+/// The output of the leading cacheable stages of a pipeline, valid for as long as
+/// their order and settings are unchanged.
+#[derive(Clone)]
+pub struct Checkpoint {
+    key: String,
+    image: Arc<DynamicImage>,
+    report: PipelineReport,
+    resized_early: bool,
+}
+
+/// `apply_filters_reported` for a preview session's `source`, resuming from
+/// `checkpoint` when the leading stages would produce the same image again, and
+/// replacing it otherwise. Only the stages after the checkpoint run on a hit.
+pub fn apply_filters_cached(
+    source: &DynamicImage,
+    options: &ProcessOptions,
+    checkpoint: &mut Option<Checkpoint>,
+) -> (DynamicImage, PipelineReport) {
+    // OCR is for exports; a preview would rerun it on every edit
+    let without_ocr;
+    let options = match options.ocr {
+        Some(_) => {
+            without_ocr = ProcessOptions { ocr: None, ..options.clone() };
+            &without_ocr
+        }
+        None => options,
+    };
+    let order = options.pipeline.as_deref().unwrap_or(&DEFAULT_PIPELINE);
+    let prefix = order.iter().take_while(|stage| CACHEABLE.contains(stage)).count();
+    if prefix == 0 {
+        return apply_filters_reported(source.clone(), options, &mut |_, _| {});
+    }
+    let (head, tail) = order.split_at(prefix);
+    // Low-memory mode resizes inside the head of the default order
+    let early_resize = options.pipeline.is_none().then_some(options.resize).flatten().filter(|r| r.early);
+    let key = format!(
+        "{:?}",
+        (
+            head,
+            options.invert_negative,
+            options.redaction.as_ref().map(|r| (&r.boxes, r.color)),
+            (options.flip_h, options.flip_v),
+            (options.perspective, options.orientation, options.deskew, options.straighten, options.rotate, options.crop),
+            (options.dust, options.denoise, options.illumination, options.dehaze, options.clahe),
+            early_resize,
+        )
+    );
+
+    let resumed = match checkpoint.as_ref().filter(|c| c.key == key) {
+        Some(hit) => hit.clone(),
+        None => {
+            let (mut report, mut resized_early) = (PipelineReport::default(), false);
+            let img = run_stages(source.clone(), head, options, &mut |_, _| {}, &mut report, &mut resized_early, &|| false);
+            let fresh = Checkpoint { key, image: Arc::new(img), report, resized_early };
+            *checkpoint = Some(fresh.clone());
+            fresh
+        }
+    };
+    let (mut report, mut resized_early) = (resumed.report, resumed.resized_early);
+    let img = run_stages((*resumed.image).clone(), tail, options, &mut |_, _| {}, &mut report, &mut resized_early, &|| false);
+    (img, report)
+}
+
+/// Runs `stages` in order. `resized_early` records whether low-memory mode already
+/// resized, so a later Resize stage is not applied twice.
+fn run_stages(
+    mut img: DynamicImage,
+    stages: &[Stage],
+    options: &ProcessOptions,
+    observer: &mut dyn FnMut(&str, &DynamicImage),
+    report: &mut PipelineReport,
+    resized_early: &mut bool,
+    stop: &dyn Fn() -> bool,
+) -> DynamicImage {
+    let custom = options.pipeline.is_some();
+    for &stage in stages {
+        if stop() {
+            report.cancelled = true;
+            break;
+        }
+        if stage == Stage::Resize && *resized_early {
+            continue;
+        }
+        img = run_stage(stage, img, options, observer, report);
+
+        // Low-memory mode shrinks right after the geometry stages of the default order,
+        // before the pixel stages run; a custom order places Resize itself
+        if !custom && stage == Stage::Crop {
+            let early_resize = options.resize.filter(|r| {
+                let (w, h) = r.scaled_size(img.width(), img.height());
+                // Padding early would run the filters over the background
+                let pads = matches!(r.size, ResizeSize::Exact { fit: ResizeFit::Pad, .. });
+                r.early && !pads && w <= img.width() && h <= img.height()
+            });
+            if let Some(resize) = &early_resize {
+                img = resize_image(img, resize);
+                observer("resize", &img);
+                *resized_early = true;
+            }
+        }
     }
+    img
+}
 
-    // 2. Combined Adjustments (Brightness, Contrast, Saturation)
-    // Fused loop for performance: iterates pixels once and avoids intermediate buffers.
-    if options.brightness != 0.0 || options.contrast != 1.0 || options.saturation != 1.0 {
-        let mut rgb_img = img.to_rgb8();
-        let raw_pixels = rgb_img.as_mut();
+/// Runs one stage, if its settings enable it.
+fn run_stage(
+    stage: Stage,
+    mut img: DynamicImage,
+    options: &ProcessOptions,
+    observer: &mut dyn FnMut(&str, &DynamicImage),
+    report: &mut PipelineReport,
+) -> DynamicImage {
+    match stage {
+        // 1. Negative Inversion (every later stage works on the positive)
+        Stage::InvertNegative => {
+            if let Some(negative) = options.invert_negative {
+                img = invert_negative(img, negative.base.map(|c| c.0), negative.linear_input);
+                observer("invert_negative", &img);
+            }
+        }
 
-        let brightness_offset = options.brightness * 100.0;
-        let contrast = options.contrast;
-        let saturation = options.saturation;
+        // 2. Redaction (in the source's frame, before anything can read what is covered)
+        Stage::Redact => {
+            if let Some(redaction) = options.redaction.as_ref().filter(|r| !r.boxes.is_empty()) {
+                img = redact(img, &redaction.boxes, redaction.color.0);
+                observer("redact", &img);
+            }
+        }
 
-        // Use Rayon to process pixel chunks in parallel
-        raw_pixels.par_chunks_mut(3).for_each(|pixel| {
-            if pixel.len() != 3 { return; }
+        // 3. Flip (ahead of rotation so mirrored captures are corrected in their own frame)
+        Stage::Flip => {
+            if options.flip_h || options.flip_v {
+                if options.flip_h {
+                    img = img.fliph();
+                }
+                if options.flip_v {
+                    img = img.flipv();
+                }
+                observer("flip", &img);
+            }
+        }
 
-            let mut r = pixel[0] as f32;
-            let mut g = pixel[1] as f32;
-            let mut b = pixel[2] as f32;
+        // 4. Perspective (keystone correction, in the capture's own frame)
+        Stage::Perspective => {
+            if let Some(perspective) = &options.perspective {
+                let quad = match perspective {
+                    Perspective::Corners { corners } => {
+                        let (w, h) = (img.width() as f32, img.height() as f32);
+                        Some(corners.map(|[x, y]| (x * w, y * h)))
+                    }
+                    Perspective::Auto => detect_quad(&img),
+                };
+                match quad.and_then(|quad| rectify(&img, quad)) {
+                    Some(rectified) => {
+                        img = rectified;
+                        observer("perspective", &img);
+                    }
+                    None => log::info!("Skipping perspective correction: no usable quad"),
+                }
+            }
+        }
 
-            // Brightness
-            if brightness_offset != 0.0 {
-                r += brightness_offset;
-                g += brightness_offset;
-                b += brightness_offset;
+        // 5. Orientation (upright text, so deskew measures its lines and not its columns)
+        Stage::Orient => {
+            if let Some(orientation) = options.orientation {
+                match detect_orientation(&img, orientation) {
+                    Ok(Some(degrees)) => {
+                        report.orientation = Some(degrees);
+                        if degrees != 0 {
+                            img = rotate_image(img, degrees as f32, [255, 255, 255]);
+                            observer("orient", &img);
+                        }
+                    }
+                    Ok(None) => log::info!("Skipping orientation: too little text to tell which way is up"),
+                    Err(e) => log::error!("Skipping orientation: {}", e),
+                }
             }
+        }
 
-            // Contrast
-            if contrast != 1.0 {
-                r = (r - 128.0) * contrast + 128.0;
-                g = (g - 128.0) * contrast + 128.0;
-                b = (b - 128.0) * contrast + 128.0;
+        // 6. Deskew (scanned pages, so the lines of text are level for thresholding)
+        Stage::Deskew => {
+            if let Some(deskew) = options.deskew {
+                match estimate_skew(&img, deskew.max_angle) {
+                    Some(skew) => {
+                        report.deskew_angle = Some(skew);
+                        if skew.abs() >= 0.05 {
+                            img = deskew_image(img, skew, deskew.fill.0);
+                            observer("deskew", &img);
+                        }
+                    }
+                    None => log::info!("Skipping deskew: no clear lines of text within {} degrees", deskew.max_angle),
+                }
             }
+        }
 
-            // Saturation
-            if saturation != 1.0 {
-                let l = 0.299 * r + 0.587 * g + 0.114 * b;
-                r = l + (r - l) * saturation;
-                g = l + (g - l) * saturation;
-                b = l + (b - l) * saturation;
+        // 7. Straighten (level the horizon before any manual rotation)
+        Stage::Straighten => {
+            if let Some(straighten) = options.straighten {
+                match estimate_tilt(&img, straighten.max_angle) {
+                    Some(tilt) if tilt.abs() >= 0.05 => {
+                        img = straighten_image(img, tilt, straighten.crop, straighten.fill.0);
+                        observer("straighten", &img);
+                    }
+                    Some(_) => {}
+                    None => log::info!("Skipping straighten: no clear lines within {} degrees", straighten.max_angle),
+                }
             }
+        }
 
-            pixel[0] = r.clamp(0.0, 255.0) as u8;
-            pixel[1] = g.clamp(0.0, 255.0) as u8;
-            pixel[2] = b.clamp(0.0, 255.0) as u8;
-        });
+        // 8. Rotation
+        Stage::Rotate => {
+            if let Some(rotate) = options.rotate.filter(|r| r.degrees.rem_euclid(360.0) != 0.0) {
+                img = rotate_image(img, rotate.degrees, rotate.fill.0);
+                observer("rotate", &img);
+            }
+        }
 
-        img = DynamicImage::ImageRgb8(rgb_img);
-    }
+        // 9. Crop (before the pixel stages so they only touch what is kept)
+        Stage::Crop => {
+            if let Some(crop) = &options.crop {
+                let (width, height) = (img.width(), img.height());
+                let region = match *crop {
+                    Crop::Auto { margin } => content_bounds(&img, margin),
+                    Crop::Trim { tolerance, margin, .. } => trim_bounds(&img, tolerance, margin),
+                    Crop::Marks { bleed } => match marks::detect(&img) {
+                        Some(found) => {
+                            let region = found.region(bleed, width, height);
+                            report.crop_marks = Some(found);
+                            region
+                        }
+                        None => {
+                            log::info!("Skipping crop: no crop marks on every edge");
+                            None
+                        }
+                    },
+                    _ => crop.region(width, height),
+                };
+                match region {
+                    Some((x, y, w, h)) if (x, y, w, h) != (0, 0, width, height) => {
+                        img = img.crop_imm(x, y, w, h);
+                        observer("crop", &img);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // 10. Dust (spots and scratches, before denoising can smear them into blotches)
+        Stage::Dust => {
+            if let Some(dust) = options.dust {
+                let (cleaned, defects) = dust::remove_dust(img, &dust);
+                img = cleaned;
+                report.dust_defects = Some(defects);
+                if defects > 0 {
+                    observer("dust", &img);
+                }
+            }
+        }
+
+        // 11. Denoise (before other stages to avoid amplifying noise)
+        Stage::Denoise => {
+            if let Some(denoise) = options.denoise.filter(|d| d.strength > 0.0) {
+                img = apply_denoise(img, &denoise);
+                observer("denoise", &img);
+            }
+        }
+
+        // 12. Illumination (divides out uneven lighting before any contrast or thresholding)
+        Stage::Illumination => {
+            if let Some(illumination) = options.illumination {
+                img = flatten_illumination(img, &illumination);
+                observer("illumination", &img);
+            }
+        }
+
+        // 13. Dehaze (before contrast so the recovered range isn't stretched twice)
+        Stage::Dehaze => {
+            if options.dehaze > 0.0 {
+                img = apply_dehaze(img, options.dehaze.min(1.0));
+                observer("dehaze", &img);
+            }
+        }
+
+        // 14. CLAHE (local histogram equalization on luminance)
+        Stage::Clahe => {
+            if let Some(clahe) = options.clahe.filter(|c| c.clip_limit > 0.0) {
+                img = apply_clahe(img, clahe.clip_limit, clahe.tile_size.max(8));
+                observer("clahe", &img);
+            }
+        }
+
+        // 15. Combined Adjustments (White Balance, Brightness, Contrast, Saturation, Channel Mixer, Curves, Toning)
+        Stage::Adjust => {
+            // Fused loop for performance: iterates pixels once and avoids intermediate buffers.
+            let wb_gains = match options.white_balance {
+                WhiteBalance::Off => None,
+                WhiteBalance::Fixed { gains } => Some(gains),
+                // Lock modes are resolved per batch; a lone image is its own reference.
+                WhiteBalance::Auto | WhiteBalance::LockReference { .. } | WhiteBalance::LockMedian => {
+                    Some(estimate_gains(&img, options.awb_method))
+                }
+            }.filter(|g| *g != [1.0, 1.0, 1.0]);
+
+            // Calibration works in linear light; the decoding table is shared by every pixel
+            let calibration = options
+                .calibration
+                .as_ref()
+                .and_then(|c| c.matrix)
+                .filter(|m| *m != [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+                .map(|m| (m, (0..256).map(|v| calibration::to_linear(v as f32)).collect::<Vec<f32>>()));
+            let mixer = (!options.channel_mixer.is_identity()).then(|| options.channel_mixer.rows());
+            let curves = options.curves.as_ref().filter(|c| !c.is_identity()).map(curve_tables);
+            let hsl = (!options.hsl.is_identity()).then(|| options.hsl.ranges());
+            let replace = options.replace_color.filter(|c| (c.target_hue - c.source_hue).rem_euclid(360.0) != 0.0);
+            let split = options.split_toning.is_active().then(|| split_tone_tints(&options.split_toning));
+            let toning = options.toning.as_ref().map(|t| t.endpoints()).map(|(s, h)| (s.map(|v| v as f32), h.map(|v| v as f32)));
+
+            if calibration.is_some() || wb_gains.is_some() || mixer.is_some() || curves.is_some() || hsl.is_some() || replace.is_some() || split.is_some() || toning.is_some() || options.brightness != 0.0 || options.contrast != 1.0 || options.saturation != 1.0 {
+                let mut rgb_img = img.to_rgb8();
+                let raw_pixels = rgb_img.as_mut();
+
+                let adjustments = simd::Adjustments {
+                    gains: wb_gains,
+                    brightness_offset: options.brightness * 100.0,
+                    contrast: options.contrast,
+                    saturation: options.saturation,
+                };
+
+                if calibration.is_none() && mixer.is_none() && curves.is_none() && hsl.is_none() && replace.is_none() && split.is_none() && toning.is_none() {
+                    // Only the basic adjustments: hand whole blocks to the SIMD kernel
+                    raw_pixels.par_chunks_mut(3 * 4096).for_each(|chunk| simd::adjust_rgb8(chunk, &adjustments));
+                } else {
+                    // Use Rayon to process pixel chunks in parallel
+                    raw_pixels.par_chunks_mut(3).for_each(|pixel| {
+                        if pixel.len() != 3 { return; }
+
+                        let [r, g, b] = match &calibration {
+                            Some((m, linear)) => calibration::apply(m, [linear[pixel[0] as usize], linear[pixel[1] as usize], linear[pixel[2] as usize]])
+                                .map(calibration::from_linear),
+                            None => [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32],
+                        };
+                        let (mut r, mut g, mut b) = adjustments.apply(r, g, b);
+
+                        // Channel Mixer
+                        if let Some(m) = mixer {
+                            (r, g, b) = (
+                                m[0][0] * r + m[0][1] * g + m[0][2] * b,
+                                m[1][0] * r + m[1][1] * g + m[1][2] * b,
+                                m[2][0] * r + m[2][1] * g + m[2][2] * b,
+                            );
+                        }
+
+                        // Tone Curves
+                        if let Some(tables) = &curves {
+                            (r, g, b) = (apply_curve(&tables[0], r), apply_curve(&tables[1], g), apply_curve(&tables[2], b));
+                        }
+
+                        // Per-range HSL
+                        if let Some(ranges) = &hsl {
+                            (r, g, b) = apply_hsl_ranges(r, g, b, ranges);
+                        }
+
+                        // Selective Color Replacement
+                        if let Some(replacement) = &replace {
+                            (r, g, b) = replace_hue(r, g, b, replacement);
+                        }
+
+                        // Split Toning: luminance-weighted chroma tints
+                        if let Some((shadow, highlight, balance)) = split {
+                            let l = ((0.299 * r + 0.587 * g + 0.114 * b) / 255.0).clamp(0.0, 1.0);
+                            let pivot = (0.5 - balance * 0.5).clamp(0.05, 0.95);
+                            let w_shadow = (1.0 - l / pivot).max(0.0);
+                            let w_highlight = ((l - pivot) / (1.0 - pivot)).max(0.0);
+                            r += shadow[0] * w_shadow + highlight[0] * w_highlight;
+                            g += shadow[1] * w_shadow + highlight[1] * w_highlight;
+                            b += shadow[2] * w_shadow + highlight[2] * w_highlight;
+                        }
+
+                        // Toning: gradient map of luminance between two colors
+                        if let Some((shadow, highlight)) = toning {
+                            let l = ((0.299 * r + 0.587 * g + 0.114 * b) / 255.0).clamp(0.0, 1.0);
+                            r = shadow[0] + (highlight[0] - shadow[0]) * l;
+                            g = shadow[1] + (highlight[1] - shadow[1]) * l;
+                            b = shadow[2] + (highlight[2] - shadow[2]) * l;
+                        }
+
+                        pixel[0] = r.clamp(0.0, 255.0) as u8;
+                        pixel[1] = g.clamp(0.0, 255.0) as u8;
+                        pixel[2] = b.clamp(0.0, 255.0) as u8;
+                    });
+                }
 
-    // 4. Adaptive Threshold
-    if options.adaptive_threshold {
-        let luma = img.to_luma8();
-        let thresholded = imageproc::contrast::adaptive_threshold(&luma, 10);
-        img = DynamicImage::ImageLuma8(thresholded);
+                img = DynamicImage::ImageRgb8(rgb_img);
+                observer("adjust", &img);
+            }
+        }
+
+        // 16. 3D LUT
+        Stage::Lut => {
+            // Loaded by the commands up front; a file whose table is missing fails before this
+            if let Some((lut_opts, table)) = options.lut.as_ref().and_then(|l| l.table.as_ref().map(|t| (l, t))) {
+                img = apply_lut(img, table, lut_opts.strength.clamp(0.0, 1.0));
+                observer("lut", &img);
+            }
+        }
+
+        // 17. Vignette
+        Stage::Vignette => {
+            if let Some(vignette) = options.vignette.filter(|v| v.amount != 0.0) {
+                img = apply_vignette(img, vignette.amount.clamp(-1.0, 1.0), vignette.midpoint.clamp(0.0, 1.0));
+                observer("vignette", &img);
+            }
+        }
+
+        // 18. Clarity (local contrast on luminance)
+        Stage::Clarity => {
+            if options.clarity != 0.0 {
+                img = apply_clarity(img, options.clarity);
+                observer("clarity", &img);
+            }
+        }
+
+        // 19. Orton Glow (after clarity so the glow softens the sharpened detail, not the reverse)
+        Stage::Orton => {
+            if let Some(orton) = options.orton.filter(|o| o.opacity > 0.0 && o.size > 0.0) {
+                img = apply_orton(img, &orton);
+                observer("orton", &img);
+            }
+        }
+
+        // 20. Gaussian Blur (before grain so the grain stays crisp)
+        Stage::Blur => {
+            if options.blur > 0.0 {
+                img = gaussian_blur(&img, options.blur);
+                observer("blur", &img);
+            }
+        }
+
+        // 21. Film Grain
+        Stage::Grain => {
+            if let Some(grain) = options.grain.filter(|g| g.amount > 0.0) {
+                img = apply_grain(img, grain.amount.min(1.0), grain.size.max(1.0), grain.seed.unwrap_or(0));
+                observer("grain", &img);
+            }
+        }
+
+        // 22. Grayscale (one channel, so thresholding works from the chosen mix)
+        Stage::Grayscale => {
+            if let Some(grayscale) = options.grayscale {
+                img = to_grayscale(&img, grayscale);
+                observer("grayscale", &img);
+            }
+        }
+
+        // 23. Binarization (Thresholding or Dithering)
+        Stage::Binarize => {
+            let legacy = Binarization::Adaptive { window: 21 };
+            let binarization = options.binarization.or(options.adaptive_threshold.then_some(legacy));
+            match binarization {
+                Some(Binarization::Adaptive { window }) => {
+                    let luma = img.to_luma8();
+                    let thresholded = imageproc::contrast::adaptive_threshold(&luma, window.max(3) / 2);
+                    img = DynamicImage::ImageLuma8(thresholded);
+                    observer("threshold", &img);
+                }
+                Some(Binarization::Otsu) => {
+                    let luma = img.to_luma8();
+                    let level = imageproc::contrast::otsu_level(&luma);
+                    img = DynamicImage::ImageLuma8(image::GrayImage::from_fn(luma.width(), luma.height(), |x, y| {
+                        image::Luma([if luma.get_pixel(x, y)[0] > level { 255 } else { 0 }])
+                    }));
+                    observer("threshold", &img);
+                }
+                Some(Binarization::Sauvola { window, k }) => {
+                    let threshold = |mean: f32, std: f32| mean * (1.0 + k * (std / 128.0 - 1.0));
+                    img = DynamicImage::ImageLuma8(local_threshold(&img.to_luma8(), window, threshold));
+                    observer("threshold", &img);
+                }
+                Some(Binarization::Niblack { window, k }) => {
+                    img = DynamicImage::ImageLuma8(local_threshold(&img.to_luma8(), window, |mean, std| mean + k * std));
+                    observer("threshold", &img);
+                }
+                Some(Binarization::Bayer { size }) => {
+                    img = DynamicImage::ImageLuma8(dither_bayer(&img.to_luma8(), size));
+                    observer("dither", &img);
+                }
+                Some(Binarization::FloydSteinberg) => {
+                    img = DynamicImage::ImageLuma8(dither_floyd_steinberg(&img.to_luma8()));
+                    observer("dither", &img);
+                }
+                None => {}
+            }
+        }
+
+        // 24. Despeckle (1-bit results only, where a speck is a clear connected component)
+        Stage::Despeckle => {
+            if let Some(despeckle) = options.despeckle.filter(|d| d.max_area > 0) {
+                match img {
+                    DynamicImage::ImageLuma8(ref luma) if luma.pixels().all(|p| p[0] == 0 || p[0] == 255) => {
+                        img = DynamicImage::ImageLuma8(despeckle_bilevel(luma, despeckle.max_area, despeckle.fill_holes));
+                        observer("despeckle", &img);
+                    }
+                    _ => log::info!("Skipping despeckle: the image is not black and white"),
+                }
+            }
+        }
+
+        // 25. OCR (reads the page once it is black and white, before resizing and overlays)
+        Stage::Ocr => {
+            if let Some(ocr) = options.ocr.as_ref() {
+                report.text = Some(recognize_text(&img, ocr));
+                observer("ocr", &img);
+            }
+        }
+
+        // 26. Resize (output dimensions, right before save)
+        Stage::Resize => {
+            if let Some(resize) = options.resize {
+                let before = (img.width(), img.height());
+                img = resize_image(img, &resize);
+                if (img.width(), img.height()) != before {
+                    if let Some(Ok(page)) = report.text.as_mut() {
+                        page.resize(img.width(), img.height());
+                    }
+                    observer("resize", &img);
+                }
+            }
+        }
+
+        // 27. Watermark
+        Stage::Watermark => {
+            // Loaded by the commands up front, like the LUT
+            let enabled = options.watermark.as_ref().filter(|w| w.opacity > 0.0 && w.scale > 0.0);
+            if let Some((watermark, mark)) = enabled.and_then(|w| w.image.as_ref().map(|m| (w, m))) {
+                img = apply_watermark(img, mark, watermark);
+                observer("watermark", &img);
+            }
+        }
+
+        // 28. Text Stamp
+        Stage::TextStamp => {
+            // The font is loaded by the commands up front, like the LUT
+            let enabled = options.text_stamp.as_ref().filter(|s| !s.template.is_empty());
+            if let Some((stamp, font)) = enabled.and_then(|s| s.font_data.as_ref().map(|f| (s, f))) {
+                // Outside a batch (previews) the template is shown unresolved
+                img = stamp::draw_stamp(img, stamp.text.as_deref().unwrap_or(&stamp.template), stamp, font);
+                observer("text_stamp", &img);
+            }
+        }
+
+        // 29. Canvas (padding to an aspect ratio and borders, around the final size)
+        Stage::Canvas => {
+            if let Some(canvas) = &options.canvas {
+                let (width, height, left, top) = canvas.layout(img.width(), img.height());
+                if (width, height) != (img.width(), img.height()) {
+                    if let Some(Ok(page)) = report.text.as_mut() {
+                        page.place(width, height, left, top);
+                    }
+                    img = extend_canvas(img, canvas);
+                    observer("canvas", &img);
+                }
+            }
+        }
     }
     img
 }
+
+/// Alpha-composites `mark`, scaled to the image, at the watermark position.
+/// 16-bit images are composited at 16 bits; everything else becomes RGB8.
+pub fn apply_watermark(img: DynamicImage, mark: &image::RgbaImage, options: &WatermarkOptions) -> DynamicImage {
+    let (width, height) = (img.width(), img.height());
+    let margin = (width.min(height) as f32 * options.margin.max(0.0)).round() as u32;
+    let max_w = width.saturating_sub(2 * margin).max(1);
+    let max_h = height.saturating_sub(2 * margin).max(1);
+    let target_w = ((width as f32 * options.scale).round() as u32).clamp(1, max_w);
+    let scale = (target_w as f32 / mark.width().max(1) as f32).min(max_h as f32 / mark.height().max(1) as f32);
+    let (mw, mh) = (((mark.width() as f32 * scale).round() as u32).max(1), ((mark.height() as f32 * scale).round() as u32).max(1));
+    // Resampled premultiplied so the color of transparent pixels cannot bleed into edges
+    let premultiplied = image::Rgba32FImage::from_fn(mark.width(), mark.height(), |x, y| {
+        let [r, g, b, a] = mark.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+        image::Rgba([r * a, g * a, b * a, a])
+    });
+    let mark = if (mw, mh) == mark.dimensions() {
+        premultiplied
+    } else {
+        image::imageops::resize(&premultiplied, mw, mh, image::imageops::FilterType::Lanczos3)
+    };
+
+    let (gx, gy) = options.position.anchor();
+    let x0 = margin + ((width - mw.min(width)).saturating_sub(2 * margin) as f32 * gx).round() as u32;
+    let y0 = margin + ((height - mh.min(height)).saturating_sub(2 * margin) as f32 * gy).round() as u32;
+    let opacity = options.opacity.clamp(0.0, 1.0);
+
+    let blend = |dst: [f32; 3], x: u32, y: u32| {
+        let [r, g, b, a] = mark.get_pixel(x, y).0;
+        let a = a.clamp(0.0, 1.0);
+        let src = [r, g, b].map(|c| c.clamp(0.0, a) * opacity);
+        [0, 1, 2].map(|c| (src[c] + dst[c] * (1.0 - a * opacity)).clamp(0.0, 1.0))
+    };
+    let covered = |x: u32, y: u32| mark.get_pixel(x, y)[3] > 0.0;
+    if img.color().bytes_per_pixel() / img.color().channel_count() > 1 {
+        let mut out = img.to_rgb16();
+        for (x, y) in (0..mh).flat_map(|y| (0..mw).map(move |x| (x, y))).filter(|&(x, y)| covered(x, y)) {
+            if let Some(px) = out.get_pixel_mut_checked(x0 + x, y0 + y) {
+                px.0 = blend(px.0.map(|c| c as f32 / 65535.0), x, y).map(|c| (c * 65535.0).round() as u16);
+            }
+        }
+        DynamicImage::ImageRgb16(out)
+    } else {
+        let mut out = img.to_rgb8();
+        for (x, y) in (0..mh).flat_map(|y| (0..mw).map(move |x| (x, y))).filter(|&(x, y)| covered(x, y)) {
+            if let Some(px) = out.get_pixel_mut_checked(x0 + x, y0 + y) {
+                px.0 = blend(px.0.map(|c| c as f32 / 255.0), x, y).map(|c| (c * 255.0).round() as u8);
+            }
+        }
+        DynamicImage::ImageRgb8(out)
+    }
+}
+
+/// Mixes the channels of `img` into one with the weights of `grayscale`, dropping alpha.
+/// 16-bit and float sources become `Luma16`, the rest `Luma8`.
+pub fn to_grayscale(img: &DynamicImage, grayscale: Grayscale) -> DynamicImage {
+    let [r, g, b] = grayscale.weights();
+    let (width, height) = (img.width(), img.height());
+    if img.color().bytes_per_pixel() / img.color().channel_count() > 1 {
+        let rgb = img.to_rgb16();
+        let luma: Vec<u16> = rgb
+            .as_raw()
+            .par_chunks_exact(3)
+            .map(|p| (p[0] as f32 * r + p[1] as f32 * g + p[2] as f32 * b).round().clamp(0.0, 65535.0) as u16)
+            .collect();
+        DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, luma).expect("gray buffer matches image dimensions"))
+    } else {
+        let rgb = img.to_rgb8();
+        let luma: Vec<u8> = rgb
+            .as_raw()
+            .par_chunks_exact(3)
+            .map(|p| (p[0] as f32 * r + p[1] as f32 * g + p[2] as f32 * b).round().clamp(0.0, 255.0) as u8)
+            .collect();
+        DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, luma).expect("gray buffer matches image dimensions"))
+    }
+}
+
+/// Fills `boxes` of `img` with `color`, keeping its buffer type; gray images get the
+/// luminance of the color.
+pub fn redact(mut img: DynamicImage, boxes: &[RedactionBox], color: [u8; 3]) -> DynamicImage {
+    use image::GenericImage;
+    let fill = image::Rgba([color[0], color[1], color[2], 255]);
+    for b in boxes {
+        let (x0, y0) = (b.x.min(img.width()), b.y.min(img.height()));
+        let (x1, y1) = (x0.saturating_add(b.width).min(img.width()), y0.saturating_add(b.height).min(img.height()));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                img.put_pixel(x, y, fill);
+            }
+        }
+    }
+    img
+}
+
+/// Places `img` on a larger canvas filled with the canvas color.
+///
+/// Binarized and grayscale images stay single-channel when the color is neutral,
+/// and 16-bit images stay 16-bit.
+pub fn extend_canvas(img: DynamicImage, canvas: &CanvasOptions) -> DynamicImage {
+    let (width, height, x, y) = canvas.layout(img.width(), img.height());
+    let [r, g, b] = canvas.color.0;
+    let (x, y) = (x as i64, y as i64);
+    match img {
+        DynamicImage::ImageLuma8(luma) if r == g && g == b => {
+            let mut out = image::GrayImage::from_pixel(width, height, Luma([r]));
+            image::imageops::overlay(&mut out, &luma, x, y);
+            DynamicImage::ImageLuma8(out)
+        }
+        img if img.color().bytes_per_pixel() / img.color().channel_count() > 1 => {
+            let wide = |c: u8| c as u16 * 257;
+            let mut out = ImageBuffer::from_pixel(width, height, Rgb([wide(r), wide(g), wide(b)]));
+            image::imageops::overlay(&mut out, &img.to_rgb16(), x, y);
+            DynamicImage::ImageRgb16(out)
+        }
+        img => {
+            let mut out = image::RgbImage::from_pixel(width, height, Rgb([r, g, b]));
+            image::imageops::overlay(&mut out, &img.to_rgb8(), x, y);
+            DynamicImage::ImageRgb8(out)
+        }
+    }
+}
+
+/// Resamples to the size `options` asks for, then crops `Fill` or pads `Pad` to the box.
+pub fn resize_image(img: DynamicImage, options: &ResizeOptions) -> DynamicImage {
+    let (width, height) = options.scaled_size(img.width(), img.height());
+    let mut img = if (width, height) == (img.width(), img.height()) {
+        img
+    } else {
+        img.resize_exact(width, height, options.filter.filter_type())
+    };
+    match options.size {
+        ResizeSize::Exact { width: bw, height: bh, fit: ResizeFit::Fill, gravity, .. } => {
+            let aspect = Crop::Aspect { width: bw.max(1) as f32, height: bh.max(1) as f32, gravity };
+            if let Some((x, y, w, h)) = aspect.region(img.width(), img.height()) {
+                if (w, h) != (img.width(), img.height()) {
+                    img = img.crop_imm(x, y, w, h);
+                }
+            }
+        }
+        ResizeSize::Exact { width: bw, height: bh, fit: ResizeFit::Pad, gravity, background } => {
+            // Smaller images that may not be upscaled are padded to the box's aspect
+            // ratio rather than its size
+            let aspect = AspectRatio { width: bw.max(1) as f32, height: bh.max(1) as f32 };
+            let canvas = CanvasOptions { aspect: Some(aspect), gravity, border: 0, color: background };
+            let (canvas_w, canvas_h, _, _) = canvas.layout(img.width(), img.height());
+            if (canvas_w, canvas_h) != (img.width(), img.height()) {
+                img = extend_canvas(img, &canvas);
+            }
+        }
+        _ => {}
+    }
+    img
+}
+
+/// Clarity: an unsharp mask with a large radius applied to luminance only.
+///
+/// The detail term is weighted toward midtones so shadows and highlights don't clip,
+/// and the radius scales with image size so previews match full-resolution output.
+fn apply_clarity(img: DynamicImage, strength: f32) -> DynamicImage {
+    let mut rgb_img = img.to_rgb8();
+    let (width, height) = rgb_img.dimensions();
+
+    let luma: Vec<f32> = rgb_img.as_raw()
+        .par_chunks(3)
+        .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+        .collect();
+    let luma = ImageBuffer::<Luma<f32>, _>::from_raw(width, height, luma)
+        .expect("luma buffer matches image dimensions");
+
+    let sigma = (width.max(height) as f32 / 150.0).clamp(4.0, 50.0);
+    let blurred = imageproc::filter::gaussian_blur_f32(&luma, sigma);
+
+    rgb_img.as_mut()
+        .par_chunks_mut(3)
+        .zip(luma.as_raw().par_iter().zip(blurred.as_raw().par_iter()))
+        .for_each(|(pixel, (&l, &blur))| {
+            let midtone = 1.0 - ((l / 127.5) - 1.0).powi(2);
+            let delta = (l - blur) * strength * midtone;
+            for c in pixel.iter_mut() {
+                *c = (*c as f32 + delta).clamp(0.0, 255.0) as u8;
+            }
+        });
+
+    DynamicImage::ImageRgb8(rgb_img)
+}
+
+/// The clockwise quarter turn that brings the text of `img` upright, when the method
+/// of `orientation` reads it with enough confidence.
+fn detect_orientation(img: &DynamicImage, orientation: Orientation) -> Result<Option<u32>, String> {
+    let (found, min_confidence) = match orientation {
+        Orientation::Projection { min_confidence } => (orientation::detect(img), min_confidence),
+        Orientation::Tesseract { min_confidence } => (tesseract_orientation(img)?, min_confidence),
+    };
+    Ok(found.filter(|o| o.confidence >= min_confidence).map(|o| o.degrees))
+}
+
+#[cfg(feature = "ocr")]
+fn tesseract_orientation(img: &DynamicImage) -> Result<Option<orientation::PageOrientation>, String> {
+    crate::ocr::orientation(img)
+}
+
+#[cfg(not(feature = "ocr"))]
+fn tesseract_orientation(_img: &DynamicImage) -> Result<Option<orientation::PageOrientation>, String> {
+    Err("Tesseract orientation detection is not available in this build (enable the `ocr` feature)".to_string())
+}
+
+#[cfg(feature = "ocr")]
+fn recognize_text(img: &DynamicImage, ocr: &OcrOptions) -> Result<OcrPage, String> {
+    crate::ocr::recognize(img, &ocr.language, ocr.format)
+}
+
+#[cfg(not(feature = "ocr"))]
+fn recognize_text(_img: &DynamicImage, _ocr: &OcrOptions) -> Result<OcrPage, String> {
+    Err("OCR is not available in this build (enable the `ocr` feature)".to_string())
+}
+
+/// Brightness of the bare paper under each pixel of a document, the strokes on it
+/// filled in from their surroundings. Worked out on a copy shrunk so the kernel
+/// spans at most 8 pixels, then scaled back up: lighting varies slowly.
+pub fn estimate_background(luma: &image::GrayImage, method: BackgroundMethod, radius: u32) -> image::GrayImage {
+    let (width, height) = luma.dimensions();
+    let radius = radius.max(1);
+    let shrink = radius.div_ceil(8);
+    let small = image::imageops::resize(luma, (width / shrink).max(1), (height / shrink).max(1), image::imageops::FilterType::Triangle);
+    let r = (radius / shrink).max(1) as u8;
+    let background = match method {
+        BackgroundMethod::Blur => {
+            // The max filter erases dark strokes narrower than the kernel first
+            let paper = imageproc::morphology::grayscale_dilate(&small, &imageproc::morphology::Mask::square(r));
+            imageproc::filter::gaussian_blur_f32(&paper, r as f32)
+        }
+        BackgroundMethod::RollingBall => rolling_ball(&small, r, radius as f32, shrink as f32),
+    };
+    image::imageops::resize(&background, width, height, image::imageops::FilterType::Triangle)
+}
+
+/// Morphological closing with a ball of `ball` pixels (on the original scale, in
+/// brightness levels as in ImageJ) rolled over the top of `small`, which was shrunk by
+/// `shrink`: the background is the lowest surface the ball reaches, so dips narrower
+/// than the ball, the strokes, are bridged while slow falloff is followed.
+fn rolling_ball(small: &image::GrayImage, r: u8, ball: f32, shrink: f32) -> image::GrayImage {
+    let (width, height) = small.dimensions();
+    let r = r as i32;
+    // Height of the ball's underside above its lowest point, per offset in the kernel
+    let kernel: Vec<(i32, i32, f32)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        .filter_map(|(dx, dy)| {
+            let d2 = ((dx * dx + dy * dy) as f32) * shrink * shrink;
+            (d2 <= ball * ball).then(|| (dx, dy, ball - (ball * ball - d2).sqrt()))
+        })
+        .collect();
+    let pass = |src: &[f32], dilate: bool| -> Vec<f32> {
+        (0..height as i32)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                let kernel = &kernel;
+                (0..width as i32).map(move |x| {
+                    let mut best = if dilate { f32::MIN } else { f32::MAX };
+                    for &(dx, dy, lift) in kernel {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                            continue;
+                        }
+                        let v = src[(ny as u32 * width + nx as u32) as usize];
+                        best = if dilate { best.max(v - lift) } else { best.min(v + lift) };
+                    }
+                    best
+                })
+            })
+            .collect()
+    };
+    let source: Vec<f32> = small.as_raw().iter().map(|&v| v as f32).collect();
+    let closed = pass(&pass(&source, true), false);
+    image::GrayImage::from_raw(width, height, closed.iter().map(|&v| v.clamp(0.0, 255.0) as u8).collect())
+        .expect("background buffer matches image dimensions")
+}
+
+/// Divides out uneven lighting, see `IlluminationOptions`: every pixel is scaled so the
+/// paper around it comes out white.
+fn flatten_illumination(img: DynamicImage, options: &IlluminationOptions) -> DynamicImage {
+    let luma = img.to_luma8();
+    let background = estimate_background(&luma, options.method, options.radius);
+    let gains: Vec<f32> = background.as_raw().par_iter().map(|&b| 255.0 / (b as f32).max(1.0)).collect();
+    if options.grayscale {
+        let data = luma.as_raw().par_iter().zip(&gains).map(|(&v, g)| (v as f32 * g).min(255.0) as u8).collect();
+        return DynamicImage::ImageLuma8(image::GrayImage::from_raw(luma.width(), luma.height(), data).expect("gray buffer matches image dimensions"));
+    }
+    let mut rgb = img.to_rgb8();
+    rgb.as_mut().par_chunks_mut(3).zip(gains.par_iter()).for_each(|(pixel, g)| {
+        for v in pixel.iter_mut() {
+            *v = (*v as f32 * g).min(255.0) as u8;
+        }
+    });
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Dehaze using the dark channel prior (He et al.).
+///
+/// Haze-free regions have at least one near-zero channel locally, so a bright dark
+/// channel indicates airlight. Transmission is estimated from it, smoothed to avoid
+/// halos, and the scene radiance recovered as `(I - A) / t + A`.
+fn apply_dehaze(img: DynamicImage, strength: f32) -> DynamicImage {
+    let mut rgb_img = img.to_rgb8();
+    let (width, height) = rgb_img.dimensions();
+    if width == 0 || height == 0 {
+        return DynamicImage::ImageRgb8(rgb_img);
+    }
+    let radius = (width.max(height) / 100).clamp(3, 15) as u8;
+
+    let min_channel = |scale: [f32; 3]| -> image::GrayImage {
+        let data: Vec<u8> = rgb_img.as_raw()
+            .par_chunks(3)
+            .map(|p| {
+                let m = (p[0] as f32 * scale[0])
+                    .min(p[1] as f32 * scale[1])
+                    .min(p[2] as f32 * scale[2]);
+                m.clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        let gray = image::GrayImage::from_raw(width, height, data)
+            .expect("gray buffer matches image dimensions");
+        imageproc::morphology::erode(&gray, imageproc::distance_transform::Norm::LInf, radius)
+    };
+
+    // Airlight: mean color of the brightest 0.1% of the dark channel
+    let dark = min_channel([1.0; 3]);
+    let mut order: Vec<usize> = (0..dark.as_raw().len()).collect();
+    order.par_sort_unstable_by(|&a, &b| dark.as_raw()[b].cmp(&dark.as_raw()[a]));
+    let top = (order.len() / 1000).max(1);
+    let mut airlight = [0f32; 3];
+    for &i in &order[..top] {
+        for (c, a) in airlight.iter_mut().enumerate() {
+            *a += rgb_img.as_raw()[i * 3 + c] as f32;
+        }
+    }
+    let airlight = airlight.map(|a| (a / top as f32).max(1.0));
+
+    let normalized_dark = min_channel(airlight.map(|a| 255.0 / a));
+    let transmission: Vec<f32> = normalized_dark.as_raw()
+        .par_iter()
+        .map(|&d| 1.0 - strength * 0.95 * (d as f32 / 255.0))
+        .collect();
+    let transmission = ImageBuffer::<Luma<f32>, _>::from_raw(width, height, transmission)
+        .expect("transmission buffer matches image dimensions");
+    let transmission = imageproc::filter::gaussian_blur_f32(&transmission, radius as f32 * 2.0);
+
+    rgb_img.as_mut()
+        .par_chunks_mut(3)
+        .zip(transmission.as_raw().par_iter())
+        .for_each(|(pixel, &t)| {
+            let t = t.max(0.1);
+            for (c, v) in pixel.iter_mut().enumerate() {
+                let a = airlight[c];
+                *v = ((*v as f32 - a) / t + a).clamp(0.0, 255.0) as u8;
+            }
+        });
+
+    DynamicImage::ImageRgb8(rgb_img)
+}
+
+/// Summary of a source/output comparison.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct DiffStats {
+    /// Largest per-channel difference found (0-255).
+    pub max_difference: u8,
+    /// Fraction of pixels with any channel difference.
+    pub changed_ratio: f32,
+}
+
+/// Renders an amplified per-pixel difference heatmap between two images.
+///
+/// `output` is resampled to the source dimensions if a geometric stage changed them.
+/// Unchanged pixels are black; differences ramp through blue, red and yellow to white.
+pub fn diff_heatmap(source: &DynamicImage, output: &DynamicImage, amplification: f32) -> (image::RgbImage, DiffStats) {
+    let a = source.to_rgb8();
+    let (width, height) = a.dimensions();
+    let b = if output.width() == width && output.height() == height {
+        output.to_rgb8()
+    } else {
+        output.resize_exact(width, height, image::imageops::FilterType::Triangle).to_rgb8()
+    };
+
+    let diffs: Vec<u8> = a.as_raw()
+        .par_chunks(3)
+        .zip(b.as_raw().par_chunks(3))
+        .map(|(pa, pb)| (0..3).map(|c| pa[c].abs_diff(pb[c])).max().unwrap_or(0))
+        .collect();
+
+    let max_difference = diffs.par_iter().copied().max().unwrap_or(0);
+    let changed = diffs.par_iter().filter(|&&d| d > 0).count();
+    let changed_ratio = if diffs.is_empty() { 0.0 } else { changed as f32 / diffs.len() as f32 };
+
+    let heat: Vec<u8> = diffs
+        .par_iter()
+        .flat_map_iter(|&d| heat_color((d as f32 * amplification / 255.0).clamp(0.0, 1.0)))
+        .collect();
+    let heatmap = ImageBuffer::from_raw(width, height, heat)
+        .expect("heatmap buffer matches image dimensions");
+
+    (heatmap, DiffStats { max_difference, changed_ratio })
+}
+
+/// Maps 0.0..=1.0 onto a black-blue-red-yellow-white ramp.
+fn heat_color(t: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 255.0],
+        [255.0, 0.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 255.0, 255.0],
+    ];
+    let pos = t * (STOPS.len() - 1) as f32;
+    let i = (pos as usize).min(STOPS.len() - 2);
+    let f = pos - i as f32;
+    let (lo, hi) = (STOPS[i], STOPS[i + 1]);
+    [0, 1, 2].map(|c| (lo[c] + (hi[c] - lo[c]) * f) as u8)
+}
+
+/// Converts RGB in 0..=255 to (hue degrees, saturation 0..=1, lightness 0..=1).
+pub fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let (r, g, b) = (r / 255.0, g / 255.0, b / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d <= f32::EPSILON {
+        return (0.0, 0.0, l);
+    }
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+    (h, s.min(1.0), l)
+}
+
+/// Inverse of `rgb_to_hsl`, returning RGB in 0..=255.
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (f32, f32, f32) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let hp = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (hp % 2.0 - 1.0).abs());
+    let (r, g, b) = match hp as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    ((r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0)
+}
+
+/// Precomputes the shadow and highlight tint offsets (hue minus its own luminance,
+/// scaled by strength) so tinting shifts chroma without changing brightness.
+fn split_tone_tints(opts: &SplitToning) -> ([f32; 3], [f32; 3], f32) {
+    let tint = |hue: f32, strength: f32| -> [f32; 3] {
+        let (r, g, b) = hsl_to_rgb(hue, 1.0, 0.5);
+        let l = 0.299 * r + 0.587 * g + 0.114 * b;
+        [r - l, g - l, b - l].map(|c| c * strength.clamp(0.0, 1.0) * 0.5)
+    };
+    (
+        tint(opts.shadow_hue, opts.shadow_strength),
+        tint(opts.highlight_hue, opts.highlight_strength),
+        opts.balance.clamp(-1.0, 1.0),
+    )
+}
+
+/// Blends the shifts of the two ranges bracketing the pixel's hue.
+fn apply_hsl_ranges(r: f32, g: f32, b: f32, ranges: &[(f32, HslShift); 8]) -> (f32, f32, f32) {
+    let (h, s, l) = rgb_to_hsl(r.clamp(0.0, 255.0), g.clamp(0.0, 255.0), b.clamp(0.0, 255.0));
+    if s == 0.0 {
+        return (r, g, b);
+    }
+
+    let next = ranges.iter().position(|&(center, _)| center > h).unwrap_or(0);
+    let prev = (next + ranges.len() - 1) % ranges.len();
+    let (c0, s0) = ranges[prev];
+    let (c1, s1) = ranges[next];
+    let span = (c1 - c0).rem_euclid(360.0);
+    let t = (h - c0).rem_euclid(360.0) / span;
+
+    let hue = s0.hue + (s1.hue - s0.hue) * t;
+    let sat = s0.saturation + (s1.saturation - s0.saturation) * t;
+    let lum = s0.luminance + (s1.luminance - s0.luminance) * t;
+    if hue == 0.0 && sat == 0.0 && lum == 0.0 {
+        return (r, g, b);
+    }
+
+    // Luminance moves weighted by saturation so near-neutral pixels are left alone
+    let new_s = (s * (1.0 + sat)).clamp(0.0, 1.0);
+    let new_l = (l + lum * 0.5 * s * if lum < 0.0 { l } else { 1.0 - l }).clamp(0.0, 1.0);
+    hsl_to_rgb(h + hue, new_s, new_l)
+}
+
+/// Rotates hues near `source_hue` to `target_hue`: fully within the tolerance,
+/// fading to nothing across the feather. The shift is weighted by saturation
+/// because the hue of near-neutral pixels is mostly noise.
+fn replace_hue(r: f32, g: f32, b: f32, replacement: &ColorReplacement) -> (f32, f32, f32) {
+    let (h, s, l) = rgb_to_hsl(r.clamp(0.0, 255.0), g.clamp(0.0, 255.0), b.clamp(0.0, 255.0));
+    if s == 0.0 {
+        return (r, g, b);
+    }
+    // Angular distance from the source, 0..=180
+    let distance = (h - replacement.source_hue).rem_euclid(360.0);
+    let distance = distance.min(360.0 - distance);
+    let tolerance = replacement.tolerance.max(0.0);
+    let feather = replacement.feather.max(0.0);
+    let weight = if distance <= tolerance {
+        1.0
+    } else if distance < tolerance + feather {
+        let t = (distance - tolerance) / feather;
+        1.0 - t * t * (3.0 - 2.0 * t)
+    } else {
+        return (r, g, b);
+    };
+    // Shortest way round the hue circle
+    let shift = (replacement.target_hue - replacement.source_hue + 180.0).rem_euclid(360.0) - 180.0;
+    let weight = weight * (s * 4.0).min(1.0);
+    hsl_to_rgb(h + shift * weight, s, l)
+}
+
+/// Grades every pixel through a 3D LUT, blending with the original by `strength`.
+fn apply_lut(img: DynamicImage, table: &lut::Lut3d, strength: f32) -> DynamicImage {
+    let mut rgb_img = img.to_rgb8();
+    rgb_img.as_mut().par_chunks_mut(3).for_each(|pixel| {
+        let input = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+        let graded = table.apply(input);
+        for c in 0..3 {
+            let v = input[c] + (graded[c] - input[c]) * strength;
+            pixel[c] = (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+    });
+    DynamicImage::ImageRgb8(rgb_img)
+}
+
+fn apply_denoise(img: DynamicImage, options: &DenoiseOptions) -> DynamicImage {
+    match options.method {
+        DenoiseMethod::Median => {
+            let radius = (options.strength.round() as u32).clamp(1, 5);
+            match img {
+                DynamicImage::ImageRgb8(rgb) => {
+                    DynamicImage::ImageRgb8(imageproc::filter::median_filter(&rgb, radius, radius))
+                },
+                DynamicImage::ImageLuma8(luma) => {
+                    DynamicImage::ImageLuma8(imageproc::filter::median_filter(&luma, radius, radius))
+                },
+                _ => {
+                    let rgb = img.to_rgb8();
+                    DynamicImage::ImageRgb8(imageproc::filter::median_filter(&rgb, radius, radius))
+                }
+            }
+        }
+        DenoiseMethod::Bilateral => apply_bilateral(img, options.strength.min(10.0)),
+        DenoiseMethod::BoxChroma => apply_box_chroma(img, (options.strength * 2.0).round().clamp(1.0, 16.0) as usize),
+        DenoiseMethod::NlMeans => apply_nl_means(img, options.strength.min(10.0), options.patch_size),
+    }
+}
+
+/// Non-local means over an 11x11 search window.
+///
+/// Patch distances are computed per search offset on a squared-difference image
+/// with a summed-area table, so the cost does not depend on the patch size. The
+/// image is split into row bands that are filtered in parallel.
+fn apply_nl_means(img: DynamicImage, strength: f32, patch_size: u32) -> DynamicImage {
+    const SEARCH_RADIUS: isize = 5;
+    const BAND_ROWS: usize = 16;
+    let p = (patch_size.clamp(3, 7) / 2) as isize;
+    let h2 = (strength * 10.0).max(0.5).powi(2);
+
+    let src = img.to_rgb8();
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    if width == 0 || height == 0 {
+        return DynamicImage::ImageRgb8(src);
+    }
+    let raw = src.as_raw();
+    let at = |x: isize, y: isize, c: usize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        raw[(y * width + x) * 3 + c] as f32
+    };
+    let patch_area = (3 * (2 * p + 1) * (2 * p + 1)) as f32;
+
+    let mut out = src.clone();
+    out.as_mut().par_chunks_mut(BAND_ROWS * width * 3).enumerate().for_each(|(band, rows)| {
+        let y0 = (band * BAND_ROWS) as isize;
+        let band_h = rows.len() / (width * 3);
+        // Extended region: the band plus a patch radius on every side
+        let (ew, eh) = (width + 2 * p as usize, band_h + 2 * p as usize);
+        let mut acc = vec![[0f32; 3]; width * band_h];
+        let mut total = vec![0f32; width * band_h];
+        let mut sat = vec![0f32; (ew + 1) * (eh + 1)];
+
+        for dy in -SEARCH_RADIUS..=SEARCH_RADIUS {
+            for dx in -SEARCH_RADIUS..=SEARCH_RADIUS {
+                for ey in 0..eh {
+                    let y = y0 - p + ey as isize;
+                    let mut row_sum = 0.0;
+                    for ex in 0..ew {
+                        let x = ex as isize - p;
+                        row_sum += (0..3).map(|c| (at(x, y, c) - at(x + dx, y + dy, c)).powi(2)).sum::<f32>();
+                        sat[(ey + 1) * (ew + 1) + ex + 1] = sat[ey * (ew + 1) + ex + 1] + row_sum;
+                    }
+                }
+                let span = 2 * p as usize + 1;
+                for by in 0..band_h {
+                    for x in 0..width {
+                        let box_sum = sat[(by + span) * (ew + 1) + x + span] - sat[by * (ew + 1) + x + span]
+                            - sat[(by + span) * (ew + 1) + x]
+                            + sat[by * (ew + 1) + x];
+                        let weight = (-(box_sum / patch_area) / h2).exp();
+                        let (sx, sy) = (x as isize + dx, y0 + by as isize + dy);
+                        let i = by * width + x;
+                        for (c, a) in acc[i].iter_mut().enumerate() {
+                            *a += weight * at(sx, sy, c);
+                        }
+                        total[i] += weight;
+                    }
+                }
+            }
+        }
+
+        for (i, px) in rows.chunks_mut(3).enumerate() {
+            for c in 0..3 {
+                px[c] = (acc[i][c] / total[i]).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    });
+    DynamicImage::ImageRgb8(out)
+}
+
+/// 5x5 bilateral filter. Neighbours are weighted by distance and by how close their
+/// color is, so flat areas are smoothed while edges (text strokes, outlines) survive.
+fn apply_bilateral(img: DynamicImage, strength: f32) -> DynamicImage {
+    const RADIUS: isize = 2;
+    let sigma_spatial = 1.5f32;
+    let sigma_range = 12.0 * strength;
+    let spatial: Vec<f32> = (-RADIUS..=RADIUS)
+        .flat_map(|dy| (-RADIUS..=RADIUS).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| (-((dx * dx + dy * dy) as f32) / (2.0 * sigma_spatial * sigma_spatial)).exp())
+        .collect();
+    let range_lut: Vec<f32> = (0..=3 * 255 * 255)
+        .step_by(3)
+        .map(|d| (-(d as f32) / (2.0 * sigma_range * sigma_range)).exp())
+        .collect();
+
+    let src = img.to_rgb8();
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let raw = src.as_raw();
+    let mut out = src.clone();
+    out.as_mut().par_chunks_mut(width * 3).enumerate().for_each(|(y, row)| {
+        for x in 0..width {
+            let center = &raw[(y * width + x) * 3..][..3];
+            let mut acc = [0f32; 3];
+            let mut total = 0f32;
+            for (k, &ws) in spatial.iter().enumerate() {
+                let dx = (k as isize % (2 * RADIUS + 1)) - RADIUS;
+                let dy = (k as isize / (2 * RADIUS + 1)) - RADIUS;
+                let nx = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+                let ny = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+                let p = &raw[(ny * width + nx) * 3..][..3];
+                let dist: i32 = (0..3).map(|c| (p[c] as i32 - center[c] as i32).pow(2)).sum();
+                let w = ws * range_lut[dist as usize / 3];
+                for c in 0..3 {
+                    acc[c] += w * p[c] as f32;
+                }
+                total += w;
+            }
+            for c in 0..3 {
+                row[x * 3 + c] = (acc[c] / total).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    });
+    DynamicImage::ImageRgb8(out)
+}
+
+/// Box-averages the Cb/Cr channels (BT.601) over a `(2 * radius + 1)` square; luma is kept.
+fn apply_box_chroma(img: DynamicImage, radius: usize) -> DynamicImage {
+    if let DynamicImage::ImageLuma8(_) = img {
+        return img;
+    }
+    let mut rgb = img.to_rgb8();
+    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+    let ycc: Vec<[f32; 3]> = rgb
+        .as_raw()
+        .par_chunks(3)
+        .map(|p| {
+            let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            [y, (b - y) * 0.564, (r - y) * 0.713]
+        })
+        .collect();
+
+    // Summed-area table over the chroma pair makes the box cost independent of radius
+    let mut sat = vec![[0f64; 2]; (width + 1) * (height + 1)];
+    for y in 0..height {
+        let mut row = [0f64; 2];
+        for x in 0..width {
+            let p = ycc[y * width + x];
+            row[0] += p[1] as f64;
+            row[1] += p[2] as f64;
+            let above = sat[y * (width + 1) + x + 1];
+            sat[(y + 1) * (width + 1) + x + 1] = [above[0] + row[0], above[1] + row[1]];
+        }
+    }
+
+    rgb.as_mut().par_chunks_mut(width * 3).enumerate().for_each(|(y, row)| {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let n = ((y1 - y0) * (x1 - x0)) as f64;
+            let at = |yy: usize, xx: usize| sat[yy * (width + 1) + xx];
+            let mean = [0, 1].map(|c| {
+                ((at(y1, x1)[c] - at(y0, x1)[c] - at(y1, x0)[c] + at(y0, x0)[c]) / n) as f32
+            });
+            let l = ycc[y * width + x][0];
+            let r = l + mean[1] / 0.713;
+            let b = l + mean[0] / 0.564;
+            let g = (l - 0.299 * r - 0.114 * b) / 0.587;
+            row[x * 3] = r.round().clamp(0.0, 255.0) as u8;
+            row[x * 3 + 1] = g.round().clamp(0.0, 255.0) as u8;
+            row[x * 3 + 2] = b.round().clamp(0.0, 255.0) as u8;
+        }
+    });
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Contrast limited adaptive histogram equalization of the luminance channel.
+///
+/// Each `tile_size` square gets its own equalization curve, with histogram bins
+/// clipped at `clip_limit` times the average bin height (the excess is spread
+/// over all bins) so noise in flat areas is not blown up. Pixels blend the curves
+/// of the four nearest tile centers. Color images keep their chroma: the change
+/// in luminance is added to every channel.
+fn apply_clahe(img: DynamicImage, clip_limit: f32, tile_size: u32) -> DynamicImage {
+    let luma = img.to_luma8();
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    if width == 0 || height == 0 {
+        return img;
+    }
+    let ts = tile_size as usize;
+    let (tiles_x, tiles_y) = (width.div_ceil(ts), height.div_ceil(ts));
+    let px = luma.as_raw();
+
+    let curves: Vec<[u8; 256]> = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .map(|t| {
+            let (tx, ty) = (t % tiles_x, t / tiles_x);
+            let (x0, y0) = (tx * ts, ty * ts);
+            let (x1, y1) = ((x0 + ts).min(width), (y0 + ts).min(height));
+            let mut hist = [0u32; 256];
+            for y in y0..y1 {
+                px[y * width + x0..y * width + x1].iter().for_each(|&v| hist[v as usize] += 1);
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u32;
+            let limit = ((clip_limit * count as f32 / 256.0) as u32).max(1);
+            let excess: u32 = hist.iter().map(|&n| n.saturating_sub(limit)).sum();
+            let (bonus, remainder) = (excess / 256, (excess % 256) as usize);
+            let mut acc = 0u32;
+            let mut curve = [0u8; 256];
+            for (i, n) in hist.iter().enumerate() {
+                acc += n.min(&limit) + bonus + u32::from(i < remainder);
+                curve[i] = (acc as f32 * 255.0 / count as f32).round().min(255.0) as u8;
+            }
+            curve
+        })
+        .collect();
+
+    // Fractional tile coordinate of a pixel relative to the tile centers
+    let locate = |p: usize, tiles: usize| {
+        let f = ((p as f32 + 0.5) / ts as f32 - 0.5).clamp(0.0, (tiles - 1) as f32);
+        let i0 = f.floor() as usize;
+        (i0, (i0 + 1).min(tiles - 1), f - i0 as f32)
+    };
+    let mut equalized = vec![0u8; px.len()];
+    equalized.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+        let (ty0, ty1, fy) = locate(y, tiles_y);
+        for (x, out) in row.iter_mut().enumerate() {
+            let (tx0, tx1, fx) = locate(x, tiles_x);
+            let v = px[y * width + x] as usize;
+            let c = |tx: usize, ty: usize| curves[ty * tiles_x + tx][v] as f32;
+            let top = c(tx0, ty0) * (1.0 - fx) + c(tx1, ty0) * fx;
+            let bottom = c(tx0, ty1) * (1.0 - fx) + c(tx1, ty1) * fx;
+            *out = (top * (1.0 - fy) + bottom * fy).round() as u8;
+        }
+    });
+
+    if !img.color().has_color() {
+        return DynamicImage::ImageLuma8(image::GrayImage::from_raw(width as u32, height as u32, equalized).expect("size matches"));
+    }
+    let mut rgb = img.to_rgb8();
+    rgb.as_mut().par_chunks_mut(3).zip(px.par_iter().zip(equalized.par_iter())).for_each(|(p, (&old, &new))| {
+        let delta = new as i16 - old as i16;
+        for c in p.iter_mut() {
+            *c = (*c as i16 + delta).clamp(0, 255) as u8;
+        }
+    });
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Screens a blurred copy, overexposed by `exposure` stops, over the image.
+pub fn apply_orton(img: DynamicImage, orton: &OrtonOptions) -> DynamicImage {
+    let mut rgb = img.to_rgb8();
+    let gain = 2f32.powf(orton.exposure);
+    let mut bright = rgb.clone();
+    bright.par_iter_mut().for_each(|v| *v = (*v as f32 * gain).round().min(255.0) as u8);
+    let sigma = (rgb.width().max(rgb.height()) as f32 * orton.size).max(0.5);
+    let glow = gaussian_blur(&DynamicImage::ImageRgb8(bright), sigma).to_rgb8();
+
+    let opacity = orton.opacity.clamp(0.0, 1.0);
+    rgb.par_iter_mut().zip(glow.as_raw().par_iter()).for_each(|(v, &g)| {
+        let (a, g) = (*v as f32 / 255.0, g as f32 / 255.0);
+        let screen = 1.0 - (1.0 - a) * (1.0 - g);
+        *v = ((a + (screen - a) * opacity) * 255.0).round().clamp(0.0, 255.0) as u8;
+    });
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Separable gaussian blur with a kernel of +-3 sigma and clamped edges.
+/// Gray and alpha images keep their channel layout; 16-bit input is reduced to 8-bit.
+pub fn gaussian_blur(img: &DynamicImage, sigma: f32) -> DynamicImage {
+    let radius = (sigma * 3.0).ceil() as isize;
+    let kernel: Vec<f32> = {
+        let raw: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+        let sum: f32 = raw.iter().sum();
+        raw.into_iter().map(|k| k / sum).collect()
+    };
+
+    let blur = |data: &mut [u8], width: usize, channels: usize| {
+        if width == 0 {
+            return;
+        }
+        let stride = width * channels;
+        let height = data.len() / stride;
+        let at = |i: usize, o: isize, n: usize| (i as isize + o).clamp(0, n as isize - 1) as usize;
+
+        let mut horizontal = vec![0f32; data.len()];
+        horizontal.par_chunks_mut(stride).zip(data.par_chunks(stride)).for_each(|(out, row)| {
+            for x in 0..width {
+                for c in 0..channels {
+                    out[x * channels + c] = kernel
+                        .iter()
+                        .enumerate()
+                        .map(|(k, w)| w * row[at(x, k as isize - radius, width) * channels + c] as f32)
+                        .sum();
+                }
+            }
+        });
+        data.par_chunks_mut(stride).enumerate().for_each(|(y, out)| {
+            for (i, v) in out.iter_mut().enumerate() {
+                let sum: f32 = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * horizontal[at(y, k as isize - radius, height) * stride + i])
+                    .sum();
+                *v = sum.round().clamp(0.0, 255.0) as u8;
+            }
+        });
+    };
+
+    match img {
+        DynamicImage::ImageLuma8(luma) => {
+            let mut out = luma.clone();
+            blur(out.as_mut(), luma.width() as usize, 1);
+            DynamicImage::ImageLuma8(out)
+        }
+        _ if img.color().has_alpha() => {
+            let mut out = img.to_rgba8();
+            blur(out.as_mut(), img.width() as usize, 4);
+            DynamicImage::ImageRgba8(out)
+        }
+        _ => {
+            let mut out = img.to_rgb8();
+            blur(out.as_mut(), img.width() as usize, 3);
+            DynamicImage::ImageRgb8(out)
+        }
+    }
+}
+
+/// Radial gain that is 1.0 inside `midpoint` and eases to `1.0 + amount` at the corners.
+/// Applied to linearized values since lens falloff is a loss of light, not of tone.
+fn apply_vignette(img: DynamicImage, amount: f32, midpoint: f32) -> DynamicImage {
+    let mut rgb_img = img.to_rgb8();
+    let (width, height) = (rgb_img.width() as usize, rgb_img.height() as usize);
+    if width == 0 {
+        return DynamicImage::ImageRgb8(rgb_img);
+    }
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let corner = (cx * cx + cy * cy).sqrt();
+    let to_linear: Vec<f32> = (0..256).map(|v| (v as f32 / 255.0).powf(2.2)).collect();
+
+    rgb_img.as_mut().par_chunks_mut(width * 3).enumerate().for_each(|(y, row)| {
+        let dy = y as f32 + 0.5 - cy;
+        for (x, pixel) in row.chunks_mut(3).enumerate() {
+            let dx = x as f32 + 0.5 - cx;
+            let r = (dx * dx + dy * dy).sqrt() / corner;
+            let t = ((r - midpoint) / (1.0 - midpoint).max(1e-3)).clamp(0.0, 1.0);
+            let gain = 1.0 + amount * t * t * (3.0 - 2.0 * t);
+            if gain == 1.0 {
+                continue;
+            }
+            for c in pixel.iter_mut() {
+                let v = (to_linear[*c as usize] * gain).min(1.0).powf(1.0 / 2.2);
+                *c = (v * 255.0).round() as u8;
+            }
+        }
+    });
+
+    DynamicImage::ImageRgb8(rgb_img)
+}
+
+/// Turns a scanned color negative into a positive.
+///
+/// Each channel is divided by the film base (the orange mask) and converted to density,
+/// `ln(base / v)`, which is proportional to the log exposure the film received. Stretching
+/// every channel's density range to 0..1 cancels both the mask and the differing contrast
+/// of the three emulsion layers. `base` is given in the input's own encoding; without it
+/// the base is the median of a thin border band, where frames usually show bare film.
+/// Gamma-encoded inputs are linearized first; RAW decodes already are linear.
+pub fn invert_negative(img: DynamicImage, base: Option<[u8; 3]>, linear_input: bool) -> DynamicImage {
+    let src = img.to_rgb32f();
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    if width == 0 || height == 0 {
+        return DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let linearize = |v: f32| if linear_input { v } else { v.max(0.0).powf(2.2) };
+    let px = src.as_raw();
+
+    let base = match base {
+        Some(c) => c.map(|v| linearize(v as f32 / 255.0)),
+        None => {
+            // At least 2 px, but never more than half the short side, which slivers under 4 px lack
+            let band = (width.max(height) / 50).max(2).min(width.min(height).div_ceil(2));
+            let mut samples: [Vec<f32>; 3] = Default::default();
+            for y in 0..height {
+                for x in 0..width {
+                    if x < band || y < band || x >= width - band || y >= height - band {
+                        for (c, channel) in samples.iter_mut().enumerate() {
+                            channel.push(px[(y * width + x) * 3 + c]);
+                        }
+                    }
+                }
+            }
+            samples.map(|mut channel| {
+                channel.sort_by(|a, b| a.total_cmp(b));
+                linearize(channel[channel.len() / 2])
+            })
+        }
+    };
+    let base = base.map(|b| b.max(1e-4));
+
+    let density: Vec<f32> = px
+        .par_chunks(3)
+        .flat_map_iter(|p| (0..3).map(move |c| (base[c] / linearize(p[c]).max(1e-4)).ln().max(0.0)))
+        .collect();
+
+    // Robust per-channel range: ignore the extreme 0.5% at each end (dust, sprocket holes)
+    let stride = (width * height / 65_536).max(1);
+    let range = [0, 1, 2].map(|c| {
+        let mut d: Vec<f32> = density.iter().skip(c).step_by(3 * stride).copied().collect();
+        d.sort_by(|a, b| a.total_cmp(b));
+        let (lo, hi) = (d[d.len() / 200], d[(d.len() - 1) - d.len() / 200]);
+        (lo, (hi - lo).max(1e-3))
+    });
+
+    let mut out = image::RgbImage::new(width as u32, height as u32);
+    out.as_mut().par_chunks_mut(3).zip(density.par_chunks(3)).for_each(|(pixel, d)| {
+        for c in 0..3 {
+            pixel[c] = (((d[c] - range[c].0) / range[c].1).clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    });
+    DynamicImage::ImageRgb8(out)
+}
+
+/// Entries in a sampled curve table, covering 0..=255 in quarter steps.
+const CURVE_TABLE_SIZE: usize = 1021;
+
+/// Samples each channel's composed curves into a lookup table of output values (0..=255).
+pub fn curve_tables(curves: &Curves) -> [Vec<f32>; 3] {
+    curves.channels().map(|chain| {
+        (0..CURVE_TABLE_SIZE)
+            .map(|i| {
+                let x = i as f32 / (CURVE_TABLE_SIZE - 1) as f32;
+                chain.iter().fold(x, |v, curve| evaluate_curve(curve, v)) * 255.0
+            })
+            .collect()
+    })
+}
+
+fn apply_curve(table: &[f32], v: f32) -> f32 {
+    let pos = (v / 255.0).clamp(0.0, 1.0) * (table.len() - 1) as f32;
+    let i = (pos as usize).min(table.len() - 2);
+    let t = pos - i as f32;
+    table[i] + (table[i + 1] - table[i]) * t
+}
+
+/// Evaluates a curve at `x` in 0.0..=1.0.
+pub fn evaluate_curve(curve: &Curve, x: f32) -> f32 {
+    let mut y = monotone_cubic(&curve.points, x);
+
+    // Parametric handles: raised-cosine bumps over each quarter of the range, faded
+    // out towards black and white so the endpoints never move
+    let handles = [(0.125, curve.shadows), (0.375, curve.darks), (0.625, curve.lights), (0.875, curve.highlights)];
+    let envelope = (4.0 * y * (1.0 - y)).clamp(0.0, 1.0);
+    let mut shift = 0.0;
+    for (center, amount) in handles {
+        let w = ((y - center) / 0.25).abs();
+        if w < 1.0 && amount != 0.0 {
+            shift += amount.clamp(-1.0, 1.0) * 0.25 * 0.5 * (1.0 + (std::f32::consts::PI * w).cos());
+        }
+    }
+    y += shift * envelope;
+    y.clamp(0.0, 1.0)
+}
+
+/// Fritsch-Carlson monotone cubic through `points`, flat beyond the first and last.
+fn monotone_cubic(points: &[[f32; 2]], x: f32) -> f32 {
+    let mut pts: Vec<[f32; 2]> = points.iter().map(|[px, py]| [px.clamp(0.0, 1.0), py.clamp(0.0, 1.0)]).collect();
+    pts.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    pts.dedup_by(|a, b| (a[0] - b[0]).abs() < 1e-6);
+    match pts.len() {
+        0 => return x,
+        1 => return pts[0][1],
+        _ => {}
+    }
+    if x <= pts[0][0] {
+        return pts[0][1];
+    }
+    if x >= pts[pts.len() - 1][0] {
+        return pts[pts.len() - 1][1];
+    }
+
+    let n = pts.len();
+    let secants: Vec<f32> = pts.windows(2).map(|w| (w[1][1] - w[0][1]) / (w[1][0] - w[0][0])).collect();
+    let mut tangents = vec![0.0; n];
+    tangents[0] = secants[0];
+    tangents[n - 1] = secants[n - 2];
+    for i in 1..n - 1 {
+        tangents[i] = if secants[i - 1] * secants[i] <= 0.0 { 0.0 } else { (secants[i - 1] + secants[i]) / 2.0 };
+    }
+    for i in 0..n - 1 {
+        if secants[i] == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+        } else {
+            let (a, b) = (tangents[i] / secants[i], tangents[i + 1] / secants[i]);
+            let h = a.hypot(b);
+            if h > 3.0 {
+                tangents[i] = 3.0 * a / h * secants[i];
+                tangents[i + 1] = 3.0 * b / h * secants[i];
+            }
+        }
+    }
+
+    let i = pts.windows(2).position(|w| x < w[1][0]).unwrap_or(n - 2);
+    let (x0, y0, x1, y1) = (pts[i][0], pts[i][1], pts[i + 1][0], pts[i + 1][1]);
+    let h = x1 - x0;
+    let t = (x - x0) / h;
+    let (t2, t3) = (t * t, t * t * t);
+    (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+        + (t3 - 2.0 * t2 + t) * h * tangents[i]
+        + (-2.0 * t3 + 3.0 * t2) * y1
+        + (t3 - t2) * h * tangents[i + 1]
+}
+
+/// Warps the quad `corners` (top-left, top-right, bottom-right, bottom-left, in pixels)
+/// onto a rectangle sized by the average lengths of its opposing edges.
+pub fn rectify(img: &DynamicImage, corners: [(f32, f32); 4]) -> Option<DynamicImage> {
+    use imageproc::geometric_transformations::{warp_into, Interpolation, Projection};
+    let dist = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let [tl, tr, br, bl] = corners;
+    let width = ((dist(tl, tr) + dist(bl, br)) / 2.0).round();
+    let height = ((dist(tl, bl) + dist(tr, br)) / 2.0).round();
+    if width < 2.0 || height < 2.0 {
+        return None;
+    }
+    let target = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+    let projection = Projection::from_control_points(corners, target)?;
+    let src = img.to_rgb8();
+    let mut out = image::RgbImage::new(width as u32, height as u32);
+    warp_into(&src, &projection, Interpolation::Bilinear, Rgb([0, 0, 0]), &mut out);
+    Some(DynamicImage::ImageRgb8(out))
+}
+
+/// Finds a page photographed against a contrasting background and returns its corners.
+///
+/// The luminance is split with Otsu's threshold on a small copy; the class that
+/// does not touch the border most is the page, and its corners are the extremes of
+/// x + y and x - y, which holds for the mild keystoning of copy-stand captures.
+pub fn detect_quad(img: &DynamicImage) -> Option<[(f32, f32); 4]> {
+    let small = img.thumbnail(512, 512).to_luma8();
+    let (w, h) = small.dimensions();
+    if w < 8 || h < 8 {
+        return None;
+    }
+    let level = imageproc::contrast::otsu_level(&small);
+    let border: Vec<u8> = (0..w)
+        .flat_map(|x| [(x, 0), (x, h - 1)])
+        .chain((0..h).flat_map(|y| [(0, y), (w - 1, y)]))
+        .map(|(x, y)| small.get_pixel(x, y)[0])
+        .collect();
+    let bright_border = border.iter().filter(|&&v| v > level).count() * 2 > border.len();
+    let is_page = |v: u8| (v > level) != bright_border;
+
+    let (mut tl, mut tr, mut br, mut bl) = ((0.0, 0.0, f32::MAX), (0.0, 0.0, f32::MIN), (0.0, 0.0, f32::MIN), (0.0, 0.0, f32::MAX));
+    let mut count = 0usize;
+    for (x, y, p) in small.enumerate_pixels() {
+        if !is_page(p[0]) {
+            continue;
+        }
+        count += 1;
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        if x + y < tl.2 { tl = (x, y, x + y) }
+        if x + y > br.2 { br = (x, y, x + y) }
+        if x - y > tr.2 { tr = (x, y, x - y) }
+        if x - y < bl.2 { bl = (x, y, x - y) }
+    }
+    // A page fills a clear share of the frame without being all of it
+    let share = count as f32 / (w * h) as f32;
+    if !(0.1..0.98).contains(&share) {
+        return None;
+    }
+    let (sx, sy) = (img.width() as f32 / w as f32, img.height() as f32 / h as f32);
+    Some([tl, tr, br, bl].map(|(x, y, _)| (x * sx, y * sy)))
+}
+
+/// The `(x, y, width, height)` of the document on a scan, plus `margin` pixels on each
+/// side, or `None` when no border stands out.
+///
+/// As with `detect_quad`, Otsu's threshold on a small copy splits document from
+/// background, the background being the class that covers most of the outer edge.
+/// Rows and columns are then peeled off each side while less than half of them is
+/// document, which drops solid borders and the speckle of noisy ones alike.
+pub fn content_bounds(img: &DynamicImage, margin: u32) -> Option<(u32, u32, u32, u32)> {
+    let small = img.thumbnail(512, 512).to_luma8();
+    let (w, h) = small.dimensions();
+    if w < 8 || h < 8 {
+        return None;
+    }
+    let level = imageproc::contrast::otsu_level(&small);
+    let border: Vec<u8> = (0..w)
+        .flat_map(|x| [(x, 0), (x, h - 1)])
+        .chain((0..h).flat_map(|y| [(0, y), (w - 1, y)]))
+        .map(|(x, y)| small.get_pixel(x, y)[0])
+        .collect();
+    let bright_border = border.iter().filter(|&&v| v > level).count() * 2 > border.len();
+    let is_content = |x: u32, y: u32| (small.get_pixel(x, y)[0] > level) != bright_border;
+
+    let (mut rows, mut cols) = (vec![0u32; h as usize], vec![0u32; w as usize]);
+    for y in 0..h {
+        for x in 0..w {
+            if is_content(x, y) {
+                rows[y as usize] += 1;
+                cols[x as usize] += 1;
+            }
+        }
+    }
+    // First and one-past-last index whose line is mostly document
+    let span = |counts: &[u32], length: u32| {
+        let filled = |c: &u32| *c * 2 >= length;
+        let start = counts.iter().position(filled)?;
+        let end = counts.len() - counts.iter().rev().position(filled)?;
+        Some((start as u32, end as u32))
+    };
+    let ((top, bottom), (left, right)) = (span(&rows, w)?, span(&cols, h)?);
+    // Too small a document is more likely a dark photo than a border
+    if (right - left) * (bottom - top) * 10 < w * h {
+        return None;
+    }
+
+    let (sx, sy) = (img.width() as f32 / w as f32, img.height() as f32 / h as f32);
+    let x0 = ((left as f32 * sx).floor() as u32).saturating_sub(margin);
+    let y0 = ((top as f32 * sy).floor() as u32).saturating_sub(margin);
+    let x1 = ((right as f32 * sx).ceil() as u32 + margin).min(img.width());
+    let y1 = ((bottom as f32 * sy).ceil() as u32 + margin).min(img.height());
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// The `(x, y, width, height)` region left once near-uniform margins are trimmed,
+/// keeping `margin` pixels of them, or `None` when the whole image is margin.
+///
+/// The margin color is the median of the four corners, so a speck of dust in one of
+/// them does not matter. Rows and columns are peeled off each side while all but one
+/// in 200 of their pixels are within `tolerance` of it on every channel.
+pub fn trim_bounds(img: &DynamicImage, tolerance: u8, margin: u32) -> Option<(u32, u32, u32, u32)> {
+    let rgb = img.to_rgb8();
+    let (w, h) = rgb.dimensions();
+    if w == 0 || h == 0 {
+        return None;
+    }
+    let corners = [(0, 0), (w - 1, 0), (0, h - 1), (w - 1, h - 1)].map(|(x, y)| rgb.get_pixel(x, y).0);
+    let background: [u8; 3] = std::array::from_fn(|c| {
+        let mut values = corners.map(|p| p[c]);
+        values.sort_unstable();
+        ((values[1] as u16 + values[2] as u16) / 2) as u8
+    });
+    let off = |x: u32, y: u32| {
+        let p = rgb.get_pixel(x, y).0;
+        (0..3).any(|c| p[c].abs_diff(background[c]) > tolerance)
+    };
+    let is_margin = |pixels: &mut dyn Iterator<Item = (u32, u32)>, len: u32| {
+        pixels.filter(|&(x, y)| off(x, y)).count() as u32 * 200 <= len
+    };
+
+    let (mut top, mut bottom) = (0, h);
+    while top < h && is_margin(&mut (0..w).map(|x| (x, top)), w) {
+        top += 1;
+    }
+    while bottom > top && is_margin(&mut (0..w).map(|x| (x, bottom - 1)), w) {
+        bottom -= 1;
+    }
+    let (mut left, mut right) = (0, w);
+    while left < w && is_margin(&mut (top..bottom).map(|y| (left, y)), bottom - top) {
+        left += 1;
+    }
+    while right > left && is_margin(&mut (top..bottom).map(|y| (right - 1, y)), bottom - top) {
+        right -= 1;
+    }
+    if top >= bottom || left >= right {
+        return None;
+    }
+    let (x0, y0) = (left.saturating_sub(margin), top.saturating_sub(margin));
+    let (x1, y1) = ((right + margin).min(w), (bottom + margin).min(h));
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Runs the stages of the options' order that come before `stage`, for steps that
+/// need the image as that stage will see it.
+pub fn apply_filters_before(img: DynamicImage, options: &ProcessOptions, stage: Stage) -> DynamicImage {
+    let order = options.pipeline.as_deref().unwrap_or(&DEFAULT_PIPELINE);
+    let head: Vec<Stage> = order.iter().copied().take_while(|s| *s != stage).collect();
+    run_stages(img, &head, options, &mut |_, _| {}, &mut PipelineReport::default(), &mut false, &|| false)
+}
+
+/// Share of a page covered by ink: pixels of a small copy clearly darker than the paper
+/// (under 70% of its 90th-percentile level, so bleed-through from the other side does
+/// not count), after dropping specks of dust. A 5% margin is ignored, as scanner edges
+/// and the shadow of the spine sit there.
+pub fn ink_coverage(img: &DynamicImage) -> f32 {
+    let small = img.thumbnail(600, 600).to_luma8();
+    let (w, h) = small.dimensions();
+    let (mx, my) = (w / 20, h / 20);
+    let inner = image::imageops::crop_imm(&small, mx, my, w - 2 * mx, h - 2 * my).to_image();
+    if inner.is_empty() {
+        return 0.0;
+    }
+    let mut levels = inner.as_raw().clone();
+    let at = levels.len() * 9 / 10;
+    let paper = *levels.select_nth_unstable(at).1 as f32;
+    let ink = image::GrayImage::from_fn(inner.width(), inner.height(), |x, y| {
+        Luma([if (inner.get_pixel(x, y)[0] as f32) < paper * 0.7 { 0 } else { 255 }])
+    });
+    let ink = despeckle_bilevel(&ink, 2, false);
+    ink.as_raw().iter().filter(|&&v| v == 0).count() as f32 / ink.as_raw().len() as f32
+}
+
+/// Whether at most `max_ink` of the page carries ink, see `ink_coverage`.
+pub fn is_blank_page(img: &DynamicImage, max_ink: f32) -> bool {
+    ink_coverage(img) <= max_ink
+}
+
+/// Column of the gutter between the two pages of a book spread, searched within
+/// `search` of the width on either side of the middle.
+///
+/// Columns of a small copy (top and bottom tenth left out, where the cover and the
+/// scanner lid show) are scored by their mean and spread of brightness. Text makes a
+/// column vary down the page, while the inner margins and the binding's shadow do
+/// not, so the gutter lies in the widest run of quiet columns: at its darkest point
+/// when the run dips into a shadow, in its middle on a flat scan.
+pub fn find_gutter(img: &DynamicImage, search: f32) -> u32 {
+    let small = img.thumbnail(800, 800).to_luma8();
+    let (w, h) = small.dimensions();
+    let reach = ((w as f32 * search.clamp(0.01, 0.5)) as u32).max(1);
+    let (lo, hi) = ((w / 2).saturating_sub(reach), (w / 2 + reach).min(w));
+    if hi < lo + 5 || h < 10 {
+        return img.width() / 2;
+    }
+    let (y0, y1) = (h / 10, h - h / 10);
+    let n = (y1 - y0) as f32;
+    let columns: Vec<(f32, f32)> = (lo..hi)
+        .map(|x| {
+            let (sum, sq) = (y0..y1).fold((0.0, 0.0), |(s, q), y| {
+                let v = small.get_pixel(x, y)[0] as f32;
+                (s + v, q + v * v)
+            });
+            let mean = sum / n;
+            (mean, (sq / n - mean * mean).max(0.0).sqrt())
+        })
+        .collect();
+    // Averaged over 5 columns, so the gaps between letters do not pass for margins
+    let smooth: Vec<(f32, f32)> = (0..columns.len())
+        .map(|i| {
+            let (a, b) = (i.saturating_sub(2), (i + 3).min(columns.len()));
+            let (m, d) = columns[a..b].iter().fold((0.0, 0.0), |acc, c| (acc.0 + c.0, acc.1 + c.1));
+            (m / (b - a) as f32, d / (b - a) as f32)
+        })
+        .collect();
+    let busiest = smooth.iter().fold(0.0f32, |a, c| a.max(c.1));
+    let quiet = (busiest * 0.3).max(8.0);
+
+    let (mut best, mut run) = ((0, 0), None);
+    for (i, &(_, spread)) in smooth.iter().chain([&(0.0, f32::MAX)]).enumerate() {
+        if spread <= quiet {
+            run.get_or_insert(i);
+        } else if let Some(start) = run.take() {
+            if i - start > best.1 - best.0 {
+                best = (start, i);
+            }
+        }
+    }
+    if best.1 == best.0 {
+        return img.width() / 2;
+    }
+    // Its two outer columns on each side are averaged with text, so are left out
+    if best.1 - best.0 > 4 {
+        best = (best.0 + 2, best.1 - 2);
+    }
+    let run = &smooth[best.0..best.1];
+    let (darkest, min) = run.iter().enumerate().fold((0, f32::MAX), |d, (i, c)| if c.0 < d.1 { (i, c.0) } else { d });
+    let max = run.iter().fold(0.0f32, |a, c| a.max(c.0));
+    let column = best.0 + if max - min > 20.0 { darkest } else { run.len() / 2 };
+    (((lo as usize + column) as f32 + 0.5) * img.width() as f32 / w as f32) as u32
+}
+
+/// The left and right pages of a spread, cut at `find_gutter`. Each keeps `overlap`
+/// pixels past the gutter.
+pub fn split_spread(img: &DynamicImage, search: f32, overlap: u32) -> (DynamicImage, DynamicImage) {
+    let gutter = find_gutter(img, search).min(img.width() - 1).max(1);
+    let left_width = (gutter + overlap).min(img.width());
+    let right_x = gutter.saturating_sub(overlap);
+    (img.crop_imm(0, 0, left_width, img.height()), img.crop_imm(right_x, 0, img.width() - right_x, img.height()))
+}
+
+/// Clockwise tilt in degrees of the dominant near-horizontal and near-vertical lines,
+/// or `None` when there are no clear lines or the best fit lies at `max_angle`.
+///
+/// Strong edges of a thumbnail are projected onto the normal of each candidate angle.
+/// Straight lines collapse into a few bins at their true angle, so the angle whose
+/// projection is most concentrated (largest sum of squared bin counts) wins.
+pub fn estimate_tilt(img: &DynamicImage, max_angle: f32) -> Option<f32> {
+    let max_angle = max_angle.clamp(0.5, 45.0);
+    let small = img.thumbnail(1024, 1024).to_luma8();
+    let (w, h) = small.dimensions();
+    if w < 16 || h < 16 {
+        return None;
+    }
+    let gx = imageproc::gradients::horizontal_sobel(&small);
+    let gy = imageproc::gradients::vertical_sobel(&small);
+
+    // Edge points as (x, y, lies on a near-horizontal line), from the strongest tenth of
+    // the gradients whose direction is compatible with a line inside the search range
+    let slope = (max_angle + 10.0).min(45.0).to_radians().tan();
+    let mut candidates = Vec::new();
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let (dx, dy) = (gx.get_pixel(x, y)[0] as f32, gy.get_pixel(x, y)[0] as f32);
+            let magnitude = dx * dx + dy * dy;
+            // Below this is noise or gentle shading (Sobel reaches 1020 on a hard edge)
+            if magnitude < 64.0 * 64.0 {
+                continue;
+            }
+            if dx.abs() <= slope * dy.abs() {
+                candidates.push((magnitude, x as f32, y as f32, true));
+            } else if dy.abs() <= slope * dx.abs() {
+                candidates.push((magnitude, x as f32, y as f32, false));
+            }
+        }
+    }
+    if candidates.len() < 64 {
+        return None;
+    }
+    let keep = (candidates.len() / 10).max(64);
+    candidates.select_nth_unstable_by(keep - 1, |a, b| b.0.total_cmp(&a.0));
+    candidates.truncate(keep);
+
+    let points: Vec<(f32, f32, bool)> = candidates.into_iter().map(|(_, x, y, horizontal)| (x, y, horizontal)).collect();
+    projection_angle(&points, (w + h) as usize, max_angle)
+}
+
+/// Finds the rotation in `-max_angle..=max_angle` that packs `points` (x, y, lies on a
+/// near-horizontal line) into the fewest projection bins: a sum of squared bin counts,
+/// searched in quarter degrees and refined to a fortieth. `reach` bounds |x| + |y|.
+fn projection_angle(points: &[(f32, f32, bool)], reach: usize, max_angle: f32) -> Option<f32> {
+    // Projections lie within -reach..=reach, one run of bins per line direction
+    let span = 2 * reach + 1;
+    let score = |tilt: f32| {
+        let (sin, cos) = tilt.to_radians().sin_cos();
+        let mut bins = vec![0u32; 2 * span];
+        for &(x, y, horizontal) in points {
+            let (rho, offset) = if horizontal { (y * cos - x * sin, 0) } else { (x * cos + y * sin, span) };
+            bins[offset + (rho + reach as f32).round().clamp(0.0, (span - 1) as f32) as usize] += 1;
+        }
+        bins.iter().map(|&n| n as f64 * n as f64).sum::<f64>()
+    };
+    let search = |from: f32, step: f32, steps: usize| {
+        (0..=steps)
+            .into_par_iter()
+            .map(|i| (from + i as f32 * step, score(from + i as f32 * step)))
+            .collect::<Vec<_>>()
+    };
+
+    let coarse_step = 0.25;
+    let coarse = search(-max_angle, coarse_step, (2.0 * max_angle / coarse_step).round() as usize);
+    let (best, peak) = coarse.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let mean = coarse.iter().map(|c| c.1).sum::<f64>() / coarse.len() as f64;
+    // A limit hit means the scene is tilted further than allowed (or has no horizon);
+    // a flat response means there are no straight lines to trust
+    if best.abs() > max_angle - coarse_step / 2.0 || peak < mean * 1.1 {
+        return None;
+    }
+    let fine = search(best - coarse_step, coarse_step / 10.0, 20);
+    fine.into_iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(|(tilt, _)| tilt)
+}
+
+/// Estimates the clockwise skew of a scanned page, in degrees, from its lines of text:
+/// the ink of an Otsu-binarized copy projects onto sharp row peaks once the angle is
+/// right. `None` when the page has too little ink or no clear lines within `max_angle`.
+pub fn estimate_skew(img: &DynamicImage, max_angle: f32) -> Option<f32> {
+    let max_angle = max_angle.clamp(0.5, 45.0);
+    // Large enough that the gaps between lines of body text survive
+    let small = img.thumbnail(1600, 1600).to_luma8();
+    let (w, h) = small.dimensions();
+    if w < 16 || h < 16 {
+        return None;
+    }
+    let level = imageproc::contrast::otsu_level(&small);
+    let dark = small.as_raw().iter().filter(|&&v| v <= level).count();
+    // Ink is the minority class, which also covers light text on a dark page
+    let ink_is_dark = dark * 2 <= small.as_raw().len();
+    let ink = if ink_is_dark { dark } else { small.as_raw().len() - dark };
+    if ink < 256 {
+        return None;
+    }
+    // A few hundred thousand points pin the angle as well as all of them
+    let stride = (ink / 200_000).max(1);
+    let points: Vec<(f32, f32, bool)> = small
+        .enumerate_pixels()
+        .filter(|(_, _, p)| (p[0] <= level) == ink_is_dark)
+        .step_by(stride)
+        .map(|(x, y, _)| (x as f32, y as f32, true))
+        .collect();
+    projection_angle(&points, (w + h) as usize, max_angle)
+}
+
+/// Squares up a page skewed clockwise by `skew` degrees, keeping its dimensions; the
+/// corners rotated in from outside the scan are filled with `fill`.
+pub fn deskew_image(img: DynamicImage, skew: f32, fill: [u8; 3]) -> DynamicImage {
+    let (w, h) = (img.width(), img.height());
+    let rotated = rotate_image(img, -skew, fill);
+    let (x, y) = (rotated.width().saturating_sub(w) / 2, rotated.height().saturating_sub(h) / 2);
+    rotated.crop_imm(x, y, w.min(rotated.width()), h.min(rotated.height()))
+}
+
+/// Levels an image tilted clockwise by `tilt` degrees. With `crop`, keeps the largest
+/// centered rectangle of the original aspect ratio, so none of `fill` shows.
+pub fn straighten_image(img: DynamicImage, tilt: f32, crop: bool, fill: [u8; 3]) -> DynamicImage {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let rotated = rotate_image(img, -tilt, fill);
+    if !crop {
+        return rotated;
+    }
+    let (sin, cos) = tilt.abs().to_radians().sin_cos();
+    let scale = (w / (w * cos + h * sin)).min(h / (w * sin + h * cos));
+    let (cw, ch) = (((w * scale).floor() as u32).max(1), ((h * scale).floor() as u32).max(1));
+    let (x, y) = (rotated.width().saturating_sub(cw) / 2, rotated.height().saturating_sub(ch) / 2);
+    rotated.crop_imm(x, y, cw.min(rotated.width()), ch.min(rotated.height()))
+}
+
+/// Rotates clockwise by `degrees`. Quarter turns move pixels without resampling and keep
+/// the buffer type; other angles are bilinearly resampled onto an enlarged RGB canvas
+/// whose uncovered corners are filled with `fill`.
+pub fn rotate_image(img: DynamicImage, degrees: f32, fill: [u8; 3]) -> DynamicImage {
+    let degrees = degrees.rem_euclid(360.0);
+    let quarter = degrees / 90.0;
+    if (quarter - quarter.round()).abs() < 1e-4 {
+        return match quarter.round() as u32 % 4 {
+            1 => img.rotate90(),
+            2 => img.rotate180(),
+            3 => img.rotate270(),
+            _ => img,
+        };
+    }
+
+    use imageproc::geometric_transformations::{warp_into, Interpolation, Projection};
+    let src = img.to_rgb8();
+    let (w, h) = (src.width() as f32, src.height() as f32);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (out_w, out_h) = ((w * cos.abs() + h * sin.abs()).round().max(1.0), (w * sin.abs() + h * cos.abs()).round().max(1.0));
+    let projection = Projection::translate(out_w / 2.0, out_h / 2.0)
+        * Projection::rotate(degrees.to_radians())
+        * Projection::translate(-w / 2.0, -h / 2.0);
+    let mut out = image::RgbImage::new(out_w as u32, out_h as u32);
+    warp_into(&src, &projection, Interpolation::Bilinear, Rgb(fill), &mut out);
+    DynamicImage::ImageRgb8(out)
+}
+
+/// Whitens black connected components (8-connected) of at most `max_area` pixels and,
+/// with `fill_holes`, blackens white ones (4-connected, the dual) of the same size,
+/// so a dot on an "i" survives a small `max_area` while scanner dust does not.
+pub fn despeckle_bilevel(luma: &image::GrayImage, max_area: u32, fill_holes: bool) -> image::GrayImage {
+    use imageproc::region_labelling::{connected_components, Connectivity};
+
+    let mut out = luma.clone();
+    let mut pass = |ink: u8, connectivity: Connectivity| {
+        let labels = connected_components(&out, connectivity, image::Luma([255 - ink]));
+        let mut areas = vec![0u32; 1];
+        for label in labels.pixels() {
+            let l = label[0] as usize;
+            if l >= areas.len() {
+                areas.resize(l + 1, 0);
+            }
+            areas[l] += 1;
+        }
+        for (p, label) in out.pixels_mut().zip(labels.pixels()) {
+            if label[0] != 0 && areas[label[0] as usize] <= max_area {
+                p[0] = 255 - ink;
+            }
+        }
+    };
+    pass(0, Connectivity::Eight);
+    if fill_holes {
+        pass(255, Connectivity::Four);
+    }
+    out
+}
+
+/// Binarizes against a per-pixel threshold computed from the mean and standard deviation
+/// of the `window` x `window` neighborhood (clipped at the borders). Summed-area tables
+/// keep the cost independent of the window size.
+pub fn local_threshold(luma: &image::GrayImage, window: u32, threshold: impl Fn(f32, f32) -> f32 + Sync) -> image::GrayImage {
+    let (w, h) = luma.dimensions();
+    let (wu, hu) = (w as usize, h as usize);
+    let radius = (window.max(3) / 2) as usize;
+    // Tables are one larger than the image in each direction, with a zero first row and column
+    let stride = wu + 1;
+    let mut sum = vec![0f64; stride * (hu + 1)];
+    let mut squares = vec![0f64; stride * (hu + 1)];
+    for y in 0..hu {
+        let (mut row, mut row_sq) = (0f64, 0f64);
+        for x in 0..wu {
+            let v = luma.as_raw()[y * wu + x] as f64;
+            row += v;
+            row_sq += v * v;
+            sum[(y + 1) * stride + x + 1] = sum[y * stride + x + 1] + row;
+            squares[(y + 1) * stride + x + 1] = squares[y * stride + x + 1] + row_sq;
+        }
+    }
+
+    let mut out = vec![0u8; wu * hu];
+    out.par_chunks_mut(wu.max(1)).enumerate().for_each(|(y, row)| {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(hu));
+        for (x, px) in row.iter_mut().enumerate() {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(wu));
+            let area = |t: &[f64]| t[y1 * stride + x1] - t[y0 * stride + x1] - t[y1 * stride + x0] + t[y0 * stride + x0];
+            let n = ((x1 - x0) * (y1 - y0)) as f64;
+            let mean = area(&sum) / n;
+            let std = (area(&squares) / n - mean * mean).max(0.0).sqrt();
+            *px = if luma.as_raw()[y * wu + x] as f32 > threshold(mean as f32, std as f32) { 255 } else { 0 };
+        }
+    });
+    image::GrayImage::from_raw(w, h, out).expect("buffer matches dimensions")
+}
+
+/// Ordered dithering against a Bayer threshold matrix; `size` is rounded to 2, 4 or 8.
+pub fn dither_bayer(luma: &image::GrayImage, size: u32) -> image::GrayImage {
+    let size = match size {
+        0..=2 => 2,
+        3..=4 => 4,
+        _ => 8,
+    };
+    // Built recursively: M(2n) = [[4M, 4M + 2], [4M + 3, 4M + 1]]
+    let mut matrix = vec![0u32];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0u32; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let v = 4 * matrix[y * n + x];
+                next[y * 2 * n + x] = v;
+                next[y * 2 * n + x + n] = v + 2;
+                next[(y + n) * 2 * n + x] = v + 3;
+                next[(y + n) * 2 * n + x + n] = v + 1;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+    let cells = (size * size) as f32;
+    let thresholds: Vec<f32> = matrix.iter().map(|&m| (m as f32 + 0.5) / cells * 255.0).collect();
+
+    let mut out = luma.clone();
+    let width = luma.width() as usize;
+    out.as_mut().par_chunks_mut(width.max(1)).enumerate().for_each(|(y, row)| {
+        let cell_row = &thresholds[(y % size) * size..][..size];
+        for (x, v) in row.iter_mut().enumerate() {
+            *v = if *v as f32 > cell_row[x % size] { 255 } else { 0 };
+        }
+    });
+    out
+}
+
+/// Floyd-Steinberg error diffusion, scanning in serpentine order to avoid directional streaks.
+pub fn dither_floyd_steinberg(luma: &image::GrayImage) -> image::GrayImage {
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    let mut values: Vec<f32> = luma.as_raw().iter().map(|&v| v as f32).collect();
+    let mut out = vec![0u8; width * height];
+    for y in 0..height {
+        let reverse = y % 2 == 1;
+        // Neighbour `dx` pixels further along the scan direction, if inside the row
+        let along = |x: usize, dx: isize| {
+            let n = x as isize + if reverse { -dx } else { dx };
+            (0..width as isize).contains(&n).then_some(n as usize)
+        };
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let old = values[y * width + x];
+            let new = if old > 127.5 { 255.0 } else { 0.0 };
+            out[y * width + x] = new as u8;
+            let error = old - new;
+            if let Some(n) = along(x, 1) {
+                values[y * width + n] += error * 7.0 / 16.0;
+            }
+            if y + 1 < height {
+                let below = (y + 1) * width;
+                if let Some(n) = along(x, -1) {
+                    values[below + n] += error * 3.0 / 16.0;
+                }
+                values[below + x] += error * 5.0 / 16.0;
+                if let Some(n) = along(x, 1) {
+                    values[below + n] += error / 16.0;
+                }
+            }
+        }
+    }
+    image::GrayImage::from_raw(width as u32, height as u32, out).expect("same dimensions as input")
+}
+
+/// Stable 64-bit FNV-1a hash of a file name, used to seed per-image noise.
+pub fn seed_from_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+/// SplitMix64 finalizer: maps a lattice coordinate to a uniform value in -1.0..1.0.
+fn lattice_noise(seed: u64, x: i64, y: i64) -> f32 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+/// Monochromatic film grain: value noise on a `size`-pixel lattice, bilinearly
+/// interpolated so grains clump, strongest in the midtones like real emulsion.
+fn apply_grain(img: DynamicImage, amount: f32, size: f32, seed: u64) -> DynamicImage {
+    let mut rgb_img = img.to_rgb8();
+    let width = rgb_img.width() as usize;
+    if width == 0 {
+        return DynamicImage::ImageRgb8(rgb_img);
+    }
+    let sigma = amount * 40.0;
+
+    rgb_img.as_mut().par_chunks_mut(width * 3).enumerate().for_each(|(y, row)| {
+        let gy = y as f32 / size;
+        let (y0, fy) = (gy.floor() as i64, gy.fract());
+        for (x, pixel) in row.chunks_mut(3).enumerate() {
+            let gx = x as f32 / size;
+            let (x0, fx) = (gx.floor() as i64, gx.fract());
+            let top = lattice_noise(seed, x0, y0) * (1.0 - fx) + lattice_noise(seed, x0 + 1, y0) * fx;
+            let bottom = lattice_noise(seed, x0, y0 + 1) * (1.0 - fx) + lattice_noise(seed, x0 + 1, y0 + 1) * fx;
+            let noise = top * (1.0 - fy) + bottom * fy;
+
+            let l = (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) / 255.0;
+            let delta = noise * sigma * (4.0 * l * (1.0 - l)).max(0.15);
+            for c in pixel.iter_mut() {
+                *c = (*c as f32 + delta).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    });
+
+    DynamicImage::ImageRgb8(rgb_img)
+}
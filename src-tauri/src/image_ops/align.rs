@@ -0,0 +1,327 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Image Alignment
+ *
+ * Sub-pixel registration of frames against a reference for the stacking
+ * features (exposure merge, focus stacking, frame averaging). Rotation is
+ * found by a small search over thumbnails scored with normalized
+ * cross-correlation, then translation by phase correlation with a parabolic
+ * peak fit at working size.
+ */
+use image::{DynamicImage, GrayImage, RgbImage};
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::Serialize;
+
+/// Long edge of the thumbnails used for the rotation search.
+const ROTATION_SIZE: u32 = 256;
+/// Long edge the translation is measured at; results are scaled back to full size.
+const TRANSLATION_SIZE: u32 = 1024;
+/// Largest rotation considered, in degrees, and the search step.
+const MAX_ROTATION: f32 = 5.0;
+const ROTATION_STEP: f32 = 0.5;
+/// Gaussian roll-off of the whitened spectrum, as a fraction of the sampling rate.
+const BAND: f32 = 0.15;
+
+/// Maps a frame onto its reference: the frame's content is the reference rotated by
+/// `rotation` degrees about the image center, then shifted by (`dx`, `dy`) pixels.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub dx: f32,
+    pub dy: f32,
+    pub rotation: f32,
+    /// Normalized cross-correlation of the aligned thumbnails, 0.0..=1.0. Values near
+    /// zero mean the frames share no structure and the transform should not be trusted.
+    pub confidence: f32,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.dx == 0.0 && self.dy == 0.0 && self.rotation == 0.0
+    }
+}
+
+/// Estimates the transform of every frame relative to `frames[reference]`.
+pub fn align_to(frames: &[DynamicImage], reference: usize) -> Result<Vec<Transform>, String> {
+    let base = frames.get(reference).ok_or("Alignment reference out of range")?;
+    if frames.iter().any(|f| f.width() != base.width() || f.height() != base.height()) {
+        return Err("All frames must have the same dimensions to be aligned".to_string());
+    }
+    let base = Pyramid::new(base);
+    frames
+        .par_iter()
+        .enumerate()
+        .map(|(k, frame)| {
+            if k == reference {
+                Ok(Transform { confidence: 1.0, ..Default::default() })
+            } else {
+                Ok(base.estimate(&Pyramid::new(frame)))
+            }
+        })
+        .collect()
+}
+
+/// Estimates how `moving` is displaced relative to `reference`.
+pub fn estimate(reference: &DynamicImage, moving: &DynamicImage) -> Result<Transform, String> {
+    align_to(&[reference.clone(), moving.clone()], 0).map(|t| t[1])
+}
+
+/// Resamples `img` into the reference frame described by `transform`.
+/// Areas that fall outside the source repeat its edge pixels so stacking
+/// weights are not skewed by black borders.
+pub fn warp(img: &DynamicImage, transform: &Transform) -> RgbImage {
+    let src = img.to_rgb8();
+    if transform.is_identity() {
+        return src;
+    }
+    let (w, h) = (src.width() as usize, src.height() as usize);
+    let center = ((w as f32 - 1.0) / 2.0, (h as f32 - 1.0) / 2.0);
+    let (sin, cos) = transform.rotation.to_radians().sin_cos();
+    let raw = src.as_raw();
+
+    let mut out = RgbImage::new(w as u32, h as u32);
+    out.as_mut().par_chunks_mut(w * 3).enumerate().for_each(|(y, row)| {
+        for x in 0..w {
+            let (px, py) = (x as f32 - center.0, y as f32 - center.1);
+            let sx = cos * px - sin * py + center.0 + transform.dx;
+            let sy = sin * px + cos * py + center.1 + transform.dy;
+            for c in 0..3 {
+                row[x * 3 + c] = bilinear(|xx, yy| raw[(yy * w + xx) * 3 + c] as f32, w, h, sx, sy)
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+        }
+    });
+    out
+}
+
+/// Luminance of one frame at the two working sizes.
+struct Pyramid {
+    full_width: u32,
+    coarse: Plane,
+    fine: Plane,
+}
+
+impl Pyramid {
+    fn new(img: &DynamicImage) -> Self {
+        let luma = img.to_luma8();
+        Self {
+            full_width: img.width(),
+            coarse: Plane::from_luma(&downscale(&luma, ROTATION_SIZE)),
+            fine: Plane::from_luma(&downscale(&luma, TRANSLATION_SIZE)),
+        }
+    }
+
+    fn estimate(&self, moving: &Self) -> Transform {
+        // Rotation: undo each candidate angle, register the translation and keep the
+        // angle whose aligned thumbnail correlates best with the reference
+        let steps = (MAX_ROTATION / ROTATION_STEP).round() as i32;
+        let scores: Vec<f32> = (-steps..=steps)
+            .into_par_iter()
+            .map(|i| {
+                let degrees = i as f32 * ROTATION_STEP;
+                let (dx, dy) = phase_correlate(&moving.coarse.resampled(degrees, (0.0, 0.0)), &self.coarse);
+                moving.coarse.resampled(degrees, (dx, dy)).correlation(&self.coarse)
+            })
+            .collect();
+        let best = scores
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i)
+            .unwrap_or(steps as usize);
+        let refine = if best > 0 && best + 1 < scores.len() {
+            parabolic_offset(scores[best - 1], scores[best], scores[best + 1])
+        } else {
+            0.0
+        };
+        let rotation = (best as f32 - steps as f32 + refine) * ROTATION_STEP;
+
+        // Translation of the derotated frame, rotated back into the frame's own axes
+        let (dx, dy) = phase_correlate(&moving.fine.resampled(rotation, (0.0, 0.0)), &self.fine);
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        let scale = self.full_width as f32 / self.fine.w as f32;
+        Transform {
+            dx: (cos * dx - sin * dy) * scale,
+            dy: (sin * dx + cos * dy) * scale,
+            rotation,
+            confidence: scores[best].clamp(0.0, 1.0),
+        }
+    }
+}
+
+fn downscale(luma: &GrayImage, long_edge: u32) -> GrayImage {
+    if luma.width().max(luma.height()) <= long_edge {
+        return luma.clone();
+    }
+    let scale = long_edge as f32 / luma.width().max(luma.height()) as f32;
+    let w = ((luma.width() as f32 * scale).round() as u32).max(1);
+    let h = ((luma.height() as f32 * scale).round() as u32).max(1);
+    image::imageops::resize(luma, w, h, image::imageops::FilterType::Triangle)
+}
+
+#[derive(Clone)]
+struct Plane {
+    w: usize,
+    h: usize,
+    data: Vec<f32>,
+}
+
+impl Plane {
+    fn from_luma(luma: &GrayImage) -> Self {
+        Self {
+            w: luma.width() as usize,
+            h: luma.height() as usize,
+            data: luma.as_raw().iter().map(|&v| v as f32).collect(),
+        }
+    }
+
+    /// Samples the plane at `R(degrees) * (p - center + shift) + center`, which undoes
+    /// a rotation about the center followed by a shift of `R(degrees) * shift`.
+    fn resampled(&self, degrees: f32, shift: (f32, f32)) -> Self {
+        if degrees == 0.0 && shift == (0.0, 0.0) {
+            return self.clone();
+        }
+        let (w, h) = (self.w, self.h);
+        let center = ((w as f32 - 1.0) / 2.0, (h as f32 - 1.0) / 2.0);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut data = vec![0.0; w * h];
+        data.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+            for (x, v) in row.iter_mut().enumerate() {
+                let (px, py) = (x as f32 - center.0 + shift.0, y as f32 - center.1 + shift.1);
+                let sx = cos * px - sin * py + center.0;
+                let sy = sin * px + cos * py + center.1;
+                *v = bilinear(|xx, yy| self.data[yy * w + xx], w, h, sx, sy);
+            }
+        });
+        Self { w, h, data }
+    }
+
+    /// Normalized cross-correlation over the central 80% (borders hold clamped samples).
+    fn correlation(&self, other: &Self) -> f32 {
+        let (mx, my) = (self.w / 10, self.h / 10);
+        let pixels: Vec<(f32, f32)> = (my..self.h - my)
+            .flat_map(|y| (mx..self.w - mx).map(move |x| y * self.w + x))
+            .map(|i| (self.data[i], other.data[i]))
+            .collect();
+        let n = pixels.len().max(1) as f32;
+        let (ma, mb) = pixels.iter().fold((0.0, 0.0), |(a, b), p| (a + p.0 / n, b + p.1 / n));
+        let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+        for (a, b) in pixels {
+            ab += (a - ma) * (b - mb);
+            aa += (a - ma) * (a - ma);
+            bb += (b - mb) * (b - mb);
+        }
+        if aa * bb > 0.0 { ab / (aa * bb).sqrt() } else { 0.0 }
+    }
+
+    /// Zero-mean, Hann-windowed copy ready for the FFT; the window keeps the
+    /// image borders from dominating the spectrum.
+    fn windowed(&self) -> Vec<Complex<f32>> {
+        let mean = self.data.iter().sum::<f32>() / self.data.len().max(1) as f32;
+        let hann = |i: usize, n: usize| {
+            if n < 2 {
+                1.0
+            } else {
+                0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (n - 1) as f32).cos()
+            }
+        };
+        self.data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| Complex::new((v - mean) * hann(i % self.w, self.w) * hann(i / self.w, self.h), 0.0))
+            .collect()
+    }
+}
+
+/// Bilinear sample of a `w` x `h` grid with clamped edges.
+pub(crate) fn bilinear(at: impl Fn(usize, usize) -> f32, w: usize, h: usize, x: f32, y: f32) -> f32 {
+    let x = x.clamp(0.0, (w - 1) as f32);
+    let y = y.clamp(0.0, (h - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Vertex offset, in -0.5..=0.5 samples, of the parabola through three neighbouring values.
+fn parabolic_offset(left: f32, center: f32, right: f32) -> f32 {
+    let denom = left - 2.0 * center + right;
+    if denom.abs() < 1e-12 {
+        0.0
+    } else {
+        (0.5 * (left - right) / denom).clamp(-0.5, 0.5)
+    }
+}
+
+/// Returns the shift (dx, dy) with `moving(p) ≈ reference(p - d)`.
+fn phase_correlate(moving: &Plane, reference: &Plane) -> (f32, f32) {
+    let (w, h) = (reference.w, reference.h);
+    let mut planner = FftPlanner::<f32>::new();
+    let mut a = moving.windowed();
+    let mut b = reference.windowed();
+    fft2(&mut planner, &mut a, w, h, false);
+    fft2(&mut planner, &mut b, w, h, false);
+
+    // Normalized cross-power spectrum keeps only the phase difference. Whitening
+    // boosts the near-empty high frequencies to the level of real structure, so
+    // they are rolled off again to keep sensor noise from drowning the peak.
+    a.par_chunks_mut(w).zip(b.par_chunks(w)).enumerate().for_each(|(fy, (row_a, row_b))| {
+        let ny = fy.min(h - fy) as f32 / h as f32;
+        for (fx, (x, y)) in row_a.iter_mut().zip(row_b.iter()).enumerate() {
+            let nx = fx.min(w - fx) as f32 / w as f32;
+            let cross = *x * y.conj();
+            let norm = cross.norm();
+            let rolloff = (-(nx * nx + ny * ny) / (2.0 * BAND * BAND)).exp();
+            *x = if norm > 1e-12 { cross * (rolloff / norm) } else { Complex::new(0.0, 0.0) };
+        }
+    });
+    fft2(&mut planner, &mut a, w, h, true);
+
+    let n = (w * h) as f32;
+    let surface: Vec<f32> = a.iter().map(|c| c.re / n).collect();
+    let (peak, &height) = surface
+        .iter()
+        .enumerate()
+        .max_by(|x, y| x.1.total_cmp(y.1))
+        .unwrap_or((0, &0.0));
+    let (px, py) = (peak % w, peak / w);
+    let at = |x: isize, y: isize| {
+        surface[(y.rem_euclid(h as isize) as usize) * w + x.rem_euclid(w as isize) as usize]
+    };
+    let (px_i, py_i) = (px as isize, py as isize);
+    let sub_x = parabolic_offset(at(px_i - 1, py_i), height, at(px_i + 1, py_i));
+    let sub_y = parabolic_offset(at(px_i, py_i - 1), height, at(px_i, py_i + 1));
+
+    // Peaks past the midpoint are negative shifts wrapped around
+    let unwrap = |p: usize, n: usize| if p > n / 2 { p as f32 - n as f32 } else { p as f32 };
+    (unwrap(px, w) + sub_x, unwrap(py, h) + sub_y)
+}
+
+/// In-place 2D FFT: rows, then columns through a transpose.
+fn fft2(planner: &mut FftPlanner<f32>, data: &mut [Complex<f32>], w: usize, h: usize, inverse: bool) {
+    let plan = |planner: &mut FftPlanner<f32>, n: usize| {
+        if inverse { planner.plan_fft_inverse(n) } else { planner.plan_fft_forward(n) }
+    };
+    let rows = plan(planner, w);
+    data.par_chunks_mut(w).for_each(|row| rows.process(row));
+
+    let mut transposed = vec![Complex::new(0.0, 0.0); w * h];
+    transposed.par_chunks_mut(h).enumerate().for_each(|(x, col)| {
+        for (y, v) in col.iter_mut().enumerate() {
+            *v = data[y * w + x];
+        }
+    });
+    let cols = plan(planner, h);
+    transposed.par_chunks_mut(h).for_each(|col| cols.process(col));
+
+    data.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+        for (x, v) in row.iter_mut().enumerate() {
+            *v = transposed[x * h + y];
+        }
+    });
+}
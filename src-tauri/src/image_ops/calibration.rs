@@ -0,0 +1,339 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Color Calibration
+ *
+ * Reads a color target photographed with a batch and computes the 3x3 matrix,
+ * in linear light, that brings its patches closest to their reference values.
+ * The target is found as a grid of uniform, similarly sized patches; for
+ * cluttered captures the outer corners of the patch grid can be given
+ * instead. Since the grid alone does not say which way up the target is,
+ * every turn that fits its shape is tried and the one the matrix fits best
+ * is kept. IT8 targets are measured one by one by their maker, so their
+ * reference values come from the CGATS file shipped with each batch.
+ */
+use image::RgbImage;
+use imageproc::geometric_transformations::Projection;
+use serde::Serialize;
+use crate::commands::ColorTarget;
+
+/// sRGB values of the 24 ColorChecker Classic patches, row by row from the brown
+/// patch at the top left, as published by X-Rite for charts made since 2014.
+pub const COLORCHECKER_24: [[u8; 3]; 24] = [
+    [115, 82, 68], [194, 150, 130], [98, 122, 157], [87, 108, 67], [133, 128, 177], [103, 189, 170],
+    [214, 126, 44], [80, 91, 166], [193, 90, 99], [94, 60, 108], [157, 188, 64], [224, 163, 46],
+    [56, 61, 150], [70, 148, 73], [175, 54, 60], [231, 199, 31], [187, 86, 149], [8, 133, 161],
+    [243, 243, 242], [200, 200, 200], [160, 160, 160], [122, 122, 121], [85, 85, 85], [52, 52, 52],
+];
+
+/// Rows (A to L) and columns (1 to 22) of the patch field of an IT8.7/1 or /2 target.
+pub const IT8_ROWS: usize = 12;
+pub const IT8_COLS: usize = 22;
+
+/// CIE XYZ under D50 to linear sRGB, Bradford-adapted to its D65 white.
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133856, -1.616867, -0.4906146],
+    [-0.9787684, 1.916141, 0.033454],
+    [0.0719453, -0.2289914, 1.405243],
+];
+
+/// Long edge captures are reduced to before looking for the target.
+const MAX_EDGE: u32 = 800;
+/// Largest channel difference from a region's first pixel still counted as the same patch.
+const TOLERANCE: i16 = 14;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Calibration {
+    /// Correction of linear sRGB values, rows giving the corrected R, G and B.
+    pub matrix: [[f32; 3]; 3],
+    /// Outer corners of the patch grid, starting at the target's top left and going
+    /// clockwise, in pixels; they can be passed back as manual corners.
+    pub corners: [(f32, f32); 4],
+    /// Patch colors as captured, row by row.
+    pub measured: Vec<[u8; 3]>,
+    /// Mean CIE76 difference of the patches from their reference values, as captured
+    /// and once corrected.
+    pub delta_e_before: f32,
+    pub delta_e_after: f32,
+}
+
+/// Reads `target` in `img`, at `corners` or wherever it is found, and fits the correction.
+pub fn calibrate(img: &image::DynamicImage, target: &ColorTarget, corners: Option<[(f32, f32); 4]>) -> Result<Calibration, String> {
+    let (rows, cols, reference) = target.layout()?;
+    let reference: Vec<[f32; 3]> = reference.iter().map(|c| c.map(|v| to_linear(v as f32))).collect();
+    let rgb = img.to_rgb8();
+    let candidates = match corners {
+        Some(corners) => vec![corners],
+        None => {
+            let quad = find_grid(&rgb, rows, cols).ok_or("No color target found")?;
+            // Its top left can be at any corner of the grid, as long as its long side matches
+            (0..4)
+                .map(|k| [quad[k], quad[(k + 1) % 4], quad[(k + 2) % 4], quad[(k + 3) % 4]])
+                .filter(|c| rows == cols || (dist(c[0], c[1]) > dist(c[0], c[3])) == (cols > rows))
+                .collect()
+        }
+    };
+
+    let fits = candidates
+        .into_iter()
+        .map(|corners| {
+            let measured = sample(&rgb, corners, rows, cols).ok_or("The target corners are outside the image")?;
+            let matrix = fit(&measured, &reference).ok_or("The target patches are too alike to calibrate against")?;
+            let residual: f32 = measured.iter().zip(&reference).map(|(m, r)| squared_distance(apply(&matrix, *m), *r)).sum();
+            Ok((residual, corners, measured, matrix))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let (_, corners, measured, matrix) = fits.into_iter().min_by(|a, b| a.0.total_cmp(&b.0)).ok_or("No color target found")?;
+
+    let mean_delta_e = |colors: &mut dyn Iterator<Item = [f32; 3]>| {
+        colors.zip(&reference).map(|(c, r)| delta_e(c, *r)).sum::<f32>() / reference.len() as f32
+    };
+    Ok(Calibration {
+        delta_e_before: mean_delta_e(&mut measured.iter().copied()),
+        delta_e_after: mean_delta_e(&mut measured.iter().map(|m| apply(&matrix, *m))),
+        measured: measured.iter().map(|m| m.map(|v| from_linear(v).round() as u8)).collect(),
+        corners,
+        matrix,
+    })
+}
+
+/// sRGB values of the IT8 patch field, row by row, from the target's CGATS reference
+/// file. Patches are read from the LAB_* fields, else XYZ_*, both measured under D50 as
+/// IT8 data is; the gray scale under the field and other samples are skipped.
+pub fn it8_reference(cgats: &str) -> Result<Vec<[u8; 3]>, String> {
+    let (mut fields, mut values) = (Vec::new(), Vec::new());
+    let mut section = None;
+    for line in cgats.lines().map(str::trim) {
+        match line {
+            "BEGIN_DATA_FORMAT" | "BEGIN_DATA" => section = Some(line),
+            "END_DATA_FORMAT" | "END_DATA" => section = None,
+            _ => match section {
+                Some("BEGIN_DATA_FORMAT") => fields.extend(line.split_whitespace()),
+                Some(_) => values.extend(line.split_whitespace().map(|v| v.trim_matches('"'))),
+                None => {}
+            },
+        }
+    }
+    let column = |name: &str| fields.iter().position(|f| f.eq_ignore_ascii_case(name));
+    let id = column("SAMPLE_ID").or_else(|| column("SAMPLE_NAME")).ok_or("Not a CGATS file with sample ids")?;
+    let (lab, xyz) = (["LAB_L", "LAB_A", "LAB_B"].map(column), ["XYZ_X", "XYZ_Y", "XYZ_Z"].map(column));
+    if values.len() % fields.len() != 0 {
+        return Err("The CGATS data does not match its format".to_string());
+    }
+
+    let mut patches = vec![None; IT8_ROWS * IT8_COLS];
+    for sample in values.chunks(fields.len()) {
+        let mut name = sample[id].chars();
+        let row = name.next().map(|c| c.to_ascii_uppercase() as usize).and_then(|c| c.checked_sub('A' as usize));
+        let Some((row, col)) = row.zip(name.as_str().parse::<usize>().ok()).filter(|&(r, c)| r < IT8_ROWS && (1..=IT8_COLS).contains(&c)) else {
+            continue;
+        };
+        let number = |i: Option<usize>| i.and_then(|i| sample[i].parse::<f32>().ok());
+        let xyz = match (lab.map(number), xyz.map(number)) {
+            ([Some(l), Some(a), Some(b)], _) => lab_to_xyz([l, a, b]),
+            (_, [Some(x), Some(y), Some(z)]) => [x, y, z].map(|v| v / 100.0),
+            _ => return Err(format!("Sample {} has no LAB or XYZ values", sample[id])),
+        };
+        patches[row * IT8_COLS + col - 1] = Some(apply(&XYZ_D50_TO_SRGB, xyz).map(|v| from_linear(v).round() as u8));
+    }
+    patches
+        .iter()
+        .enumerate()
+        .map(|(i, p)| p.ok_or_else(|| format!("The reference lacks patch {}{}", (b'A' + (i / IT8_COLS) as u8) as char, i % IT8_COLS + 1)))
+        .collect()
+}
+
+/// CIE XYZ, with white at Y = 1, of a CIELAB color under D50.
+fn lab_to_xyz(lab: [f32; 3]) -> [f32; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let f = |t: f32| if t > 6.0 / 29.0 { t.powi(3) } else { 3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0) };
+    [0.96422 * f(fy + lab[1] / 500.0), f(fy), 0.82521 * f(fy - lab[2] / 200.0)]
+}
+
+/// Linear light, in 0.0..=1.0, of an sRGB-encoded value in 0.0..=255.0.
+pub fn to_linear(v: f32) -> f32 {
+    let v = v / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+/// The sRGB-encoded value, in 0.0..=255.0, of linear light in 0.0..=1.0.
+pub fn from_linear(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    255.0 * if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+pub fn apply(m: &[[f32; 3]; 3], c: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * c[0] + row[1] * c[1] + row[2] * c[2])
+}
+
+fn dist(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn squared_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+/// Outer corners of a `rows` by `cols` grid of patches, in image order (top-left first,
+/// clockwise), or `None` when no such grid is seen.
+///
+/// Uniform regions are grown from every pixel of a small, softened copy; the square,
+/// solid ones are patch candidates. The largest group of similar size, without the
+/// members far from all others, should be the target, and its corner patches are the
+/// extremes of x + y and x - y, which holds while it is tilted well below 45°.
+fn find_grid(rgb: &RgbImage, rows: usize, cols: usize) -> Option<[(f32, f32); 4]> {
+    let scale = (MAX_EDGE as f32 / rgb.width().max(rgb.height()) as f32).min(1.0);
+    let (w, h) = (((rgb.width() as f32 * scale).round() as u32).max(1), ((rgb.height() as f32 * scale).round() as u32).max(1));
+    let small = image::imageops::blur(&image::imageops::resize(rgb, w, h, image::imageops::FilterType::Triangle), 1.0);
+    let (w, h) = (w as usize, h as usize);
+    let px = small.as_raw();
+    let (min_area, max_area) = ((w * h / 20000).max(12), w * h / (rows * cols));
+
+    let mut seen = vec![false; w * h];
+    let mut patches: Vec<(f32, f32, usize)> = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if seen[start] {
+            continue;
+        }
+        let seed = [px[start * 3] as i16, px[start * 3 + 1] as i16, px[start * 3 + 2] as i16];
+        let close = |i: usize| (0..3).all(|c| (px[i * 3 + c] as i16 - seed[c]).abs() <= TOLERANCE);
+        let (mut area, mut sx, mut sy) = (0usize, 0usize, 0usize);
+        let (mut x0, mut x1, mut y0, mut y1) = (usize::MAX, 0, usize::MAX, 0);
+        seen[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % w, i / w);
+            area += 1;
+            (sx, sy) = (sx + x, sy + y);
+            (x0, x1, y0, y1) = (x0.min(x), x1.max(x), y0.min(y), y1.max(y));
+            let neighbours = [(x > 0).then(|| i - 1), (x + 1 < w).then(|| i + 1), (y > 0).then(|| i - w), (y + 1 < h).then(|| i + w)];
+            for n in neighbours.into_iter().flatten() {
+                if !seen[n] && close(n) {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        let (bw, bh) = (x1 - x0 + 1, y1 - y0 + 1);
+        // Square-ish and filling most of its box: a patch, not the frame around them
+        if (min_area..=max_area).contains(&area) && bw.max(bh) <= bw.min(bh) * 2 && area * 2 >= bw * bh {
+            patches.push((sx as f32 / area as f32, sy as f32 / area as f32, area));
+        }
+    }
+
+    let similar = |a: usize, b: usize| a <= b * 2 && b <= a * 2;
+    let &(_, _, typical) = patches.iter().max_by_key(|p| patches.iter().filter(|q| similar(p.2, q.2)).count())?;
+    let group: Vec<(f32, f32)> = patches.iter().filter(|p| similar(p.2, typical)).map(|p| (p.0, p.1)).collect();
+    let nearest = |p: (f32, f32)| group.iter().filter(|&&q| q != p).map(|&q| dist(p, q)).fold(f32::MAX, f32::min);
+    let mut spacings: Vec<f32> = group.iter().map(|&p| nearest(p)).collect();
+    spacings.sort_by(f32::total_cmp);
+    let spacing = *spacings.get(spacings.len() / 2)?;
+    let group: Vec<(f32, f32)> = group.iter().copied().filter(|&p| nearest(p) <= spacing * 1.6).collect();
+    if group.len() * 2 < rows * cols {
+        return None;
+    }
+
+    let extreme = |key: fn(&(f32, f32)) -> f32| group.iter().copied().max_by(|a, b| key(a).total_cmp(&key(b)));
+    let tl = extreme(|p| -p.0 - p.1)?;
+    let tr = extreme(|p| p.0 - p.1)?;
+    let br = extreme(|p| p.0 + p.1)?;
+    let bl = extreme(|p| p.1 - p.0)?;
+    let (across, down) = if dist(tl, tr) >= dist(tl, bl) { (rows.max(cols), rows.min(cols)) } else { (rows.min(cols), rows.max(cols)) };
+    let (u, v) = ((across - 1) as f32, (down - 1) as f32);
+    let grid = Projection::from_control_points([(0.0, 0.0), (u, 0.0), (u, v), (0.0, v)], [tl, tr, br, bl])?;
+
+    // Most patch centres have to be where the grid puts them
+    let found = (0..across * down)
+        .filter(|i| {
+            let at = grid * ((i % across) as f32, (i / across) as f32);
+            group.iter().any(|&p| dist(p, at) <= spacing * 0.35)
+        })
+        .count();
+    if found * 4 < across * down * 3 {
+        return None;
+    }
+    let outer = [(-0.5, -0.5), (u + 0.5, -0.5), (u + 0.5, v + 0.5), (-0.5, v + 0.5)];
+    Some(outer.map(|p| {
+        let (x, y) = grid * p;
+        (x / scale, y / scale)
+    }))
+}
+
+/// Mean linear color at the centre of every patch of the grid within `corners`, row by row.
+fn sample(rgb: &RgbImage, corners: [(f32, f32); 4], rows: usize, cols: usize) -> Option<Vec<[f32; 3]>> {
+    let (c, r) = (cols as f32, rows as f32);
+    let grid = Projection::from_control_points([(0.0, 0.0), (c, 0.0), (c, r), (0.0, r)], corners)?;
+    let patch = (dist(corners[0], corners[1]) / c).min(dist(corners[0], corners[3]) / r);
+    // The middle of each patch, clear of blurred edges and the gaps between them
+    let radius = (patch * 0.2).max(0.0) as i64;
+    let (w, h) = (rgb.width() as i64, rgb.height() as i64);
+    (0..rows * cols)
+        .map(|i| {
+            let (x, y) = grid * ((i % cols) as f32 + 0.5, (i / cols) as f32 + 0.5);
+            let (x, y) = (x.round() as i64, y.round() as i64);
+            if x < 0 || y < 0 || x >= w || y >= h {
+                return None;
+            }
+            let mut sum = [0.0f32; 3];
+            let mut n = 0.0;
+            for py in (y - radius).max(0)..=(y + radius).min(h - 1) {
+                for px in (x - radius).max(0)..=(x + radius).min(w - 1) {
+                    let p = rgb.get_pixel(px as u32, py as u32);
+                    for ch in 0..3 {
+                        sum[ch] += to_linear(p[ch] as f32);
+                    }
+                    n += 1.0;
+                }
+            }
+            Some(sum.map(|s| s / n))
+        })
+        .collect()
+}
+
+/// Least-squares matrix taking `measured` to `reference`: M = (Σ r mᵀ)(Σ m mᵀ)⁻¹.
+fn fit(measured: &[[f32; 3]], reference: &[[f32; 3]]) -> Option<[[f32; 3]; 3]> {
+    let (mut a, mut b) = ([[0.0f64; 3]; 3], [[0.0f64; 3]; 3]);
+    for (m, r) in measured.iter().zip(reference) {
+        for i in 0..3 {
+            for j in 0..3 {
+                a[i][j] += m[i] as f64 * m[j] as f64;
+                b[i][j] += r[i] as f64 * m[j] as f64;
+            }
+        }
+    }
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1, c0, c1) = ((i + 1) % 3, (i + 2) % 3, (j + 1) % 3, (j + 2) % 3);
+        a[r0][c0] * a[r1][c1] - a[r0][c1] * a[r1][c0]
+    };
+    let det: f64 = (0..3).map(|j| a[0][j] * cofactor(0, j)).sum();
+    let trace = a[0][0] + a[1][1] + a[2][2];
+    // Only grays, or patches varying in one channel, leave the other channels undetermined
+    if det.abs() <= trace.powi(3) * 1e-9 {
+        return None;
+    }
+    let mut m = [[0.0f32; 3]; 3];
+    for (k, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            // A is symmetric, so its inverse is the cofactor matrix over the determinant
+            *value = ((0..3).map(|i| b[k][i] * cofactor(i, j)).sum::<f64>() / det) as f32;
+        }
+    }
+    Some(m)
+}
+
+/// CIE76 difference of two linear sRGB colors, through CIELAB under D65.
+fn delta_e(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let lab = |c: [f32; 3]| {
+        let c = c.map(|v| v.clamp(0.0, 1.0));
+        let xyz = [
+            (0.4124 * c[0] + 0.3576 * c[1] + 0.1805 * c[2]) / 0.95047,
+            0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2],
+            (0.0193 * c[0] + 0.1192 * c[1] + 0.9505 * c[2]) / 1.08883,
+        ];
+        let f = xyz.map(|t| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 });
+        [116.0 * f[1] - 16.0, 500.0 * (f[0] - f[1]), 200.0 * (f[1] - f[2])]
+    };
+    let (a, b) = (lab(a), lab(b));
+    squared_distance(a, b).sqrt()
+}
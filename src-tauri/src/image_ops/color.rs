@@ -0,0 +1,168 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Color Management
+ *
+ * The pipeline works in sRGB. On save, pixels can be converted to another
+ * output space through `moxcms` with the chosen rendering intent, and the
+ * matching ICC profile is embedded so viewers and print drivers read the
+ * numbers correctly. A soft proof converts to the output space and back,
+ * so previews show what the conversion does to out-of-gamut or gray output.
+ * Previews can also simulate color vision deficiencies.
+ */
+use image::{DynamicImage, ImageBuffer};
+use moxcms::{ColorProfile, Layout, LocalizableString, ProfileText, TransformOptions};
+use rayon::prelude::*;
+use crate::commands::{ColorConversion, ColorDeficiency, OutputColorSpace, RenderingIntent};
+
+pub fn profile(space: OutputColorSpace) -> ColorProfile {
+    match space {
+        OutputColorSpace::Srgb => ColorProfile::new_srgb(),
+        OutputColorSpace::AdobeRgb => ColorProfile::new_adobe_rgb(),
+        OutputColorSpace::GrayGamma22 => {
+            let mut gray = ColorProfile::new_gray_with_gamma(2.2);
+            gray.description =
+                Some(ProfileText::Localizable(vec![LocalizableString::new("en".to_string(), "US".to_string(), "Gray Gamma 2.2".to_string())]));
+            gray
+        }
+    }
+}
+
+/// The ICC profile to embed in files written in `space`.
+pub fn icc_profile(space: OutputColorSpace) -> Result<Vec<u8>, String> {
+    profile(space).encode().map_err(|e| format!("Failed to encode {:?} profile: {}", space, e))
+}
+
+/// The built-in space `icc` is the embedded profile of, if any.
+pub fn identify_profile(icc: &[u8]) -> Option<OutputColorSpace> {
+    [OutputColorSpace::Srgb, OutputColorSpace::AdobeRgb, OutputColorSpace::GrayGamma22]
+        .into_iter()
+        .find(|&space| icc_profile(space).is_ok_and(|p| p == icc))
+}
+
+fn transform_options(intent: RenderingIntent) -> TransformOptions {
+    TransformOptions {
+        rendering_intent: match intent {
+            RenderingIntent::Perceptual => moxcms::RenderingIntent::Perceptual,
+            RenderingIntent::RelativeColorimetric => moxcms::RenderingIntent::RelativeColorimetric,
+            RenderingIntent::Saturation => moxcms::RenderingIntent::Saturation,
+            RenderingIntent::AbsoluteColorimetric => moxcms::RenderingIntent::AbsoluteColorimetric,
+        },
+        ..Default::default()
+    }
+}
+
+/// Converts an sRGB image to the output space. Gray output drops color, 16-bit
+/// images stay 16-bit and alpha is carried through.
+pub fn convert(img: &DynamicImage, conversion: &ColorConversion) -> Result<DynamicImage, String> {
+    transform(img, &ColorProfile::new_srgb(), &profile(conversion.space), conversion.intent)
+}
+
+/// Renders `img` as it will look once converted: sRGB to the output space with the
+/// chosen intent, then back to sRGB for display.
+pub fn soft_proof(img: &DynamicImage, conversion: &ColorConversion) -> Result<DynamicImage, String> {
+    if conversion.space == OutputColorSpace::Srgb {
+        return Ok(img.clone());
+    }
+    let converted = convert(img, conversion)?;
+    transform(&converted, &profile(conversion.space), &ColorProfile::new_srgb(), RenderingIntent::RelativeColorimetric)
+}
+
+fn transform(img: &DynamicImage, from: &ColorProfile, to: &ColorProfile, intent: RenderingIntent) -> Result<DynamicImage, String> {
+    let color = img.color();
+    let (gray_in, gray_out) = (from.color_space == moxcms::DataColorSpace::Gray, to.color_space == moxcms::DataColorSpace::Gray);
+    if from.color_space == to.color_space && !gray_in && from.red_colorant == to.red_colorant && from.blue_colorant == to.blue_colorant {
+        return Ok(img.clone());
+    }
+    let alpha = color.has_alpha();
+    let wide = color.bytes_per_pixel() / color.channel_count() > 1;
+    let layout = |gray: bool| match (gray, alpha) {
+        (false, false) => Layout::Rgb,
+        (false, true) => Layout::Rgba,
+        (true, false) => Layout::Gray,
+        (true, true) => Layout::GrayAlpha,
+    };
+    let (src_layout, dst_layout) = (layout(gray_in), layout(gray_out));
+    let (width, height) = (img.width(), img.height());
+    let options = transform_options(intent);
+    let cms = |e: moxcms::CmsError| format!("Color conversion failed: {}", e);
+
+    let pixels = width as usize * height as usize;
+    macro_rules! run {
+        ($create:ident, [$rgb:ident, $rgba:ident, $luma:ident, $luma_alpha:ident], $zero:expr, [$o_rgb:ident, $o_rgba:ident, $o_luma:ident, $o_luma_alpha:ident]) => {{
+            let src = match (gray_in, alpha) {
+                (false, false) => img.$rgb().into_raw(),
+                (false, true) => img.$rgba().into_raw(),
+                (true, false) => img.$luma().into_raw(),
+                (true, true) => img.$luma_alpha().into_raw(),
+            };
+            let executor = from.$create(src_layout, to, dst_layout, options).map_err(cms)?;
+            let mut dst = vec![$zero; pixels * dst_layout.channels()];
+            executor.transform(&src, &mut dst).map_err(cms)?;
+            match (gray_out, alpha) {
+                (false, false) => ImageBuffer::from_raw(width, height, dst).map(DynamicImage::$o_rgb),
+                (false, true) => ImageBuffer::from_raw(width, height, dst).map(DynamicImage::$o_rgba),
+                (true, false) => ImageBuffer::from_raw(width, height, dst).map(DynamicImage::$o_luma),
+                (true, true) => ImageBuffer::from_raw(width, height, dst).map(DynamicImage::$o_luma_alpha),
+            }
+        }};
+    }
+
+    let out = if wide {
+        run!(
+            create_transform_16bit,
+            [to_rgb16, to_rgba16, to_luma16, to_luma_alpha16],
+            0u16,
+            [ImageRgb16, ImageRgba16, ImageLuma16, ImageLumaA16]
+        )
+    } else {
+        run!(
+            create_transform_8bit,
+            [to_rgb8, to_rgba8, to_luma8, to_luma_alpha8],
+            0u8,
+            [ImageRgb8, ImageRgba8, ImageLuma8, ImageLumaA8]
+        )
+    };
+    out.ok_or_else(|| "Color conversion produced a malformed buffer".to_string())
+}
+
+/// Renders `img` as seen with `deficiency`, using the matrices of Machado, Oliveira and
+/// Fernandes (2009) for complete dichromacy, applied in linear light. Alpha is kept;
+/// gray images look the same to everyone and are returned as they are.
+pub fn simulate_deficiency(img: &DynamicImage, deficiency: ColorDeficiency) -> DynamicImage {
+    let m = match deficiency {
+        ColorDeficiency::Protanopia => [[0.152286, 1.052583, -0.204868], [0.114503, 0.786281, 0.099216], [-0.003882, -0.048116, 1.051998]],
+        ColorDeficiency::Deuteranopia => [[0.367322, 0.860646, -0.227968], [0.280085, 0.672501, 0.047413], [-0.011820, 0.042940, 0.968881]],
+        ColorDeficiency::Tritanopia => [[1.255528, -0.076749, -0.178779], [-0.078411, 0.930809, 0.147602], [0.004733, 0.691367, 0.303900]],
+    };
+    let color = img.color();
+    if !color.has_color() {
+        return img.clone();
+    }
+    let decode: Vec<f32> = (0..256)
+        .map(|v| {
+            let v = v as f32 / 255.0;
+            if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+        })
+        .collect();
+    let encode = |v: f32| {
+        let v = v.clamp(0.0, 1.0);
+        let v = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+        (v * 255.0 + 0.5) as u8
+    };
+    let simulate = |p: &mut [u8]| {
+        let rgb = [decode[p[0] as usize], decode[p[1] as usize], decode[p[2] as usize]];
+        for (c, row) in m.iter().enumerate() {
+            p[c] = encode(row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+        }
+    };
+    if color.has_alpha() {
+        let mut rgba = img.to_rgba8();
+        rgba.as_mut().par_chunks_mut(4).for_each(simulate);
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = img.to_rgb8();
+        rgb.as_mut().par_chunks_mut(3).for_each(simulate);
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
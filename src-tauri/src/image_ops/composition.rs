@@ -0,0 +1,102 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Composition Scoring
+ *
+ * Rough culling aids that complement the sharpness figure: where the subject
+ * sits relative to the rule-of-thirds power points, how level the horizon is,
+ * and how much of the frame is clipped. Scores run from 0.0 to 1.0 so the grid
+ * can sort by any of them; they rank a shoot, they do not judge a photograph.
+ */
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::Serialize;
+
+/// Long edge of the copy the subject is located on.
+const SALIENCY_SIZE: u32 = 128;
+/// Tilt at which the level score reaches zero; also the search limit.
+const MAX_TILT: f32 = 10.0;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Composition {
+    /// Weighted mean of the scores below (thirds 0.4, level 0.3, exposure 0.3); without
+    /// a detectable subject, the other two share the weight.
+    pub score: f32,
+    /// Closeness of the subject to the nearest power point; `None` without a clear subject.
+    pub thirds: Option<f32>,
+    pub level: f32,
+    pub exposure: f32,
+    /// Subject position as fractions of the width and height.
+    pub subject: Option<[f32; 2]>,
+    /// Clockwise horizon tilt in degrees, when there are straight lines to measure.
+    pub tilt: Option<f32>,
+    /// Share of pixels at the ends of the luminance range.
+    pub clipped_shadows: f32,
+    pub clipped_highlights: f32,
+}
+
+pub fn score(img: &DynamicImage) -> Composition {
+    let subject = locate_subject(img);
+    // Distance from a power point to the center, where the score bottoms out
+    let reach = (2.0f32 / 36.0).sqrt();
+    let thirds = subject.map(|[x, y]| {
+        let d = [1.0 / 3.0, 2.0 / 3.0]
+            .iter()
+            .flat_map(|&px| [1.0 / 3.0, 2.0 / 3.0].map(|py| ((x - px).powi(2) + (y - py).powi(2)).sqrt()))
+            .fold(f32::MAX, f32::min);
+        (1.0 - d / reach).clamp(0.0, 1.0)
+    });
+
+    let tilt = super::estimate_tilt(img, MAX_TILT);
+    let level = tilt.map_or(1.0, |t| (1.0 - t.abs() / MAX_TILT).clamp(0.0, 1.0));
+
+    let luma = img.thumbnail(512, 512).to_luma8();
+    let total = luma.as_raw().len().max(1) as f32;
+    let clipped_shadows = luma.as_raw().iter().filter(|&&v| v <= 2).count() as f32 / total;
+    let clipped_highlights = luma.as_raw().iter().filter(|&&v| v >= 253).count() as f32 / total;
+    // A tenth of the frame clipped is as bad as it gets
+    let exposure = (1.0 - (clipped_shadows + clipped_highlights) * 10.0).clamp(0.0, 1.0);
+
+    let score = match thirds {
+        Some(thirds) => 0.4 * thirds + 0.3 * level + 0.3 * exposure,
+        None => 0.5 * level + 0.5 * exposure,
+    };
+    Composition { score, thirds, level, exposure, subject, tilt, clipped_shadows, clipped_highlights }
+}
+
+/// Centroid of the salient region, after the frequency-tuned saliency of Achanta et al.:
+/// each pixel of a lightly blurred copy is scored by its color distance from the image mean.
+fn locate_subject(img: &DynamicImage) -> Option<[f32; 2]> {
+    let small = image::imageops::blur(&img.thumbnail(SALIENCY_SIZE, SALIENCY_SIZE).to_rgb8(), 1.0);
+    let (w, h) = small.dimensions();
+    if w < 8 || h < 8 {
+        return None;
+    }
+    let pixels: Vec<[f32; 3]> = small.pixels().map(|p| p.0.map(|c| c as f32)).collect();
+    let n = pixels.len() as f32;
+    let mean = pixels.iter().fold([0.0; 3], |acc, p| [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]);
+    let saliency: Vec<f32> = pixels
+        .par_iter()
+        .map(|p| ((p[0] - mean[0]).powi(2) + (p[1] - mean[1]).powi(2) + (p[2] - mean[2]).powi(2)).sqrt())
+        .collect();
+
+    let average = saliency.iter().sum::<f32>() / n;
+    let peak = saliency.iter().copied().fold(0.0, f32::max);
+    // A frame with nothing standing out from its mean color has no subject to place
+    if peak < 24.0 || peak < average * 1.5 {
+        return None;
+    }
+    // Only the clearly salient pixels vote, so a large plain background does not pull
+    // the centroid to the middle
+    let threshold = average + (peak - average) * 0.5;
+    let (mut sx, mut sy, mut total) = (0.0f64, 0.0f64, 0.0f64);
+    for (i, &s) in saliency.iter().enumerate() {
+        if s > threshold {
+            let weight = (s - threshold) as f64;
+            sx += weight * ((i as u32 % w) as f64 + 0.5);
+            sy += weight * ((i as u32 / w) as f64 + 0.5);
+            total += weight;
+        }
+    }
+    (total > 0.0).then(|| [(sx / total / w as f64) as f32, (sy / total / h as f64) as f32])
+}
@@ -0,0 +1,168 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Dust Removal
+ *
+ * Software dust and scratch removal for film and slide scans made without
+ * an infrared channel. Defects are pixels that stand out from a median of
+ * their surroundings by more than the scan's own noise allows; only
+ * connected groups that are small, or thin like a scratch, and that stand
+ * out from a fairly even surround are kept, so edges, corners and texture
+ * of the picture stay untouched. The kept groups are then filled inward
+ * from their borders with the surrounding color.
+ */
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb32FImage};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use crate::commands::DustOptions;
+
+/// Deviations never count as defects below this, so clean grain is left alone.
+const MIN_THRESHOLD: f32 = 6.0;
+/// Width of the surround a defect is compared with.
+const RING: u32 = 2;
+
+/// Removes dust and scratches from `img` and returns it with the number of defects
+/// filled. 8- and 16-bit images keep their depth and channel layout.
+pub fn remove_dust(img: DynamicImage, options: &DustOptions) -> (DynamicImage, usize) {
+    let (mask, defects) = detect(&img.to_luma8(), options);
+    if defects == 0 {
+        return (img, 0);
+    }
+    let color = img.color();
+    let deep = color.bytes_per_pixel() / color.channel_count() > 1;
+    let mut rgb = img.to_rgb32f();
+    inpaint(&mut rgb, &mask);
+    // Alpha is left as it was; only the color is filled
+    let mut filled = DynamicImage::ImageRgb32F(rgb).to_rgba32f();
+    if color.has_alpha() {
+        for (p, a) in filled.pixels_mut().zip(img.to_rgba32f().pixels()) {
+            p[3] = a[3];
+        }
+    }
+    let filled = DynamicImage::ImageRgba32F(filled);
+    let out = match (color.has_color(), color.has_alpha(), deep) {
+        (false, false, false) => DynamicImage::ImageLuma8(filled.to_luma8()),
+        (false, false, true) => DynamicImage::ImageLuma16(filled.to_luma16()),
+        (false, true, false) => DynamicImage::ImageLumaA8(filled.to_luma_alpha8()),
+        (false, true, true) => DynamicImage::ImageLumaA16(filled.to_luma_alpha16()),
+        (true, false, false) => DynamicImage::ImageRgb8(filled.to_rgb8()),
+        (true, false, true) => DynamicImage::ImageRgb16(filled.to_rgb16()),
+        (true, true, false) => DynamicImage::ImageRgba8(filled.to_rgba8()),
+        (true, true, true) => DynamicImage::ImageRgba16(filled.to_rgba16()),
+    };
+    (out, defects)
+}
+
+/// The mask of defects to fill, grown by a pixel to cover their soft rims, and how many
+/// there are.
+fn detect(luma: &GrayImage, options: &DustOptions) -> (Vec<bool>, usize) {
+    let max_size = options.max_size.max(1);
+    let (w, h) = luma.dimensions();
+    let median = imageproc::filter::median_filter(luma, max_size, max_size);
+    let deviation: Vec<f32> = luma.pixels().zip(median.pixels()).map(|(p, m)| p[0] as f32 - m[0] as f32).collect();
+
+    // The noise floor from the median absolute deviation of a sample
+    let mut sample: Vec<f32> = deviation.iter().step_by((deviation.len() / 100_000).max(1)).map(|d| d.abs()).collect();
+    sample.sort_by(f32::total_cmp);
+    let sigma = 1.4826 * sample.get(sample.len() / 2).copied().unwrap_or(0.0);
+    let k = 2.0 + 8.0 * (1.0 - options.sensitivity.clamp(0.0, 1.0));
+    let threshold = (k * sigma).max(MIN_THRESHOLD);
+
+    let candidate = GrayImage::from_fn(w, h, |x, y| Luma([if deviation[(y * w + x) as usize].abs() > threshold { 255 } else { 0 }]));
+    let labels = connected_components(&candidate, Connectivity::Eight, Luma([0u8]));
+    // Bounding box and area per component
+    let count = labels.pixels().map(|l| l[0]).max().unwrap_or(0) as usize;
+    let mut boxes = vec![(u32::MAX, u32::MAX, 0u32, 0u32, 0u32); count + 1];
+    for (x, y, l) in labels.enumerate_pixels() {
+        let b = &mut boxes[l[0] as usize];
+        *b = (b.0.min(x), b.1.min(y), b.2.max(x), b.3.max(y), b.4 + 1);
+    }
+    // Spots fit in a `max_size` square; scratches may be long but at most half as thick
+    let keep: Vec<bool> = boxes
+        .iter()
+        .enumerate()
+        .map(|(i, &(x0, y0, x1, y1, area))| {
+            if i == 0 || area == 0 {
+                return false;
+            }
+            let (bw, bh) = (x1 - x0 + 1, y1 - y0 + 1);
+            let thickness = area as f32 / bw.max(bh) as f32;
+            let shaped = (bw <= max_size && bh <= max_size) || (options.scratches && thickness <= (max_size as f32 / 2.0).max(1.0));
+            shaped && isolated(luma, &labels, i as u32, (x0, y0, x1, y1))
+        })
+        .collect();
+    let defects = keep.iter().filter(|&&k| k).count();
+
+    let core: Vec<bool> = labels.pixels().map(|l| keep[l[0] as usize]).collect();
+    let mut mask = core.clone();
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            if core[(y * w as i64 + x) as usize] {
+                for (nx, ny) in neighbors(x, y, w, h) {
+                    mask[ny * w as usize + nx] = true;
+                }
+            }
+        }
+    }
+    (mask, defects)
+}
+
+/// Whether component `label` (bounding box `x0, y0, x1, y1`) differs from the pixels
+/// around it by more than they differ among themselves. The corners of dark shapes,
+/// which a median also rounds off, sit in a surround of both dark and light and fail.
+fn isolated(luma: &GrayImage, labels: &ImageBuffer<Luma<u32>, Vec<u32>>, label: u32, (x0, y0, x1, y1): (u32, u32, u32, u32)) -> bool {
+    let (w, h) = luma.dimensions();
+    let (mut inside, mut ring) = ((0.0, 0usize), Vec::new());
+    for y in y0.saturating_sub(RING)..(y1 + RING + 1).min(h) {
+        for x in x0.saturating_sub(RING)..(x1 + RING + 1).min(w) {
+            let v = luma.get_pixel(x, y)[0] as f32;
+            match labels.get_pixel(x, y)[0] {
+                l if l == label => inside = (inside.0 + v, inside.1 + 1),
+                0 => ring.push(v),
+                _ => {}
+            }
+        }
+    }
+    if ring.is_empty() || inside.1 == 0 {
+        return false;
+    }
+    let n = ring.len() as f32;
+    let mean = ring.iter().sum::<f32>() / n;
+    let spread = (ring.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+    (inside.0 / inside.1 as f32 - mean).abs() > 2.0 * spread
+}
+
+/// Fills the masked pixels of `img` layer by layer from their borders, each with the
+/// mean of its already known neighbors.
+fn inpaint(img: &mut Rgb32FImage, mask: &[bool]) {
+    let (w, h) = img.dimensions();
+    let mut unknown = mask.to_vec();
+    let mut pending: Vec<(i64, i64)> = (0..h as i64).flat_map(|y| (0..w as i64).map(move |x| (x, y))).filter(|&(x, y)| unknown[(y * w as i64 + x) as usize]).collect();
+    while !pending.is_empty() {
+        let mut filled = Vec::new();
+        for &(x, y) in &pending {
+            let known: Vec<[f32; 3]> = neighbors(x, y, w, h).filter(|&(nx, ny)| !unknown[ny * w as usize + nx]).map(|(nx, ny)| img.get_pixel(nx as u32, ny as u32).0).collect();
+            if !known.is_empty() {
+                let n = known.len() as f32;
+                let mean = known.iter().fold([0.0; 3], |acc, p| [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]);
+                filled.push((x, y, mean));
+            }
+        }
+        if filled.is_empty() {
+            // A mask covering the whole image has no border to fill from
+            break;
+        }
+        for &(x, y, mean) in &filled {
+            img.put_pixel(x as u32, y as u32, image::Rgb(mean));
+            unknown[(y * w as i64 + x) as usize] = false;
+        }
+        pending.retain(|&(x, y)| unknown[(y * w as i64 + x) as usize]);
+    }
+}
+
+/// The 8-neighbors of (`x`, `y`) inside a `w` x `h` image.
+fn neighbors(x: i64, y: i64, w: u32, h: u32) -> impl Iterator<Item = (usize, usize)> {
+    (-1..=1i64)
+        .flat_map(move |dy| (-1..=1i64).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64)
+        .map(|(nx, ny)| (nx as usize, ny as usize))
+}
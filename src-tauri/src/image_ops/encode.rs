@@ -0,0 +1,120 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Output Encoding
+ *
+ * Writes processed images to disk. Formats that need settings beyond what
+ * `image::save` exposes (JPEG chroma subsampling and restart markers, TIFF
+ * compression and tiling) or that `image` cannot write (JPEG 2000) are
+ * routed to dedicated encoders; everything else falls back to `image`. A
+ * pixel density, when set, is written into the JFIF header, the TIFF
+ * resolution tags, a JP2 resolution box or a PNG pHYs chunk. PDF outputs
+ * carry the OCR text, when there is any, as a searchable layer.
+ */
+use image::{DynamicImage, ImageEncoder};
+use std::io::Write;
+use std::path::Path;
+use crate::commands::{ChromaSubsampling, JpegOptions, OutputOptions};
+
+pub mod jp2;
+pub mod pdf;
+pub mod tiff;
+
+/// Saves `img` to `path`, picking the encoder from the file extension. With an output
+/// color space, pixels are converted first and the profile is embedded where the format
+/// can hold one (JPEG, TIFF, JP2, PNG, WebP); the density is tagged in JPEG, TIFF, JP2
+/// and PNG.
+pub fn save_image(img: &DynamicImage, path: &str, output: &OutputOptions) -> Result<(), String> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    // The PDF writer converts pages itself, so combined PDFs match single-page ones
+    if ext == "pdf" {
+        return self::pdf::save_pdf(img, path, output);
+    }
+
+    let converted;
+    let (img, icc) = match &output.color {
+        Some(conversion) => {
+            converted = super::color::convert(img, conversion)?;
+            (&converted, Some(super::color::icc_profile(conversion.space)?))
+        }
+        None => (img, None),
+    };
+    let create = || std::fs::File::create(path).map(std::io::BufWriter::new).map_err(|e| e.to_string());
+
+    match ext.as_str() {
+        "jpg" | "jpeg" => encode_jpeg(img, create()?, &output.jpeg, icc.as_deref(), output.dpi),
+        "tif" | "tiff" => self::tiff::save_tiff(img, path, &output.tiff, icc.as_deref(), output.dpi),
+        "jp2" => self::jp2::save_jp2(img, path, &output.jp2, icc.as_deref(), output.dpi),
+        "png" if output.dpi.is_some() => save_png(img, path, icc, output.dpi),
+        "png" if icc.is_some() => with_icc(img, image::codecs::png::PngEncoder::new(create()?), icc),
+        "webp" if icc.is_some() => with_icc(img, image::codecs::webp::WebPEncoder::new_lossless(create()?), icc),
+        _ => img.save(path).map_err(|e| e.to_string()),
+    }
+}
+
+/// Whether `img` is a grayscale page holding nothing but black and white, as the
+/// binarization stage produces.
+pub fn is_bilevel(img: &DynamicImage) -> bool {
+    !img.color().has_color() && img.to_luma8().pixels().all(|p| p[0] == 0 || p[0] == 255)
+}
+
+fn with_icc<E: ImageEncoder>(img: &DynamicImage, mut encoder: E, icc: Option<Vec<u8>>) -> Result<(), String> {
+    if let Some(icc) = icc {
+        encoder.set_icc_profile(icc).map_err(|e| e.to_string())?;
+    }
+    img.write_with_encoder(encoder).map_err(|e| e.to_string())
+}
+
+/// Encodes to memory, then adds a pHYs chunk ahead of the image data, as the `image`
+/// PNG encoder cannot write one.
+fn save_png(img: &DynamicImage, path: &str, icc: Option<Vec<u8>>, dpi: Option<f32>) -> Result<(), String> {
+    let mut encoded = Vec::new();
+    with_icc(img, image::codecs::png::PngEncoder::new(&mut encoded), icc)?;
+    let mut png = img_parts::png::Png::from_bytes(encoded.into()).map_err(|e| e.to_string())?;
+    if let Some(dpi) = dpi {
+        // Pixels per meter on both axes, unit 1 = meter
+        let ppm = ((dpi as f64 / 0.0254).round() as u32).to_be_bytes();
+        let phys = [ppm.as_slice(), ppm.as_slice(), &[1]].concat();
+        let at = png.chunks().iter().position(|c| c.kind() == *b"IDAT").unwrap_or(1);
+        png.chunks_mut().insert(at, img_parts::png::PngChunk::new(*b"pHYs", phys.into()));
+    }
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    png.encoder().write_to(std::io::BufWriter::new(file)).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Encodes a baseline JPEG with the configured quality, subsampling and restart interval,
+/// and `dpi` in its JFIF header.
+pub fn encode_jpeg<W: Write>(img: &DynamicImage, writer: W, options: &JpegOptions, icc: Option<&[u8]>, dpi: Option<f32>) -> Result<(), String> {
+    let width = u16::try_from(img.width()).map_err(|_| "Image too wide for JPEG".to_string())?;
+    let height = u16::try_from(img.height()).map_err(|_| "Image too tall for JPEG".to_string())?;
+
+    let mut encoder = jpeg_encoder::Encoder::new(writer, options.quality.clamp(1, 100));
+    encoder.set_sampling_factor(match options.subsampling {
+        ChromaSubsampling::S444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        ChromaSubsampling::S422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        ChromaSubsampling::S420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder.set_restart_interval(options.restart_interval);
+    if let Some(dpi) = dpi {
+        let dpi = dpi.round().clamp(1.0, u16::MAX as f32) as u16;
+        encoder.set_density(jpeg_encoder::Density::Inch { x: dpi, y: dpi });
+    }
+    if let Some(icc) = icc {
+        encoder.add_icc_profile(icc).map_err(|e| e.to_string())?;
+    }
+
+    let result = match img {
+        DynamicImage::ImageLuma8(luma) => {
+            encoder.encode(luma.as_raw(), width, height, jpeg_encoder::ColorType::Luma)
+        }
+        _ if !img.color().has_color() => {
+            encoder.encode(img.to_luma8().as_raw(), width, height, jpeg_encoder::ColorType::Luma)
+        }
+        _ => encoder.encode(img.to_rgb8().as_raw(), width, height, jpeg_encoder::ColorType::Rgb),
+    };
+    result.map_err(|e| e.to_string())
+}
@@ -0,0 +1,418 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk TIFF Writer
+ *
+ * Baseline TIFF pages written segment by segment (strips or tiles) so that
+ * compression, predictors and CCITT Group 4 for bilevel pages can be chosen
+ * per job. The `tiff` crate provides the IFD plumbing and LZW/Deflate/PackBits
+ * compressors; Group 4 coding comes from the `fax` crate. Pyramidal files
+ * follow the full-size page with tiled half-size levels marked as reduced
+ * resolution images, the layout deep-zoom servers such as IIPImage read.
+ */
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use ::tiff::encoder::compression::{CompressionAlgorithm, Compressor, Deflate, Lzw, Packbits};
+use ::tiff::encoder::{Rational, TiffEncoder};
+use ::tiff::tags::Tag;
+use crate::commands::{OutputOptions, TiffCompression, TiffOptions};
+
+/// Target uncompressed size of one strip, per the TIFF 6.0 recommendation of ~8 KB
+/// scaled up for modern readers.
+const STRIP_BYTES: usize = 64 * 1024;
+/// Tile edge of pyramidal files that do not set one.
+const PYRAMID_TILE: u32 = 256;
+
+/// Writes `img` as a single-page TIFF file, with its pyramid levels when asked.
+pub fn save_tiff(img: &DynamicImage, path: &str, options: &TiffOptions, icc: Option<&[u8]>, dpi: Option<f32>) -> Result<(), String> {
+    if options.pyramid && options.compression == TiffCompression::Group4 {
+        return Err("Pyramidal TIFFs are tiled and cannot use Group 4".to_string());
+    }
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = TiffEncoder::new(std::io::BufWriter::new(file)).map_err(|e| e.to_string())?;
+    if !options.pyramid {
+        return write_page(&mut encoder, img, options, icc, dpi);
+    }
+    let tile = options.tile_size.unwrap_or(PYRAMID_TILE);
+    let options = TiffOptions { tile_size: Some(tile), ..*options };
+    write_ifd(&mut encoder, img, &options, icc, dpi, false)?;
+    // Halve until the whole level fits in one tile
+    let mut level = img.clone();
+    while level.width() > tile || level.height() > tile {
+        let (width, height) = (level.width().div_ceil(2), level.height().div_ceil(2));
+        level = level.resize_exact(width, height, image::imageops::FilterType::Triangle);
+        let level_dpi = dpi.map(|d| d * width as f32 / img.width() as f32);
+        write_ifd(&mut encoder, &level, &options, icc, level_dpi, true)?;
+    }
+    Ok(())
+}
+
+/// A TIFF file that pages are appended to one at a time, for archival deliveries of a
+/// whole document in one file.
+pub struct MultipageTiff {
+    encoder: TiffEncoder<SharedFile>,
+    file: SharedFile,
+    pages: usize,
+}
+
+/// The buffered file, shared with the encoder so `finish` can still flush it: the
+/// encoder does not hand its writer back.
+#[derive(Clone)]
+struct SharedFile(Rc<RefCell<BufWriter<std::fs::File>>>);
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.borrow_mut().seek(pos)
+    }
+}
+
+impl MultipageTiff {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+        let file = SharedFile(Rc::new(RefCell::new(BufWriter::new(file))));
+        let encoder = TiffEncoder::new(file.clone()).map_err(|e| e.to_string())?;
+        Ok(Self { encoder, file, pages: 0 })
+    }
+
+    /// Appends `img` in the output color space and at the output density of `output`,
+    /// compressed as `options` says.
+    pub fn append(&mut self, img: &DynamicImage, output: &OutputOptions, options: &TiffOptions) -> Result<(), String> {
+        let converted;
+        let (img, icc) = match &output.color {
+            Some(conversion) => {
+                converted = crate::image_ops::color::convert(img, conversion)?;
+                (&converted, Some(crate::image_ops::color::icc_profile(conversion.space)?))
+            }
+            None => (img, None),
+        };
+        write_page(&mut self.encoder, img, options, icc.as_deref(), output.dpi)?;
+        self.pages += 1;
+        Ok(())
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Flushes the file, so a write error is reported rather than lost on drop.
+    pub fn finish(self) -> Result<(), String> {
+        let Self { encoder, mut file, .. } = self;
+        drop(encoder);
+        file.flush().map_err(|e| format!("Failed to write TIFF: {}", e))
+    }
+}
+
+/// Appends `img` as a new page (IFD) to `encoder`, with `icc` as its embedded profile.
+/// Pages are tagged at `dpi`, or the customary 72 dpi when it is unknown.
+pub fn write_page<W: Write + Seek>(
+    encoder: &mut TiffEncoder<W>,
+    img: &DynamicImage,
+    options: &TiffOptions,
+    icc: Option<&[u8]>,
+    dpi: Option<f32>,
+) -> Result<(), String> {
+    write_ifd(encoder, img, options, icc, dpi, false)
+}
+
+/// Writes one IFD; `reduced` marks a pyramid level of the page before it.
+fn write_ifd<W: Write + Seek>(
+    encoder: &mut TiffEncoder<W>,
+    img: &DynamicImage,
+    options: &TiffOptions,
+    icc: Option<&[u8]>,
+    dpi: Option<f32>,
+    reduced: bool,
+) -> Result<(), String> {
+    let raster = if options.compression == TiffCompression::Group4 {
+        Raster::bilevel(img)
+    } else {
+        Raster::from_image(img)
+    };
+    let (width, height) = (img.width(), img.height());
+    let predictor = options.predictor
+        && matches!(options.compression, TiffCompression::Lzw | TiffCompression::Deflate);
+
+    // Group 4 is coded over the whole page; tiling only applies to the byte-oriented codecs
+    let layout = match options.tile_size {
+        Some(size) if options.compression != TiffCompression::Group4 => {
+            if size == 0 || size % 16 != 0 {
+                return Err(format!("TIFF tile size must be a multiple of 16, got {}", size));
+            }
+            Layout::Tiles(size)
+        }
+        _ => {
+            let row_bytes = raster.row_bytes(width).max(1);
+            let rows = match options.compression {
+                TiffCompression::Group4 => height,
+                _ => ((STRIP_BYTES / row_bytes) as u32).clamp(1, height.max(1)),
+            };
+            Layout::Strips(rows)
+        }
+    };
+
+    let segments = raster.segments(width, height, layout, predictor, options.compression)?;
+
+    let tiff_err = |e: ::tiff::TiffError| e.to_string();
+    let mut dir = encoder.image_directory().map_err(tiff_err)?;
+    if reduced {
+        dir.write_tag(Tag::NewSubfileType, 1u32).map_err(tiff_err)?;
+    }
+    dir.write_tag(Tag::ImageWidth, width).map_err(tiff_err)?;
+    dir.write_tag(Tag::ImageLength, height).map_err(tiff_err)?;
+    dir.write_tag(Tag::BitsPerSample, &vec![raster.bits; raster.spp as usize][..]).map_err(tiff_err)?;
+    dir.write_tag(Tag::Compression, compression_tag(options.compression)).map_err(tiff_err)?;
+    dir.write_tag(Tag::PhotometricInterpretation, raster.photometric).map_err(tiff_err)?;
+    dir.write_tag(Tag::SamplesPerPixel, raster.spp).map_err(tiff_err)?;
+    dir.write_tag(Tag::PlanarConfiguration, 1u16).map_err(tiff_err)?;
+    let dpi = dpi.filter(|d| *d > 0.0).unwrap_or(72.0);
+    let resolution = if dpi.fract() == 0.0 {
+        Rational { n: dpi as u32, d: 1 }
+    } else {
+        Rational { n: (dpi * 100.0).round() as u32, d: 100 }
+    };
+    dir.write_tag(Tag::XResolution, resolution.clone()).map_err(tiff_err)?;
+    dir.write_tag(Tag::YResolution, resolution).map_err(tiff_err)?;
+    dir.write_tag(Tag::ResolutionUnit, 2u16).map_err(tiff_err)?;
+    if predictor {
+        dir.write_tag(Tag::Predictor, 2u16).map_err(tiff_err)?;
+    }
+    if raster.alpha {
+        // Unassociated alpha
+        dir.write_tag(Tag::ExtraSamples, &[2u16][..]).map_err(tiff_err)?;
+    }
+    // A bilevel page has no color to manage
+    if let Some(icc) = icc.filter(|_| raster.bits > 1) {
+        dir.write_tag(Tag::IccProfile, icc).map_err(tiff_err)?;
+    }
+
+    let mut offsets = Vec::with_capacity(segments.len());
+    let mut counts = Vec::with_capacity(segments.len());
+    for segment in &segments {
+        let offset = dir.write_data(segment.as_slice()).map_err(tiff_err)?;
+        offsets.push(u32::try_from(offset).map_err(|_| "TIFF exceeds 4 GB".to_string())?);
+        counts.push(segment.len() as u32);
+    }
+
+    match layout {
+        Layout::Strips(rows) => {
+            dir.write_tag(Tag::RowsPerStrip, rows).map_err(tiff_err)?;
+            dir.write_tag(Tag::StripOffsets, offsets.as_slice()).map_err(tiff_err)?;
+            dir.write_tag(Tag::StripByteCounts, counts.as_slice()).map_err(tiff_err)?;
+        }
+        Layout::Tiles(size) => {
+            dir.write_tag(Tag::TileWidth, size).map_err(tiff_err)?;
+            dir.write_tag(Tag::TileLength, size).map_err(tiff_err)?;
+            dir.write_tag(Tag::TileOffsets, offsets.as_slice()).map_err(tiff_err)?;
+            dir.write_tag(Tag::TileByteCounts, counts.as_slice()).map_err(tiff_err)?;
+        }
+    }
+    dir.finish().map_err(tiff_err)
+}
+
+fn compression_tag(compression: TiffCompression) -> u16 {
+    match compression {
+        TiffCompression::None => 1,
+        TiffCompression::Group4 => 4,
+        TiffCompression::Lzw => 5,
+        TiffCompression::Deflate => 8,
+        TiffCompression::Packbits => 32773,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Rows per strip
+    Strips(u32),
+    /// Square tile edge
+    Tiles(u32),
+}
+
+enum Samples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    /// 1 bit per pixel, `true` = black
+    Bilevel(Vec<bool>),
+}
+
+struct Raster {
+    samples: Samples,
+    spp: u16,
+    bits: u16,
+    photometric: u16,
+    alpha: bool,
+}
+
+impl Raster {
+    fn from_image(img: &DynamicImage) -> Self {
+        let color = img.color();
+        let wide = color.bytes_per_pixel() / color.channel_count() > 1;
+        let samples = match (color.has_color(), color.has_alpha(), wide) {
+            (false, false, false) => Samples::U8(img.to_luma8().into_raw()),
+            (false, true, false) => Samples::U8(img.to_luma_alpha8().into_raw()),
+            (true, false, false) => Samples::U8(img.to_rgb8().into_raw()),
+            (true, true, false) => Samples::U8(img.to_rgba8().into_raw()),
+            (false, false, true) => Samples::U16(img.to_luma16().into_raw()),
+            (false, true, true) => Samples::U16(img.to_luma_alpha16().into_raw()),
+            (true, false, true) => Samples::U16(img.to_rgb16().into_raw()),
+            (true, true, true) => Samples::U16(img.to_rgba16().into_raw()),
+        };
+        let spp = match (color.has_color(), color.has_alpha()) {
+            (false, false) => 1,
+            (false, true) => 2,
+            (true, false) => 3,
+            (true, true) => 4,
+        };
+        Self {
+            samples,
+            spp,
+            bits: if wide { 16 } else { 8 },
+            photometric: if color.has_color() { 2 } else { 1 },
+            alpha: color.has_alpha(),
+        }
+    }
+
+    /// Thresholds at mid-gray; pages coming out of the binarization stage are already bilevel.
+    fn bilevel(img: &DynamicImage) -> Self {
+        let black = img.to_luma8().into_raw().into_iter().map(|v| v < 128).collect();
+        Self {
+            samples: Samples::Bilevel(black),
+            spp: 1,
+            bits: 1,
+            // WhiteIsZero is the customary interpretation for CCITT pages
+            photometric: 0,
+            alpha: false,
+        }
+    }
+
+    fn row_bytes(&self, width: u32) -> usize {
+        width as usize * self.spp as usize * self.bits as usize / 8
+    }
+
+    /// Cuts the raster into encoded strips or tiles.
+    fn segments(
+        &self,
+        width: u32,
+        height: u32,
+        layout: Layout,
+        predictor: bool,
+        compression: TiffCompression,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        if let Samples::Bilevel(black) = &self.samples {
+            return Ok(vec![encode_group4(black, width, height)?]);
+        }
+
+        let (seg_w, seg_h) = match layout {
+            Layout::Strips(rows) => (width, rows),
+            Layout::Tiles(size) => (size, size),
+        };
+        let spp = self.spp as usize;
+        let mut out = Vec::new();
+        for y0 in (0..height).step_by(seg_h.max(1) as usize) {
+            for x0 in (0..width).step_by(seg_w.max(1) as usize) {
+                // Strips end at the image edge, tiles are always full size (zero padded)
+                let rows = match layout {
+                    Layout::Strips(_) => seg_h.min(height - y0),
+                    Layout::Tiles(_) => seg_h,
+                };
+                let bytes = match &self.samples {
+                    Samples::U8(data) => {
+                        let mut seg = cut(data, width, height, spp, x0, y0, seg_w, rows);
+                        if predictor {
+                            horizontal_diff(&mut seg, seg_w as usize * spp, spp, u8::wrapping_sub);
+                        }
+                        seg
+                    }
+                    Samples::U16(data) => {
+                        let mut seg = cut(data, width, height, spp, x0, y0, seg_w, rows);
+                        if predictor {
+                            horizontal_diff(&mut seg, seg_w as usize * spp, spp, u16::wrapping_sub);
+                        }
+                        // The encoder declares the host's byte order in the header
+                        if cfg!(target_endian = "big") {
+                            seg.iter().flat_map(|v| v.to_be_bytes()).collect()
+                        } else {
+                            seg.iter().flat_map(|v| v.to_le_bytes()).collect()
+                        }
+                    }
+                    Samples::Bilevel(_) => return Err("Bilevel rasters are only coded as Group 4".to_string()),
+                };
+                out.push(compress(compression, &bytes)?);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Copies a `seg_w` x `rows` window starting at (`x0`, `y0`), zero filling outside the image.
+#[allow(clippy::too_many_arguments)]
+fn cut<T: Copy + Default>(
+    data: &[T],
+    width: u32,
+    height: u32,
+    spp: usize,
+    x0: u32,
+    y0: u32,
+    seg_w: u32,
+    rows: u32,
+) -> Vec<T> {
+    let mut seg = vec![T::default(); seg_w as usize * rows as usize * spp];
+    let copy_w = seg_w.min(width.saturating_sub(x0)) as usize * spp;
+    for row in 0..rows {
+        let y = y0 + row;
+        if y >= height {
+            break;
+        }
+        let src = (y as usize * width as usize + x0 as usize) * spp;
+        let dst = row as usize * seg_w as usize * spp;
+        seg[dst..dst + copy_w].copy_from_slice(&data[src..src + copy_w]);
+    }
+    seg
+}
+
+/// TIFF predictor 2: each sample stores the difference to the same channel of its left neighbour.
+fn horizontal_diff<T: Copy>(seg: &mut [T], row_len: usize, spp: usize, sub: fn(T, T) -> T) {
+    for row in seg.chunks_mut(row_len) {
+        for i in (spp..row.len()).rev() {
+            row[i] = sub(row[i], row[i - spp]);
+        }
+    }
+}
+
+fn compress(compression: TiffCompression, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut compressor = match compression {
+        TiffCompression::None => return Ok(bytes.to_vec()),
+        TiffCompression::Lzw => Compressor::Lzw(Lzw),
+        TiffCompression::Deflate => Compressor::Deflate(Deflate::default()),
+        TiffCompression::Packbits => Compressor::Packbits(Packbits),
+        TiffCompression::Group4 => return Err("Group 4 pages are coded as bilevel rasters".to_string()),
+    };
+    let mut out = Vec::with_capacity(bytes.len() / 2);
+    compressor.write_to(&mut out, bytes).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+pub(super) fn encode_group4(black: &[bool], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let line_width = u16::try_from(width).map_err(|_| "Page too wide for Group 4".to_string())?;
+    let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+    for row in black.chunks(width.max(1) as usize).take(height as usize) {
+        let pels = row.iter().map(|&b| if b { fax::Color::Black } else { fax::Color::White });
+        // VecWriter is infallible
+        let _ = encoder.encode_line(pels, line_width);
+    }
+    match encoder.finish() {
+        Ok(writer) => Ok(writer.finish()),
+        Err(never) => match never {},
+    }
+}
@@ -0,0 +1,336 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Exposure Merge
+ *
+ * Mertens-style exposure fusion for bracketed sequences: every frame is
+ * weighted per pixel by contrast, saturation and well-exposedness and the
+ * frames are blended across a Laplacian pyramid to avoid seams. Hand-held
+ * brackets are aligned first; optional ghost removal restricts moving content
+ * to a single reference exposure.
+ */
+use image::{DynamicImage, GrayImage, Luma, RgbImage};
+use rayon::prelude::*;
+use crate::commands::HdrOptions;
+use super::align;
+
+/// Fuses a bracketed exposure sequence into one well-exposed 8-bit image.
+pub fn merge_exposures(frames: &[DynamicImage], options: &HdrOptions) -> Result<DynamicImage, String> {
+    let first = frames.first().ok_or("No exposures to merge")?;
+    let (width, height) = (first.width() as usize, first.height() as usize);
+    if frames.iter().any(|f| f.width() as usize != width || f.height() as usize != height) {
+        return Err("All exposures must have the same dimensions".to_string());
+    }
+    if frames.len() == 1 {
+        return Ok(DynamicImage::ImageRgb8(first.to_rgb8()));
+    }
+
+    let reference = options.reference.unwrap_or(frames.len() / 2).min(frames.len() - 1);
+    let rgb: Vec<RgbImage> = if options.align {
+        // Phase correlation only sees structure, so differently exposed frames still register
+        let transforms = align::align_to(frames, reference)?;
+        frames.par_iter().zip(transforms.par_iter()).map(|(f, t)| align::warp(f, t)).collect()
+    } else {
+        frames.par_iter().map(|f| f.to_rgb8()).collect()
+    };
+    let mut weights: Vec<Plane> = rgb.par_iter().map(fusion_weights).collect();
+
+    if options.deghost {
+        let threshold = options.ghost_threshold.clamp(0.01, 1.0);
+        let masks = ghost_masks(&rgb, reference, threshold);
+        for (k, mask) in masks.into_iter().enumerate() {
+            if let Some(mask) = mask {
+                weights[k].data.par_iter_mut().zip(mask.data.par_iter()).for_each(|(w, m)| *w *= 1.0 - m);
+            }
+        }
+        // Where every other frame was suppressed the reference must carry the pixel alone
+        let total: Vec<f32> = (0..width * height)
+            .into_par_iter()
+            .map(|i| weights.iter().map(|w| w.data[i]).sum())
+            .collect();
+        weights[reference].data.par_iter_mut().zip(total.par_iter()).for_each(|(w, &t)| {
+            if t < 1e-6 {
+                *w = 1.0;
+            }
+        });
+    }
+
+    normalize_weights(&mut weights);
+
+    let levels = pyramid_levels(width, height);
+    let fused = rgb
+        .par_iter()
+        .zip(weights.par_iter())
+        .map(|(img, w)| {
+            let lap = laplacian_pyramid(Plane::from_rgb(img), levels);
+            let gauss = gaussian_pyramid(w.clone(), levels);
+            lap.into_iter().zip(gauss).map(|(l, g)| l.weighted(&g)).collect::<Vec<_>>()
+        })
+        .reduce_with(|a, b| a.into_iter().zip(b).map(|(x, y)| x.add(&y)).collect())
+        .ok_or("No exposures to merge")?;
+
+    let out = collapse(fused).to_rgb();
+    Ok(DynamicImage::ImageRgb8(out))
+}
+
+/// Per-frame ghost masks (1.0 = moving content) relative to `reference`.
+///
+/// Each frame's luminance is histogram-matched to the reference so the exposure
+/// difference cancels out; remaining differences in pixels that are well exposed
+/// in both frames are motion. Luminance is lightly smoothed first so sensor noise
+/// does not register as movement. Masks are dilated and feathered so people walking
+/// through the scene are replaced as a whole rather than pixel by pixel.
+fn ghost_masks(frames: &[RgbImage], reference: usize, threshold: f32) -> Vec<Option<Plane>> {
+    let smoothed = |img: &RgbImage| {
+        imageproc::filter::gaussian_blur_f32(&DynamicImage::ImageRgb8(img.clone()).to_luma8(), 1.0)
+    };
+    let ref_luma = smoothed(&frames[reference]);
+    let (width, height) = ref_luma.dimensions();
+    let radius = ((width.max(height) / 200).clamp(2, 12)) as u8;
+
+    frames
+        .par_iter()
+        .enumerate()
+        .map(|(k, frame)| {
+            if k == reference {
+                return None;
+            }
+            let luma = smoothed(frame);
+            let mapping = match_histogram(&luma, &ref_luma);
+            let data: Vec<u8> = luma
+                .as_raw()
+                .par_iter()
+                .zip(ref_luma.as_raw().par_iter())
+                .map(|(&v, &r)| {
+                    let usable = (8..=247).contains(&v) && (8..=247).contains(&r);
+                    let diff = (mapping[v as usize] as f32 - r as f32).abs() / 255.0;
+                    if usable && diff > threshold { 255 } else { 0 }
+                })
+                .collect();
+            let raw = GrayImage::from_raw(width, height, data).expect("mask matches frame size");
+            // Dilation covers the soft edges of moving subjects before feathering
+            let grown = imageproc::morphology::dilate(&raw, imageproc::distance_transform::Norm::LInf, radius);
+            let feathered = imageproc::filter::gaussian_blur_f32(&grown, radius as f32);
+            Some(Plane {
+                w: width as usize,
+                h: height as usize,
+                c: 1,
+                data: feathered.pixels().map(|&Luma([v])| v as f32 / 255.0).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Builds a lookup table taking `src` luminance levels to those of `dst` with the same CDF rank.
+fn match_histogram(src: &GrayImage, dst: &GrayImage) -> [u8; 256] {
+    let cdf = |img: &GrayImage| {
+        let mut hist = [0u64; 256];
+        img.as_raw().iter().for_each(|&v| hist[v as usize] += 1);
+        let total = img.as_raw().len().max(1) as f64;
+        let mut acc = 0u64;
+        hist.map(|n| {
+            acc += n;
+            acc as f64 / total
+        })
+    };
+    let (src_cdf, dst_cdf) = (cdf(src), cdf(dst));
+    let mut mapping = [0u8; 256];
+    let mut j = 0;
+    for (i, m) in mapping.iter_mut().enumerate() {
+        while j < 255 && dst_cdf[j] < src_cdf[i] {
+            j += 1;
+        }
+        *m = j as u8;
+    }
+    mapping
+}
+
+/// Mertens quality measure: contrast x saturation x well-exposedness.
+fn fusion_weights(img: &RgbImage) -> Plane {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let px = img.as_raw();
+    let gray: Vec<f32> = px
+        .par_chunks(3)
+        .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.0)
+        .collect();
+
+    let data = (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % w, i / w);
+            let at = |dx: isize, dy: isize| {
+                let nx = (x as isize + dx).clamp(0, w as isize - 1) as usize;
+                let ny = (y as isize + dy).clamp(0, h as isize - 1) as usize;
+                gray[ny * w + nx]
+            };
+            let contrast = (at(-1, 0) + at(1, 0) + at(0, -1) + at(0, 1) - 4.0 * gray[i]).abs();
+
+            let rgb = [0, 1, 2].map(|c| px[i * 3 + c] as f32 / 255.0);
+            let mean = (rgb[0] + rgb[1] + rgb[2]) / 3.0;
+            let saturation = (rgb.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / 3.0).sqrt();
+            let exposedness: f32 = rgb.iter().map(|v| (-(v - 0.5).powi(2) / 0.08).exp()).product();
+
+            (contrast + 1e-3) * (saturation + 1e-3) * exposedness + 1e-12
+        })
+        .collect();
+    Plane { w, h, c: 1, data }
+}
+
+fn normalize_weights(weights: &mut [Plane]) {
+    let n = weights[0].data.len();
+    let totals: Vec<f32> = (0..n)
+        .into_par_iter()
+        .map(|i| weights.iter().map(|w| w.data[i]).sum::<f32>().max(1e-12))
+        .collect();
+    for w in weights.iter_mut() {
+        w.data.par_iter_mut().zip(totals.par_iter()).for_each(|(v, t)| *v /= t);
+    }
+}
+
+fn pyramid_levels(width: usize, height: usize) -> usize {
+    let min = width.min(height).max(1) as f32;
+    (min.log2().floor() as usize).saturating_sub(2).clamp(1, 8)
+}
+
+/// Interleaved float image with `c` channels.
+#[derive(Clone)]
+struct Plane {
+    w: usize,
+    h: usize,
+    c: usize,
+    data: Vec<f32>,
+}
+
+impl Plane {
+    fn from_rgb(img: &RgbImage) -> Self {
+        Self {
+            w: img.width() as usize,
+            h: img.height() as usize,
+            c: 3,
+            data: img.as_raw().iter().map(|&v| v as f32 / 255.0).collect(),
+        }
+    }
+
+    fn to_rgb(&self) -> RgbImage {
+        let data = self.data.iter().map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8).collect();
+        RgbImage::from_raw(self.w as u32, self.h as u32, data).expect("plane matches dimensions")
+    }
+
+    /// 5-tap binomial blur, [1 4 6 4 1] / 16, separable with clamped edges.
+    fn blur(&self) -> Self {
+        const K: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let (w, h, c) = (self.w, self.h, self.c);
+        let pass = |src: &[f32], horizontal: bool| -> Vec<f32> {
+            let mut out = vec![0.0; src.len()];
+            out.par_chunks_mut(w * c).enumerate().for_each(|(y, row)| {
+                for x in 0..w {
+                    for ch in 0..c {
+                        row[x * c + ch] = (0..5)
+                            .map(|t| {
+                                let o = t as isize - 2;
+                                let (sx, sy) = if horizontal {
+                                    ((x as isize + o).clamp(0, w as isize - 1) as usize, y)
+                                } else {
+                                    (x, (y as isize + o).clamp(0, h as isize - 1) as usize)
+                                };
+                                K[t] * src[(sy * w + sx) * c + ch]
+                            })
+                            .sum();
+                    }
+                }
+            });
+            out
+        };
+        let horizontal = pass(&self.data, true);
+        Self { data: pass(&horizontal, false), ..*self }
+    }
+
+    fn downsample(&self) -> Self {
+        let blurred = self.blur();
+        let (w, h) = (self.w.div_ceil(2), self.h.div_ceil(2));
+        let c = self.c;
+        let mut data = vec![0.0; w * h * c];
+        data.par_chunks_mut(w * c).enumerate().for_each(|(y, row)| {
+            for x in 0..w {
+                let src = ((y * 2).min(self.h - 1) * self.w + (x * 2).min(self.w - 1)) * c;
+                row[x * c..x * c + c].copy_from_slice(&blurred.data[src..src + c]);
+            }
+        });
+        Self { w, h, c, data }
+    }
+
+    /// Bilinear upsample to `w` x `h`.
+    fn upsample(&self, w: usize, h: usize) -> Self {
+        let c = self.c;
+        let mut data = vec![0.0; w * h * c];
+        data.par_chunks_mut(w * c).enumerate().for_each(|(y, row)| {
+            let sy = ((y as f32 - 0.5) / 2.0).max(0.0);
+            let (y0, fy) = (sy.floor() as usize, sy.fract());
+            let y1 = (y0 + 1).min(self.h - 1);
+            for x in 0..w {
+                let sx = ((x as f32 - 0.5) / 2.0).max(0.0);
+                let (x0, fx) = (sx.floor() as usize, sx.fract());
+                let x1 = (x0 + 1).min(self.w - 1);
+                let x0 = x0.min(self.w - 1);
+                let y0 = y0.min(self.h - 1);
+                for ch in 0..c {
+                    let p = |xx: usize, yy: usize| self.data[(yy * self.w + xx) * c + ch];
+                    let top = p(x0, y0) * (1.0 - fx) + p(x1, y0) * fx;
+                    let bottom = p(x0, y1) * (1.0 - fx) + p(x1, y1) * fx;
+                    row[x * c + ch] = top * (1.0 - fy) + bottom * fy;
+                }
+            }
+        });
+        Self { w, h, c, data }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let data = self.data.par_iter().zip(other.data.par_iter()).map(|(a, b)| a + b).collect();
+        Self { data, ..*self }
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        let data = self.data.par_iter().zip(other.data.par_iter()).map(|(a, b)| a - b).collect();
+        Self { data, ..*self }
+    }
+
+    /// Multiplies every channel by a single-channel weight plane of the same size.
+    fn weighted(&self, weight: &Self) -> Self {
+        let c = self.c;
+        let data = self
+            .data
+            .par_chunks(c)
+            .zip(weight.data.par_iter())
+            .flat_map_iter(|(px, &w)| px.iter().map(move |v| v * w))
+            .collect();
+        Self { data, ..*self }
+    }
+}
+
+fn gaussian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = vec![base];
+    for _ in 1..levels {
+        let next = pyramid.last().expect("pyramid has a base level").downsample();
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+fn laplacian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let gauss = gaussian_pyramid(base, levels);
+    let mut out: Vec<Plane> = gauss
+        .windows(2)
+        .map(|pair| pair[0].sub(&pair[1].upsample(pair[0].w, pair[0].h)))
+        .collect();
+    out.push(gauss.last().expect("pyramid has a base level").clone());
+    out
+}
+
+fn collapse(pyramid: Vec<Plane>) -> Plane {
+    let mut levels = pyramid.into_iter().rev();
+    let mut acc = levels.next().expect("pyramid has a base level");
+    for level in levels {
+        acc = level.add(&acc.upsample(level.w, level.h));
+    }
+    acc
+}
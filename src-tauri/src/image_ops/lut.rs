@@ -0,0 +1,105 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk 3D LUT Support
+ *
+ * Parser for Adobe/Resolve `.cube` files and trilinear lookup used by the
+ * LUT stage of the filter pipeline.
+ */
+use std::path::Path;
+
+/// A parsed 3D lookup table with `size³` RGB entries, red varying fastest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Reads and parses a `.cube` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read LUT {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid LUT {}: {}", path.display(), e))
+    }
+
+    /// Parses the text of a `.cube` file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let keyword = parts.next().unwrap_or_default();
+            let triple = |parts: std::str::SplitWhitespace| -> Result<[f32; 3], String> {
+                let values: Vec<f32> = parts
+                    .map(|v| v.parse::<f32>().map_err(|_| format!("line {}: bad number '{}'", n + 1, v)))
+                    .collect::<Result<_, _>>()?;
+                <[f32; 3]>::try_from(values).map_err(|_| format!("line {}: expected 3 values", n + 1))
+            };
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".to_string()),
+                "LUT_3D_SIZE" => {
+                    let n_size = parts
+                        .next()
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .filter(|&v| (2..=256).contains(&v))
+                        .ok_or_else(|| format!("line {}: invalid LUT_3D_SIZE", n + 1))?;
+                    size = Some(n_size);
+                }
+                "DOMAIN_MIN" => domain_min = triple(parts)?,
+                "DOMAIN_MAX" => domain_max = triple(parts)?,
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    table.push(triple(line.split_whitespace())?);
+                }
+                // Unknown keywords (e.g. LUT_3D_INPUT_RANGE from other tools) are ignored
+                _ => {}
+            }
+        }
+
+        let size = size.ok_or("missing LUT_3D_SIZE")?;
+        if table.len() != size * size * size {
+            return Err(format!("expected {} entries, found {}", size * size * size, table.len()));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err("DOMAIN_MAX must exceed DOMAIN_MIN".to_string());
+        }
+        Ok(Self { size, domain_min, domain_max, table })
+    }
+
+    /// Looks up an RGB triple (0.0..=1.0) with trilinear interpolation.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let mut base = [0usize; 3];
+        let mut frac = [0f32; 3];
+        for c in 0..3 {
+            let t = (rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]);
+            let pos = t.clamp(0.0, 1.0) * max;
+            base[c] = (pos as usize).min(self.size - 2);
+            frac[c] = pos - base[c] as f32;
+        }
+
+        let at = |r: usize, g: usize, b: usize| self.table[(b * self.size + g) * self.size + r];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+
+        let [r, g, b] = base;
+        let c00 = lerp(at(r, g, b), at(r + 1, g, b), frac[0]);
+        let c10 = lerp(at(r, g + 1, b), at(r + 1, g + 1, b), frac[0]);
+        let c01 = lerp(at(r, g, b + 1), at(r + 1, g, b + 1), frac[0]);
+        let c11 = lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), frac[0]);
+        let c0 = lerp(c00, c10, frac[1]);
+        let c1 = lerp(c01, c11, frac[1]);
+        lerp(c0, c1, frac[2])
+    }
+}
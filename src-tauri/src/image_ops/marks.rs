@@ -0,0 +1,138 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Crop Marks
+ *
+ * Finds the printed crop marks around scanned artwork and the trim box they
+ * stand for. Crop marks are short hairlines in the margins that continue the
+ * trim edges: horizontal ones to the left and right of the artwork at its top
+ * and bottom edges, vertical ones above and below it at its left and right
+ * edges. They are found as thin, solid connected strokes of ink lying in the
+ * outer third of the sheet along their own direction, and each trim edge is
+ * the median centerline of the strokes on it, to a fraction of a pixel.
+ * Registration targets (a circle with a crosshair) are reported too.
+ */
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use serde::Serialize;
+
+/// Strokes this far off the median line of an edge belong to something else.
+const LINE_TOLERANCE: f32 = 3.0;
+
+/// A straight hairline of a crop mark.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct MarkStroke {
+    pub horizontal: bool,
+    /// Centerline: the y of a horizontal stroke, the x of a vertical one.
+    pub center: f32,
+    /// Extent along the stroke.
+    pub start: u32,
+    pub end: u32,
+    pub thickness: u32,
+}
+
+/// The trim box the crop marks of a sheet stand for, with the marks behind it, in the
+/// pixels of the image the crop stage received.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CropMarks {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    /// Strokes the box was measured from.
+    pub strokes: Vec<MarkStroke>,
+    /// Centers of the registration targets found.
+    pub registration: Vec<(f32, f32)>,
+}
+
+impl CropMarks {
+    /// The trim box grown by `bleed` pixels on every side, clamped to a `width` x `height`
+    /// image, as a crop region.
+    pub fn region(&self, bleed: u32, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let bleed = bleed as f32;
+        let x0 = (self.left - bleed).round().clamp(0.0, width as f32) as u32;
+        let y0 = (self.top - bleed).round().clamp(0.0, height as f32) as u32;
+        let x1 = (self.right + bleed).round().clamp(0.0, width as f32) as u32;
+        let y1 = (self.bottom + bleed).round().clamp(0.0, height as f32) as u32;
+        (x1 > x0 && y1 > y0).then(|| (x0, y0, x1 - x0, y1 - y0))
+    }
+}
+
+/// The crop marks of `img`, or `None` unless every trim edge has at least one.
+pub fn detect(img: &DynamicImage) -> Option<CropMarks> {
+    let luma = img.to_luma8();
+    let (w, h) = luma.dimensions();
+    let short = w.min(h) as f32;
+    let min_length = (short * 0.015).max(10.0) as u32;
+    let max_thickness = (short * 0.005).max(3.0) as u32;
+
+    // Ink is anything clearly darker than the paper
+    let level = imageproc::contrast::otsu_level(&luma);
+    let ink = GrayImage::from_fn(w, h, |x, y| Luma([if luma.get_pixel(x, y)[0] <= level { 255 } else { 0 }]));
+    let labels = connected_components(&ink, Connectivity::Eight, Luma([0u8]));
+    let count = labels.pixels().map(|l| l[0]).max().unwrap_or(0) as usize;
+    // Box, area and coordinate sums per component
+    let mut parts = vec![(u32::MAX, u32::MAX, 0u32, 0u32, 0u32, 0.0f64, 0.0f64); count + 1];
+    for (x, y, l) in labels.enumerate_pixels() {
+        let p = &mut parts[l[0] as usize];
+        *p = (p.0.min(x), p.1.min(y), p.2.max(x), p.3.max(y), p.4 + 1, p.5 + x as f64, p.6 + y as f64);
+    }
+
+    let mut strokes = Vec::new();
+    let mut registration = Vec::new();
+    for (label, &(x0, y0, x1, y1, area, sx, sy)) in parts.iter().enumerate().skip(1).filter(|(_, p)| p.4 > 0) {
+        let (bw, bh) = (x1 - x0 + 1, y1 - y0 + 1);
+        let (long, thin) = (bw.max(bh), bw.min(bh));
+        let (cx, cy) = ((sx / area as f64) as f32, (sy / area as f64) as f32);
+        if long >= min_length && thin <= max_thickness && area as f32 >= 0.6 * (bw * bh) as f32 {
+            let horizontal = bw > bh;
+            // Marks sit in the margins, so in the outer third along their own direction
+            let (from, to, size) = if horizontal { (x0, x1, w) } else { (y0, y1, h) };
+            if to < size / 3 || from > size - size / 3 {
+                strokes.push(MarkStroke { horizontal, center: if horizontal { cy } else { cx }, start: from, end: to, thickness: thin });
+            }
+        } else if is_target(&labels, label as u32, (x0, y0, x1, y1), area, min_length, short) {
+            registration.push((cx, cy));
+        }
+    }
+
+    let edge = |horizontal: bool, low: bool| -> Option<f32> {
+        let size = if horizontal { h } else { w } as f32;
+        let mut centers: Vec<f32> = strokes.iter().filter(|s| s.horizontal == horizontal && (s.center < size / 2.0) == low).map(|s| s.center).collect();
+        centers.sort_by(f32::total_cmp);
+        // The marks of one edge share a line; keep the line most strokes agree on
+        let best = centers.iter().max_by_key(|&&c| centers.iter().filter(|&&o| (o - c).abs() <= LINE_TOLERANCE).count())?;
+        let line: Vec<f32> = centers.iter().copied().filter(|c| (c - best).abs() <= LINE_TOLERANCE).collect();
+        Some(line[line.len() / 2])
+    };
+    let (top, bottom, left, right) = (edge(true, true)?, edge(true, false)?, edge(false, true)?, edge(false, false)?);
+    // Keep the strokes that lie on an edge and outside the box, as crop marks do
+    let strokes: Vec<MarkStroke> = strokes
+        .into_iter()
+        .filter(|s| {
+            let on_edge = if s.horizontal { [top, bottom] } else { [left, right] }.iter().any(|e| (s.center - e).abs() <= LINE_TOLERANCE);
+            let (low, high) = if s.horizontal { (left, right) } else { (top, bottom) };
+            on_edge && ((s.end as f32) < low + LINE_TOLERANCE || (s.start as f32) > high - LINE_TOLERANCE)
+        })
+        .collect();
+    (right > left && bottom > top).then_some(CropMarks { left, top, right, bottom, strokes, registration })
+}
+
+/// Whether component `label`, with box `x0, y0, x1, y1`, looks like a registration
+/// target: a roughly square outline crossed by lines through its middle.
+fn is_target(labels: &ImageBuffer<Luma<u32>, Vec<u32>>, label: u32, (x0, y0, x1, y1): (u32, u32, u32, u32), area: u32, min_length: u32, short: f32) -> bool {
+    let (bw, bh) = (x1 - x0 + 1, y1 - y0 + 1);
+    if bw < min_length || bw as f32 > short * 0.08 || bw.abs_diff(bh) * 5 > bw {
+        return false;
+    }
+    let fill = area as f32 / (bw * bh) as f32;
+    if !(0.05..0.5).contains(&fill) {
+        return false;
+    }
+    // The crosshair runs through the middle, give or take a pixel
+    let (mx, my) = ((x0 + x1) / 2, (y0 + y1) / 2);
+    let near = |a: u32, lo: u32, hi: u32| a.saturating_sub(1).max(lo)..=(a + 1).min(hi);
+    let across = (x0..=x1).filter(|&x| near(my, y0, y1).any(|y| labels.get_pixel(x, y)[0] == label)).count() as f32 / bw as f32;
+    let down = (y0..=y1).filter(|&y| near(mx, x0, x1).any(|x| labels.get_pixel(x, y)[0] == label)).count() as f32 / bh as f32;
+    across > 0.8 && down > 0.8
+}
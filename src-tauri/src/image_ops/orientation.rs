@@ -0,0 +1,111 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Page Orientation
+ *
+ * Finds which way up a page of text is from its ink alone. Lines of text
+ * make the ink profile across them far spikier than the one along them,
+ * which tells pages lying on their side from upright or upside-down ones.
+ * Which end is up comes from the letters: in Latin scripts ascenders and
+ * capitals rise above the x-height band of a line more often than
+ * descenders drop below its baseline, so on an upright page most of the ink
+ * outside the band of each line sits above it.
+ */
+use image::DynamicImage;
+
+/// Long edge pages are reduced to before measuring.
+const MAX_EDGE: u32 = 1200;
+/// Lines of text a page needs before its orientation is trusted.
+const MIN_LINES: usize = 3;
+
+/// How far a page has to be turned to read right side up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageOrientation {
+    /// Clockwise correction: 0, 90, 180 or 270.
+    pub degrees: u32,
+    /// 0.0-1.0: the share of the ink outside the x-height bands that points the
+    /// detected way, above what points the other way.
+    pub confidence: f32,
+}
+
+/// The orientation of the text on `img`, or `None` for pages without enough of it.
+pub fn detect(img: &DynamicImage) -> Option<PageOrientation> {
+    let small = if img.width().max(img.height()) > MAX_EDGE {
+        img.thumbnail(MAX_EDGE, MAX_EDGE).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let (w, h) = small.dimensions();
+    if w < 32 || h < 32 {
+        return None;
+    }
+    let level = imageproc::contrast::otsu_level(&small);
+    // Ink is the minority class, dark on paper or light on a negative
+    let dark_ink = small.pixels().filter(|p| p[0] > level).count() * 2 > (w * h) as usize;
+    let ink: Vec<bool> = small.pixels().map(|p| (p[0] <= level) == dark_ink).collect();
+
+    let rows: Vec<u32> = (0..h).map(|y| (0..w).filter(|&x| ink[(y * w + x) as usize]).count() as u32).collect();
+    let cols: Vec<u32> = (0..w).map(|x| (0..h).filter(|&y| ink[(y * w + x) as usize]).count() as u32).collect();
+    let sideways = spikiness(&cols) > spikiness(&rows);
+    let (rise, drop, lines) = if sideways {
+        // Turned a quarter clockwise, so the lines run across
+        let turned: Vec<bool> = (0..w).flat_map(|y| (0..h).map(move |x| (x, y))).map(|(x, y)| ink[((h - 1 - x) * w + y) as usize]).collect();
+        extents(&turned, h, w)
+    } else {
+        extents(&ink, w, h)
+    };
+    if lines < MIN_LINES || rise + drop == 0 {
+        return None;
+    }
+    let confidence = (rise as f32 - drop as f32).abs() / (rise + drop) as f32;
+    let upside_down = drop > rise;
+    let degrees = match (sideways, upside_down) {
+        (false, false) => 0,
+        (false, true) => 180,
+        (true, false) => 90,
+        (true, true) => 270,
+    };
+    Some(PageOrientation { degrees, confidence })
+}
+
+/// Coefficient of variation of a projection profile, between its first and last inked line.
+fn spikiness(profile: &[u32]) -> f32 {
+    let Some(start) = profile.iter().position(|&c| c > 0) else { return 0.0 };
+    let end = profile.len() - profile.iter().rev().position(|&c| c > 0).unwrap_or(0);
+    let span = &profile[start..end];
+    let mean = span.iter().sum::<u32>() as f32 / span.len() as f32;
+    let variance = span.iter().map(|&c| (c as f32 - mean).powi(2)).sum::<f32>() / span.len() as f32;
+    variance.sqrt() / mean.max(f32::EPSILON)
+}
+
+/// Ink above and below the x-height bands of the lines of a `w` x `h` grid whose lines
+/// run across, and how many lines there are. A line is a run of rows holding ink, its
+/// band the rows holding at least half of its fullest row.
+fn extents(ink: &[bool], w: u32, h: u32) -> (u32, u32, usize) {
+    let rows: Vec<u32> = (0..h).map(|y| ink[(y * w) as usize..((y + 1) * w) as usize].iter().filter(|&&i| i).count() as u32).collect();
+    // Stray specks between lines do not join them
+    let floor = rows.iter().max().copied().unwrap_or(0) / 20;
+    let (mut rise, mut drop, mut lines) = (0, 0, 0);
+    let mut y = 0;
+    while y < rows.len() {
+        if rows[y] <= floor {
+            y += 1;
+            continue;
+        }
+        let start = y;
+        while y < rows.len() && rows[y] > floor {
+            y += 1;
+        }
+        let line = &rows[start..y];
+        if line.len() < 4 {
+            continue;
+        }
+        let peak = line.iter().max().copied().unwrap_or(0);
+        let top = line.iter().position(|&c| c * 2 >= peak).unwrap_or(0);
+        let bottom = line.len() - line.iter().rev().position(|&c| c * 2 >= peak).unwrap_or(0);
+        rise += line[..top].iter().sum::<u32>();
+        drop += line[bottom..].iter().sum::<u32>();
+        lines += 1;
+    }
+    (rise, drop, lines)
+}
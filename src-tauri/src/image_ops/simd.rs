@@ -0,0 +1,286 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk SIMD Kernels
+ *
+ * Hot loops of the pipeline with explicit NEON paths for AArch64 (Apple
+ * Silicon, ARM laptops), where NEON is part of the baseline and needs no
+ * runtime detection. Each kernel has a scalar reference that handles image
+ * edges, tails and every other architecture; the NEON paths perform the same
+ * floating point operations in the same order, so results are bit-identical.
+ */
+use rayon::prelude::*;
+
+/// Per-pixel white balance, brightness, contrast and saturation, in pipeline order.
+/// Stages at their neutral value are skipped so they never perturb the pixel.
+#[derive(Clone, Copy, Debug)]
+pub struct Adjustments {
+    pub gains: Option<[f32; 3]>,
+    pub brightness_offset: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+}
+
+impl Adjustments {
+    #[inline]
+    pub fn apply(&self, mut r: f32, mut g: f32, mut b: f32) -> (f32, f32, f32) {
+        // White Balance
+        if let Some(gains) = self.gains {
+            r *= gains[0];
+            g *= gains[1];
+            b *= gains[2];
+        }
+
+        // Brightness
+        if self.brightness_offset != 0.0 {
+            r += self.brightness_offset;
+            g += self.brightness_offset;
+            b += self.brightness_offset;
+        }
+
+        // Contrast
+        if self.contrast != 1.0 {
+            r = (r - 128.0) * self.contrast + 128.0;
+            g = (g - 128.0) * self.contrast + 128.0;
+            b = (b - 128.0) * self.contrast + 128.0;
+        }
+
+        // Saturation
+        if self.saturation != 1.0 {
+            let l = 0.299 * r + 0.587 * g + 0.114 * b;
+            r = l + (r - l) * self.saturation;
+            g = l + (g - l) * self.saturation;
+            b = l + (b - l) * self.saturation;
+        }
+        (r, g, b)
+    }
+}
+
+/// Applies `adjustments` to interleaved RGB8 `pixels` in place.
+pub fn adjust_rgb8(pixels: &mut [u8], adjustments: &Adjustments) {
+    #[cfg(target_arch = "aarch64")]
+    let done = neon::adjust_rgb8(pixels, adjustments);
+    #[cfg(not(target_arch = "aarch64"))]
+    let done = 0;
+    adjust_rgb8_scalar(&mut pixels[done..], adjustments);
+}
+
+/// Scalar reference for `adjust_rgb8`.
+pub fn adjust_rgb8_scalar(pixels: &mut [u8], adjustments: &Adjustments) {
+    for pixel in pixels.chunks_exact_mut(3) {
+        let (r, g, b) = adjustments.apply(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+        pixel[0] = r.clamp(0.0, 255.0) as u8;
+        pixel[1] = g.clamp(0.0, 255.0) as u8;
+        pixel[2] = b.clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Bilinear demosaic of an RGGB mosaic into RGB8, scaling by `white_level`.
+/// Rows are processed in parallel; edges are clamped.
+pub fn demosaic_rggb(data: &[u16], width: usize, height: usize, white_level: f32) -> Vec<u8> {
+    demosaic_rows(data, width, height, white_level, true)
+}
+
+/// Scalar reference for `demosaic_rggb`.
+pub fn demosaic_rggb_scalar(data: &[u16], width: usize, height: usize, white_level: f32) -> Vec<u8> {
+    demosaic_rows(data, width, height, white_level, false)
+}
+
+fn demosaic_rows(data: &[u16], width: usize, height: usize, white_level: f32, vectorized: bool) -> Vec<u8> {
+    let mut out = vec![0u8; width * height * 3];
+    if width == 0 {
+        return out;
+    }
+    out.par_chunks_mut(width * 3).enumerate().for_each(|(y, row_out)| {
+        // Clamping the row index is all the vertical edge handling needed
+        let row = |y: usize| &data[y * width..(y + 1) * width];
+        let (up, mid, down) = (row(y.saturating_sub(1)), row(y), row((y + 1).min(height - 1)));
+        let even_row = y % 2 == 0;
+
+        #[cfg(target_arch = "aarch64")]
+        let (start, end) = if vectorized {
+            neon::demosaic_row(up, mid, down, even_row, white_level, row_out)
+        } else {
+            (0, 0)
+        };
+        #[cfg(not(target_arch = "aarch64"))]
+        let (start, end) = {
+            let _ = vectorized;
+            (0, 0)
+        };
+        demosaic_row_scalar(up, mid, down, even_row, white_level, row_out, 0..start);
+        demosaic_row_scalar(up, mid, down, even_row, white_level, row_out, end..width);
+    });
+    out
+}
+
+fn demosaic_row_scalar(
+    up: &[u16],
+    mid: &[u16],
+    down: &[u16],
+    even_row: bool,
+    white_level: f32,
+    out: &mut [u8],
+    columns: std::ops::Range<usize>,
+) {
+    let width = mid.len();
+    for x in columns {
+        let (l, r) = (x.saturating_sub(1), (x + 1).min(width - 1));
+        let (u, m, d) = (|i: usize| up[i] as u32, |i: usize| mid[i] as u32, |i: usize| down[i] as u32);
+        let (red, green, blue) = match (even_row, x % 2 == 0) {
+            // Red site
+            (true, true) => (m(x), (u(x) + d(x) + m(l) + m(r)) / 4, (u(l) + u(r) + d(l) + d(r)) / 4),
+            // Green site on a red row
+            (true, false) => ((m(l) + m(r)) / 2, m(x), (u(x) + d(x)) / 2),
+            // Green site on a blue row
+            (false, true) => ((u(x) + d(x)) / 2, m(x), (m(l) + m(r)) / 2),
+            // Blue site
+            (false, false) => ((u(l) + u(r) + d(l) + d(r)) / 4, (u(x) + d(x) + m(l) + m(r)) / 4, m(x)),
+        };
+        // Scale to 8-bit using white level
+        let scale = |v: u32| ((v as f32 / white_level) * 255.0).clamp(0.0, 255.0) as u8;
+        out[x * 3] = scale(red);
+        out[x * 3 + 1] = scale(green);
+        out[x * 3 + 2] = scale(blue);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::Adjustments;
+    use std::arch::aarch64::*;
+
+    /// Processes whole blocks of 16 pixels; returns the number of bytes handled.
+    pub fn adjust_rgb8(pixels: &mut [u8], adjustments: &Adjustments) -> usize {
+        let blocks = pixels.len() / 48;
+        // SAFETY: every block read and written lies within `pixels`
+        unsafe {
+            for i in 0..blocks {
+                let ptr = pixels.as_mut_ptr().add(i * 48);
+                let v = vld3q_u8(ptr);
+                let (mut r, mut g, mut b) = (widen(v.0), widen(v.1), widen(v.2));
+                for q in 0..4 {
+                    (r[q], g[q], b[q]) = adjust(r[q], g[q], b[q], adjustments);
+                }
+                vst3q_u8(ptr, uint8x16x3_t(narrow(r), narrow(g), narrow(b)));
+            }
+        }
+        blocks * 48
+    }
+
+    /// Mirrors `Adjustments::apply` on four pixels.
+    unsafe fn adjust(
+        mut r: float32x4_t,
+        mut g: float32x4_t,
+        mut b: float32x4_t,
+        a: &Adjustments,
+    ) -> (float32x4_t, float32x4_t, float32x4_t) {
+        if let Some(gains) = a.gains {
+            r = vmulq_f32(r, vdupq_n_f32(gains[0]));
+            g = vmulq_f32(g, vdupq_n_f32(gains[1]));
+            b = vmulq_f32(b, vdupq_n_f32(gains[2]));
+        }
+        if a.brightness_offset != 0.0 {
+            let offset = vdupq_n_f32(a.brightness_offset);
+            r = vaddq_f32(r, offset);
+            g = vaddq_f32(g, offset);
+            b = vaddq_f32(b, offset);
+        }
+        if a.contrast != 1.0 {
+            let (mid, contrast) = (vdupq_n_f32(128.0), vdupq_n_f32(a.contrast));
+            r = vaddq_f32(vmulq_f32(vsubq_f32(r, mid), contrast), mid);
+            g = vaddq_f32(vmulq_f32(vsubq_f32(g, mid), contrast), mid);
+            b = vaddq_f32(vmulq_f32(vsubq_f32(b, mid), contrast), mid);
+        }
+        if a.saturation != 1.0 {
+            // Separate multiplies and adds: a fused multiply-add would round differently
+            let l = vaddq_f32(
+                vaddq_f32(vmulq_f32(vdupq_n_f32(0.299), r), vmulq_f32(vdupq_n_f32(0.587), g)),
+                vmulq_f32(vdupq_n_f32(0.114), b),
+            );
+            let saturation = vdupq_n_f32(a.saturation);
+            r = vaddq_f32(l, vmulq_f32(vsubq_f32(r, l), saturation));
+            g = vaddq_f32(l, vmulq_f32(vsubq_f32(g, l), saturation));
+            b = vaddq_f32(l, vmulq_f32(vsubq_f32(b, l), saturation));
+        }
+        (r, g, b)
+    }
+
+    unsafe fn widen(v: uint8x16_t) -> [float32x4_t; 4] {
+        let (lo, hi) = (vmovl_u8(vget_low_u8(v)), vmovl_high_u8(v));
+        [
+            vcvtq_f32_u32(vmovl_u16(vget_low_u16(lo))),
+            vcvtq_f32_u32(vmovl_high_u16(lo)),
+            vcvtq_f32_u32(vmovl_u16(vget_low_u16(hi))),
+            vcvtq_f32_u32(vmovl_high_u16(hi)),
+        ]
+    }
+
+    /// Clamps to 0..=255 and truncates, like `clamp(0.0, 255.0) as u8`.
+    unsafe fn to_u32(v: float32x4_t) -> uint32x4_t {
+        vcvtq_u32_f32(vminq_f32(vmaxq_f32(v, vdupq_n_f32(0.0)), vdupq_n_f32(255.0)))
+    }
+
+    unsafe fn narrow(q: [float32x4_t; 4]) -> uint8x16_t {
+        let lo = vcombine_u16(vmovn_u32(to_u32(q[0])), vmovn_u32(to_u32(q[1])));
+        let hi = vcombine_u16(vmovn_u32(to_u32(q[2])), vmovn_u32(to_u32(q[3])));
+        vcombine_u8(vmovn_u16(lo), vmovn_u16(hi))
+    }
+
+    /// Demosaics the interior of one row eight pixels at a time and returns the
+    /// column range written; the caller fills the rest with the scalar kernel.
+    ///
+    /// `vld2` splits a row into even and odd columns, which on a Bayer row are
+    /// exactly the two CFA colors, so every neighbour is a plain vector load.
+    pub fn demosaic_row(
+        up: &[u16],
+        mid: &[u16],
+        down: &[u16],
+        even_row: bool,
+        white_level: f32,
+        out: &mut [u8],
+    ) -> (usize, usize) {
+        let width = mid.len();
+        // Loads reach two columns either side of the block
+        if width < 12 {
+            return (0, 0);
+        }
+        let mut x = 2;
+        // SAFETY: loads cover columns x - 2 .. x + 10 and stores pixels x .. x + 8,
+        // all below `width` by the loop condition
+        unsafe {
+            let white = vdupq_n_f32(white_level);
+            while x + 10 <= width {
+                let load = |row: &[u16]| {
+                    let (prev, cur, next) = (
+                        vld2_u16(row.as_ptr().add(x - 2)),
+                        vld2_u16(row.as_ptr().add(x)),
+                        vld2_u16(row.as_ptr().add(x + 2)),
+                    );
+                    // Even column, odd column, previous odd column, next even column
+                    (vmovl_u16(cur.0), vmovl_u16(cur.1), vmovl_u16(prev.1), vmovl_u16(next.0))
+                };
+                let (ue, uo, uop, uen) = load(up);
+                let (me, mo, mop, men) = load(mid);
+                let (de, d_o, dop, den) = load(down);
+                let sum4 = |a, b, c, d| vshrq_n_u32::<2>(vaddq_u32(vaddq_u32(a, b), vaddq_u32(c, d)));
+                let sum2 = |a, b| vshrq_n_u32::<1>(vaddq_u32(a, b));
+
+                // (even pixel, odd pixel) per channel
+                let (red, green, blue) = if even_row {
+                    ((me, sum2(me, men)), (sum4(ue, de, mop, mo), mo), (sum4(uop, uo, dop, d_o), sum2(uo, d_o)))
+                } else {
+                    ((sum2(ue, de), sum4(ue, uen, de, den)), (me, sum4(uo, d_o, me, men)), (sum2(mop, mo), mo))
+                };
+                let channel = |(even, odd): (uint32x4_t, uint32x4_t)| {
+                    let scale = |v| vmovn_u32(to_u32(vmulq_f32(vdivq_f32(vcvtq_f32_u32(v), white), vdupq_n_f32(255.0))));
+                    let pixels = vzip_u16(scale(even), scale(odd));
+                    vmovn_u16(vcombine_u16(pixels.0, pixels.1))
+                };
+                vst3_u8(out.as_mut_ptr().add(x * 3), uint8x8x3_t(channel(red), channel(green), channel(blue)));
+                x += 8;
+            }
+        }
+        (2, x)
+    }
+}
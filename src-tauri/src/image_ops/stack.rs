@@ -0,0 +1,280 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Frame Stacking
+ *
+ * Registers a sequence of frames and integrates them with per-pixel sigma
+ * clipping, rejecting satellites, planes and hot pixels. Astrophotography
+ * frames are mostly black with a few point sources, which defeats phase
+ * correlation, so they are registered by matching triangles of detected stars.
+ */
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::Serialize;
+use super::align::{self, Transform};
+use crate::commands::{StackOptions, StackRegistration};
+
+/// Stars used to build matching triangles; the cost grows with the cube of this.
+const TRIANGLE_STARS: usize = 15;
+/// Relative tolerance when comparing triangle side ratios.
+const TRIANGLE_TOLERANCE: f32 = 0.01;
+/// Maximum residual, in pixels, for a star pair to count as an inlier.
+const INLIER_DISTANCE: f32 = 2.0;
+
+/// A detected point source with sub-pixel centroid.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Star {
+    pub x: f32,
+    pub y: f32,
+    /// Background-subtracted sum over the centroid window.
+    pub flux: f32,
+}
+
+/// Finds up to `max_stars` point sources, brightest first.
+///
+/// The background level and noise come from the median and the median absolute
+/// deviation, which ignore the stars themselves. Local maxima more than five
+/// sigma above the background are refined to their intensity-weighted centroid.
+pub fn detect_stars(luma: &GrayImage, max_stars: usize) -> Vec<Star> {
+    let (w, h) = (luma.width() as usize, luma.height() as usize);
+    if w < 7 || h < 7 {
+        return Vec::new();
+    }
+    let px = luma.as_raw();
+    let median = |values: &mut Vec<u8>| {
+        values.sort_unstable();
+        values[values.len() / 2] as f32
+    };
+    let background = median(&mut px.to_vec());
+    let mad = median(&mut px.iter().map(|&v| (v as f32 - background).abs() as u8).collect());
+    // MAD of pure quantization noise is 0; keep the threshold above the background anyway
+    let threshold = background + (5.0 * 1.4826 * mad).max(8.0);
+
+    let mut stars: Vec<Star> = (3..h - 3)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (3..w - 3).filter_map(move |x| {
+                let v = px[y * w + x];
+                if (v as f32) < threshold {
+                    return None;
+                }
+                // Strict maximum over the 5x5 neighbourhood; ties resolve to the first pixel
+                for dy in -2isize..=2 {
+                    for dx in -2isize..=2 {
+                        let (nx, ny) = ((x as isize + dx) as usize, (y as isize + dy) as usize);
+                        let n = px[ny * w + nx];
+                        if n > v || (n == v && (ny, nx) < (y, x)) {
+                            return None;
+                        }
+                    }
+                }
+                let (mut sx, mut sy, mut flux) = (0.0, 0.0, 0.0);
+                for dy in -3isize..=3 {
+                    for dx in -3isize..=3 {
+                        let (nx, ny) = ((x as isize + dx) as usize, (y as isize + dy) as usize);
+                        let weight = (px[ny * w + nx] as f32 - background).max(0.0);
+                        sx += weight * nx as f32;
+                        sy += weight * ny as f32;
+                        flux += weight;
+                    }
+                }
+                (flux > 0.0).then(|| Star { x: sx / flux, y: sy / flux, flux })
+            })
+        })
+        .collect();
+
+    stars.sort_by(|a, b| b.flux.total_cmp(&a.flux));
+    stars.truncate(max_stars);
+    stars
+}
+
+/// Registers `moving` against `reference` star lists by triangle similarity.
+///
+/// Triangles are described by their sorted side ratios, which do not change under
+/// rotation and translation. Every matching triangle pair votes for the three star
+/// correspondences it implies; the best-supported pairs seed a rigid fit that is
+/// refined on all stars lying within `INLIER_DISTANCE` of their prediction.
+pub fn match_stars(reference: &[Star], moving: &[Star], center: (f32, f32)) -> Option<Transform> {
+    let ref_tris = triangles(reference);
+    let mov_tris = triangles(moving);
+    if ref_tris.is_empty() || mov_tris.is_empty() {
+        return None;
+    }
+
+    let (nr, nm) = (reference.len().min(TRIANGLE_STARS), moving.len().min(TRIANGLE_STARS));
+    let mut votes = vec![0u32; nr * nm];
+    for a in &ref_tris {
+        for b in &mov_tris {
+            if (a.ratios.0 - b.ratios.0).abs() < TRIANGLE_TOLERANCE
+                && (a.ratios.1 - b.ratios.1).abs() < TRIANGLE_TOLERANCE
+            {
+                for k in 0..3 {
+                    votes[a.stars[k] * nm + b.stars[k]] += 1;
+                }
+            }
+        }
+    }
+
+    // Keep each reference star's best partner, strongest votes first
+    let mut pairs: Vec<(u32, usize, usize)> = (0..nr)
+        .filter_map(|r| {
+            let (m, &v) = votes[r * nm..(r + 1) * nm].iter().enumerate().max_by_key(|(_, v)| **v)?;
+            (v > 0).then_some((v, r, m))
+        })
+        .collect();
+    pairs.sort_by_key(|p| std::cmp::Reverse(p.0));
+    let seed: Vec<(Star, Star)> = pairs.iter().take(6).map(|&(_, r, m)| (reference[r], moving[m])).collect();
+    if seed.len() < 2 {
+        return None;
+    }
+
+    let mut transform = fit_rigid(&seed, center);
+    // Refine on every star that the seed transform predicts well
+    for _ in 0..2 {
+        let inliers: Vec<(Star, Star)> = reference
+            .iter()
+            .filter_map(|r| {
+                let (px, py) = apply(&transform, center, r.x, r.y);
+                moving
+                    .iter()
+                    .map(|m| (m, (m.x - px).hypot(m.y - py)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .filter(|(_, d)| *d < INLIER_DISTANCE)
+                    .map(|(m, _)| (*r, *m))
+            })
+            .collect();
+        if inliers.len() < 2 {
+            break;
+        }
+        transform = fit_rigid(&inliers, center);
+        transform.confidence = inliers.len() as f32 / reference.len().min(moving.len()).max(1) as f32;
+    }
+    transform.confidence = transform.confidence.clamp(0.0, 1.0);
+    Some(transform)
+}
+
+/// Registers and integrates `frames` into a 16-bit image.
+pub fn stack_frames(frames: &[DynamicImage], options: &StackOptions) -> Result<DynamicImage, String> {
+    let reference = options.reference.min(frames.len().saturating_sub(1));
+    let base = frames.get(reference).ok_or("No frames to stack")?;
+    if frames.iter().any(|f| f.width() != base.width() || f.height() != base.height()) {
+        return Err("All frames must have the same dimensions to be stacked".to_string());
+    }
+
+    let transforms: Vec<Transform> = match options.registration {
+        StackRegistration::None => vec![Transform::default(); frames.len()],
+        StackRegistration::Phase => align::align_to(frames, reference)?,
+        StackRegistration::Stars => {
+            let center = ((base.width() as f32 - 1.0) / 2.0, (base.height() as f32 - 1.0) / 2.0);
+            let stars: Vec<Vec<Star>> = frames.par_iter().map(|f| detect_stars(&f.to_luma8(), 50)).collect();
+            stars
+                .iter()
+                .enumerate()
+                .map(|(k, s)| {
+                    if k == reference {
+                        return Ok(Transform { confidence: 1.0, ..Default::default() });
+                    }
+                    match_stars(&stars[reference], s, center)
+                        .ok_or_else(|| format!("Could not match stars of frame {} to the reference", k))
+                })
+                .collect::<Result<_, String>>()?
+        }
+    };
+
+    let warped: Vec<RgbImage> = frames.par_iter().zip(transforms.par_iter()).map(|(f, t)| align::warp(f, t)).collect();
+    Ok(DynamicImage::ImageRgb16(sigma_clip(&warped, options.kappa.max(0.5), options.iterations.max(1))))
+}
+
+/// Per-sample kappa-sigma clipped mean, scaled to 16 bits so averaging gains precision.
+fn sigma_clip(frames: &[RgbImage], kappa: f32, iterations: u32) -> image::ImageBuffer<Rgb<u16>, Vec<u16>> {
+    let (w, h) = frames[0].dimensions();
+    let raws: Vec<&[u8]> = frames.iter().map(|f| f.as_raw().as_slice()).collect();
+    let data: Vec<u16> = (0..raws[0].len())
+        .into_par_iter()
+        .map_init(
+            || Vec::with_capacity(raws.len()),
+            |samples: &mut Vec<f32>, i| {
+                samples.clear();
+                samples.extend(raws.iter().map(|r| r[i] as f32));
+                let mut mean = samples.iter().sum::<f32>() / samples.len() as f32;
+                for _ in 0..iterations {
+                    let n = samples.len() as f32;
+                    let sigma = (samples.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+                    let before = samples.len();
+                    if sigma > 0.0 {
+                        samples.retain(|v| (v - mean).abs() <= kappa * sigma);
+                    }
+                    if samples.is_empty() || samples.len() == before {
+                        break;
+                    }
+                    mean = samples.iter().sum::<f32>() / samples.len() as f32;
+                }
+                (mean * 257.0).round().clamp(0.0, 65535.0) as u16
+            },
+        )
+        .collect();
+    image::ImageBuffer::from_raw(w, h, data).expect("stack matches frame size")
+}
+
+struct Triangle {
+    /// Star indices ordered opposite the longest, middle and shortest side.
+    stars: [usize; 3],
+    /// Middle and shortest side over the longest side.
+    ratios: (f32, f32),
+}
+
+fn triangles(stars: &[Star]) -> Vec<Triangle> {
+    let n = stars.len().min(TRIANGLE_STARS);
+    let dist = |a: usize, b: usize| (stars[a].x - stars[b].x).hypot(stars[a].y - stars[b].y);
+    let mut out = Vec::new();
+    for i in 0..n {
+        for j in i + 1..n {
+            for k in j + 1..n {
+                // Each side paired with the vertex opposite it
+                let mut sides = [(dist(j, k), i), (dist(i, k), j), (dist(i, j), k)];
+                sides.sort_by(|a, b| b.0.total_cmp(&a.0));
+                let longest = sides[0].0;
+                // Skip degenerate and near-isosceles triangles whose vertex order is ambiguous
+                if longest < 10.0
+                    || sides[0].0 - sides[1].0 < longest * 0.02
+                    || sides[1].0 - sides[2].0 < longest * 0.02
+                {
+                    continue;
+                }
+                out.push(Triangle {
+                    stars: [sides[0].1, sides[1].1, sides[2].1],
+                    ratios: (sides[1].0 / longest, sides[2].0 / longest),
+                });
+            }
+        }
+    }
+    out
+}
+
+/// Least-squares rotation and translation taking reference points onto moving points.
+fn fit_rigid(pairs: &[(Star, Star)], center: (f32, f32)) -> Transform {
+    let n = pairs.len() as f32;
+    let (rx, ry) = pairs.iter().fold((0.0, 0.0), |a, (r, _)| (a.0 + r.x / n, a.1 + r.y / n));
+    let (mx, my) = pairs.iter().fold((0.0, 0.0), |a, (_, m)| (a.0 + m.x / n, a.1 + m.y / n));
+    let (mut cross, mut dot) = (0.0f32, 0.0f32);
+    for (r, m) in pairs {
+        let (ax, ay, bx, by) = (r.x - rx, r.y - ry, m.x - mx, m.y - my);
+        cross += ax * by - ay * bx;
+        dot += ax * bx + ay * by;
+    }
+    let theta = cross.atan2(dot);
+    let (sin, cos) = theta.sin_cos();
+    let (cx, cy) = (rx - center.0, ry - center.1);
+    Transform {
+        dx: mx - center.0 - (cos * cx - sin * cy),
+        dy: my - center.1 - (sin * cx + cos * cy),
+        rotation: theta.to_degrees(),
+        confidence: 0.0,
+    }
+}
+
+fn apply(t: &Transform, center: (f32, f32), x: f32, y: f32) -> (f32, f32) {
+    let (sin, cos) = t.rotation.to_radians().sin_cos();
+    let (px, py) = (x - center.0, y - center.1);
+    (cos * px - sin * py + center.0 + t.dx, sin * px + cos * py + center.1 + t.dy)
+}
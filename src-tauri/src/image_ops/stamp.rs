@@ -0,0 +1,148 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Text Stamp
+ *
+ * Burns a line of text into each output, built from a template such as
+ * "{filename} {date} #{index:04}", for evidence photography and archival
+ * reference prints where the identification must travel with the pixels.
+ */
+use ab_glyph::{FontArc, PxScale};
+use image::{DynamicImage, Rgb};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use std::sync::Arc;
+use crate::commands::TextStampOptions;
+
+/// Per-file values substituted into a stamp template.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StampContext {
+    /// File name with extension.
+    pub filename: String,
+    /// 1-based position in the batch.
+    pub index: usize,
+    /// "YYYY-MM-DD" and "HH:MM:SS" of capture, or of the file's last modification.
+    pub date: String,
+    pub time: String,
+}
+
+/// Source of template token values, so tokens can be added (e.g. `{client_code}` from
+/// a project database) without changes to the template engine.
+pub trait TokenProvider: Send + Sync {
+    /// Value of `{name}` or `{name:argument}` for the file in `context`, or `None` when
+    /// the provider does not know the token.
+    fn resolve(&self, name: &str, argument: Option<&str>, context: &StampContext) -> Option<String>;
+}
+
+/// `{filename}`, `{stem}`, `{date}`, `{time}`, `{index}` and `{index:N}` (zero-padded
+/// to N digits).
+pub struct BuiltinTokens;
+
+impl TokenProvider for BuiltinTokens {
+    fn resolve(&self, name: &str, argument: Option<&str>, context: &StampContext) -> Option<String> {
+        match name {
+            "filename" => Some(context.filename.clone()),
+            "stem" => Some(std::path::Path::new(&context.filename).file_stem().unwrap_or_default().to_string_lossy().to_string()),
+            "date" => Some(context.date.clone()),
+            "time" => Some(context.time.clone()),
+            "index" => {
+                let width = argument.and_then(|w| w.parse::<usize>().ok()).unwrap_or(0);
+                Some(format!("{:0width$}", context.index, width = width))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Substitutes the built-in tokens; see `BuiltinTokens`. Unknown placeholders are kept
+/// verbatim.
+pub fn render_template(template: &str, context: &StampContext) -> String {
+    render_template_with(template, context, &[])
+}
+
+/// Substitutes the built-in tokens, then those of `providers` in order; the first
+/// provider knowing a token supplies its value. Unknown placeholders are kept verbatim.
+pub fn render_template_with(template: &str, context: &StampContext, providers: &[Arc<dyn TokenProvider>]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let token = &rest[start + 1..start + len];
+        let (name, argument) = token.split_once(':').map_or((token, None), |(n, a)| (n, Some(a)));
+        let value = BuiltinTokens
+            .resolve(name, argument, context)
+            .or_else(|| providers.iter().find_map(|p| p.resolve(name, argument, context)));
+        match value {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// "YYYY-MM-DD" and "HH:MM:SS" (UTC) of a Unix timestamp.
+pub fn format_timestamp(secs: u64) -> (String, String) {
+    // Days to civil date, after Howard Hinnant's algorithm
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let t = secs % 86_400;
+    (format!("{:04}-{:02}-{:02}", year, month, day), format!("{:02}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60))
+}
+
+/// Draws `text` at the stamp position, over an optional background box.
+/// 16-bit images are stamped at 16 bits; everything else becomes RGB8.
+pub fn draw_stamp(img: DynamicImage, text: &str, options: &TextStampOptions, font: &FontArc) -> DynamicImage {
+    if text.is_empty() {
+        return img;
+    }
+    let (width, height) = (img.width(), img.height());
+    let short = width.min(height) as f32;
+    let scale = PxScale::from((short * options.size).max(4.0));
+    let (tw, th) = text_size(scale, font, text);
+    let margin = (short * options.margin.max(0.0)).round() as u32;
+    let pad = (scale.y * 0.25).round() as u32;
+    let (box_w, box_h) = (tw + 2 * pad, th + 2 * pad);
+
+    let (gx, gy) = options.position.anchor();
+    let bx = margin + (width.saturating_sub(box_w + 2 * margin) as f32 * gx).round() as u32;
+    let by = margin + (height.saturating_sub(box_h + 2 * margin) as f32 * gy).round() as u32;
+    let rect = Rect::at(bx as i32, by as i32).of_size(box_w.max(1), box_h.max(1));
+    let (tx, ty) = ((bx + pad) as i32, (by + pad) as i32);
+
+    let [r, g, b] = options.color.0;
+    if img.color().bytes_per_pixel() / img.color().channel_count() > 1 {
+        let wide = |c: u8| c as u16 * 257;
+        let mut out = img.to_rgb16();
+        if let Some(bg) = options.background {
+            draw_filled_rect_mut(&mut out, rect, Rgb(bg.0.map(wide)));
+        }
+        draw_text_mut(&mut out, Rgb([wide(r), wide(g), wide(b)]), tx, ty, scale, font, text);
+        DynamicImage::ImageRgb16(out)
+    } else {
+        let mut out = img.to_rgb8();
+        if let Some(bg) = options.background {
+            draw_filled_rect_mut(&mut out, rect, Rgb(bg.0));
+        }
+        draw_text_mut(&mut out, Rgb([r, g, b]), tx, ty, scale, font, text);
+        DynamicImage::ImageRgb8(out)
+    }
+}
+
+/// Loads a TrueType/OpenType font for stamping.
+pub fn load_font(path: &str) -> Result<FontArc, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read font {}: {}", path, e))?;
+    FontArc::try_from_vec(data).map_err(|e| format!("Invalid font {}: {}", path, e))
+}
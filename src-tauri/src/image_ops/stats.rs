@@ -0,0 +1,141 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Image Statistics
+ *
+ * Per-output quality figures written as a `.stats.json` sidecar for QA
+ * scripts in digitization projects: per-channel distribution, a coarse
+ * histogram, clipping, a sharpness score, and a hash of the options that
+ * produced the file so outputs can be grouped by the settings they share.
+ */
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::Serialize;
+
+/// Bins in the histogram summary.
+pub const HISTOGRAM_BINS: usize = 16;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageStats {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    /// Red, green and blue, in that order. Values are normalized to 0.0..=1.0.
+    pub channels: Vec<ChannelStats>,
+    /// Variance of the Laplacian of luminance (normalized); higher is sharper.
+    /// Only comparable between images of similar size and content.
+    pub sharpness: f64,
+    /// SHA-256 of the processing options, identical for every file of a batch.
+    pub options_hash: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChannelStats {
+    pub name: &'static str,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p01: f64,
+    pub median: f64,
+    pub p99: f64,
+    /// Share of pixels at the lowest and highest code value.
+    pub clipped_shadows: f64,
+    pub clipped_highlights: f64,
+    /// Share of pixels in each of `HISTOGRAM_BINS` equal-width bins.
+    pub histogram: Vec<f64>,
+}
+
+/// Computes the statistics of a finished image. 16-bit outputs are measured at 16 bits.
+pub fn compute(img: &DynamicImage, options_hash: String) -> ImageStats {
+    let sixteen = img.color().bytes_per_pixel() / img.color().channel_count() > 1;
+    let (channels, bit_depth) = if sixteen {
+        let rgb = img.to_rgb16();
+        (channel_stats(rgb.as_raw(), u16::MAX as usize), 16)
+    } else {
+        let rgb = img.to_rgb8();
+        (channel_stats(rgb.as_raw(), u8::MAX as usize), 8)
+    };
+    ImageStats {
+        width: img.width(),
+        height: img.height(),
+        bit_depth,
+        channels,
+        sharpness: laplacian_variance(img),
+        options_hash,
+    }
+}
+
+fn channel_stats<T: Copy + Into<u32> + Sync>(samples: &[T], max: usize) -> Vec<ChannelStats> {
+    ["red", "green", "blue"]
+        .into_par_iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let mut counts = vec![0u64; max + 1];
+            for px in samples.chunks_exact(3) {
+                counts[px[c].into() as usize] += 1;
+            }
+            let total = (samples.len() / 3).max(1) as f64;
+            let scale = max as f64;
+            let (mut sum, mut sum_sq) = (0.0, 0.0);
+            for (v, &n) in counts.iter().enumerate() {
+                let v = v as f64 / scale;
+                sum += v * n as f64;
+                sum_sq += v * v * n as f64;
+            }
+            let mean = sum / total;
+            let percentile = |p: f64| {
+                let target = (p * total).ceil().max(1.0) as u64;
+                let mut seen = 0;
+                counts.iter().position(|&n| {
+                    seen += n;
+                    seen >= target
+                }).unwrap_or(max) as f64 / scale
+            };
+            let mut histogram = vec![0.0; HISTOGRAM_BINS];
+            for (v, &n) in counts.iter().enumerate() {
+                histogram[v * HISTOGRAM_BINS / (max + 1)] += n as f64 / total;
+            }
+            ChannelStats {
+                name,
+                mean,
+                std_dev: (sum_sq / total - mean * mean).max(0.0).sqrt(),
+                min: counts.iter().position(|&n| n > 0).unwrap_or(0) as f64 / scale,
+                max: counts.iter().rposition(|&n| n > 0).unwrap_or(0) as f64 / scale,
+                p01: percentile(0.01),
+                median: percentile(0.5),
+                p99: percentile(0.99),
+                clipped_shadows: counts[0] as f64 / total,
+                clipped_highlights: counts[max] as f64 / total,
+                histogram,
+            }
+        })
+        .collect()
+}
+
+/// Variance of the 4-neighbour Laplacian over the interior of the luminance.
+fn laplacian_variance(img: &DynamicImage) -> f64 {
+    let luma = img.to_luma16();
+    let (w, h) = (luma.width() as usize, luma.height() as usize);
+    if w < 3 || h < 3 {
+        return 0.0;
+    }
+    let px = luma.as_raw();
+    let (sum, sum_sq) = (1..h - 1)
+        .into_par_iter()
+        .map(|y| {
+            let (mut sum, mut sum_sq) = (0.0f64, 0.0f64);
+            for x in 1..w - 1 {
+                let i = y * w + x;
+                let lap = (px[i - 1] as f64 + px[i + 1] as f64 + px[i - w] as f64 + px[i + w] as f64 - 4.0 * px[i] as f64)
+                    / u16::MAX as f64;
+                sum += lap;
+                sum_sq += lap * lap;
+            }
+            (sum, sum_sq)
+        })
+        .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+    let n = ((w - 2) * (h - 2)) as f64;
+    let mean = sum / n;
+    (sum_sq / n - mean * mean).max(0.0)
+}
@@ -0,0 +1,288 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Stitching
+ *
+ * Merges the overlapping tiles of a foldout or oversize capture into one
+ * image. Each tile is registered to the one before it by matching BRIEF
+ * descriptors of FAST corners at working size, with a similarity transform
+ * (shift, rotation and scale) fitted to the matches by RANSAC, so camera
+ * moves between shots and small changes of height are both absorbed. Tiles
+ * are then resampled onto a shared canvas and feathered across their
+ * overlaps, each weighted by its distance to its own edge.
+ */
+use image::{DynamicImage, GrayImage, ImageBuffer};
+use imageproc::binary_descriptors::brief::{brief, BriefDescriptor, TestPair};
+use imageproc::binary_descriptors::BinaryDescriptor;
+use imageproc::point::Point;
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::commands::StitchOptions;
+
+/// Long edge the features are detected at; placements are scaled back to full size.
+const WORK_SIZE: u32 = 1200;
+/// Corners described per tile.
+const CORNERS: usize = 2000;
+/// Keeps corners clear of the edge for their 31x31 BRIEF patch.
+const EDGE: u32 = 20;
+const DESCRIPTOR_BITS: usize = 256;
+/// A match is kept when its distance is below this share of the second best's.
+const RATIO: f32 = 0.8;
+const RANSAC_ROUNDS: usize = 2000;
+/// Residual, in working pixels, for a match to count as an inlier.
+const INLIER_DISTANCE: f32 = 3.0;
+/// Inliers two tiles need before their placement is trusted.
+const MIN_INLIERS: usize = 12;
+/// Canvases above this many pixels are refused rather than allocated.
+const MAX_PIXELS: u64 = 1 << 30;
+
+/// A matched point of the moving tile and its counterpart in the fixed one.
+type Match = ((f32, f32), (f32, f32));
+
+/// Similarity transform from tile pixels to canvas pixels:
+/// `x' = a x - b y + tx`, `y' = b x + a y + ty`.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    pub a: f32,
+    pub b: f32,
+    pub tx: f32,
+    pub ty: f32,
+    /// Matches that agreed with the registration to the previous tile; 0 for the first.
+    pub inliers: usize,
+}
+
+impl Placement {
+    const IDENTITY: Placement = Placement { a: 1.0, b: 0.0, tx: 0.0, ty: 0.0, inliers: 0 };
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (self.a * x - self.b * y + self.tx, self.b * x + self.a * y + self.ty)
+    }
+
+    /// Scale of the placement; 1.0 when the tile keeps its size.
+    pub fn scale(&self) -> f32 {
+        self.a.hypot(self.b)
+    }
+
+    /// Rotation of the placement in degrees, counterclockwise in image coordinates.
+    pub fn rotation(&self) -> f32 {
+        self.b.atan2(self.a).to_degrees()
+    }
+
+    fn then(&self, outer: &Placement) -> Placement {
+        let (tx, ty) = outer.apply(self.tx, self.ty);
+        Placement { a: outer.a * self.a - outer.b * self.b, b: outer.b * self.a + outer.a * self.b, tx, ty, inliers: self.inliers }
+    }
+
+    fn inverse(&self) -> Placement {
+        let norm = self.a * self.a + self.b * self.b;
+        let (a, b) = (self.a / norm, -self.b / norm);
+        Placement { a, b, tx: -(a * self.tx - b * self.ty), ty: -(b * self.tx + a * self.ty), inliers: self.inliers }
+    }
+}
+
+/// Stitches `tiles` in the order given, each overlapping the one before it, and returns
+/// the merged image with the placement of every tile on it.
+pub fn stitch_tiles(tiles: &[DynamicImage], options: &StitchOptions) -> Result<(DynamicImage, Vec<Placement>), String> {
+    if tiles.len() < 2 {
+        return Err("Stitching needs at least two tiles".to_string());
+    }
+    let pairs = test_pairs();
+    let features = tiles.par_iter().map(|t| Features::new(t, &pairs)).collect::<Result<Vec<_>, _>>()?;
+
+    // Each tile registered to the previous one, chained into the frame of the first
+    let mut placements = vec![Placement::IDENTITY];
+    for (k, pair) in features.windows(2).enumerate() {
+        let step = register(&pair[0], &pair[1]).ok_or_else(|| format!("Tiles {} and {} share too few features to be stitched", k + 1, k + 2))?;
+        placements.push(Placement { inliers: step.inliers, ..step.then(&placements[k]) });
+    }
+
+    let corners = tiles.iter().zip(&placements).flat_map(|(t, p)| {
+        let (w, h) = (t.width() as f32, t.height() as f32);
+        [p.apply(0.0, 0.0), p.apply(w, 0.0), p.apply(0.0, h), p.apply(w, h)]
+    });
+    let (x0, y0, x1, y1) = corners.fold((f32::MAX, f32::MAX, f32::MIN, f32::MIN), |(x0, y0, x1, y1), (x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)));
+    let (width, height) = ((x1 - x0).ceil() as u32, (y1 - y0).ceil() as u32);
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(format!("Stitched canvas of {}x{} is too large", width, height));
+    }
+    let shift = Placement { tx: -x0.floor(), ty: -y0.floor(), ..Placement::IDENTITY };
+    let placements: Vec<Placement> = placements.iter().map(|p| Placement { inliers: p.inliers, ..p.then(&shift) }).collect();
+
+    let feather = options.feather.max(1) as f32;
+    let [r, g, b] = options.background.0;
+    let merged = if tiles.iter().any(|t| t.color().bytes_per_pixel() / t.color().channel_count() > 1) {
+        let sources: Vec<_> = tiles.iter().map(|t| t.to_rgb16()).collect();
+        let sources: Vec<_> = sources.iter().map(|s| (s.width(), s.height(), s.as_raw().as_slice())).collect();
+        let wide = |c: u8| c as f32 * 257.0;
+        let out = blend(&sources, &placements, (width, height), feather, [wide(r), wide(g), wide(b)], |v| v.round().clamp(0.0, 65535.0) as u16);
+        DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, out).expect("canvas buffer matches its dimensions"))
+    } else {
+        let sources: Vec<_> = tiles.iter().map(|t| t.to_rgb8()).collect();
+        let sources: Vec<_> = sources.iter().map(|s| (s.width(), s.height(), s.as_raw().as_slice())).collect();
+        let out = blend(&sources, &placements, (width, height), feather, [r as f32, g as f32, b as f32], |v| v.round().clamp(0.0, 255.0) as u8);
+        DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, out).expect("canvas buffer matches its dimensions"))
+    };
+    Ok((merged, placements))
+}
+
+/// Resamples every RGB tile (width, height, samples) onto an RGB canvas, weighting each
+/// by its distance to its own edge up to `feather` pixels; pixels no tile covers take
+/// `background`.
+fn blend<P: Copy + Default + Into<f32> + Send + Sync>(
+    tiles: &[(u32, u32, &[P])],
+    placements: &[Placement],
+    (width, height): (u32, u32),
+    feather: f32,
+    background: [f32; 3],
+    store: impl Fn(f32) -> P + Sync,
+) -> Vec<P> {
+    let inverse: Vec<Placement> = placements.iter().map(Placement::inverse).collect();
+    let mut out = vec![P::default(); width as usize * height as usize * 3];
+    out.par_chunks_mut(width as usize * 3).enumerate().for_each(|(y, row)| {
+        for x in 0..width as usize {
+            let (mut sum, mut total) = ([0.0f32; 3], 0.0);
+            for (&(tw, th, raw), inv) in tiles.iter().zip(&inverse) {
+                let (sx, sy) = inv.apply(x as f32 + 0.5, y as f32 + 0.5);
+                let edge = sx.min(sy).min(tw as f32 - sx).min(th as f32 - sy);
+                if edge <= 0.0 {
+                    continue;
+                }
+                let weight = (edge / feather).min(1.0);
+                let (tw, th) = (tw as usize, th as usize);
+                for (c, s) in sum.iter_mut().enumerate() {
+                    *s += weight * super::align::bilinear(|xx, yy| raw[(yy * tw + xx) * 3 + c].into(), tw, th, sx - 0.5, sy - 0.5);
+                }
+                total += weight;
+            }
+            let px = if total > 0.0 { sum.map(|s| s / total) } else { background };
+            for c in 0..3 {
+                row[x * 3 + c] = store(px[c]);
+            }
+        }
+    });
+    out
+}
+
+/// Corners of one tile at working size with their descriptors.
+struct Features {
+    /// Working size over full size.
+    scale: f32,
+    descriptors: Vec<BriefDescriptor>,
+}
+
+impl Features {
+    fn new(tile: &DynamicImage, pairs: &[TestPair]) -> Result<Self, String> {
+        let long = tile.width().max(tile.height());
+        let gray = if long > WORK_SIZE { tile.thumbnail(WORK_SIZE, WORK_SIZE).to_luma8() } else { tile.to_luma8() };
+        if gray.width() < 4 * EDGE || gray.height() < 4 * EDGE {
+            return Err(format!("Tile of {}x{} is too small to stitch", tile.width(), tile.height()));
+        }
+        let gray: GrayImage = imageproc::filter::gaussian_blur_f32(&gray, 1.0);
+        let corners = imageproc::corners::oriented_fast(&gray, None, CORNERS, EDGE, Some(0));
+        let keypoints: Vec<Point<u32>> = corners.iter().map(|c| Point::new(c.corner.x, c.corner.y)).collect();
+        let (descriptors, _) = brief(&gray, &keypoints, DESCRIPTOR_BITS, Some(&pairs.to_vec()))?;
+        Ok(Self { scale: gray.width() as f32 / tile.width() as f32, descriptors })
+    }
+}
+
+/// BRIEF test pairs from a fixed seed, so a set of tiles always stitches the same way.
+fn test_pairs() -> Vec<TestPair> {
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut uniform = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 11) as f32 / (1u64 << 53) as f32 * 2.0 - 1.0
+    };
+    // Coordinates near-normal around the patch center, sigma 6.6 as in the BRIEF paper
+    let mut coord = move || loop {
+        let v = 15.0 + (0..4).map(|_| uniform()).sum::<f32>() * 5.7;
+        if (0.0..31.0).contains(&v) {
+            return v as u32;
+        }
+    };
+    (0..DESCRIPTOR_BITS).map(|_| TestPair { p0: Point::new(coord(), coord()), p1: Point::new(coord(), coord()) }).collect()
+}
+
+/// The placement of `moving` in the full-size frame of `fixed`.
+fn register(fixed: &Features, moving: &Features) -> Option<Placement> {
+    // Brute-force matching with the ratio test; a few thousand descriptors per side
+    let matches: Vec<Match> = moving
+        .descriptors
+        .par_iter()
+        .filter_map(|d| {
+            let (mut best, mut second, mut index) = (u32::MAX, u32::MAX, 0);
+            for (i, f) in fixed.descriptors.iter().enumerate() {
+                let dist = d.hamming_distance(f);
+                if dist < best {
+                    (second, best, index) = (best, dist, i);
+                } else if dist < second {
+                    second = dist;
+                }
+            }
+            (second != u32::MAX && (best as f32) < RATIO * second as f32).then(|| {
+                let (p, q) = (d.position(), fixed.descriptors[index].position());
+                ((p.x as f32, p.y as f32), (q.x as f32, q.y as f32))
+            })
+        })
+        .collect();
+    if matches.len() < MIN_INLIERS {
+        return None;
+    }
+
+    let inliers_of = |p: &Placement| -> Vec<usize> {
+        (0..matches.len())
+            .filter(|&i| {
+                let ((px, py), (qx, qy)) = matches[i];
+                let (x, y) = p.apply(px, py);
+                (x - qx).hypot(y - qy) <= INLIER_DISTANCE
+            })
+            .collect()
+    };
+    let (mut state, count) = (0x2545_F491_4F6C_DD1Du64, matches.len() as u64);
+    let mut pick = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % count) as usize
+    };
+    let mut best: Vec<usize> = Vec::new();
+    for _ in 0..RANSAC_ROUNDS {
+        let (i, j) = (pick(), pick());
+        let Some(candidate) = fit(&[matches[i], matches[j]]) else { continue };
+        // Tiles of one object are shot from about the same height
+        if !(0.5..2.0).contains(&candidate.scale()) {
+            continue;
+        }
+        let inliers = inliers_of(&candidate);
+        if inliers.len() > best.len() {
+            best = inliers;
+        }
+    }
+    if best.len() < MIN_INLIERS {
+        return None;
+    }
+    let refined = fit(&best.iter().map(|&i| matches[i]).collect::<Vec<_>>())?;
+    let inliers = inliers_of(&refined).len();
+    // Back to full-size pixels on both sides
+    let to_full = Placement { a: refined.a * moving.scale / fixed.scale, b: refined.b * moving.scale / fixed.scale, tx: refined.tx / fixed.scale, ty: refined.ty / fixed.scale, inliers };
+    Some(to_full)
+}
+
+/// Least-squares similarity mapping the first point of each pair onto the second.
+fn fit(pairs: &[Match]) -> Option<Placement> {
+    let n = pairs.len() as f32;
+    let (pcx, pcy, qcx, qcy) = pairs.iter().fold((0.0, 0.0, 0.0, 0.0), |(a, b, c, d), ((px, py), (qx, qy))| (a + px / n, b + py / n, c + qx / n, d + qy / n));
+    let (mut dot, mut cross, mut norm) = (0.0, 0.0, 0.0);
+    for ((px, py), (qx, qy)) in pairs {
+        let (px, py, qx, qy) = (px - pcx, py - pcy, qx - qcx, qy - qcy);
+        dot += px * qx + py * qy;
+        cross += px * qy - py * qx;
+        norm += px * px + py * py;
+    }
+    if norm < 1e-3 {
+        return None;
+    }
+    let (a, b) = (dot / norm, cross / norm);
+    Some(Placement { a, b, tx: qcx - (a * pcx - b * pcy), ty: qcy - (b * pcx + a * pcy), inliers: pairs.len() })
+}
@@ -0,0 +1,83 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Shared File Intake
+ *
+ * Collects files the OS hands to the app: launch arguments when a RAW is
+ * double-clicked on Windows/Linux, "open with" events on macOS/iOS, and
+ * share-sheet URIs forwarded by the mobile shell. Files that arrive before
+ * the webview is listening wait in the inbox until the UI takes them.
+ */
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use crate::image_ops;
+use crate::storage;
+
+/// Extensions the app registers file associations for and accepts when shared.
+pub const SUPPORTED_EXTENSIONS: [&str; 9] = ["arw", "cr2", "nef", "dng", "jpg", "jpeg", "png", "tif", "tiff"];
+
+/// Files delivered by the OS, registered as Tauri managed state.
+#[derive(Default)]
+pub struct SharedInbox {
+    pending: Mutex<Vec<String>>,
+    /// Everything ever delivered; only these may be added to the fs scope.
+    received: Mutex<HashSet<String>>,
+}
+
+impl SharedInbox {
+    pub fn push(&self, locations: &[String]) {
+        self.received.lock().unwrap().extend(locations.iter().cloned());
+        self.pending.lock().unwrap().extend(locations.iter().cloned());
+    }
+
+    /// Drains the files not yet taken by the UI.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    pub fn was_received(&self, location: &str) -> bool {
+        self.received.lock().unwrap().contains(location)
+    }
+}
+
+/// Turns `file://` URLs into plain paths; other URIs and paths are returned unchanged.
+pub fn normalize(location: &str) -> String {
+    if location.starts_with("file://") {
+        if let Ok(path) = tauri::Url::parse(location).map_err(|_| ()).and_then(|u| u.to_file_path()) {
+            return path.to_string_lossy().to_string();
+        }
+    }
+    location.to_string()
+}
+
+/// Whether the app can open `location`. URIs often lack an extension, so they are
+/// accepted and left to the decoder.
+pub fn is_supported(location: &str) -> bool {
+    if storage::is_uri(location) {
+        return true;
+    }
+    let ext = std::path::Path::new(location).extension().and_then(|e| e.to_str()).unwrap_or_default();
+    SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()) || image_ops::is_raw_path(location)
+}
+
+/// Supported files passed on the command line, as file associations do on Windows and Linux.
+pub fn launch_files() -> Vec<String> {
+    std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| normalize(&arg))
+        .filter(|location| is_supported(location))
+        .collect()
+}
+
+/// Queues files delivered while the app runs and tells the UI about them.
+pub fn receive<R: Runtime>(app: &AppHandle<R>, locations: Vec<String>) {
+    let locations: Vec<String> = locations.iter().map(|l| normalize(l)).filter(|l| is_supported(l)).collect();
+    if locations.is_empty() {
+        return;
+    }
+    log::info!("Received {} shared file(s)", locations.len());
+    app.state::<SharedInbox>().push(&locations);
+    let _ = app.emit("shared-files", locations);
+}
@@ -0,0 +1,180 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Job Checkpoints
+ *
+ * Every bulk job gets a checkpoint file in the app data directory with its
+ * file list and a hash of its options, and a journal next to it that each
+ * finished file is appended to as soon as it is written. If the app crashes
+ * or the machine loses power mid-job, relaunching lists the job and it can be
+ * resumed with only the files that had not finished; files that failed are
+ * tried again. The UI sends the options again on resume and they must match;
+ * the white balance, trim and calibration the batch locked are kept with the
+ * checkpoint and reused.
+ */
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::commands::BatchLocks;
+
+/// Files processed between two checkpoint writes; the journal covers the ones between.
+pub const CHUNK_SIZE: usize = 500;
+/// Directory in app data holding one checkpoint file per interrupted job.
+pub const DIR_NAME: &str = "checkpoints";
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobCheckpoint {
+    pub id: String,
+    /// `(batch index, (input, output))`, in processing order.
+    pub files: Vec<(usize, (String, String))>,
+    /// SHA-256 of the options the job runs with, as for stats sidecars.
+    pub options_hash: String,
+    /// What the batch locked at its start, reapplied on resume.
+    #[serde(default)]
+    pub locks: Option<BatchLocks>,
+    /// Leading entries of `files` that finished.
+    pub completed: usize,
+    /// Unix time the job started.
+    pub created: u64,
+    /// Batch indices of files past `completed` that finished, from the journal.
+    #[serde(skip)]
+    pub done: HashSet<usize>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// The append-only list of finished files of a job, one batch index per line. Shared by
+/// the files of a batch as they finish.
+pub struct Journal {
+    file: Mutex<std::fs::File>,
+}
+
+impl Journal {
+    /// Synced before returning, so the file counts as finished even after a power cut.
+    pub fn record(&self, index: usize) -> Result<(), String> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", index).and_then(|_| file.sync_data()).map_err(|e| format!("Failed to journal file {}: {}", index, e))
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct JobSummary {
+    pub id: String,
+    pub total: usize,
+    pub completed: usize,
+    pub created: u64,
+}
+
+impl JobCheckpoint {
+    /// Starts a checkpoint for a job kept in `dir`; nothing is written before `save`.
+    pub fn new(dir: &Path, files: Vec<(usize, (String, String))>, options_hash: String) -> Self {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Self {
+            id: format!("{:x}", now.as_nanos()),
+            files,
+            options_hash,
+            locks: None,
+            completed: 0,
+            created: now.as_secs(),
+            done: HashSet::new(),
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn load(dir: &Path, id: &str) -> Result<Self, String> {
+        // Ids are generated hex; anything else would reach outside the directory
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Unknown job: {}", id));
+        }
+        let bytes = std::fs::read(dir.join(format!("{}.json", id))).map_err(|_| format!("Unknown job: {}", id))?;
+        let mut job: Self = serde_json::from_slice(&bytes).map_err(|e| format!("Unreadable checkpoint {}: {}", id, e))?;
+        job.dir = dir.to_path_buf();
+        // A line a crash cut short has no newline yet, and its file is simply redone
+        let journal = std::fs::read_to_string(job.journal_path()).unwrap_or_default();
+        job.done = journal.split_inclusive('\n').filter_map(|line| line.strip_suffix('\n')?.trim().parse().ok()).collect();
+        job.advance(&[]);
+        Ok(job)
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.done", self.id))
+    }
+
+    /// Opens the journal for appending; the checkpoint should be saved first. A line a
+    /// crash cut short is dropped, so the next index is not appended to it.
+    pub fn journal(&self) -> Result<Journal, String> {
+        let path = self.journal_path();
+        if let Ok(journal) = std::fs::read(&path) {
+            let end = journal.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+            if end < journal.len() {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_len(end as u64))
+                    .map_err(|e| format!("Failed to repair journal {}: {}", path.display(), e))?;
+            }
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+        Ok(Journal { file: Mutex::new(file) })
+    }
+
+    /// Marks the files with batch indices `finished` done, and moves `completed` past
+    /// the leading ones.
+    pub fn advance(&mut self, finished: &[usize]) {
+        self.done.extend(finished);
+        while self.files.get(self.completed).is_some_and(|(index, _)| self.done.contains(index)) {
+            self.completed += 1;
+        }
+        let files = &self.files[self.completed.min(self.files.len())..];
+        let pending: HashSet<usize> = files.iter().map(|(index, _)| *index).collect();
+        self.done.retain(|index| pending.contains(index));
+    }
+
+    /// Files that have not finished, in processing order.
+    pub fn remaining(&self) -> Vec<(usize, (String, String))> {
+        self.files[self.completed.min(self.files.len())..].iter().filter(|(index, _)| !self.done.contains(index)).cloned().collect()
+    }
+
+    pub fn summary(&self) -> JobSummary {
+        JobSummary { id: self.id.clone(), total: self.files.len(), completed: self.completed + self.done.len(), created: self.created }
+    }
+
+    /// Written next to the checkpoint and renamed, so a power cut never leaves it truncated.
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let file = self.dir.join(format!("{}.json", self.id));
+        let tmp = file.with_extension("json.tmp");
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &file))
+            .map_err(|e| format!("Failed to save checkpoint {}: {}", file.display(), e))
+    }
+
+    pub fn remove(&self) {
+        let _ = std::fs::remove_file(self.dir.join(format!("{}.json", self.id)));
+        let _ = std::fs::remove_file(self.journal_path());
+    }
+}
+
+/// Interrupted jobs with a checkpoint in `dir`, oldest first.
+pub fn list(dir: &Path) -> Vec<JobSummary> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut jobs: Vec<JobSummary> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = name.strip_suffix(".json")?;
+            JobCheckpoint::load(dir, id).ok().map(|job| job.summary())
+        })
+        .collect();
+    jobs.sort_by_key(|job| job.created);
+    jobs
+}
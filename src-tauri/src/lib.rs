@@ -1,6 +1,38 @@
+pub mod bagit;
+pub mod barcode;
+pub mod catalog;
 pub mod commands;
+pub mod conformance;
+pub mod debug;
+pub mod device;
+pub mod execution;
+pub mod fixity;
+pub mod iiif;
 pub mod image_ops;
+pub mod intake;
+pub mod jobs;
+pub mod looks;
+pub mod memory;
+pub mod metadata;
+pub mod mets;
+pub mod mix;
+pub mod naming;
+pub mod preview;
+pub mod scan;
+pub mod scheduler;
+pub mod settings;
+pub mod storage;
+pub mod submission;
+pub mod verification;
+#[cfg(feature = "timelapse")]
+pub mod timelapse;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+#[cfg(feature = "keywords")]
+pub mod tagger;
 
+use tauri::Manager;
+use tauri_plugin_fs::FsExt;
 use tauri_plugin_log::Builder as LogBuilder;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -9,11 +41,109 @@ pub fn run() {
     .plugin(tauri_plugin_fs::init())
     .plugin(tauri_plugin_dialog::init())
     .plugin(LogBuilder::default().build())
+    .manage(preview::PreviewSessions::default())
+    .manage(preview::PreviewSettings::default())
+    .manage(memory::MemorySettings::default())
+    .manage(debug::StageDumps::default())
+    .manage(device::DeviceState::default())
+    .manage(intake::SharedInbox::default())
+    .manage(verification::PendingBatches::default())
+    .manage(catalog::Catalog::default())
+    .manage(scheduler::Scheduler::default())
+    .manage(scheduler::RunningJobs::default())
+    .manage(naming::TokenRegistry::default())
+    .manage(settings::SettingsStore::default())
+    .on_window_event(|window, event| {
+        // Preview sessions, held and scheduled batches are per window; free them with the window
+        if let tauri::WindowEvent::Destroyed = event {
+            window.state::<preview::PreviewSessions>().close_window(window.label());
+            window.state::<verification::PendingBatches>().close_window(window.label());
+            window.state::<scheduler::Scheduler>().close_window(window.label());
+            window.state::<scheduler::RunningJobs>().close_window(window.label());
+        }
+    })
+    .setup(|app| {
+        // Probed before the first rayon call, which would panic where threads cannot start
+        execution::threads_available();
+        // Files double-clicked or "opened with" on Windows and Linux arrive as arguments
+        let files = intake::launch_files();
+        if !files.is_empty() {
+            app.state::<intake::SharedInbox>().push(&files);
+        }
+        // Installed looks reference their LUTs by absolute path inside app data
+        if let Ok(dir) = app.path().app_data_dir() {
+            let _ = app.fs_scope().allow_directory(looks::looks_dir(&dir), true);
+            app.state::<catalog::Catalog>().open(&dir.join(catalog::FILE_NAME));
+            let stored = app.state::<settings::SettingsStore>().open(&dir.join(settings::FILE_NAME));
+            app.state::<preview::PreviewSettings>().set_encoding(stored.preview_encoding);
+            execution::set_mode(stored.execution_mode);
+            app.state::<memory::MemorySettings>().set_mode(stored.low_memory_mode);
+        }
+        Ok(())
+    })
     .invoke_handler(tauri::generate_handler![
         commands::process_image,
         commands::process_bulk,
-        commands::decode_raw
+        commands::continue_batch,
+        commands::cancel_batch,
+        commands::interrupted_jobs,
+        commands::resume_job,
+        commands::discard_job,
+        commands::schedule_batch,
+        commands::list_scheduled_batches,
+        commands::cancel_scheduled_batch,
+        commands::cancel_bulk,
+        commands::pause_bulk,
+        commands::resume_bulk,
+        commands::decode_raw,
+        commands::raw_histogram,
+        commands::align_images,
+        commands::score_composition,
+        commands::merge_exposures,
+        commands::stack_images,
+        commands::stitch_images,
+        commands::export_timelapse,
+        commands::export_pdf,
+        commands::create_bag,
+        commands::sync_metadata,
+        commands::suggest_keywords,
+        commands::write_keywords,
+        commands::set_note,
+        commands::get_notes,
+        commands::get_file_states,
+        commands::set_edited,
+        commands::validate_for_target,
+        commands::validate_outputs,
+        commands::detect_color_target,
+        commands::export_mets,
+        commands::submission_profiles,
+        commands::memory_status,
+        commands::set_low_memory_mode,
+        commands::execution_status,
+        commands::set_execution_mode,
+        commands::set_stage_dump,
+        commands::set_custom_tokens,
+        commands::report_device_conditions,
+        commands::take_shared_files,
+        commands::import_shared,
+        commands::scan_folder,
+        commands::install_look,
+        commands::list_looks,
+        commands::open_preview_session,
+        commands::close_preview_session,
+        commands::default_pipeline,
+        commands::render_preview,
+        commands::render_pipeline_stages,
+        commands::render_diff,
+        commands::set_preview_encoding
     ])
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|_app, _event| {
+        // macOS and iOS deliver "open with" and share targets as events instead
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        if let tauri::RunEvent::Opened { urls } = _event {
+            intake::receive(_app, urls.iter().map(|u| u.to_string()).collect());
+        }
+    });
 }
@@ -0,0 +1,219 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Looks
+ *
+ * Installs and lists `.cliolook` bundles: a zip holding a pipeline, the
+ * assets it references (LUTs, watermark images) and a manifest with the
+ * SHA-256 of every file. The `signature` entry is the SHA-256 of the
+ * manifest itself, so a bundle that was truncated or edited after packing
+ * is rejected as a whole. It proves integrity, not who published the look.
+ *
+ * Bundle layout:
+ *   manifest.json   name, author, description, files { name: sha256 }
+ *   signature       hex SHA-256 of manifest.json
+ *   pipeline.json   ProcessOptions; asset paths are relative to the bundle
+ *   assets/...      LUTs and watermark images
+ */
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use crate::commands::ProcessOptions;
+
+pub const EXTENSION: &str = "cliolook";
+const MANIFEST: &str = "manifest.json";
+const SIGNATURE: &str = "signature";
+const PIPELINE: &str = "pipeline.json";
+/// Bundles are pipelines plus a few assets; anything larger is not a look.
+const MAX_BUNDLE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LookManifest {
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    /// SHA-256 (hex) of every bundled file other than the manifest and signature.
+    pub files: BTreeMap<String, String>,
+}
+
+/// An installed look as shown in the UI.
+#[derive(Serialize, Clone, Debug)]
+pub struct LookInfo {
+    pub id: String,
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    /// Pipeline with asset paths pointing at the installed copies, ready to pass as `options`.
+    pub options: serde_json::Value,
+    /// Installed image assets (e.g. watermarks) for the UI to offer.
+    pub assets: Vec<String>,
+}
+
+pub fn digest(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Directory names are derived from the look name so reinstalling replaces it.
+pub fn look_id(name: &str) -> String {
+    let id: String = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    id.split('-').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("-")
+}
+
+/// Reads and verifies a bundle, returning its manifest and files.
+pub fn read_bundle(bytes: &[u8]) -> Result<(LookManifest, BTreeMap<String, Vec<u8>>), String> {
+    let mut entries = read_zip(bytes)?;
+    let manifest_bytes = entries.remove(MANIFEST).ok_or("Look bundle has no manifest.json")?;
+    let signature = entries.remove(SIGNATURE).ok_or("Look bundle is not signed")?;
+    if String::from_utf8_lossy(&signature).trim() != digest(&manifest_bytes) {
+        return Err("Look bundle signature does not match its manifest".to_string());
+    }
+    let manifest: LookManifest =
+        serde_json::from_slice(&manifest_bytes).map_err(|e| format!("Invalid look manifest: {}", e))?;
+    if look_id(&manifest.name).is_empty() {
+        return Err("Look manifest needs a name".to_string());
+    }
+
+    for (name, data) in &entries {
+        match manifest.files.get(name) {
+            Some(hash) if hash.eq_ignore_ascii_case(&digest(data)) => {}
+            Some(_) => return Err(format!("Look file {} is corrupted", name)),
+            None => return Err(format!("Look file {} is not listed in the manifest", name)),
+        }
+    }
+    if let Some(missing) = manifest.files.keys().find(|name| !entries.contains_key(*name)) {
+        return Err(format!("Look file {} is missing", missing));
+    }
+    if !entries.contains_key(PIPELINE) {
+        return Err("Look bundle has no pipeline.json".to_string());
+    }
+    Ok((manifest, entries))
+}
+
+/// Verifies `bytes` and installs the look under `root`, replacing any look of the same name.
+pub fn install(bytes: &[u8], root: &Path) -> Result<LookInfo, String> {
+    let (manifest, entries) = read_bundle(bytes)?;
+    let mut pipeline: serde_json::Value =
+        serde_json::from_slice(&entries[PIPELINE]).map_err(|e| format!("Invalid look pipeline: {}", e))?;
+
+    let id = look_id(&manifest.name);
+    let dir = root.join(&id);
+    let staging = root.join(format!(".{}-installing", id));
+    let _ = std::fs::remove_dir_all(&staging);
+    let result = (|| {
+        for (name, data) in &entries {
+            let target = staging.join(name);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&target, data).map_err(|e| e.to_string())?;
+        }
+        // Point asset references at their installed location and check the pipeline
+        // deserializes before replacing a working install
+        for pointer in ["/lut/path", "/watermark/path", "/text_stamp/font"] {
+            if let Some(path) = pipeline.pointer_mut(pointer) {
+                let relative = path.as_str().unwrap_or_default().to_string();
+                if !entries.contains_key(&relative) {
+                    return Err(format!("Look asset {} is not bundled", relative));
+                }
+                *path = dir.join(&relative).to_string_lossy().to_string().into();
+            }
+        }
+        serde_json::from_value::<ProcessOptions>(pipeline.clone()).map_err(|e| format!("Invalid look pipeline: {}", e))?;
+        std::fs::write(staging.join(PIPELINE), pipeline.to_string()).map_err(|e| e.to_string())?;
+        std::fs::write(staging.join(MANIFEST), serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::rename(&staging, &dir).map_err(|e| e.to_string())?;
+    load(&dir)
+}
+
+/// Every look installed under `root`, sorted by name. Unreadable installs are skipped.
+pub fn list(root: &Path) -> Vec<LookInfo> {
+    let Ok(dirs) = std::fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut looks: Vec<LookInfo> = dirs
+        .flatten()
+        .filter(|d| !d.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|d| load(&d.path()).ok())
+        .collect();
+    looks.sort_by_key(|look| look.name.to_lowercase());
+    looks
+}
+
+fn load(dir: &Path) -> Result<LookInfo, String> {
+    let manifest: LookManifest =
+        serde_json::from_slice(&std::fs::read(dir.join(MANIFEST)).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    let options: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.join(PIPELINE)).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    let assets = manifest
+        .files
+        .keys()
+        .filter(|name| {
+            let ext = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+            matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "svg" | "webp")
+        })
+        .map(|name| dir.join(name).to_string_lossy().to_string())
+        .collect();
+    Ok(LookInfo {
+        id: dir.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        name: manifest.name,
+        author: manifest.author,
+        description: manifest.description,
+        options,
+        assets,
+    })
+}
+
+/// Where looks are installed inside the app data directory.
+pub fn looks_dir(app_data: &Path) -> PathBuf {
+    app_data.join("looks")
+}
+
+fn read_zip(bytes: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    if bytes.len() as u64 > MAX_BUNDLE_BYTES {
+        return Err("Look bundle is too large".to_string());
+    }
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(|e| format!("Look bundle is not a zip archive: {}", e))?;
+
+    let mut entries = BTreeMap::new();
+    let mut total = 0u64;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| format!("Corrupted look bundle: {}", e))?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        if name.starts_with('/') || name.contains('\\') || name.split('/').any(|part| part == ".." || part.is_empty()) {
+            return Err(format!("Look bundle contains an unsafe path: {}", name));
+        }
+        // The declared size can lie, so the read is capped by what is left of the budget too
+        let budget = (MAX_BUNDLE_BYTES * 4).saturating_sub(total);
+        if file.size() > budget {
+            return Err("Look bundle is too large".to_string());
+        }
+        let mut data = Vec::with_capacity(file.size() as usize);
+        (&mut file).take(budget + 1).read_to_end(&mut data).map_err(|e| format!("Failed to read look file {}: {}", name, e))?;
+        total += data.len() as u64;
+        if total > MAX_BUNDLE_BYTES * 4 {
+            return Err("Look bundle is too large".to_string());
+        }
+        entries.insert(name, data);
+    }
+    Ok(entries)
+}
@@ -1,4 +1,4 @@
-/**
+/*
  * Author: Alejandro Ramírez
  * Project: ClioBulk
  * Logic: High-performance image processing application entry point (Tauri).
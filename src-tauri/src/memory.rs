@@ -0,0 +1,96 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Low-Memory Mode
+ *
+ * Decides whether the backend should trade speed for a smaller footprint.
+ * In low-memory mode bulk jobs keep at most two images in flight, RAW
+ * previews are decoded at half size without demosaicing, preview sessions
+ * are not kept around once another one is opened, and downscaling resizes
+ * run before the filters instead of after them.
+ */
+use serde::Serialize;
+use std::sync::Mutex;
+use crate::commands::LowMemoryMode;
+
+/// Machines with this much RAM or less switch to low-memory mode in `Auto`.
+pub const LOW_MEMORY_THRESHOLD: u64 = 8 * 1024 * 1024 * 1024;
+
+/// Current mode and what it resolved to, as reported to the UI.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct MemoryStatus {
+    pub mode: LowMemoryMode,
+    /// Total physical memory, if it could be detected on this platform.
+    pub total_bytes: Option<u64>,
+    pub active: bool,
+}
+
+/// Low-memory settings, registered as Tauri managed state.
+pub struct MemorySettings {
+    mode: Mutex<LowMemoryMode>,
+    total_bytes: Option<u64>,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        Self::new(total_memory())
+    }
+}
+
+impl MemorySettings {
+    /// Settings for a machine with `total_bytes` of RAM, starting in `Auto`.
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        Self { mode: Mutex::new(LowMemoryMode::Auto), total_bytes }
+    }
+
+    pub fn set_mode(&self, mode: LowMemoryMode) {
+        *self.mode.lock().unwrap() = mode;
+    }
+
+    pub fn status(&self) -> MemoryStatus {
+        let mode = *self.mode.lock().unwrap();
+        let active = match mode {
+            LowMemoryMode::On => true,
+            LowMemoryMode::Off => false,
+            // Unknown RAM is treated as plenty, matching the behavior before this mode existed
+            LowMemoryMode::Auto => self.total_bytes.is_some_and(|t| t <= LOW_MEMORY_THRESHOLD),
+        };
+        MemoryStatus { mode, total_bytes: self.total_bytes, active }
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.status().active
+    }
+
+    /// Number of images a batch may hold decoded at once, given `cores` logical CPUs.
+    pub fn concurrency(&self, cores: usize) -> usize {
+        if !self.is_low() {
+            // 75% of logical cores for maximum throughput
+            return (cores * 3 / 4).max(1);
+        }
+        match self.total_bytes {
+            Some(total) if total <= LOW_MEMORY_THRESHOLD / 2 => 1,
+            _ => cores.clamp(1, 2),
+        }
+    }
+}
+
+/// Total physical memory in bytes, where the platform exposes it without extra dependencies.
+pub fn total_memory() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kib * 1024)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("sysctl").args(["-n", "hw.memsize"]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        None
+    }
+}
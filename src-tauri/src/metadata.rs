@@ -0,0 +1,545 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Metadata Sync
+ *
+ * Copies EXIF (and IPTC for JPEG) from source RAW files into exports that
+ * were written without metadata. Sources are read with `kamadak-exif`, which
+ * understands the TIFF-based RAW containers (CR2, NEF, ARW, DNG), and the
+ * segments are spliced into JPEG/PNG/WebP exports with `img-parts` so the
+ * image data itself is never re-encoded. Keywords confirmed in the UI are
+ * added to the IPTC record of JPEG exports the same way.
+ */
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag, Value};
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+/// IFD0 tags worth carrying over. Layout tags (dimensions, compression, strips)
+/// describe the RAW data, and Orientation may already be baked into the export.
+const COPIED_TIFF_TAGS: [Tag; 6] = [
+    Tag::Make,
+    Tag::Model,
+    Tag::DateTime,
+    Tag::Artist,
+    Tag::Copyright,
+    Tag::ImageDescription,
+];
+/// IPTC-NAA record stored in IFD0 by some cameras and DAM tools.
+const IPTC_TAG: Tag = Tag(Context::Tiff, 33723);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    /// Missing metadata was written into the export.
+    Updated,
+    /// The export already carried everything the source had.
+    UpToDate,
+    /// No source matched the export by name or capture time.
+    NoSource,
+    /// The export format cannot hold spliced metadata (e.g. TIFF).
+    Unsupported,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MetadataSync {
+    pub export: String,
+    pub source: Option<String>,
+    pub status: SyncStatus,
+    pub exif: bool,
+    pub iptc: bool,
+    pub error: Option<String>,
+}
+
+/// Pairs every export with the index of its source.
+///
+/// Exports match a source with the same file stem, or whose stem extends it with
+/// a separator (`IMG_0001_edit.jpg` -> `IMG_0001.CR2`); the longest stem wins.
+/// When names are ambiguous (same stem in several folders) or absent, the capture
+/// time recorded in the export, if any, picks the source with the same time.
+pub fn match_sources(sources: &[String], exports: &[String]) -> Vec<Option<usize>> {
+    let stem = |p: &str| {
+        Path::new(p).file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default()
+    };
+    let source_stems: Vec<String> = sources.iter().map(|s| stem(s)).collect();
+    let mut capture_times: Vec<Option<Option<String>>> = vec![None; sources.len()];
+    let mut source_time = |i: usize| capture_times[i].get_or_insert_with(|| capture_time(&sources[i])).clone();
+
+    exports
+        .iter()
+        .map(|export| {
+            let name = stem(export);
+            let matches_name = |s: &str| {
+                name == s
+                    || (name.starts_with(s) && name[s.len()..].starts_with(['_', '-', ' ', '.']))
+            };
+            let longest = source_stems.iter().filter(|s| !s.is_empty() && matches_name(s)).map(|s| s.len()).max();
+            let candidates: Vec<usize> = match longest {
+                Some(len) => (0..sources.len()).filter(|&i| source_stems[i].len() == len && matches_name(&source_stems[i])).collect(),
+                None => (0..sources.len()).collect(),
+            };
+            if candidates.len() == 1 && longest.is_some() {
+                return Some(candidates[0]);
+            }
+            let time = capture_time(export)?;
+            candidates.into_iter().find(|&i| source_time(i).as_deref() == Some(time.as_str()))
+        })
+        .collect()
+}
+
+/// Copies whatever metadata `export` is missing from `source`, rewriting the export in place.
+pub fn copy_missing(source: &str, export: &str) -> MetadataSync {
+    let mut result = MetadataSync {
+        export: export.to_string(),
+        source: Some(source.to_string()),
+        status: SyncStatus::UpToDate,
+        exif: false,
+        iptc: false,
+        error: None,
+    };
+    match splice(source, export, &mut result) {
+        Ok(status) => result.status = status,
+        Err(e) => {
+            result.status = SyncStatus::Failed;
+            result.error = Some(e);
+        }
+    }
+    result
+}
+
+fn splice(source: &str, export: &str, result: &mut MetadataSync) -> Result<SyncStatus, String> {
+    let bytes = std::fs::read(export).map_err(|e| format!("Failed to read {}: {}", export, e))?;
+    let Some(mut image) = DynImage::from_bytes(Bytes::from(bytes)).map_err(|e| e.to_string())? else {
+        return Ok(SyncStatus::Unsupported);
+    };
+
+    let file = std::fs::File::open(source).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("No readable metadata in {}: {}", source, e))?;
+
+    if image.exif().is_none() {
+        image.set_exif(Some(Bytes::from(encode_exif(&exif)?)));
+        result.exif = true;
+    }
+    if let DynImage::Jpeg(jpeg) = &mut image {
+        let has_iptc = jpeg.segments_by_marker(img_parts::jpeg::markers::APP13).next().is_some();
+        if let Some(record) = exif.get_field(IPTC_TAG, In::PRIMARY).filter(|_| !has_iptc) {
+            let app13 = photoshop_iptc(&iptc_bytes(&record.value, exif.little_endian()));
+            // After SOI and the APP0/APP1 headers, ahead of the quantization tables
+            insert_app13(jpeg, app13);
+            result.iptc = true;
+        }
+    }
+    if !result.exif && !result.iptc {
+        return Ok(SyncStatus::UpToDate);
+    }
+
+    write_in_place(image, export)?;
+    Ok(SyncStatus::Updated)
+}
+
+/// Writes next to `path` and renames so a failure never leaves a truncated file.
+fn write_in_place(image: DynImage, path: &str) -> Result<(), String> {
+    let tmp = format!("{}.cliobulk-tmp", path);
+    let written = std::fs::File::create(&tmp)
+        .map_err(|e| e.to_string())
+        .and_then(|f| image.encoder().write_to(std::io::BufWriter::new(f)).map_err(|e| e.to_string()));
+    if let Err(e) = written.and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string())) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(format!("Failed to update {}: {}", path, e));
+    }
+    Ok(())
+}
+
+fn insert_app13(jpeg: &mut img_parts::jpeg::Jpeg, app13: Vec<u8>) {
+    // After SOI and the APP0/APP1 headers, ahead of the quantization tables
+    let at = jpeg.segments().iter().position(|s| !(0xE0..=0xE2).contains(&s.marker())).unwrap_or(0);
+    jpeg.segments_mut().insert(at, img_parts::jpeg::JpegSegment::new_with_contents(img_parts::jpeg::markers::APP13, Bytes::from(app13)));
+}
+
+/// Re-serializes the copyable fields of the primary image as a standalone EXIF block.
+fn encode_exif(exif: &exif::Exif) -> Result<Vec<u8>, String> {
+    let fields: Vec<&Field> = exif
+        .fields()
+        .filter(|f| f.ifd_num == In::PRIMARY)
+        .filter(|f| match f.tag.context() {
+            Context::Tiff => COPIED_TIFF_TAGS.contains(&f.tag),
+            // Maker notes hold offsets into the original file and break when moved
+            Context::Exif => f.tag != Tag::MakerNote,
+            Context::Gps => true,
+            _ => false,
+        })
+        .collect();
+    if fields.is_empty() {
+        return Err("Source has no EXIF fields to copy".to_string());
+    }
+    let mut writer = Writer::new();
+    fields.into_iter().for_each(|f| writer.push_field(f));
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    writer.write(&mut buffer, exif.little_endian()).map_err(|e| e.to_string())?;
+    Ok(buffer.into_inner())
+}
+
+fn iptc_bytes(value: &Value, little_endian: bool) -> Vec<u8> {
+    match value {
+        Value::Undefined(bytes, _) => bytes.clone(),
+        Value::Byte(bytes) => bytes.clone(),
+        // Some writers declare the record as LONGs; undo the file's byte order
+        Value::Long(longs) => longs
+            .iter()
+            .flat_map(|l| if little_endian { l.to_le_bytes() } else { l.to_be_bytes() })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Wraps an IPTC record in the Photoshop image resource block JPEG readers expect in APP13.
+fn photoshop_iptc(record: &[u8]) -> Vec<u8> {
+    // Empty, even-padded Pascal name
+    serialize_resources(&[(0x0404, vec![0, 0], record.to_vec())])
+}
+
+/// Keywords confirmed for one file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct KeywordAssignment {
+    pub path: String,
+    pub keywords: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct KeywordUpdate {
+    pub path: String,
+    /// Keywords that were not already present.
+    pub added: Vec<String>,
+    pub status: SyncStatus,
+    pub error: Option<String>,
+}
+
+/// A keyword proposed by the tagging model, with its score after the activation.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SuggestedKeyword {
+    pub keyword: String,
+    pub confidence: f32,
+}
+
+/// Suggestions for one file, best first, or why it could not be tagged.
+#[derive(Serialize, Clone, Debug)]
+pub struct KeywordSuggestions {
+    pub path: String,
+    pub keywords: Vec<SuggestedKeyword>,
+    pub error: Option<String>,
+}
+
+/// IPTC datasets are limited to 64 bytes for keywords (2:25).
+const MAX_KEYWORD_BYTES: usize = 64;
+
+/// Adds keywords to a JPEG's IPTC record (one 2:25 dataset each), in place. Existing
+/// datasets are kept and keywords already present (case-insensitively) are skipped.
+pub fn write_keywords(assignment: &KeywordAssignment) -> KeywordUpdate {
+    let mut result = KeywordUpdate { path: assignment.path.clone(), added: Vec::new(), status: SyncStatus::UpToDate, error: None };
+    match add_keywords(&assignment.path, &assignment.keywords, &mut result.added) {
+        Ok(status) => result.status = status,
+        Err(e) => {
+            result.status = SyncStatus::Failed;
+            result.error = Some(e);
+        }
+    }
+    result
+}
+
+fn add_keywords(path: &str, keywords: &[String], added: &mut Vec<String>) -> Result<SyncStatus, String> {
+    if crate::storage::is_uri(path) {
+        return Err("Keywords are written in place, which needs a file path".to_string());
+    }
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let Some(DynImage::Jpeg(mut jpeg)) = DynImage::from_bytes(Bytes::from(bytes)).map_err(|e| e.to_string())? else {
+        return Ok(SyncStatus::Unsupported);
+    };
+
+    let app13 = jpeg.segments().iter().position(|s| s.marker() == img_parts::jpeg::markers::APP13);
+    let mut resources = app13.map(|i| photoshop_resources(jpeg.segments()[i].contents())).unwrap_or_default();
+    let record_index = resources.iter().position(|r| r.0 == 0x0404);
+    // New records declare UTF-8 (1:90 ESC % G) and the record version (2:00)
+    let mut record = record_index.map(|i| resources[i].2.clone()).unwrap_or_else(|| {
+        [dataset(1, 90, b"\x1b%G"), dataset(2, 0, &4u16.to_be_bytes())].concat()
+    });
+
+    let mut present: Vec<String> = iptc_datasets(&record)
+        .filter(|&(rec, ds, _)| (rec, ds) == (2, 25))
+        .map(|(_, _, data)| String::from_utf8_lossy(data).to_lowercase())
+        .collect();
+    let mut new_datasets = Vec::new();
+    for keyword in keywords {
+        let mut keyword = keyword.trim();
+        while keyword.len() > MAX_KEYWORD_BYTES {
+            let cut = (0..=MAX_KEYWORD_BYTES).rev().find(|&i| keyword.is_char_boundary(i)).unwrap_or(0);
+            keyword = keyword[..cut].trim_end();
+        }
+        if keyword.is_empty() || present.contains(&keyword.to_lowercase()) {
+            continue;
+        }
+        present.push(keyword.to_lowercase());
+        new_datasets.extend(dataset(2, 25, keyword.as_bytes()));
+        added.push(keyword.to_string());
+    }
+    if added.is_empty() {
+        return Ok(SyncStatus::UpToDate);
+    }
+
+    // Keywords belong with the rest of record 2, ahead of any later records
+    let at = iptc_offsets(&record).find(|&(_, rec)| rec > 2).map_or(record.len(), |(offset, _)| offset);
+    record.splice(at..at, new_datasets);
+    match record_index {
+        Some(i) => resources[i].2 = record,
+        None => resources.push((0x0404, vec![0, 0], record)),
+    }
+    let contents = serialize_resources(&resources);
+    match app13 {
+        Some(i) => jpeg.segments_mut()[i] = img_parts::jpeg::JpegSegment::new_with_contents(img_parts::jpeg::markers::APP13, Bytes::from(contents)),
+        None => insert_app13(&mut jpeg, contents),
+    }
+    write_in_place(DynImage::Jpeg(jpeg), path)?;
+    Ok(SyncStatus::Updated)
+}
+
+fn dataset(record: u8, number: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1c, record, number];
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+/// `(offset, record)` of every dataset, stopping at the first malformed one.
+fn iptc_offsets(record: &[u8]) -> impl Iterator<Item = (usize, u8)> + '_ {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        if record.get(at) != Some(&0x1c) || at + 5 > record.len() {
+            return None;
+        }
+        let len = u16::from_be_bytes([record[at + 3], record[at + 4]]) as usize;
+        // Extended (>32 KiB) datasets never hold keywords; stop rather than misparse
+        if len & 0x8000 != 0 {
+            return None;
+        }
+        let item = (at, record[at + 1]);
+        at += 5 + len;
+        Some(item)
+    })
+}
+
+/// `(record, dataset, data)` of every dataset.
+fn iptc_datasets(record: &[u8]) -> impl Iterator<Item = (u8, u8, &[u8])> + '_ {
+    iptc_offsets(record).filter_map(move |(at, rec)| {
+        let len = u16::from_be_bytes([record[at + 3], record[at + 4]]) as usize;
+        Some((rec, record[at + 2], record.get(at + 5..at + 5 + len)?))
+    })
+}
+
+/// Image resources of a Photoshop APP13 segment as `(id, padded Pascal name, data)`.
+fn photoshop_resources(app13: &[u8]) -> Vec<(u16, Vec<u8>, Vec<u8>)> {
+    let mut resources = Vec::new();
+    let Some(mut rest) = app13.strip_prefix(b"Photoshop 3.0\0") else {
+        return resources;
+    };
+    while rest.len() >= 12 && rest.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([rest[4], rest[5]]);
+        let name_len = (1 + rest[6] as usize).next_multiple_of(2);
+        let Some(size) = rest.get(6 + name_len..10 + name_len).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) else {
+            break;
+        };
+        let start = 10 + name_len;
+        let Some(data) = rest.get(start..start + size) else {
+            break;
+        };
+        resources.push((id, rest[6..6 + name_len].to_vec(), data.to_vec()));
+        rest = rest.get(start + size.next_multiple_of(2)..).unwrap_or_default();
+    }
+    resources
+}
+
+fn serialize_resources(resources: &[(u16, Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut out = b"Photoshop 3.0\0".to_vec();
+    for (id, name, data) in resources {
+        out.extend_from_slice(b"8BIM");
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+    out
+}
+
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Sets `text` as the XMP description (dc:description) of a JPEG or PNG export, in place.
+///
+/// An XMP packet already in the file keeps every other property; only its description is
+/// replaced. A packet that cannot be merged into (no `rdf:RDF`) is replaced whole.
+pub fn embed_description(path: &str, text: &str) -> Result<SyncStatus, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let Some(mut image) = DynImage::from_bytes(Bytes::from(bytes)).map_err(|e| e.to_string())? else {
+        return Ok(SyncStatus::Unsupported);
+    };
+    match &mut image {
+        DynImage::Jpeg(jpeg) => {
+            let app1 = img_parts::jpeg::markers::APP1;
+            let is_xmp = |s: &img_parts::jpeg::JpegSegment| s.marker() == app1 && s.contents().starts_with(XMP_NAMESPACE);
+            let existing = jpeg.segments().iter().find(|s| is_xmp(s)).map(|s| String::from_utf8_lossy(&s.contents()[XMP_NAMESPACE.len()..]).into_owned());
+            let packet = merge_description(existing.as_deref(), text);
+            jpeg.segments_mut().retain(|s| !is_xmp(s));
+            // After SOI, APP0 and the EXIF APP1, where readers look for it
+            let at = jpeg.segments().iter().position(|s| !(0xE0..=0xE1).contains(&s.marker())).unwrap_or(0);
+            let contents = [XMP_NAMESPACE, packet.as_bytes()].concat();
+            jpeg.segments_mut().insert(at, img_parts::jpeg::JpegSegment::new_with_contents(app1, Bytes::from(contents)));
+        }
+        DynImage::Png(png) => {
+            let is_xmp = |c: &img_parts::png::PngChunk| c.kind() == *b"iTXt" && c.contents().starts_with(PNG_XMP_KEYWORD);
+            let existing = png.chunks().iter().find(|c| is_xmp(c)).and_then(|c| itxt_text(&c.contents()[PNG_XMP_KEYWORD.len()..]));
+            let packet = merge_description(existing.as_deref(), text);
+            png.chunks_mut().retain(|c| !is_xmp(c));
+            // iTXt: keyword, uncompressed, no language or translated keyword
+            let contents = [PNG_XMP_KEYWORD, b"\0\0\0\0".as_slice(), packet.as_bytes()].concat();
+            let at = png.chunks().iter().position(|c| c.kind() == *b"IDAT").unwrap_or(1);
+            png.chunks_mut().insert(at, img_parts::png::PngChunk::new(*b"iTXt", Bytes::from(contents)));
+        }
+        // WebP needs its VP8X header rewritten for XMP; not supported yet
+        _ => return Ok(SyncStatus::Unsupported),
+    }
+    write_in_place(image, path)?;
+    Ok(SyncStatus::Updated)
+}
+
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Text of an iTXt chunk after its keyword: compression flag and method, language and
+/// translated keyword, then the (possibly zlib-compressed) text.
+fn itxt_text(data: &[u8]) -> Option<String> {
+    let (&compressed, rest) = data.split_first()?;
+    let rest = rest.get(1..)?;
+    let lang = rest.iter().position(|&b| b == 0)?;
+    let rest = &rest[lang + 1..];
+    let translated = rest.iter().position(|&b| b == 0)?;
+    let text = &rest[translated + 1..];
+    if compressed == 0 {
+        return Some(String::from_utf8_lossy(text).into_owned());
+    }
+    let mut out = String::new();
+    flate2::read::ZlibDecoder::new(text).read_to_string(&mut out).ok()?;
+    Some(out)
+}
+
+/// `existing` with its description replaced by `description`, or a new packet holding
+/// only the description.
+fn merge_description(existing: Option<&str>, description: &str) -> String {
+    let Some(packet) = existing.filter(|p| p.contains("</rdf:RDF>")) else {
+        return xmp_packet(description);
+    };
+    // Whatever prefix the packet binds to Dublin Core, "dc" when it binds none
+    let bound = packet.find(&format!("=\"{}\"", DC_NAMESPACE)).and_then(|at| {
+        let start = packet[..at].rfind("xmlns:")? + "xmlns:".len();
+        Some(packet[start..at].to_string())
+    });
+    let prefix = bound.clone().unwrap_or_else(|| "dc".to_string());
+    let mut packet = remove_property(packet, &format!("{}:description", prefix));
+
+    let element = description_element(&prefix, description);
+    match packet.find("<rdf:Description") {
+        Some(start) => {
+            let Some(end) = packet[start..].find('>').map(|e| start + e) else {
+                return xmp_packet(description);
+            };
+            let declare = if bound.is_none() { format!(" xmlns:dc=\"{}\"", DC_NAMESPACE) } else { String::new() };
+            if packet[..end].ends_with('/') {
+                // <rdf:Description .../> becomes an element that can hold the description
+                packet.replace_range(end - 1..end + 1, &format!("{}>{}</rdf:Description>", declare, element));
+            } else {
+                packet.replace_range(end..end + 1, &format!("{}>{}", declare, element));
+            }
+        }
+        None => {
+            let at = packet.find("</rdf:RDF>").unwrap_or(packet.len());
+            let description = format!("<rdf:Description rdf:about=\"\" xmlns:dc=\"{}\">{}</rdf:Description>", DC_NAMESPACE, element);
+            packet.insert_str(at, &description);
+        }
+    }
+    packet
+}
+
+/// `packet` without the `name` element or attribute.
+fn remove_property(packet: &str, name: &str) -> String {
+    let mut packet = packet.to_string();
+    let (open, close) = (format!("<{}", name), format!("</{}>", name));
+    while let Some(start) = packet.find(&open).filter(|&s| packet[s + open.len()..].starts_with(['>', ' ', '/'])) {
+        let self_closing = packet[start..].find('>').filter(|&e| packet[start..start + e].ends_with('/')).map(|e| start + e + 1);
+        let end = self_closing.or_else(|| packet[start..].find(&close).map(|e| start + e + close.len()));
+        match end {
+            Some(end) => packet.replace_range(start..end, ""),
+            None => break,
+        }
+    }
+    let attribute = format!(" {}=\"", name);
+    if let Some(start) = packet.find(&attribute) {
+        if let Some(len) = packet[start + attribute.len()..].find('"') {
+            packet.replace_range(start..start + attribute.len() + len + 1, "");
+        }
+    }
+    packet
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn description_element(prefix: &str, description: &str) -> String {
+    format!(
+        "<{p}:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></{p}:description>",
+        escape_xml(description),
+        p = prefix
+    )
+}
+
+fn xmp_packet(description: &str) -> String {
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"{}\">{}</rdf:Description>",
+            "</rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+        ),
+        DC_NAMESPACE,
+        description_element("dc", description)
+    )
+}
+
+/// DateTimeOriginal of a file, as recorded ("YYYY:MM:DD HH:MM:SS").
+pub fn capture_time(path: &str) -> Option<String> {
+    primary_ascii(&read_exif(path)?, Tag::DateTimeOriginal)
+}
+
+/// Make and Model of the camera or scanner a file was recorded with.
+pub fn capture_device(path: &str) -> (Option<String>, Option<String>) {
+    match read_exif(path) {
+        Some(exif) => (primary_ascii(&exif, Tag::Make), primary_ascii(&exif, Tag::Model)),
+        None => (None, None),
+    }
+}
+
+fn read_exif(path: &str) -> Option<exif::Exif> {
+    let file = std::fs::File::open(path).ok()?;
+    exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)).ok()
+}
+
+fn primary_ascii(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(parts) => parts.first().map(|p| String::from_utf8_lossy(p).trim().to_string()).filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
@@ -0,0 +1,249 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk METS/ALTO
+ *
+ * Packages digitized text batches the way library repositories ingest them:
+ * an ALTO 4 file per page, with the blocks, lines and words the OCR stage
+ * read and their coordinates on the written page, and a METS document over
+ * the batch tying the page images, their full text and their MIX technical
+ * records into one object with a physical page sequence. The document
+ * refers to its files by paths relative to its own folder and records a
+ * checksum for each, so the folder can be bagged or moved as it is.
+ */
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use crate::commands::{ChecksumAlgorithm, MetsOptions};
+use crate::fixity;
+use crate::image_ops::{OcrPage, OcrWord};
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MetsSummary {
+    pub path: String,
+    pub pages: usize,
+    /// Pages with an ALTO file.
+    pub alto: usize,
+    /// Pages with a MIX record.
+    pub mix: usize,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A word box scaled onto the output: left, top, right and bottom.
+type Area = (u32, u32, u32, u32);
+
+fn union(a: Area, b: Area) -> Area {
+    (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))
+}
+
+fn position(area: Area) -> String {
+    format!("HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"", area.0, area.1, area.2 - area.0, area.3 - area.1)
+}
+
+/// The ALTO file of `page` as written at `size` to `image_path`.
+///
+/// Tesseract lists words in reading order, so a word starts a new line unless it
+/// continues the previous one to the right at about the same height, and a line
+/// starts a new block when it is more than a line's height below the one before.
+pub fn alto_page(page: &OcrPage, size: (u32, u32), image_path: &str) -> String {
+    let (sx, sy) = (size.0 as f32 / page.width.max(1) as f32, size.1 as f32 / page.height.max(1) as f32);
+    let area = |w: &OcrWord| -> Area {
+        let scale = |v: u32, s: f32| (v as f32 * s).round() as u32;
+        (scale(w.left, sx), scale(w.top, sy), scale(w.left + w.width, sx), scale(w.top + w.height, sy))
+    };
+
+    let mut lines: Vec<(Area, Vec<(&OcrWord, Area)>)> = Vec::new();
+    for word in page.words.iter().filter(|w| w.width > 0 && w.height > 0 && !w.text.trim().is_empty()) {
+        let box_ = area(word);
+        let continues = lines.last().and_then(|(_, words)| words.last()).is_some_and(|(_, prev)| {
+            let (mid, prev_mid) = (box_.1 + box_.3, prev.1 + prev.3);
+            box_.0 > prev.0 && mid.abs_diff(prev_mid) < (box_.3 - box_.1).max(prev.3 - prev.1)
+        });
+        match lines.last_mut() {
+            Some((line, words)) if continues => {
+                *line = union(*line, box_);
+                words.push((word, box_));
+            }
+            _ => lines.push((box_, vec![(word, box_)])),
+        }
+    }
+    let mut blocks: Vec<(Area, Vec<usize>)> = Vec::new();
+    for (i, (line, _)) in lines.iter().enumerate() {
+        match blocks.last_mut() {
+            Some((block, members)) if line.1 <= block.3 + (block.3 - block.1) / members.len() as u32 => {
+                *block = union(*block, *line);
+                members.push(i);
+            }
+            _ => blocks.push((*line, vec![i])),
+        }
+    }
+
+    let file_name = Path::new(image_path).file_name().map_or_else(|| image_path.to_string(), |n| n.to_string_lossy().to_string());
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<alto xmlns=\"http://www.loc.gov/standards/alto/ns-v4#\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ",
+        "xsi:schemaLocation=\"http://www.loc.gov/standards/alto/ns-v4# http://www.loc.gov/standards/alto/v4/alto-4-2.xsd\">\n",
+        "  <Description>\n    <MeasurementUnit>pixel</MeasurementUnit>\n",
+    ));
+    xml.push_str(&format!("    <sourceImageInformation>\n      <fileName>{}</fileName>\n    </sourceImageInformation>\n", escape(&file_name)));
+    xml.push_str("  </Description>\n  <Layout>\n");
+    let full = (0, 0, size.0, size.1);
+    xml.push_str(&format!("    <Page ID=\"P1\" PHYSICAL_IMG_NR=\"1\" WIDTH=\"{}\" HEIGHT=\"{}\">\n", size.0, size.1));
+    xml.push_str(&format!("      <PrintSpace {}>\n", position(full)));
+    let mut string_id = 0;
+    for (b, (block, members)) in blocks.iter().enumerate() {
+        xml.push_str(&format!("        <TextBlock ID=\"B{}\" {}>\n", b + 1, position(*block)));
+        for &l in members {
+            let (line, words) = &lines[l];
+            xml.push_str(&format!("          <TextLine ID=\"L{}\" {}>\n", l + 1, position(*line)));
+            for (w, (word, box_)) in words.iter().enumerate() {
+                if w > 0 {
+                    xml.push_str("            <SP/>\n");
+                }
+                string_id += 1;
+                xml.push_str(&format!(
+                    "            <String ID=\"S{}\" CONTENT=\"{}\" {}/>\n",
+                    string_id,
+                    escape(word.text.trim()),
+                    position(*box_)
+                ));
+            }
+            xml.push_str("          </TextLine>\n");
+        }
+        xml.push_str("        </TextBlock>\n");
+    }
+    xml.push_str("      </PrintSpace>\n    </Page>\n  </Layout>\n</alto>\n");
+    xml
+}
+
+/// A relative path as a URI reference, percent-encoding what is not allowed unescaped.
+fn href(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf")) {
+        return "application/pdf";
+    }
+    image::ImageFormat::from_path(path).map_or("application/octet-stream", |f| f.to_mime_type())
+}
+
+fn checksum_type(algorithm: ChecksumAlgorithm) -> &'static str {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => "MD5",
+        ChecksumAlgorithm::Sha256 => "SHA-256",
+    }
+}
+
+/// One `mets:file` entry; `admid` links it to a technical record.
+fn file_entry(id: &str, path: &Path, name: &str, algorithm: ChecksumAlgorithm, admid: Option<&str>) -> Result<String, String> {
+    let size = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?.len();
+    let digest = fixity::digest_file(path, &[algorithm])?.remove(0);
+    let admid = admid.map_or(String::new(), |a| format!(" ADMID=\"{}\"", a));
+    Ok(format!(
+        "      <mets:file ID=\"{}\" MIMETYPE=\"{}\" SIZE=\"{}\" CHECKSUM=\"{}\" CHECKSUMTYPE=\"{}\"{}>\n        <mets:FLocat LOCTYPE=\"URL\" xlink:href=\"{}\"/>\n      </mets:file>\n",
+        id,
+        mime_type(path),
+        size,
+        digest,
+        checksum_type(algorithm),
+        admid,
+        escape(&href(name))
+    ))
+}
+
+/// Writes the METS document at `mets_path` over `pages`, in page order, picking up the
+/// `<name>.alto.xml` and `<name>.mix.xml` files next to each.
+pub fn export(mets_path: &Path, pages: &[PathBuf], options: &MetsOptions) -> Result<MetsSummary, String> {
+    if pages.is_empty() {
+        return Err("A METS document needs at least one page".to_string());
+    }
+    let dir = mets_path.parent().unwrap_or(Path::new(""));
+    let name = |path: &Path| -> Result<String, String> {
+        if !path.starts_with(dir) {
+            return Err(format!("{} is outside the METS folder {}", path.display(), dir.display()));
+        }
+        Ok(fixity::entry_name(path, dir))
+    };
+
+    let (mut tech, mut masters, mut texts, mut divs) = (String::new(), String::new(), String::new(), String::new());
+    let (mut alto_count, mut mix_count) = (0, 0);
+    for (i, page) in pages.iter().enumerate() {
+        let n = i + 1;
+        let mix = page.with_extension("mix.xml");
+        let admid = if mix.is_file() {
+            tech.push_str(&format!(
+                "    <mets:techMD ID=\"TECH{}\">\n      <mets:mdRef LOCTYPE=\"URL\" MDTYPE=\"NISOIMG\" MIMETYPE=\"text/xml\" xlink:href=\"{}\"/>\n    </mets:techMD>\n",
+                n,
+                escape(&href(&name(&mix)?))
+            ));
+            mix_count += 1;
+            Some(format!("TECH{}", n))
+        } else {
+            None
+        };
+        masters.push_str(&file_entry(&format!("IMG{}", n), page, &name(page)?, options.algorithm, admid.as_deref())?);
+        let mut fptrs = format!("        <mets:fptr FILEID=\"IMG{}\"/>\n", n);
+        let alto = page.with_extension("alto.xml");
+        if alto.is_file() {
+            texts.push_str(&file_entry(&format!("ALTO{}", n), &alto, &name(&alto)?, options.algorithm, None)?);
+            fptrs.push_str(&format!("        <mets:fptr FILEID=\"ALTO{}\"/>\n", n));
+            alto_count += 1;
+        }
+        divs.push_str(&format!("      <mets:div ID=\"PHYS{0}\" TYPE=\"page\" ORDER=\"{0}\" ORDERLABEL=\"{0}\">\n{1}      </mets:div>\n", n, fptrs));
+    }
+
+    let (date, time) = crate::image_ops::stamp::format_timestamp(
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+    );
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<mets:mets xmlns:mets=\"http://www.loc.gov/METS/\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" xmlns:mods=\"http://www.loc.gov/mods/v3\"",
+    );
+    if let Some(id) = &options.identifier {
+        xml.push_str(&format!(" OBJID=\"{}\"", escape(id)));
+    }
+    if let Some(title) = &options.title {
+        xml.push_str(&format!(" LABEL=\"{}\"", escape(title)));
+    }
+    xml.push_str(&format!(">\n  <mets:metsHdr CREATEDATE=\"{}T{}Z\">\n", date, time));
+    if let Some(organization) = &options.organization {
+        xml.push_str(&format!("    <mets:agent ROLE=\"CREATOR\" TYPE=\"ORGANIZATION\">\n      <mets:name>{}</mets:name>\n    </mets:agent>\n", escape(organization)));
+    }
+    xml.push_str(&format!(
+        "    <mets:agent ROLE=\"CREATOR\" TYPE=\"OTHER\" OTHERTYPE=\"SOFTWARE\">\n      <mets:name>ClioBulk {}</mets:name>\n    </mets:agent>\n  </mets:metsHdr>\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    let described = options.title.is_some() || options.identifier.is_some();
+    if described {
+        xml.push_str("  <mets:dmdSec ID=\"DMD1\">\n    <mets:mdWrap MDTYPE=\"MODS\">\n      <mets:xmlData>\n        <mods:mods>\n");
+        if let Some(title) = &options.title {
+            xml.push_str(&format!("          <mods:titleInfo>\n            <mods:title>{}</mods:title>\n          </mods:titleInfo>\n", escape(title)));
+        }
+        if let Some(id) = &options.identifier {
+            xml.push_str(&format!("          <mods:identifier type=\"local\">{}</mods:identifier>\n", escape(id)));
+        }
+        xml.push_str("        </mods:mods>\n      </mets:xmlData>\n    </mets:mdWrap>\n  </mets:dmdSec>\n");
+    }
+    if !tech.is_empty() {
+        xml.push_str(&format!("  <mets:amdSec ID=\"AMD1\">\n{}  </mets:amdSec>\n", tech));
+    }
+    xml.push_str(&format!("  <mets:fileSec>\n    <mets:fileGrp USE=\"MASTER\">\n{}    </mets:fileGrp>\n", masters));
+    if !texts.is_empty() {
+        xml.push_str(&format!("    <mets:fileGrp USE=\"FULLTEXT\">\n{}    </mets:fileGrp>\n", texts));
+    }
+    xml.push_str("  </mets:fileSec>\n  <mets:structMap TYPE=\"PHYSICAL\">\n");
+    let label = options.title.as_ref().map_or(String::new(), |t| format!(" LABEL=\"{}\"", escape(t)));
+    let dmdid = if described { " DMDID=\"DMD1\"" } else { "" };
+    xml.push_str(&format!("    <mets:div TYPE=\"physSequence\"{}{}>\n{}    </mets:div>\n", label, dmdid, divs));
+    xml.push_str("  </mets:structMap>\n</mets:mets>\n");
+
+    std::fs::write(mets_path, xml).map_err(|e| format!("Failed to write {}: {}", mets_path.display(), e))?;
+    Ok(MetsSummary { path: mets_path.to_string_lossy().to_string(), pages: pages.len(), alto: alto_count, mix: mix_count })
+}
@@ -0,0 +1,337 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Technical Metadata
+ *
+ * Per-file technical metadata records in the NISO MIX 2.0 data dictionary,
+ * the technical section repositories expect next to a digitized master:
+ * what the file is (format, size, compression, checksums), how its pixels
+ * are stored (dimensions, samples, bit depth, resolution, profile), what it
+ * was captured with and which processing produced it. Storage facts are read
+ * back from the written file's header rather than taken from the job
+ * settings, so encoder fallbacks (a bilevel Group 4 page, say) are recorded
+ * as they happened. Records are written as MIX XML or as the same fields in
+ * JSON.
+ */
+use image::{ImageDecoder, ImageFormat};
+use serde::Serialize;
+use std::io::Cursor;
+use std::path::Path;
+use crate::commands::{ChecksumAlgorithm, OutputColorSpace};
+use crate::image_ops::color;
+use crate::{fixity, metadata, submission};
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TechnicalRecord {
+    /// File name of the output.
+    pub object_identifier: String,
+    pub file_size: u64,
+    /// MIME type.
+    pub format_name: String,
+    /// "big endian" or "little endian", for formats that can be either.
+    pub byte_order: Option<String>,
+    pub compression: String,
+    pub fixity: Vec<Checksum>,
+    pub width: u32,
+    pub height: u32,
+    /// TIFF photometric interpretation: `RGB`, `YCbCr`, `BlackIsZero`, `WhiteIsZero`...
+    pub color_space: String,
+    /// Name of the embedded ICC profile, "unknown" for one that is not built in.
+    pub icc_profile: Option<String>,
+    /// Pixels per inch.
+    pub resolution: Option<f32>,
+    pub bits_per_sample: Vec<u8>,
+    pub samples_per_pixel: u8,
+    pub capture: CaptureInformation,
+    pub processing: ProcessingInformation,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Checksum {
+    pub algorithm: String,
+    pub digest: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CaptureInformation {
+    /// File name of the source.
+    pub source: String,
+    /// ISO 8601, from the source's EXIF DateTimeOriginal.
+    pub date_time_created: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ProcessingInformation {
+    /// ISO 8601, UTC.
+    pub date_time_processed: String,
+    pub software: String,
+    pub software_version: String,
+    /// Pipeline stages that changed the image, in the order they ran.
+    pub actions: Vec<String>,
+}
+
+/// The record of `output`, produced from `source` by the stages in `actions`.
+pub fn record(source: &str, output: &str, algorithms: &[ChecksumAlgorithm], actions: &[String]) -> Result<TechnicalRecord, String> {
+    let data = std::fs::read(output).map_err(|e| format!("Failed to read {}: {}", output, e))?;
+    let format = image::guess_format(&data).map_err(|_| format!("Not a recognized image format: {}", output))?;
+    let layout = if format == ImageFormat::Tiff { tiff_layout(&data)? } else { layout(&data, format)? };
+    let file_name = |path: &str| Path::new(path).file_name().map_or_else(|| path.to_string(), |n| n.to_string_lossy().to_string());
+    let digests = fixity::digest_file(Path::new(output), algorithms)?;
+    let (manufacturer, model) = metadata::capture_device(source);
+    let (date, time) = crate::image_ops::stamp::format_timestamp(
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+    );
+
+    Ok(TechnicalRecord {
+        object_identifier: file_name(output),
+        file_size: data.len() as u64,
+        format_name: format.to_mime_type().to_string(),
+        byte_order: layout.byte_order.map(str::to_string),
+        compression: layout.compression,
+        fixity: algorithms
+            .iter()
+            .zip(digests)
+            .map(|(algorithm, digest)| Checksum { algorithm: digest_name(*algorithm).to_string(), digest })
+            .collect(),
+        width: layout.width,
+        height: layout.height,
+        color_space: layout.color_space.to_string(),
+        icc_profile: layout.icc.map(|icc| match color::identify_profile(&icc) {
+            Some(space) => profile_name(space).to_string(),
+            None => "unknown".to_string(),
+        }),
+        resolution: submission::tagged_dpi(output),
+        samples_per_pixel: layout.bits_per_sample.len() as u8,
+        bits_per_sample: layout.bits_per_sample,
+        capture: CaptureInformation {
+            source: file_name(source),
+            // EXIF writes "YYYY:MM:DD HH:MM:SS"
+            date_time_created: metadata::capture_time(source).map(|t| t.replacen(':', "-", 2).replacen(' ', "T", 1)),
+            manufacturer,
+            model,
+        },
+        processing: ProcessingInformation {
+            date_time_processed: format!("{}T{}Z", date, time),
+            software: "ClioBulk".to_string(),
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+            actions: actions.to_vec(),
+        },
+    })
+}
+
+/// How a file stores its pixels, from its header.
+struct Layout {
+    width: u32,
+    height: u32,
+    byte_order: Option<&'static str>,
+    compression: String,
+    color_space: &'static str,
+    bits_per_sample: Vec<u8>,
+    icc: Option<Vec<u8>>,
+}
+
+fn layout(data: &[u8], format: ImageFormat) -> Result<Layout, String> {
+    let reader = image::ImageReader::with_format(Cursor::new(data), format);
+    let mut decoder = reader.into_decoder().map_err(|e| format!("Unreadable header: {}", e))?;
+    let icc = decoder.icc_profile().ok().flatten();
+    let (width, height) = decoder.dimensions();
+    let stored = decoder.original_color_type();
+    let channels = stored.channel_count();
+    let bits = (stored.bits_per_pixel() / channels.max(1) as u16) as u8;
+    let color_space = match (format, channels >= 3) {
+        // Baseline JPEG stores color as luma and chroma
+        (ImageFormat::Jpeg, true) => "YCbCr",
+        (_, true) => "RGB",
+        (_, false) => "BlackIsZero",
+    };
+    let compression = match format {
+        ImageFormat::Jpeg => "JPEG",
+        ImageFormat::Png => "Deflate",
+        ImageFormat::Gif => "LZW",
+        ImageFormat::Bmp | ImageFormat::Pnm => "Uncompressed",
+        _ => "Unknown",
+    };
+    let byte_order = matches!(format, ImageFormat::Jpeg | ImageFormat::Png).then_some("big endian");
+    Ok(Layout { width, height, byte_order, compression: compression.to_string(), color_space, bits_per_sample: vec![bits; channels as usize], icc })
+}
+
+fn tiff_layout(data: &[u8]) -> Result<Layout, String> {
+    use tiff::tags::Tag;
+    let tiff_err = |e: tiff::TiffError| format!("Unreadable TIFF header: {}", e);
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).map_err(tiff_err)?;
+    let (width, height) = decoder.dimensions().map_err(tiff_err)?;
+    let byte_order = if data.starts_with(b"MM") { "big endian" } else { "little endian" };
+    let compression = match decoder.find_tag_unsigned::<u16>(Tag::Compression).map_err(tiff_err)?.unwrap_or(1) {
+        1 => "Uncompressed".to_string(),
+        2 => "CCITT 1D".to_string(),
+        3 => "CCITT Group 3".to_string(),
+        4 => "CCITT Group 4".to_string(),
+        5 => "LZW".to_string(),
+        6 | 7 => "JPEG".to_string(),
+        8 | 32946 => "Deflate".to_string(),
+        32773 => "PackBits".to_string(),
+        34712 => "JPEG 2000".to_string(),
+        other => format!("Unknown ({})", other),
+    };
+    let color_space = match decoder.find_tag_unsigned::<u16>(Tag::PhotometricInterpretation).map_err(tiff_err)? {
+        Some(0) => "WhiteIsZero",
+        Some(1) => "BlackIsZero",
+        Some(2) => "RGB",
+        Some(3) => "PaletteColor",
+        Some(5) => "CMYK",
+        Some(6) => "YCbCr",
+        Some(8) => "CIELab",
+        _ => "Unknown",
+    };
+    let samples = decoder.find_tag_unsigned::<u16>(Tag::SamplesPerPixel).map_err(tiff_err)?.unwrap_or(1) as usize;
+    let bits = decoder.find_tag_unsigned_vec::<u8>(Tag::BitsPerSample).map_err(tiff_err)?.unwrap_or_else(|| vec![1]);
+    // A single value stands for every sample
+    let bits_per_sample = match bits.first() {
+        _ if bits.len() == samples => bits,
+        Some(&first) => vec![first; samples],
+        None => return Err("Unreadable TIFF header: BitsPerSample is empty".to_string()),
+    };
+    // Profiles are written as bytes, but other writers may tag them undefined; either way one is embedded
+    let icc = decoder.find_tag(Tag::IccProfile).map_err(tiff_err)?.map(|v| v.into_u8_vec().unwrap_or_default());
+    Ok(Layout { width, height, byte_order: Some(byte_order), compression, color_space, bits_per_sample, icc })
+}
+
+fn digest_name(algorithm: ChecksumAlgorithm) -> &'static str {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => "MD5",
+        ChecksumAlgorithm::Sha256 => "SHA-256",
+    }
+}
+
+fn profile_name(space: OutputColorSpace) -> &'static str {
+    match space {
+        OutputColorSpace::Srgb => "sRGB IEC61966-2.1",
+        OutputColorSpace::AdobeRgb => "Adobe RGB (1998)",
+        OutputColorSpace::GrayGamma22 => "Gray Gamma 2.2",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The record as a MIX 2.0 document.
+pub fn to_xml(record: &TechnicalRecord) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<mix:mix xmlns:mix=\"http://www.loc.gov/mix/v20\">\n");
+    let open = |depth: usize, name: &str, xml: &mut String| xml.push_str(&format!("{}<mix:{}>\n", "  ".repeat(depth), name));
+    let close = |depth: usize, name: &str, xml: &mut String| xml.push_str(&format!("{}</mix:{}>\n", "  ".repeat(depth), name));
+    let leaf = |depth: usize, name: &str, value: &str, xml: &mut String| {
+        xml.push_str(&format!("{}<mix:{name}>{}</mix:{name}>\n", "  ".repeat(depth), escape(value), name = name))
+    };
+
+    open(1, "BasicDigitalObjectInformation", &mut xml);
+    open(2, "ObjectIdentifier", &mut xml);
+    leaf(3, "objectIdentifierType", "filename", &mut xml);
+    leaf(3, "objectIdentifierValue", &record.object_identifier, &mut xml);
+    close(2, "ObjectIdentifier", &mut xml);
+    leaf(2, "fileSize", &record.file_size.to_string(), &mut xml);
+    open(2, "FormatDesignation", &mut xml);
+    leaf(3, "formatName", &record.format_name, &mut xml);
+    close(2, "FormatDesignation", &mut xml);
+    if let Some(order) = &record.byte_order {
+        leaf(2, "byteOrder", order, &mut xml);
+    }
+    open(2, "Compression", &mut xml);
+    leaf(3, "compressionScheme", &record.compression, &mut xml);
+    close(2, "Compression", &mut xml);
+    for checksum in &record.fixity {
+        open(2, "Fixity", &mut xml);
+        leaf(3, "messageDigestAlgorithm", &checksum.algorithm, &mut xml);
+        leaf(3, "messageDigest", &checksum.digest, &mut xml);
+        leaf(3, "messageDigestOriginator", &record.processing.software, &mut xml);
+        close(2, "Fixity", &mut xml);
+    }
+    close(1, "BasicDigitalObjectInformation", &mut xml);
+
+    open(1, "BasicImageInformation", &mut xml);
+    open(2, "BasicImageCharacteristics", &mut xml);
+    leaf(3, "imageWidth", &record.width.to_string(), &mut xml);
+    leaf(3, "imageHeight", &record.height.to_string(), &mut xml);
+    open(3, "PhotometricInterpretation", &mut xml);
+    leaf(4, "colorSpace", &record.color_space, &mut xml);
+    if let Some(profile) = &record.icc_profile {
+        open(4, "ColorProfile", &mut xml);
+        open(5, "IccProfile", &mut xml);
+        leaf(6, "iccProfileName", profile, &mut xml);
+        close(5, "IccProfile", &mut xml);
+        close(4, "ColorProfile", &mut xml);
+    }
+    close(3, "PhotometricInterpretation", &mut xml);
+    close(2, "BasicImageCharacteristics", &mut xml);
+    close(1, "BasicImageInformation", &mut xml);
+
+    let capture = &record.capture;
+    open(1, "ImageCaptureMetadata", &mut xml);
+    open(2, "SourceInformation", &mut xml);
+    open(3, "SourceID", &mut xml);
+    leaf(4, "sourceIDType", "filename", &mut xml);
+    leaf(4, "sourceIDValue", &capture.source, &mut xml);
+    close(3, "SourceID", &mut xml);
+    close(2, "SourceInformation", &mut xml);
+    if let Some(created) = &capture.date_time_created {
+        open(2, "GeneralCaptureInformation", &mut xml);
+        leaf(3, "dateTimeCreated", created, &mut xml);
+        close(2, "GeneralCaptureInformation", &mut xml);
+    }
+    if capture.manufacturer.is_some() || capture.model.is_some() {
+        open(2, "DigitalCameraCapture", &mut xml);
+        if let Some(manufacturer) = &capture.manufacturer {
+            leaf(3, "digitalCameraManufacturer", manufacturer, &mut xml);
+        }
+        if let Some(model) = &capture.model {
+            open(3, "DigitalCameraModel", &mut xml);
+            leaf(4, "digitalCameraModelName", model, &mut xml);
+            close(3, "DigitalCameraModel", &mut xml);
+        }
+        close(2, "DigitalCameraCapture", &mut xml);
+    }
+    close(1, "ImageCaptureMetadata", &mut xml);
+
+    open(1, "ImageAssessmentMetadata", &mut xml);
+    if let Some(dpi) = record.resolution {
+        // Rationals of hundredths keep fractional densities such as 72.5
+        let (numerator, denominator) = if dpi.fract() == 0.0 { (dpi as u32, 1) } else { ((dpi * 100.0).round() as u32, 100) };
+        open(2, "SpatialMetrics", &mut xml);
+        leaf(3, "samplingFrequencyUnit", "in.", &mut xml);
+        for axis in ["xSamplingFrequency", "ySamplingFrequency"] {
+            open(3, axis, &mut xml);
+            leaf(4, "numerator", &numerator.to_string(), &mut xml);
+            leaf(4, "denominator", &denominator.to_string(), &mut xml);
+            close(3, axis, &mut xml);
+        }
+        close(2, "SpatialMetrics", &mut xml);
+    }
+    open(2, "ImageColorEncoding", &mut xml);
+    open(3, "BitsPerSample", &mut xml);
+    let bits: Vec<String> = record.bits_per_sample.iter().map(|b| b.to_string()).collect();
+    leaf(4, "bitsPerSampleValue", &bits.join(","), &mut xml);
+    leaf(4, "bitsPerSampleUnit", "integer", &mut xml);
+    close(3, "BitsPerSample", &mut xml);
+    leaf(3, "samplesPerPixel", &record.samples_per_pixel.to_string(), &mut xml);
+    close(2, "ImageColorEncoding", &mut xml);
+    close(1, "ImageAssessmentMetadata", &mut xml);
+
+    let processing = &record.processing;
+    open(1, "ChangeHistory", &mut xml);
+    open(2, "ImageProcessing", &mut xml);
+    leaf(3, "dateTimeProcessed", &processing.date_time_processed, &mut xml);
+    leaf(3, "sourceData", &capture.source, &mut xml);
+    open(3, "ProcessingSoftware", &mut xml);
+    leaf(4, "processingSoftwareName", &processing.software, &mut xml);
+    leaf(4, "processingSoftwareVersion", &processing.software_version, &mut xml);
+    close(3, "ProcessingSoftware", &mut xml);
+    for action in &processing.actions {
+        leaf(3, "processingActions", action, &mut xml);
+    }
+    close(2, "ImageProcessing", &mut xml);
+    close(1, "ChangeHistory", &mut xml);
+    xml.push_str("</mix:mix>\n");
+    xml
+}
@@ -0,0 +1,83 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Naming Tokens
+ *
+ * Extra tokens for templates, on top of the built-in `{filename}`, `{date}`,
+ * `{index}` and friends. Code embedding the app registers `TokenProvider`s
+ * with the registry (a lookup in a project database, say); scripts driving
+ * the UI set plain values, for the whole batch or per file name, through
+ * `set_custom_tokens`. Built-in tokens always take precedence, so a provider
+ * cannot change what an existing template means.
+ */
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use crate::image_ops::stamp::{self, StampContext, TokenProvider};
+
+/// Registry id of the values set through `set_custom_tokens`.
+pub const CUSTOM_ID: &str = "custom";
+
+/// Token providers by id, in registration order, registered as Tauri managed state.
+#[derive(Default)]
+pub struct TokenRegistry {
+    providers: Mutex<Vec<(String, Arc<dyn TokenProvider>)>>,
+}
+
+impl TokenRegistry {
+    /// Adds `provider` under `id`, replacing (in place) one registered before with it.
+    pub fn register(&self, id: &str, provider: Arc<dyn TokenProvider>) {
+        let mut providers = self.providers.lock().unwrap();
+        match providers.iter_mut().find(|(existing, _)| existing == id) {
+            Some(entry) => entry.1 = provider,
+            None => providers.push((id.to_string(), provider)),
+        }
+    }
+
+    /// Removes the provider registered under `id`; false when there was none.
+    pub fn unregister(&self, id: &str) -> bool {
+        let mut providers = self.providers.lock().unwrap();
+        let before = providers.len();
+        providers.retain(|(existing, _)| existing != id);
+        providers.len() != before
+    }
+
+    /// Resolves `template` with the built-in tokens and every registered provider.
+    pub fn render(&self, template: &str, context: &StampContext) -> String {
+        // Cloned out so a slow provider does not hold the lock across the render
+        let providers: Vec<_> = self.providers.lock().unwrap().iter().map(|(_, p)| p.clone()).collect();
+        stamp::render_template_with(template, context, &providers)
+    }
+}
+
+/// Plain token values: `files` by file name (as in `{filename}`), falling back to
+/// `values` for every file.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CustomTokens {
+    pub values: BTreeMap<String, String>,
+    pub files: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl CustomTokens {
+    /// Checks that every token name can appear in a template.
+    pub fn validate(&self) -> Result<(), String> {
+        let names = self.values.keys().chain(self.files.values().flat_map(|v| v.keys()));
+        for name in names {
+            if name.is_empty() || name.contains(['{', '}', ':']) {
+                return Err(format!("Invalid token name: {:?}", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.files.values().all(|v| v.is_empty())
+    }
+}
+
+impl TokenProvider for CustomTokens {
+    fn resolve(&self, name: &str, _argument: Option<&str>, context: &StampContext) -> Option<String> {
+        self.files.get(&context.filename).and_then(|v| v.get(name)).or_else(|| self.values.get(name)).cloned()
+    }
+}
@@ -0,0 +1,131 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk OCR
+ *
+ * Pages are piped as PNG into a `tesseract` child process, which writes the
+ * recognized text (or hOCR) and a TSV table of word boxes into a scratch
+ * directory in one run. Only compiled with the `ocr` feature since it depends
+ * on the Tesseract CLI and its language data being installed. Each process is
+ * held to one thread, so the batch concurrency alone decides how many pages
+ * are read at once. Orientation detection runs Tesseract's OSD page
+ * segmentation mode, which needs `osd.traineddata`.
+ */
+use image::DynamicImage;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::commands::OcrFormat;
+use crate::image_ops::orientation::PageOrientation;
+use crate::image_ops::{OcrPage, OcrWord};
+
+/// Binary run when `CLIOBULK_TESSERACT` does not point at a specific build.
+const DEFAULT_TESSERACT: &str = "tesseract";
+
+/// Tells apart the scratch outputs of pages read at the same time.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Text of `img` in `language` (Tesseract codes, `+`-joined), as plain text or hOCR,
+/// with the boxes of its words.
+pub fn recognize(img: &DynamicImage, language: &str, format: OcrFormat) -> Result<OcrPage, String> {
+    if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '+') {
+        return Err(format!("Invalid OCR language: {}", language));
+    }
+    let base = std::env::temp_dir().join(format!("cliobulk-ocr-{}-{}", std::process::id(), RUNS.fetch_add(1, Ordering::Relaxed)));
+    // ALTO is written from the word boxes, at the size of the output
+    let config = match format {
+        OcrFormat::Text | OcrFormat::Alto => "txt",
+        OcrFormat::Hocr => "hocr",
+    };
+    let mut command = tesseract();
+    command.arg("stdin").arg(&base).args(["-l", language, config, "tsv"]).stdout(Stdio::null());
+    let (output, fed) = run(command, img)?;
+
+    let read = |ext: &str| {
+        let path = base.with_extension(ext);
+        let text = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+        text
+    };
+    let (text, tsv) = (read(config), read("tsv"));
+    if !output.status.success() {
+        return Err(format!("tesseract failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    fed.map_err(|e| format!("tesseract stopped reading the page: {}", e))?;
+    let text = text.map_err(|e| format!("tesseract wrote no text: {}", e))?;
+    let words = tsv.map(|tsv| parse_tsv(&tsv)).unwrap_or_default();
+    Ok(OcrPage { text, words, width: img.width(), height: img.height() })
+}
+
+/// The clockwise quarter turn that puts the text of `img` upright, with Tesseract's
+/// confidence in it, or `None` when the page has too little text to tell.
+pub fn orientation(img: &DynamicImage) -> Result<Option<PageOrientation>, String> {
+    let mut command = tesseract();
+    command.args(["stdin", "stdout", "--psm", "0"]).stdout(Stdio::piped());
+    let (output, fed) = run(command, img)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Too few characters") {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        return Err(format!("tesseract failed: {}", stderr.trim()));
+    }
+    fed.map_err(|e| format!("tesseract stopped reading the page: {}", e))?;
+    Ok(parse_osd(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `Rotate` and `Orientation confidence` of Tesseract's OSD report.
+fn parse_osd(report: &str) -> Option<PageOrientation> {
+    let field = |name: &str| report.lines().find_map(|l| l.strip_prefix(name)).map(str::trim);
+    let degrees = field("Rotate:")?.parse::<u32>().ok()? % 360;
+    let confidence = field("Orientation confidence:")?.parse().ok()?;
+    Some(PageOrientation { degrees, confidence })
+}
+
+/// The Tesseract command, held to one thread.
+fn tesseract() -> Command {
+    let tesseract = std::env::var("CLIOBULK_TESSERACT").unwrap_or_else(|_| DEFAULT_TESSERACT.to_string());
+    let mut command = Command::new(tesseract);
+    command.env("OMP_THREAD_LIMIT", "1");
+    command
+}
+
+/// Runs `command` with `img` piped in as PNG, and returns its output and whether the
+/// whole page was taken in.
+fn run(mut command: Command, img: &DynamicImage) -> Result<(std::process::Output, std::io::Result<()>), String> {
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).map_err(|e| e.to_string())?;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", command.get_program().to_string_lossy(), e))?;
+    // Fed from a thread so a full stderr pipe cannot stall the write
+    let mut stdin = child.stdin.take().ok_or("tesseract has no input")?;
+    let feeder = std::thread::spawn(move || stdin.write_all(&png));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let fed = feeder.join().map_err(|_| "tesseract input thread panicked".to_string())?;
+    Ok((output, fed))
+}
+
+/// Word rows (level 5) of Tesseract's TSV output: level, page, block, paragraph, line,
+/// word, left, top, width, height, confidence, text.
+fn parse_tsv(tsv: &str) -> Vec<OcrWord> {
+    tsv.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(12, '\t').collect();
+            if fields.len() < 12 || fields[0] != "5" || fields[11].trim().is_empty() {
+                return None;
+            }
+            let number = |i: usize| fields[i].parse::<u32>().ok();
+            Some(OcrWord {
+                text: fields[11].trim().to_string(),
+                left: number(6)?,
+                top: number(7)?,
+                width: number(8)?,
+                height: number(9)?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,108 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Preview Sessions
+ *
+ * Keeps a decoded, preview-sized copy of the image being edited in managed
+ * state so interactive commands can re-run the pipeline without paying for
+ * a full RAW decode on every slider change, along with the settings that
+ * control how previews are encoded for the webview. Each session also keeps
+ * the output of the slow leading stages of its last render, so a tone or
+ * color slider only re-runs the stages after them.
+ */
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::commands::PreviewEncoding;
+use crate::image_ops::Checkpoint;
+
+/// Long edge, in pixels, of the image held by a preview session.
+pub const PREVIEW_SIZE: u32 = 1200;
+
+/// Registry of open preview sessions, registered as Tauri managed state.
+///
+/// Each session belongs to the window that opened it, so a loupe window and a grid
+/// window can preview different images without ever rendering each other's sessions.
+#[derive(Default)]
+pub struct PreviewSessions {
+    next_id: AtomicU64,
+    sources: Mutex<HashMap<u64, (String, Arc<DynamicImage>)>>,
+    checkpoints: Mutex<HashMap<u64, Checkpoint>>,
+}
+
+impl PreviewSessions {
+    /// Stores a downscaled copy of `img` for window `owner` and returns the new session id.
+    pub fn open(&self, owner: &str, img: &DynamicImage) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let source = Arc::new(img.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE));
+        self.sources.lock().unwrap().insert(id, (owner.to_string(), source));
+        id
+    }
+
+    /// Returns the session's source image, if the session belongs to `owner`.
+    pub fn source(&self, owner: &str, id: u64) -> Result<Arc<DynamicImage>, String> {
+        self.sources
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|(o, _)| o == owner)
+            .map(|(_, source)| source.clone())
+            .ok_or_else(|| format!("Unknown preview session: {}", id))
+    }
+
+    /// The session's cached leading stages, if the session belongs to `owner`.
+    pub fn checkpoint(&self, owner: &str, id: u64) -> Option<Checkpoint> {
+        self.source(owner, id).ok()?;
+        self.checkpoints.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Keeps `checkpoint` for the session's next render, unless the session has closed.
+    pub fn store_checkpoint(&self, owner: &str, id: u64, checkpoint: Checkpoint) {
+        if self.source(owner, id).is_ok() {
+            self.checkpoints.lock().unwrap().insert(id, checkpoint);
+        }
+    }
+
+    /// Forgets the session's cached stages, e.g. once low-memory mode is on.
+    pub fn clear_checkpoint(&self, owner: &str, id: u64) {
+        if self.source(owner, id).is_ok() {
+            self.checkpoints.lock().unwrap().remove(&id);
+        }
+    }
+
+    /// Drops every session opened by window `owner`, e.g. when it closes.
+    pub fn close_window(&self, owner: &str) {
+        let mut sources = self.sources.lock().unwrap();
+        sources.retain(|_, (o, _)| o != owner);
+        self.checkpoints.lock().unwrap().retain(|id, _| sources.contains_key(id));
+    }
+
+    /// Drops a session owned by `owner`; returns whether it existed.
+    pub fn close(&self, owner: &str, id: u64) -> bool {
+        let mut sources = self.sources.lock().unwrap();
+        if sources.get(&id).is_some_and(|(o, _)| o == owner) {
+            sources.remove(&id);
+            self.checkpoints.lock().unwrap().remove(&id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// User-configurable preview settings, registered as Tauri managed state.
+#[derive(Default)]
+pub struct PreviewSettings {
+    encoding: Mutex<PreviewEncoding>,
+}
+
+impl PreviewSettings {
+    pub fn encoding(&self) -> PreviewEncoding {
+        *self.encoding.lock().unwrap()
+    }
+
+    pub fn set_encoding(&self, encoding: PreviewEncoding) {
+        *self.encoding.lock().unwrap() = encoding;
+    }
+}
@@ -0,0 +1,80 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Folder Scan
+ *
+ * Walks a folder tree for the images the app can open. Archives of hundreds
+ * of thousands of files take minutes to list on network drives, so the walk
+ * reports running totals as it goes and the UI can show what was found so
+ * far instead of waiting for the whole list.
+ */
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::intake;
+
+/// Minimum time between two progress events of a running scan, in milliseconds.
+pub const PROGRESS_INTERVAL_MS: u64 = 200;
+
+/// Running totals of a scan.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FolderStats {
+    pub files: usize,
+    pub bytes: u64,
+    /// Files per lowercase extension.
+    pub formats: BTreeMap<String, usize>,
+    /// Directories entered so far.
+    pub folders: usize,
+    /// Set on the last report, once the whole tree was walked.
+    pub done: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FolderScan {
+    /// Supported files, sorted by path.
+    pub files: Vec<String>,
+    pub stats: FolderStats,
+}
+
+/// Lists the supported files under `root`, calling `progress` after each one found
+/// and once more with `done` set. Symlinks are not followed, so a link back up
+/// the tree cannot loop; unreadable subfolders are skipped.
+pub fn scan(root: &Path, progress: &mut dyn FnMut(&FolderStats)) -> Result<FolderScan, String> {
+    if !root.is_dir() {
+        return Err(format!("Not a folder: {}", root.display()));
+    }
+    let mut stats = FolderStats::default();
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            log::info!("Skipping unreadable folder: {}", dir.display());
+            continue;
+        };
+        stats.folders += 1;
+        for entry in entries.flatten() {
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if kind.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let location = path.to_string_lossy().to_string();
+            if !kind.is_file() || !intake::is_supported(&location) {
+                continue;
+            }
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            stats.files += 1;
+            stats.bytes += entry.metadata().map(|m| m.len()).unwrap_or_default();
+            *stats.formats.entry(ext).or_default() += 1;
+            files.push(location);
+            progress(&stats);
+        }
+    }
+    files.sort();
+    stats.done = true;
+    progress(&stats);
+    Ok(FolderScan { files, stats })
+}
@@ -0,0 +1,200 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Batch Scheduler
+ *
+ * Batches can wait for a start time and stop at a deadline, so a long job
+ * runs only overnight on a shared workstation. Stopping is clean: files
+ * already being processed finish, and the ones not yet started are held
+ * like the remainder of a verification run, to be continued or cancelled.
+ * Bulk jobs started right away are registered too, so they can be cancelled
+ * while they run, or paused: a paused job finishes the files in flight and
+ * starts the next one once it is resumed.
+ */
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// When a scheduled batch may run, as Unix times in seconds. The UI resolves
+/// wall-clock windows such as "1 AM to 7 AM" to their next occurrence.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct BatchWindow {
+    /// No file starts before this; `None` starts right away.
+    pub start_at: Option<u64>,
+    /// No file starts after this; `None` runs to the end of the batch.
+    pub stop_at: Option<u64>,
+}
+
+impl BatchWindow {
+    pub fn validate(&self, now: u64) -> Result<(), String> {
+        match (self.start_at, self.stop_at) {
+            (_, Some(stop)) if stop <= now => Err("The batch window has already closed".to_string()),
+            (Some(start), Some(stop)) if stop <= start => Err("The batch window stops before it starts".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn started(&self, now: u64) -> bool {
+        self.start_at.map_or(true, |start| now >= start)
+    }
+}
+
+/// Tells running batch files whether to stop starting new ones.
+#[derive(Clone, Debug, Default)]
+pub struct Halt {
+    pub stop_at: Option<u64>,
+    pub cancelled: Arc<AtomicBool>,
+    /// No file starts while set, until it is cleared or the batch is cancelled.
+    pub paused: Arc<AtomicBool>,
+}
+
+impl Halt {
+    pub fn reached(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.stop_at.is_some_and(|stop| now() >= stop)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed) && !self.is_cancelled()
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ScheduledBatch {
+    pub id: u64,
+    pub files: usize,
+    #[serde(flatten)]
+    pub window: BatchWindow,
+    /// Past its start time and processing.
+    pub running: bool,
+}
+
+struct Job {
+    owner: String,
+    batch: ScheduledBatch,
+    halt: Halt,
+}
+
+/// Batches waiting for or running in their window, registered as Tauri managed state.
+///
+/// Like held verification batches, each belongs to the window that scheduled it.
+#[derive(Default)]
+pub struct Scheduler {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, Job>>,
+}
+
+impl Scheduler {
+    /// Registers a batch of `files` files; the returned `Halt` is raised by `cancel`.
+    pub fn add(&self, owner: &str, files: usize, window: BatchWindow) -> (u64, Halt) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let halt = Halt { stop_at: window.stop_at, ..Default::default() };
+        let batch = ScheduledBatch { id, files, window, running: false };
+        self.jobs.lock().unwrap().insert(id, Job { owner: owner.to_string(), batch, halt: halt.clone() });
+        (id, halt)
+    }
+
+    pub fn set_running(&self, id: u64) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.batch.running = true;
+        }
+    }
+
+    pub fn finish(&self, id: u64) {
+        self.jobs.lock().unwrap().remove(&id);
+    }
+
+    /// Cancels a batch owned by `owner`: before its start it never runs, while running
+    /// it stops starting files and drops the rest. Returns whether the batch existed.
+    pub fn cancel(&self, owner: &str, id: u64) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        match jobs.get(&id) {
+            Some(job) if job.owner == owner => {
+                job.halt.cancelled.store(true, Ordering::Relaxed);
+                jobs.remove(&id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn list(&self, owner: &str) -> Vec<ScheduledBatch> {
+        let mut batches: Vec<ScheduledBatch> =
+            self.jobs.lock().unwrap().values().filter(|job| job.owner == owner).map(|job| job.batch.clone()).collect();
+        batches.sort_by_key(|batch| batch.id);
+        batches
+    }
+
+    pub fn close_window(&self, owner: &str) {
+        self.jobs.lock().unwrap().retain(|_, job| {
+            if job.owner == owner {
+                job.halt.cancelled.store(true, Ordering::Relaxed);
+            }
+            job.owner != owner
+        });
+    }
+}
+
+/// Bulk jobs running now, registered as Tauri managed state so `cancel_bulk` can raise
+/// their `Halt`. Each belongs to the window that started it.
+#[derive(Default)]
+pub struct RunningJobs {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, (String, Halt)>>,
+}
+
+impl RunningJobs {
+    /// Registers a job of `owner`; the returned `Halt` is raised by `cancel`.
+    pub fn start(&self, owner: &str) -> (u64, Halt) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let halt = Halt::default();
+        self.jobs.lock().unwrap().insert(id, (owner.to_string(), halt.clone()));
+        (id, halt)
+    }
+
+    pub fn finish(&self, id: u64) {
+        self.jobs.lock().unwrap().remove(&id);
+    }
+
+    /// Cancels a job owned by `owner`. Returns whether it was running.
+    pub fn cancel(&self, owner: &str, id: u64) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs.get(&id) {
+            Some((job_owner, halt)) if job_owner == owner => {
+                halt.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pauses or resumes a job owned by `owner`. Returns whether it was running.
+    pub fn set_paused(&self, owner: &str, id: u64, paused: bool) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs.get(&id) {
+            Some((job_owner, halt)) if job_owner == owner => {
+                halt.paused.store(paused, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn close_window(&self, owner: &str) {
+        self.jobs.lock().unwrap().retain(|_, (job_owner, halt)| {
+            if job_owner == owner {
+                halt.cancelled.store(true, Ordering::Relaxed);
+            }
+            job_owner != owner
+        });
+    }
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,71 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Settings
+ *
+ * App-wide preferences kept across launches in `settings.json` in the app
+ * data directory. The live values stay in the managed state that uses them
+ * (e.g. `PreviewSettings`, `MemorySettings`); this store only loads them at startup and saves
+ * each change, so a setting picked once does not have to be picked again.
+ */
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::commands::{ExecutionMode, LowMemoryMode, PreviewEncoding};
+use crate::storage;
+
+pub const FILE_NAME: &str = "settings.json";
+
+/// Fields missing from an older file keep their defaults.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct StoredSettings {
+    pub preview_encoding: PreviewEncoding,
+    pub execution_mode: ExecutionMode,
+    pub low_memory_mode: LowMemoryMode,
+}
+
+/// The settings file, registered as Tauri managed state. Until `open` is called
+/// changes are kept in memory only.
+#[derive(Default)]
+pub struct SettingsStore {
+    file: Mutex<Option<PathBuf>>,
+    data: Mutex<StoredSettings>,
+}
+
+impl SettingsStore {
+    /// Loads `file`, saves every later change to it and returns what was loaded. A file
+    /// that cannot be parsed is set aside as `.bak` and the defaults are used.
+    pub fn open(&self, file: &Path) -> StoredSettings {
+        let data = match std::fs::read(file) {
+            Ok(bytes) => serde_json::from_slice::<StoredSettings>(&bytes).unwrap_or_else(|e| {
+                error!("Unreadable settings {}: {}", file.display(), e);
+                let _ = std::fs::rename(file, file.with_extension("json.bak"));
+                StoredSettings::default()
+            }),
+            Err(_) => StoredSettings::default(),
+        };
+        *self.data.lock().unwrap() = data;
+        *self.file.lock().unwrap() = Some(file.to_path_buf());
+        data
+    }
+
+    pub fn get(&self) -> StoredSettings {
+        *self.data.lock().unwrap()
+    }
+
+    /// Applies `change` and saves the result.
+    pub fn update(&self, change: impl FnOnce(&mut StoredSettings)) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        change(&mut data);
+        let Some(file) = self.file.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_vec_pretty(&*data).map_err(|e| e.to_string())?;
+        storage::write_atomic(&file, |tmp| std::fs::write(tmp, &json).map_err(|e| format!("Failed to save settings {}: {}", file.display(), e)))
+    }
+}
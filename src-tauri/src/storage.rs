@@ -0,0 +1,133 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Input/Output Storage
+ *
+ * Resolves the locations commands read from and write to. Desktop builds
+ * receive plain paths, checked against the fs scope. Mobile file pickers and
+ * share sheets hand out URIs instead (Android `content://` under scoped
+ * storage), which the OS has already granted and which can only be opened
+ * through the fs plugin, never with `std::fs`.
+ */
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_fs::{FilePath, FsExt, OpenOptions};
+use crate::commands::OutputOptions;
+use crate::image_ops;
+
+/// Whether `location` is a URI rather than a filesystem path.
+pub fn is_uri(location: &str) -> bool {
+    // A one-letter scheme is a Windows drive ("C:\..."), not a URI
+    location.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// URIs carry their own grant from the picker; paths must be inside the fs scope.
+pub fn is_allowed<R: Runtime>(app: &AppHandle<R>, location: &str) -> bool {
+    is_uri(location) || app.fs_scope().is_allowed(location)
+}
+
+/// File name a URI refers to, used to pick decoders and encoders by extension.
+///
+/// Document URIs percent-encode the whole path into their last segment
+/// (`.../document/primary%3APictures%2Fscan.tif`), so it is decoded first.
+pub fn file_name_hint(location: &str) -> String {
+    let last = location.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+    let decoded = percent_decode(last);
+    decoded.rsplit(['/', ':']).next().unwrap_or_default().to_string()
+}
+
+/// Reads a whole file, through the fs plugin for URIs.
+pub fn read_bytes<R: Runtime>(app: &AppHandle<R>, location: &str) -> Result<Vec<u8>, String> {
+    if !is_uri(location) {
+        return std::fs::read(location).map_err(|e| format!("Failed to read {}: {}", location, e));
+    }
+    let path: FilePath = location.parse().map_err(|e: std::convert::Infallible| e.to_string())?;
+    app.fs().read(path).map_err(|e| format!("Failed to read {}: {}", location, e))
+}
+
+/// Opens an input, reading URIs through the fs plugin.
+pub fn open_input<R: Runtime>(app: &AppHandle<R>, location: &str) -> Result<DynamicImage, String> {
+    if !is_uri(location) {
+        return image_ops::open_image(location);
+    }
+    let bytes = read_bytes(app, location)?;
+    image_ops::decode_image_bytes(&bytes, &file_name_hint(location))
+}
+
+/// Saves an output, writing URIs through the fs plugin. Paths are written with
+/// `write_atomic`, so an encoder failing halfway never leaves a truncated output.
+///
+/// Encoders need a seekable file with a meaningful extension, so URI outputs are
+/// encoded into the app cache first and then copied into the granted document.
+pub fn save_output<R: Runtime>(
+    app: &AppHandle<R>,
+    img: &DynamicImage,
+    location: &str,
+    output: &OutputOptions,
+) -> Result<(), String> {
+    if !is_uri(location) {
+        return write_atomic(Path::new(location), |tmp| image_ops::encode::save_image(img, &tmp.to_string_lossy(), output));
+    }
+    let cache = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&cache).map_err(|e| e.to_string())?;
+    let name = match file_name_hint(location) {
+        name if name.contains('.') => name,
+        _ => "output.jpg".to_string(),
+    };
+    let scratch = cache.join(format!("{}-{}", image_ops::seed_from_name(location), name));
+    let scratch_str = scratch.to_string_lossy().to_string();
+
+    let result = image_ops::encode::save_image(img, &scratch_str, output).and_then(|_| {
+        let path: FilePath = location.parse().map_err(|e: std::convert::Infallible| e.to_string())?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        let mut target = app.fs().open(path, options).map_err(|e| format!("Failed to open {}: {}", location, e))?;
+        let mut source = std::fs::File::open(&scratch).map_err(|e| e.to_string())?;
+        std::io::copy(&mut source, &mut target).map(|_| ()).map_err(|e| e.to_string())
+    });
+    let _ = std::fs::remove_file(&scratch);
+    result
+}
+
+/// Where `path` is written before it is renamed into place. The temp file keeps the
+/// extension, for writers that pick a format by it.
+pub fn temp_sibling(path: &Path) -> PathBuf {
+    match path.extension() {
+        Some(ext) => path.with_extension(format!("tmp.{}", ext.to_string_lossy())),
+        None => path.with_extension("tmp"),
+    }
+}
+
+/// Runs `write` against a temp file next to `path` and renames it over `path` once it
+/// succeeds, so a failed or interrupted export never leaves a partial file behind.
+pub fn write_atomic(path: &Path, write: impl FnOnce(&Path) -> Result<(), String>) -> Result<(), String> {
+    let tmp = temp_sibling(path);
+    let result = write(&tmp).and_then(|_| std::fs::rename(&tmp, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e)));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,465 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Submission Profiles
+ *
+ * Checks finished files against the technical requirements of stock agencies
+ * and print labs (pixel size, tagged resolution, file format, color space,
+ * embedded metadata) so rejections are caught before upload, not after a
+ * review queue. Only headers and metadata are read; pixels are never decoded.
+ *
+ * The built-in profiles follow the published contributor and lab guidelines
+ * at the time of writing. Agencies revise them, so every rule can also be
+ * given explicitly as a custom profile.
+ */
+use exif::{Context, In, Tag, Value};
+use img_parts::{Bytes, DynImage, ImageICC};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// ICC profile stored in a TIFF IFD0.
+const ICC_TAG: Tag = Tag(Context::Tiff, 34675);
+
+/// A named profile or a full set of rules.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum TargetProfile {
+    Named(ProfileName),
+    Custom(SubmissionProfile),
+}
+
+impl TargetProfile {
+    pub fn profile(&self) -> SubmissionProfile {
+        match self {
+            TargetProfile::Named(name) => name.profile(),
+            TargetProfile::Custom(profile) => profile.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileName {
+    Getty,
+    Shutterstock,
+    AdobeStock,
+    /// Photo lab prints from sRGB JPEGs at 300 dpi.
+    PrintLab,
+    /// Giclee/fine-art labs: TIFF, wide-gamut profiles accepted, 300 dpi.
+    FineArtPrint,
+}
+
+impl ProfileName {
+    pub const ALL: [ProfileName; 5] =
+        [ProfileName::Getty, ProfileName::Shutterstock, ProfileName::AdobeStock, ProfileName::PrintLab, ProfileName::FineArtPrint];
+
+    pub fn profile(self) -> SubmissionProfile {
+        let jpeg = vec!["jpeg".to_string()];
+        match self {
+            ProfileName::Getty => SubmissionProfile {
+                name: "Getty Images".to_string(),
+                min_megapixels: Some(4.0),
+                formats: jpeg,
+                color_spaces: vec![ColorSpace::Srgb, ColorSpace::AdobeRgb],
+                required_metadata: vec![MetadataField::Description, MetadataField::Copyright],
+                ..Default::default()
+            },
+            ProfileName::Shutterstock => SubmissionProfile {
+                name: "Shutterstock".to_string(),
+                min_megapixels: Some(4.0),
+                max_file_mb: Some(50.0),
+                formats: jpeg,
+                color_spaces: vec![ColorSpace::Srgb],
+                ..Default::default()
+            },
+            ProfileName::AdobeStock => SubmissionProfile {
+                name: "Adobe Stock".to_string(),
+                min_megapixels: Some(4.0),
+                max_megapixels: Some(100.0),
+                max_file_mb: Some(45.0),
+                formats: jpeg,
+                color_spaces: vec![ColorSpace::Srgb],
+                ..Default::default()
+            },
+            ProfileName::PrintLab => SubmissionProfile {
+                name: "Print lab (300 dpi)".to_string(),
+                min_dpi: Some(300.0),
+                formats: vec!["jpeg".to_string(), "tiff".to_string()],
+                color_spaces: vec![ColorSpace::Srgb],
+                ..Default::default()
+            },
+            ProfileName::FineArtPrint => SubmissionProfile {
+                name: "Fine-art print".to_string(),
+                min_dpi: Some(300.0),
+                formats: vec!["tiff".to_string()],
+                color_spaces: vec![ColorSpace::Srgb, ColorSpace::AdobeRgb, ColorSpace::ProPhoto],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Requirements a file must meet. Unset rules are not checked.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SubmissionProfile {
+    pub name: String,
+    pub min_megapixels: Option<f32>,
+    pub max_megapixels: Option<f32>,
+    pub min_long_edge: Option<u32>,
+    pub min_short_edge: Option<u32>,
+    /// Tagged resolution (EXIF, JFIF or PNG pHYs).
+    pub min_dpi: Option<f32>,
+    /// Print size in inches, either orientation; the pixels must reach it at `min_dpi`
+    /// (300 if unset), whatever resolution is tagged.
+    pub print_size: Option<[f32; 2]>,
+    pub max_file_mb: Option<f32>,
+    /// Accepted formats ("jpeg", "tiff", "png", "webp"); empty accepts any.
+    pub formats: Vec<String>,
+    /// Accepted embedded color spaces; empty accepts any.
+    pub color_spaces: Vec<ColorSpace>,
+    pub required_metadata: Vec<MetadataField>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    Srgb,
+    AdobeRgb,
+    DisplayP3,
+    ProPhoto,
+    Gray,
+    Cmyk,
+    /// An embedded profile that is none of the above, by description.
+    Other(String),
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataField {
+    /// EXIF ImageDescription.
+    Description,
+    Copyright,
+    Artist,
+    CaptureDate,
+    /// IPTC keywords or XMP dc:subject.
+    Keywords,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    Unreadable,
+    Resolution,
+    Dpi,
+    PrintSize,
+    FileSize,
+    Format,
+    ColorSpace,
+    Metadata,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    /// Likely accepted, but worth a look (e.g. an untagged file assumed to be sRGB).
+    Warning,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Violation {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FileReport {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: Option<String>,
+    pub dpi: Option<f32>,
+    pub color_space: Option<ColorSpace>,
+    pub violations: Vec<Violation>,
+    /// No errors; warnings do not fail a file.
+    pub passed: bool,
+}
+
+/// What a file declares about itself, read from its headers.
+#[derive(Clone, Debug, Default)]
+struct FileFacts {
+    width: u32,
+    height: u32,
+    bytes: u64,
+    format: Option<String>,
+    dpi: Option<f32>,
+    color_space: Option<ColorSpace>,
+    metadata: Vec<MetadataField>,
+}
+
+/// Checks one file against `profile`.
+pub fn validate(path: &str, profile: &SubmissionProfile) -> FileReport {
+    match inspect(path) {
+        Ok(facts) => {
+            let violations = check(&facts, profile);
+            FileReport {
+                path: path.to_string(),
+                width: facts.width,
+                height: facts.height,
+                format: facts.format,
+                dpi: facts.dpi,
+                color_space: facts.color_space,
+                passed: violations.iter().all(|v| v.severity != Severity::Error),
+                violations,
+            }
+        }
+        Err(e) => FileReport {
+            path: path.to_string(),
+            width: 0,
+            height: 0,
+            format: None,
+            dpi: None,
+            color_space: None,
+            violations: vec![Violation { rule: Rule::Unreadable, severity: Severity::Error, message: e }],
+            passed: false,
+        },
+    }
+}
+
+fn check(facts: &FileFacts, profile: &SubmissionProfile) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut fail = |rule, severity, message: String| violations.push(Violation { rule, severity, message });
+    let (long, short) = (facts.width.max(facts.height), facts.width.min(facts.height));
+    let megapixels = facts.width as f32 * facts.height as f32 / 1_000_000.0;
+
+    if let Some(min) = profile.min_megapixels.filter(|&min| megapixels < min) {
+        fail(Rule::Resolution, Severity::Error, format!("{:.1} MP is below the {:.1} MP minimum", megapixels, min));
+    }
+    if let Some(max) = profile.max_megapixels.filter(|&max| megapixels > max) {
+        fail(Rule::Resolution, Severity::Error, format!("{:.1} MP is above the {:.1} MP maximum", megapixels, max));
+    }
+    if let Some(min) = profile.min_long_edge.filter(|&min| long < min) {
+        fail(Rule::Resolution, Severity::Error, format!("Long edge of {} px is below {} px", long, min));
+    }
+    if let Some(min) = profile.min_short_edge.filter(|&min| short < min) {
+        fail(Rule::Resolution, Severity::Error, format!("Short edge of {} px is below {} px", short, min));
+    }
+
+    if let Some(min) = profile.min_dpi {
+        match facts.dpi {
+            Some(dpi) if dpi + 0.5 < min => {
+                fail(Rule::Dpi, Severity::Error, format!("Tagged at {:.0} dpi, {:.0} dpi required", dpi, min))
+            }
+            Some(_) => {}
+            None => fail(Rule::Dpi, Severity::Error, format!("No resolution tagged, {:.0} dpi required", min)),
+        }
+    }
+    if let Some([a, b]) = profile.print_size {
+        let dpi = profile.min_dpi.unwrap_or(300.0);
+        let (need_long, need_short) = ((a.max(b) * dpi).ceil() as u32, (a.min(b) * dpi).ceil() as u32);
+        if long < need_long || short < need_short {
+            fail(
+                Rule::PrintSize,
+                Severity::Error,
+                format!("{}x{} px is too small for {}x{} in at {:.0} dpi ({}x{} px)", long, short, a.max(b), a.min(b), dpi, need_long, need_short),
+            );
+        }
+    }
+    if let Some(max) = profile.max_file_mb {
+        let mb = facts.bytes as f32 / (1024.0 * 1024.0);
+        if mb > max {
+            fail(Rule::FileSize, Severity::Error, format!("{:.1} MB is above the {:.0} MB limit", mb, max));
+        }
+    }
+
+    if !profile.formats.is_empty() && !facts.format.as_ref().is_some_and(|f| profile.formats.iter().any(|a| a.eq_ignore_ascii_case(f))) {
+        fail(
+            Rule::Format,
+            Severity::Error,
+            format!("{} is not accepted ({})", facts.format.as_deref().unwrap_or("Unknown format"), profile.formats.join(", ")),
+        );
+    }
+
+    if !profile.color_spaces.is_empty() {
+        match &facts.color_space {
+            Some(space) if profile.color_spaces.contains(space) => {}
+            Some(space) => fail(Rule::ColorSpace, Severity::Error, format!("{:?} is not an accepted color space", space)),
+            // Untagged files are displayed as sRGB by agencies and labs alike
+            None if profile.color_spaces.contains(&ColorSpace::Srgb) => {
+                fail(Rule::ColorSpace, Severity::Warning, "No color profile embedded; it will be treated as sRGB".to_string())
+            }
+            None => fail(Rule::ColorSpace, Severity::Error, "No color profile embedded".to_string()),
+        }
+    }
+
+    for field in profile.required_metadata.iter().filter(|f| !facts.metadata.contains(f)) {
+        fail(Rule::Metadata, Severity::Error, format!("Missing {:?} metadata", field));
+    }
+    violations
+}
+
+fn inspect(path: &str) -> Result<FileFacts, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let reader = image::ImageReader::new(Cursor::new(&data)).with_guessed_format().map_err(|e| e.to_string())?;
+    let format = reader.format().map(|f| format!("{:?}", f).to_lowercase());
+    let (width, height) = reader.into_dimensions().map_err(|e| format!("Unsupported image {}: {}", path, e))?;
+    let mut facts = FileFacts { width, height, bytes: data.len() as u64, format, ..Default::default() };
+
+    let exif = exif::Reader::new().read_from_container(&mut Cursor::new(&data)).ok();
+    let container = DynImage::from_bytes(Bytes::copy_from_slice(&data)).ok().flatten();
+    let mut icc = container.as_ref().and_then(|c| c.icc_profile()).map(|b| b.to_vec());
+
+    if let Some(exif) = &exif {
+        let ascii = |tag| match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+            Some(Value::Ascii(parts)) => parts.iter().any(|p| !String::from_utf8_lossy(p).trim().is_empty()),
+            _ => false,
+        };
+        for (field, tag) in [
+            (MetadataField::Description, Tag::ImageDescription),
+            (MetadataField::Copyright, Tag::Copyright),
+            (MetadataField::Artist, Tag::Artist),
+            (MetadataField::CaptureDate, Tag::DateTimeOriginal),
+        ] {
+            if ascii(tag) {
+                facts.metadata.push(field);
+            }
+        }
+        facts.dpi = exif_dpi(exif);
+        if icc.is_none() {
+            icc = exif.get_field(ICC_TAG, In::PRIMARY).and_then(|f| match &f.value {
+                Value::Undefined(bytes, _) => Some(bytes.clone()),
+                Value::Byte(bytes) => Some(bytes.clone()),
+                _ => None,
+            });
+        }
+    }
+
+    match &container {
+        Some(DynImage::Jpeg(jpeg)) => {
+            if facts.dpi.is_none() {
+                facts.dpi = jpeg.segments_by_marker(img_parts::jpeg::markers::APP0).find_map(|s| jfif_dpi(s.contents()));
+            }
+            let iptc_keywords =
+                jpeg.segments_by_marker(img_parts::jpeg::markers::APP13).any(|s| s.contents().windows(3).any(|w| w == [0x1c, 0x02, 0x19]));
+            if iptc_keywords {
+                facts.metadata.push(MetadataField::Keywords);
+            }
+        }
+        Some(DynImage::Png(png)) if facts.dpi.is_none() => {
+            facts.dpi = png.chunk_by_type(*b"pHYs").and_then(|c| phys_dpi(c.contents()));
+        }
+        _ => {}
+    }
+    if !facts.metadata.contains(&MetadataField::Keywords) && contains(&data, b"<dc:subject>") {
+        facts.metadata.push(MetadataField::Keywords);
+    }
+
+    facts.color_space = icc.as_deref().and_then(icc_color_space).or_else(|| {
+        let declared = exif.as_ref().and_then(|e| e.get_field(Tag::ColorSpace, In::PRIMARY)).and_then(|f| f.value.get_uint(0));
+        let interop = exif
+            .as_ref()
+            .and_then(|e| e.get_field(Tag::InteroperabilityIndex, In::PRIMARY))
+            .map(|f| f.display_value().to_string());
+        match declared {
+            Some(1) => Some(ColorSpace::Srgb),
+            // DCF option file: "uncalibrated" plus the R03 interoperability index means Adobe RGB
+            Some(0xffff) if interop.is_some_and(|i| i.contains("R03")) => Some(ColorSpace::AdobeRgb),
+            _ => None,
+        }
+    });
+    Ok(facts)
+}
+
+/// The resolution a file is tagged with (EXIF, JFIF or PNG pHYs), in dpi.
+pub fn tagged_dpi(path: &str) -> Option<f32> {
+    inspect(path).ok()?.dpi
+}
+
+fn exif_dpi(exif: &exif::Exif) -> Option<f32> {
+    let x = match &exif.get_field(Tag::XResolution, In::PRIMARY)?.value {
+        Value::Rational(r) => r.first()?.to_f64(),
+        _ => return None,
+    };
+    let unit = exif.get_field(Tag::ResolutionUnit, In::PRIMARY).and_then(|f| f.value.get_uint(0)).unwrap_or(2);
+    match unit {
+        2 => Some(x as f32),
+        3 => Some((x * 2.54) as f32),
+        _ => None,
+    }
+}
+
+/// JFIF APP0: "JFIF\0", version, units (1 = dpi, 2 = dots/cm), X and Y density.
+fn jfif_dpi(app0: &[u8]) -> Option<f32> {
+    if app0.len() < 12 || !app0.starts_with(b"JFIF\0") {
+        return None;
+    }
+    let x = u16::from_be_bytes([app0[8], app0[9]]) as f32;
+    match app0[7] {
+        1 => Some(x),
+        2 => Some(x * 2.54),
+        _ => None,
+    }
+}
+
+/// PNG pHYs: X and Y pixels per unit, unit 1 = meter.
+fn phys_dpi(phys: &[u8]) -> Option<f32> {
+    if phys.len() < 9 || phys[8] != 1 {
+        return None;
+    }
+    Some(u32::from_be_bytes([phys[0], phys[1], phys[2], phys[3]]) as f32 * 0.0254)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Classifies an ICC profile by its data color space and description.
+fn icc_color_space(icc: &[u8]) -> Option<ColorSpace> {
+    match icc.get(16..20)? {
+        b"GRAY" => return Some(ColorSpace::Gray),
+        b"CMYK" => return Some(ColorSpace::Cmyk),
+        _ => {}
+    }
+    let description = icc_description(icc)?;
+    let folded = description.to_lowercase().replace(' ', "");
+    Some(if folded.contains("srgb") || folded.contains("iec61966-2") {
+        ColorSpace::Srgb
+    } else if folded.contains("adobergb") || folded.contains("compatiblewithadobe") {
+        ColorSpace::AdobeRgb
+    } else if folded.contains("p3") {
+        ColorSpace::DisplayP3
+    } else if folded.contains("prophoto") || folded.contains("romm") {
+        ColorSpace::ProPhoto
+    } else {
+        ColorSpace::Other(description)
+    })
+}
+
+/// Text of the `desc` tag: ICC v2 `desc` (ASCII) or v4 `mluc` (first record, UTF-16BE).
+fn icc_description(icc: &[u8]) -> Option<String> {
+    let be32 = |at: usize| icc.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let count = be32(128)?;
+    let (offset, size) = (0..count.min(256)).find_map(|i| {
+        let entry = 132 + i * 12;
+        if icc.get(entry..entry + 4)? != b"desc" {
+            return None;
+        }
+        Some((be32(entry + 4)?, be32(entry + 8)?))
+    })?;
+    let tag = icc.get(offset..offset.checked_add(size)?)?;
+    match tag.get(..4)? {
+        b"desc" => {
+            let len = be32(offset + 8)?;
+            let text = tag.get(12..12 + len)?;
+            Some(String::from_utf8_lossy(text).trim_end_matches('\0').trim().to_string())
+        }
+        b"mluc" => {
+            let (len, at) = (be32(offset + 20)?, be32(offset + 24)?);
+            let units: Vec<u16> = tag.get(at..at + len)?.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            Some(String::from_utf16_lossy(&units).trim_end_matches('\0').trim().to_string())
+        }
+        _ => None,
+    }
+}
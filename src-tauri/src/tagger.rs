@@ -0,0 +1,85 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Keyword Tagger
+ *
+ * Suggests keywords with an on-device ONNX image-tagging model, run with
+ * tract so no native runtime has to be installed. The user picks the model
+ * and a labels file with one keyword per line, in the order of the model's
+ * outputs. Images are resized to the model's square input, normalized with
+ * the ImageNet mean and deviation most taggers are trained with, and fed as
+ * a 1x3xSxS float tensor. Only compiled with the `keywords` feature;
+ * suggestions are returned to the UI and written only once confirmed.
+ */
+use image::DynamicImage;
+use tract_onnx::prelude::*;
+use crate::commands::{TaggerActivation, TaggerOptions};
+use crate::metadata::SuggestedKeyword;
+
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// A loaded model, shared by every image of a call.
+pub struct Tagger {
+    plan: Arc<TypedRunnableModel>,
+    labels: Vec<String>,
+    size: u32,
+}
+
+impl Tagger {
+    /// Parses `model` (ONNX bytes) for a square input of `size` pixels. Blank lines in
+    /// `labels` are kept, so the remaining labels stay aligned with the outputs.
+    pub fn new(model: &[u8], labels: &str, size: u32) -> Result<Self, String> {
+        if !(16..=1024).contains(&size) {
+            return Err(format!("Invalid tagger input size: {}", size));
+        }
+        let labels: Vec<String> = labels.lines().map(|l| l.trim().to_string()).collect();
+        if labels.iter().all(|l| l.is_empty()) {
+            return Err("Tagger labels file is empty".to_string());
+        }
+        let plan = tract_onnx::onnx()
+            .model_for_read(&mut std::io::Cursor::new(model))
+            .and_then(|m| m.with_input_fact(0, f32::fact([1, 3, size as usize, size as usize]).into()))
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+            .map_err(|e| format!("Failed to load tagging model: {}", e))?;
+        Ok(Self { plan, labels, size })
+    }
+
+    /// Keywords scoring at least `options.threshold`, best first, at most `options.max_keywords`.
+    pub fn suggest(&self, img: &DynamicImage, options: &TaggerOptions) -> Result<Vec<SuggestedKeyword>, String> {
+        let s = self.size as usize;
+        let rgb = img.resize_exact(self.size, self.size, image::imageops::FilterType::Triangle).to_rgb8();
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, s, s), |(_, c, y, x)| {
+            (rgb.get_pixel(x as u32, y as u32)[c] as f32 / 255.0 - MEAN[c]) / STD[c]
+        })
+        .into();
+        let outputs = self.plan.run(tvec!(input.into())).map_err(|e| format!("Tagging model failed: {}", e))?;
+        let scores = outputs[0].to_plain_array_view::<f32>().map_err(|e| format!("Tagging model output is not float: {}", e))?;
+        let scores: Vec<f32> = scores.iter().copied().collect();
+        if scores.len() != self.labels.len() {
+            return Err(format!("Tagging model has {} outputs but {} labels", scores.len(), self.labels.len()));
+        }
+
+        let confidences: Vec<f32> = match options.activation {
+            TaggerActivation::Sigmoid => scores.iter().map(|&v| 1.0 / (1.0 + (-v).exp())).collect(),
+            TaggerActivation::Softmax => {
+                let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let exps: Vec<f32> = scores.iter().map(|&v| (v - max).exp()).collect();
+                let sum: f32 = exps.iter().sum();
+                exps.iter().map(|e| e / sum).collect()
+            }
+            TaggerActivation::None => scores,
+        };
+        let mut suggested: Vec<SuggestedKeyword> = self
+            .labels
+            .iter()
+            .zip(confidences)
+            .filter(|(label, confidence)| !label.is_empty() && *confidence >= options.threshold)
+            .map(|(label, confidence)| SuggestedKeyword { keyword: label.clone(), confidence })
+            .collect();
+        suggested.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        suggested.truncate(options.max_keywords);
+        Ok(suggested)
+    }
+}
@@ -0,0 +1,100 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Timelapse Encoder
+ *
+ * Streams processed frames as raw RGB into an `ffmpeg` child process, which
+ * encodes them to H.264 in an MP4 container. The movie is written next to
+ * its target and renamed into place once ffmpeg finalizes it, so a failed
+ * export leaves nothing behind. Only compiled with the `timelapse` feature
+ * since it depends on an ffmpeg binary being installed.
+ */
+use image::RgbImage;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use crate::storage;
+
+/// Binary run when `CLIOBULK_FFMPEG` does not point at a specific build.
+const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+pub struct FfmpegEncoder {
+    /// Taken by `finish`; an encoder dropped with it still set is abandoned.
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    width: u32,
+    height: u32,
+    out_path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl FfmpegEncoder {
+    /// Starts ffmpeg writing `out_path`; every frame pushed must be `width` x `height`.
+    pub fn start(out_path: &str, fps: f32, width: u32, height: u32) -> Result<Self, String> {
+        let ffmpeg = std::env::var("CLIOBULK_FFMPEG").unwrap_or_else(|_| DEFAULT_FFMPEG.to_string());
+        let out_path = PathBuf::from(out_path);
+        let tmp_path = storage::temp_sibling(&out_path);
+        let mut child = Command::new(&ffmpeg)
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{}x{}", width, height), "-r", &fps.to_string(), "-i", "-"])
+            // yuv420p needs even dimensions and is what players expect from H.264
+            .args(["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .args(["-movflags", "+faststart"])
+            .arg(&tmp_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", ffmpeg, e))?;
+        let stdin = child.stdin.take();
+        Ok(Self { child: Some(child), stdin, width, height, out_path, tmp_path })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn push(&mut self, frame: &RgbImage) -> Result<(), String> {
+        if frame.dimensions() != (self.width, self.height) {
+            return Err(format!(
+                "Frame is {}x{}, expected {}x{}",
+                frame.width(),
+                frame.height(),
+                self.width,
+                self.height
+            ));
+        }
+        let stdin = self.stdin.as_mut().ok_or("Encoder already finished")?;
+        stdin.write_all(frame.as_raw()).map_err(|e| format!("ffmpeg stopped accepting frames: {}", e))
+    }
+
+    /// Closes the input, waits for ffmpeg to finalize the file and moves it into place.
+    pub fn finish(mut self) -> Result<(), String> {
+        drop(self.stdin.take());
+        let child = self.child.take().ok_or("Encoder already finished")?;
+        let output = child.wait_with_output().map_err(|e| e.to_string());
+        let result = output.and_then(|output| match output.status.success() {
+            true => Ok(()),
+            false => Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr).trim())),
+        });
+        let result = result.and_then(|_| {
+            std::fs::rename(&self.tmp_path, &self.out_path).map_err(|e| format!("Failed to write {}: {}", self.out_path.display(), e))
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+        result
+    }
+}
+
+impl Drop for FfmpegEncoder {
+    /// An export that stopped on an error stops ffmpeg and removes its partial file.
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            drop(self.stdin.take());
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
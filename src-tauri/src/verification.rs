@@ -0,0 +1,73 @@
+/**
+ * Author: Alejandro Ramírez
+ *
+ * ClioBulk Verification Batches
+ *
+ * A verification run processes a random sample of a large batch first and
+ * parks the rest here until the user has looked at the results and either
+ * continues or cancels, so a wrong setting costs a handful of files instead
+ * of the whole shoot.
+ */
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use crate::commands::ProcessOptions;
+
+/// Files held back by a verification run, with the options they will be processed with.
+#[derive(Clone)]
+pub struct HeldBatch {
+    /// `(batch index, (input, output))`
+    pub files: Vec<(usize, (String, String))>,
+    /// Options after asset loading and white balance locking, so the remainder
+    /// matches the sample exactly.
+    pub options: ProcessOptions,
+}
+
+/// Batches awaiting confirmation, registered as Tauri managed state.
+///
+/// Like preview sessions, each batch belongs to the window that started it.
+#[derive(Default)]
+pub struct PendingBatches {
+    next_id: AtomicU64,
+    batches: Mutex<HashMap<u64, (String, HeldBatch)>>,
+}
+
+impl PendingBatches {
+    pub fn hold(&self, owner: &str, batch: HeldBatch) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.batches.lock().unwrap().insert(id, (owner.to_string(), batch));
+        id
+    }
+
+    /// Removes and returns a batch owned by `owner`.
+    pub fn take(&self, owner: &str, id: u64) -> Result<HeldBatch, String> {
+        let mut batches = self.batches.lock().unwrap();
+        match batches.get(&id) {
+            Some((o, _)) if o == owner => Ok(batches.remove(&id).expect("checked above").1),
+            _ => Err(format!("Unknown verification batch: {}", id)),
+        }
+    }
+
+    pub fn close_window(&self, owner: &str) {
+        self.batches.lock().unwrap().retain(|_, (o, _)| o != owner);
+    }
+}
+
+/// Picks `percent` of `len` files (at least one) at random, returned in batch order.
+pub fn sample_indices(len: usize, percent: f32, seed: u64) -> Vec<usize> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let count = ((len as f32 * percent.clamp(0.0, 100.0) / 100.0).ceil() as usize).clamp(1, len);
+    // Partial Fisher-Yates shuffle
+    let mut indices: Vec<usize> = (0..len).collect();
+    let mut state = seed;
+    for i in 0..count {
+        state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        let j = i + ((state >> 33) as usize) % (len - i);
+        indices.swap(i, j);
+    }
+    let mut sample = indices[..count].to_vec();
+    sample.sort_unstable();
+    sample
+}
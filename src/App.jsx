@@ -33,7 +33,7 @@ import { parseCubeLUT } from './utils/webgl-engine';
 import { createTextWatermark } from './utils/watermark';
 import { decodeRaw, isRaw } from './utils/raw-decoder';
 import { invoke } from '@tauri-apps/api/core';
-import { listen } from '@tauri-apps/api/event';
+import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
 import { open } from '@tauri-apps/plugin-dialog';
 import { downloadDir } from '@tauri-apps/api/path';
 import { logger } from './utils/logger';
@@ -198,7 +198,8 @@ function App() {
     if (tauriActive) {
       // Setup listener for real-time progress events from the Rust backend
       const setupListener = async () => {
-        const unlisten = await listen('process-progress', (event) => {
+        // Progress is emitted to the window that started the job only
+        const unlisten = await getCurrentWebviewWindow().listen('process-progress', (event) => {
           const { path, success, error, progress: p, stage } = event.payload;
 
           // Performance Optimization: Only update status when processing is actually complete or failed.